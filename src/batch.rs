@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+
+use lsp_types::{Diagnostic, DiagnosticSeverity, NumberOrString, PublishDiagnosticsParams};
+
+use crate::cli::ReportFormat;
+use crate::config::Config;
+use crate::config_env::EnvSettings;
+use crate::config_file;
+use crate::diagnostics::runner::CheckJob;
+use crate::diagnostics::{ClientDiagnosticSupport, check_command};
+use crate::doc::position::PositionEncoding;
+use crate::doc::uri::uri_to_path;
+
+/// Checks the workspace at `root` once, with the settings of its
+/// `hitagi.toml` and the `HITAGI_*` environment variables, and prints its
+/// diagnostics. Returns the exit code: 1 when any diagnostic is an error, 2
+/// when the check could not run, and 0 otherwise.
+pub fn run(root: &Path, format: ReportFormat) -> i32 {
+    let root = std::path::absolute(root).unwrap_or_else(|_| root.to_path_buf());
+    // Human output counts columns in characters, as rustc does; JSON uses
+    // the LSP default.
+    let encoding = match format {
+        ReportFormat::Human => PositionEncoding::Utf32,
+        ReportFormat::Json => PositionEncoding::Utf16,
+    };
+    let mut config = Config::default();
+    if let Some((path, settings)) = config_file::load(&root) {
+        match settings {
+            Ok(settings) => {
+                for issue in config.update_from_settings(&settings) {
+                    eprintln!("hitagi: {}: {issue}", path.display());
+                }
+            }
+            Err(err) => {
+                eprintln!("hitagi: could not read {}: {err}", path.display());
+                return 2;
+            }
+        }
+    }
+    let env = EnvSettings::read();
+    for issue in config.update_from_settings(&env.settings) {
+        eprintln!("hitagi: {issue} (in the environment)");
+    }
+    for issue in &env.issues {
+        eprintln!("hitagi: {issue}");
+    }
+    let files = match check(&root, &config, encoding) {
+        Ok(files) => files,
+        Err(err) => {
+            eprintln!("hitagi: {err}");
+            return 2;
+        }
+    };
+    match format {
+        ReportFormat::Human => {
+            let color = io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+            print!("{}", render_human(&root, &files, color));
+        }
+        ReportFormat::Json => match serde_json::to_string_pretty(&files) {
+            Ok(json) => println!("{json}"),
+            Err(err) => {
+                eprintln!("hitagi: {err}");
+                return 2;
+            }
+        },
+    }
+    i32::from(has_errors(&files))
+}
+
+/// Runs the check the editor runs on save, reporting every file rather than
+/// only open ones, with the diagnostics of each file in order.
+pub fn check(
+    root: &Path,
+    config: &Config,
+    encoding: PositionEncoding,
+) -> Result<Vec<PublishDiagnosticsParams>, String> {
+    let mut diagnostics = config.diagnostics.clone();
+    diagnostics.open_files_only = false;
+    let job = CheckJob {
+        root: root.to_path_buf(),
+        command: check_command(config.check.command.as_deref(), &config.check, root, None),
+        support: ClientDiagnosticSupport::default(),
+        open_documents: HashMap::new(),
+        client_uris: HashMap::new(),
+        config: diagnostics,
+        encoding,
+    };
+    let mut files: Vec<_> = job
+        .run()?
+        .into_iter()
+        .filter(|(_, diagnostics)| !diagnostics.is_empty())
+        .map(|(uri, mut diagnostics)| {
+            diagnostics.sort_by_key(|diagnostic| (diagnostic.range.start, diagnostic.range.end));
+            PublishDiagnosticsParams::new(uri, diagnostics, None)
+        })
+        .collect();
+    files.sort_by(|a, b| a.uri.as_str().cmp(b.uri.as_str()));
+    Ok(files)
+}
+
+pub fn has_errors(files: &[PublishDiagnosticsParams]) -> bool {
+    files
+        .iter()
+        .flat_map(|file| &file.diagnostics)
+        .any(|diagnostic| diagnostic.severity == Some(DiagnosticSeverity::ERROR))
+}
+
+/// Lists the diagnostics under the path of their file, relative to `root`,
+/// followed by the number of errors and warnings. `color` adds ANSI colors.
+pub fn render_human(root: &Path, files: &[PublishDiagnosticsParams], color: bool) -> String {
+    let paint = |style: &str, text: &str| {
+        if color {
+            format!("\x1b[{style}m{text}\x1b[0m")
+        } else {
+            text.to_string()
+        }
+    };
+    let mut out = String::new();
+    let mut errors = 0;
+    let mut warnings = 0;
+    for file in files {
+        let path = match uri_to_path(&file.uri) {
+            Some(path) => path
+                .strip_prefix(root)
+                .unwrap_or(&path)
+                .display()
+                .to_string(),
+            None => file.uri.as_str().to_string(),
+        };
+        out.push_str(&format!("{}\n", paint("1", &path)));
+        for diagnostic in &file.diagnostics {
+            let (label, style) = severity_label(diagnostic);
+            match diagnostic.severity {
+                Some(DiagnosticSeverity::ERROR) => errors += 1,
+                Some(DiagnosticSeverity::WARNING) => warnings += 1,
+                _ => {}
+            }
+            let code = match &diagnostic.code {
+                Some(NumberOrString::String(code)) => format!("[{code}]"),
+                Some(NumberOrString::Number(code)) => format!("[{code}]"),
+                None => String::new(),
+            };
+            let start = diagnostic.range.start;
+            let mut lines = diagnostic.message.lines();
+            out.push_str(&format!(
+                "  {}:{} {}: {}\n",
+                start.line + 1,
+                start.character + 1,
+                paint(style, &format!("{label}{code}")),
+                lines.next().unwrap_or_default()
+            ));
+            for line in lines {
+                out.push_str(&format!("      {line}\n"));
+            }
+        }
+        out.push('\n');
+    }
+    let plural =
+        |count: usize, noun: &str| format!("{count} {noun}{}", if count == 1 { "" } else { "s" });
+    out.push_str(&format!(
+        "{}, {}\n",
+        plural(errors, "error"),
+        plural(warnings, "warning")
+    ));
+    out
+}
+
+/// The label of a diagnostic's severity and the ANSI style it is shown in.
+fn severity_label(diagnostic: &Diagnostic) -> (&'static str, &'static str) {
+    match diagnostic.severity {
+        Some(DiagnosticSeverity::ERROR) => ("error", "1;31"),
+        Some(DiagnosticSeverity::WARNING) => ("warning", "1;33"),
+        Some(DiagnosticSeverity::INFORMATION) => ("info", "1;36"),
+        _ => ("hint", "1;36"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    fn fixture_crate() -> PathBuf {
+        let root = temp_dir("batch");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(
+            root.join("Cargo.toml"),
+            "[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("src/main.rs"),
+            "fn main() {\n    let n: i32 = \"one\";\n}\n",
+        )
+        .unwrap();
+        root
+    }
+
+    #[test]
+    fn a_crate_with_an_error_is_reported_as_failing() {
+        let root = fixture_crate();
+        let files = check(&root, &Config::default(), PositionEncoding::Utf32).unwrap();
+        assert_eq!(files.len(), 1);
+        assert!(has_errors(&files));
+
+        let output = render_human(&root, &files, false);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[0], "src/main.rs");
+        assert_eq!(lines[1], "  2:18 error[E0308]: mismatched types");
+        assert_eq!(lines.last(), Some(&"1 error, 0 warnings"));
+        let colored = render_human(&root, &files, true);
+        assert!(
+            colored.contains("\x1b[1;31merror[E0308]\x1b[0m"),
+            "{colored}"
+        );
+
+        // The editor's settings apply, e.g. hiding a lint.
+        let mut config = Config::default();
+        config.diagnostics.disabled = vec!["E0308".to_string()];
+        let files = check(&root, &config, PositionEncoding::Utf32).unwrap();
+        assert!(!has_errors(&files));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,253 @@
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: hitagi [OPTIONS]
+       hitagi check [--root <PATH>] [--format <FORMAT>]
+
+A minimalist Rust language server, speaking LSP on stdin and stdout unless
+told to use a TCP socket.
+
+Commands:
+  check                   Run the check command once, print its diagnostics
+                          and exit with status 1 if any of them is an error
+
+Options:
+      --stdio             Communicate over stdin and stdout (the default)
+      --listen <ADDR>     Listen on ADDR, e.g. 127.0.0.1:9257, and serve the
+                          first client that connects
+      --connect <ADDR>    Connect to a client listening on ADDR
+      --log-file <PATH>   Write log lines to PATH instead of stderr; also read
+                          from $HITAGI_LOG_FILE
+  -V, --version           Print the version and exit
+  -h, --help              Print this help and exit
+
+Check options:
+      --root <PATH>       The workspace to check (default: the current
+                          directory)
+      --format <FORMAT>   `human` (default) or `json`, an array of
+                          publishDiagnostics params
+";
+
+/// What the command line asks for.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Mode {
+    Serve {
+        transport: Transport,
+        log_file: Option<PathBuf>,
+    },
+    /// Check the workspace at `root` once and print the diagnostics.
+    Check {
+        root: PathBuf,
+        format: ReportFormat,
+    },
+    Version,
+    Help,
+}
+
+/// How `hitagi check` prints diagnostics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Human,
+    Json,
+}
+
+/// How the server reaches its client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transport {
+    Stdio,
+    /// Accept one connection on this address.
+    Listen(String),
+    /// Connect to a client listening on this address.
+    Connect(String),
+}
+
+/// Parses the arguments after the program name. The log file falls back to
+/// `$HITAGI_LOG_FILE`.
+pub fn parse(args: impl IntoIterator<Item = OsString>) -> Result<Mode, String> {
+    let mut args = args.into_iter().peekable();
+    if args.next_if(|arg| arg == "check").is_some() {
+        return parse_check(args);
+    }
+    let mut transport = Transport::Stdio;
+    let mut log_file = None;
+    while let Some(arg) = args.next() {
+        let arg = utf8(arg)?;
+        let (flag, inline) = split_flag(&arg);
+        let mut value = || flag_value(flag, inline.clone(), &mut args);
+        match flag {
+            "-V" | "--version" if inline.is_none() => return Ok(Mode::Version),
+            "-h" | "--help" if inline.is_none() => return Ok(Mode::Help),
+            "--stdio" if inline.is_none() => transport = Transport::Stdio,
+            "--listen" => transport = Transport::Listen(value()?),
+            "--connect" => transport = Transport::Connect(value()?),
+            "--log-file" => log_file = Some(PathBuf::from(value()?)),
+            _ => return Err(format!("unexpected argument '{arg}'")),
+        }
+    }
+    let log_file = log_file.or_else(|| {
+        std::env::var_os("HITAGI_LOG_FILE")
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+    });
+    Ok(Mode::Serve {
+        transport,
+        log_file,
+    })
+}
+
+fn parse_check(mut args: impl Iterator<Item = OsString>) -> Result<Mode, String> {
+    let mut root = None;
+    let mut format = ReportFormat::Human;
+    while let Some(arg) = args.next() {
+        let arg = utf8(arg)?;
+        let (flag, inline) = split_flag(&arg);
+        let mut value = || flag_value(flag, inline.clone(), &mut args);
+        match flag {
+            "-h" | "--help" if inline.is_none() => return Ok(Mode::Help),
+            "--root" => root = Some(PathBuf::from(value()?)),
+            "--format" => {
+                format = match value()?.as_str() {
+                    "human" => ReportFormat::Human,
+                    "json" => ReportFormat::Json,
+                    other => return Err(format!("unknown format '{other}'")),
+                }
+            }
+            _ => return Err(format!("unexpected argument '{arg}'")),
+        }
+    }
+    let root = match root {
+        Some(root) => root,
+        None => std::env::current_dir()
+            .map_err(|err| format!("cannot read the current directory: {err}"))?,
+    };
+    Ok(Mode::Check { root, format })
+}
+
+fn utf8(arg: OsString) -> Result<String, String> {
+    arg.into_string()
+        .map_err(|arg| format!("unexpected argument '{}'", arg.to_string_lossy()))
+}
+
+/// Splits an argument into the flag and the value given inline, since
+/// options taking a value accept it as `--name value` or `--name=value`.
+fn split_flag(arg: &str) -> (&str, Option<String>) {
+    match arg.split_once('=') {
+        Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+        _ => (arg, None),
+    }
+}
+
+/// The value of `flag`: the one given inline, or else the next argument.
+fn flag_value(
+    flag: &str,
+    inline: Option<String>,
+    args: &mut impl Iterator<Item = OsString>,
+) -> Result<String, String> {
+    inline
+        .or_else(|| args.next().and_then(|value| value.into_string().ok()))
+        .ok_or_else(|| format!("'{flag}' needs a value"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Mode, String> {
+        parse(args.iter().map(OsString::from))
+    }
+
+    #[test]
+    fn probes_exit_without_serving() {
+        assert_eq!(parse_args(&["--version"]), Ok(Mode::Version));
+        assert_eq!(parse_args(&["--stdio", "-V"]), Ok(Mode::Version));
+        assert_eq!(parse_args(&["--help"]), Ok(Mode::Help));
+        assert_eq!(parse_args(&["-h", "--bogus"]), Ok(Mode::Help));
+    }
+
+    #[test]
+    fn the_log_file_comes_from_the_command_line() {
+        assert_eq!(
+            parse_args(&["--log-file", "/tmp/a.log"]),
+            Ok(Mode::Serve {
+                transport: Transport::Stdio,
+                log_file: Some(PathBuf::from("/tmp/a.log"))
+            })
+        );
+        assert_eq!(
+            parse_args(&["--stdio", "--log-file=/tmp/b.log"]),
+            Ok(Mode::Serve {
+                transport: Transport::Stdio,
+                log_file: Some(PathBuf::from("/tmp/b.log"))
+            })
+        );
+    }
+
+    #[test]
+    fn sockets_are_chosen_with_listen_or_connect() {
+        let transport = |args: &[&str]| match parse_args(args) {
+            Ok(Mode::Serve { transport, .. }) => transport,
+            other => panic!("{other:?}"),
+        };
+        assert_eq!(
+            transport(&["--listen", "127.0.0.1:9257"]),
+            Transport::Listen("127.0.0.1:9257".to_string())
+        );
+        assert_eq!(
+            transport(&["--connect=localhost:9257"]),
+            Transport::Connect("localhost:9257".to_string())
+        );
+        // The last transport given wins.
+        assert_eq!(
+            transport(&["--listen", "127.0.0.1:1", "--stdio"]),
+            Transport::Stdio
+        );
+    }
+
+    #[test]
+    fn check_takes_a_root_and_a_format() {
+        assert_eq!(
+            parse_args(&["check", "--root", "/work", "--format=json"]),
+            Ok(Mode::Check {
+                root: PathBuf::from("/work"),
+                format: ReportFormat::Json
+            })
+        );
+        assert_eq!(
+            parse_args(&["check"]),
+            Ok(Mode::Check {
+                root: std::env::current_dir().unwrap(),
+                format: ReportFormat::Human
+            })
+        );
+        assert_eq!(
+            parse_args(&["check", "--format", "xml"]),
+            Err("unknown format 'xml'".to_string())
+        );
+        // Server options do not apply to `check`.
+        assert_eq!(
+            parse_args(&["check", "--stdio"]),
+            Err("unexpected argument '--stdio'".to_string())
+        );
+    }
+
+    #[test]
+    fn unknown_flags_are_rejected() {
+        assert_eq!(
+            parse_args(&["--tcp"]),
+            Err("unexpected argument '--tcp'".to_string())
+        );
+        assert_eq!(
+            parse_args(&["--version=2"]),
+            Err("unexpected argument '--version=2'".to_string())
+        );
+        assert_eq!(
+            parse_args(&["--stdio", "--log-file"]),
+            Err("'--log-file' needs a value".to_string())
+        );
+        assert_eq!(
+            parse_args(&["--listen"]),
+            Err("'--listen' needs a value".to_string())
+        );
+    }
+}
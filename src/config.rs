@@ -1,8 +1,37 @@
-use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
 
-#[derive(Debug, Clone, Copy)]
+use lsp_types::{DiagnosticSeverity, MarkupKind};
+use serde_json::{Map, Value, json};
+
+/// Which sources features look at besides the open documents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum WorkspaceMode {
+    /// Only the documents open in the client; nothing is read from disk.
     OpenFilesOnly,
+    /// Every `.rs` file under the workspace root as well.
+    Workspace,
+}
+
+/// Which files on disk hover and inlay hints look at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkspaceConfig {
+    pub mode: WorkspaceMode,
+    /// Directories left out of the workspace, by name or by path relative
+    /// to the root, besides `target` and `.git`.
+    pub exclude_dirs: Vec<String>,
+    /// The number of files on disk read before the walk stops.
+    pub max_files: usize,
+}
+
+impl Default for WorkspaceConfig {
+    fn default() -> Self {
+        Self {
+            mode: WorkspaceMode::Workspace,
+            exclude_dirs: Vec::new(),
+            max_files: 10_000,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -13,73 +42,792 @@ pub enum LogLevel {
     Debug,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InlayHintsConfig {
+    pub hide_obvious_types: bool,
+    pub padding: bool,
+    pub binding_mode_hints: bool,
+    pub closing_brace_min_lines: usize,
+    pub pattern_param_hints: bool,
+}
+
+impl Default for InlayHintsConfig {
+    fn default() -> Self {
+        Self {
+            hide_obvious_types: true,
+            padding: true,
+            binding_mode_hints: false,
+            closing_brace_min_lines: 25,
+            pattern_param_hints: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HoverConfig {
+    /// Overrides the format advertised by the client when set.
+    pub content_format: Option<MarkupKind>,
+    pub max_lines: usize,
+    pub show_docs: bool,
+    pub keywords: bool,
+    pub sysroot: bool,
+}
+
+impl Default for HoverConfig {
+    fn default() -> Self {
+        Self {
+            content_format: None,
+            max_lines: 20,
+            show_docs: true,
+            keywords: true,
+            sysroot: true,
+        }
+    }
+}
+
+/// How the output of the check command is turned into diagnostics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// cargo's `--message-format=json` messages on stdout.
+    CargoJson,
+    /// Lines of stdout and stderr matched against `check.outputPattern`.
+    Regex,
+}
+
+/// Which packages the default check command covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckScope {
+    Workspace,
+    /// Only the workspace member owning the saved file, with `-p`.
+    Package,
+}
+
+/// When and how the check runs.
+#[derive(Debug, Clone)]
+pub struct CheckConfig {
+    pub on_save: bool,
+    /// Replaces the default `cargo check` command wholesale when set.
+    pub command: Option<Vec<String>>,
+    pub all_targets: bool,
+    pub features: Vec<String>,
+    pub no_default_features: bool,
+    pub target_triple: Option<String>,
+    /// Environment variables set for the check, with `${workspaceRoot}`
+    /// substituted in values.
+    pub extra_env: HashMap<String, String>,
+    /// Builds into `target/hitagi` so checks never wait on the user's own
+    /// builds for the target directory lock.
+    pub separate_target_dir: bool,
+    /// Seconds before a check is killed, or `0` for no limit.
+    pub timeout_seconds: u64,
+    pub output_format: OutputFormat,
+    /// The pattern used by the `regex` output format, replacing the one for
+    /// `rustc --error-format=short` output when set.
+    pub output_pattern: Option<String>,
+    pub scope: CheckScope,
+    /// Checks files outside any Cargo project with `rustc` on their own.
+    pub standalone_files: bool,
+}
+
+impl Default for CheckConfig {
+    fn default() -> Self {
+        Self {
+            on_save: true,
+            command: None,
+            all_targets: false,
+            features: Vec::new(),
+            no_default_features: false,
+            target_triple: None,
+            extra_env: HashMap::new(),
+            separate_target_dir: false,
+            timeout_seconds: 300,
+            output_format: OutputFormat::CargoJson,
+            output_pattern: None,
+            scope: CheckScope::Workspace,
+            standalone_files: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DiagnosticsConfig {
+    /// Publish diagnostics only for open documents instead of every file
+    /// cargo reports.
+    pub open_files_only: bool,
+    /// Use cargo's rendered text, with source excerpts and notes, as the
+    /// message.
+    pub use_rendered: bool,
+    pub rendered_max_lines: usize,
+    /// Severity per lint or error code, replacing the one cargo reports.
+    /// `None` drops the diagnostic.
+    pub severity_overrides: HashMap<String, Option<DiagnosticSeverity>>,
+    /// Lint or error codes whose diagnostics are dropped.
+    pub disabled: Vec<String>,
+    /// Globs relative to the root for files whose diagnostics are dropped.
+    pub exclude_paths: Vec<String>,
+}
+
+impl Default for DiagnosticsConfig {
+    fn default() -> Self {
+        Self {
+            open_files_only: true,
+            use_rendered: false,
+            rendered_max_lines: 40,
+            severity_overrides: HashMap::new(),
+            disabled: Vec::new(),
+            exclude_paths: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LimitsConfig {
+    /// Documents larger than this many bytes are kept in sync but not
+    /// analyzed, so hover and inlay hints skip them.
+    pub max_analyzed_file_size: usize,
+    /// Messages from the client whose body is larger than this many bytes
+    /// are skipped instead of read into memory.
+    pub max_message_size: usize,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_analyzed_file_size: 2 * 1024 * 1024,
+            max_message_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
-    pub workspace_mode: WorkspaceMode,
-    pub check_on_save: bool,
-    pub check_command: Vec<String>,
+    pub workspace: WorkspaceConfig,
+    pub check: CheckConfig,
     pub log_level: LogLevel,
+    pub inlay_hints: InlayHintsConfig,
+    pub hover: HoverConfig,
+    pub diagnostics: DiagnosticsConfig,
+    pub limits: LimitsConfig,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
-            workspace_mode: WorkspaceMode::OpenFilesOnly,
-            check_on_save: true,
-            check_command: vec![
-                "cargo".to_string(),
-                "check".to_string(),
-                "-q".to_string(),
-                "--message-format=json".to_string(),
-            ],
+            workspace: WorkspaceConfig::default(),
+            check: CheckConfig::default(),
             log_level: LogLevel::Warn,
+            inlay_hints: InlayHintsConfig::default(),
+            hover: HoverConfig::default(),
+            diagnostics: DiagnosticsConfig::default(),
+            limits: LimitsConfig::default(),
         }
     }
 }
 
 impl Config {
-    pub fn update_from_settings(&mut self, settings: &Value) {
-        let root = if let Some(obj) = settings.as_object() {
-            if let Some(inner) = obj.get("hitagi") {
-                inner
-            } else {
-                settings
-            }
-        } else {
-            settings
+    /// The defaults updated from `settings`, along with the settings that
+    /// were left out for being unknown or invalid.
+    pub fn from_settings(settings: &Value) -> (Self, Vec<ConfigIssue>) {
+        let mut config = Self::default();
+        let issues = config.update_from_settings(settings);
+        (config, issues)
+    }
+
+    /// Applies the valid settings in `settings` and returns what is wrong
+    /// with the others.
+    pub fn update_from_settings(&mut self, settings: &Value) -> Vec<ConfigIssue> {
+        let mut issues = Vec::new();
+        let settings = settings_root(settings);
+        let Some(table) = settings.as_object() else {
+            return issues;
         };
+        let mut root = Settings::new(table, String::new(), &mut issues);
+
+        if let Some(mode) = root.choice("workspaceMode", WORKSPACE_MODES) {
+            self.workspace.mode = mode;
+        }
+        if let Some(workspace) = root.table("workspace") {
+            self.workspace.update_from_settings(workspace);
+        }
+
+        // The flat spellings of `check.onSave` and `check.command`, which
+        // the nested ones override.
+        if let Some(check) = root.bool("checkOnSave") {
+            self.check.on_save = check;
+        }
+        if let Some(command) = root.command("checkCommand") {
+            self.check.command = Some(command);
+        }
+
+        if let Some(check) = root.table("check") {
+            self.check.update_from_settings(check);
+        }
+
+        if let Some(level) = root.choice(
+            "logLevel",
+            &[
+                ("error", LogLevel::Error),
+                ("warn", LogLevel::Warn),
+                ("info", LogLevel::Info),
+                ("debug", LogLevel::Debug),
+            ],
+        ) {
+            self.log_level = level;
+        }
+
+        if let Some(hints) = root.table("inlayHints") {
+            self.inlay_hints.update_from_settings(hints);
+        }
+
+        if let Some(hover) = root.table("hover") {
+            self.hover.update_from_settings(hover);
+        }
 
-        if let Some(mode) = root.get("workspaceMode").and_then(|v| v.as_str()) {
-            if mode.eq_ignore_ascii_case("openFilesOnly") {
-                self.workspace_mode = WorkspaceMode::OpenFilesOnly;
+        if let Some(diagnostics) = root.table("diagnostics") {
+            self.diagnostics.update_from_settings(diagnostics);
+        }
+
+        if let Some(limits) = root.table("limits") {
+            self.limits.update_from_settings(limits);
+        }
+        drop(root);
+        issues
+    }
+}
+
+/// The settings of hitagi in `settings` as one tree of tables. Clients send
+/// them either on their own or under a `hitagi` key (`stapler` in older
+/// versions), and with nested tables, dotted keys such as
+/// `"hitagi.check.onSave"`, or a mix of both.
+pub fn settings_root(settings: &Value) -> Value {
+    let settings = expand_dotted_keys(settings);
+    let root = ["hitagi", "stapler"]
+        .iter()
+        .find_map(|name| settings.get(*name))
+        .unwrap_or(&settings);
+    expand_dotted_keys(root)
+}
+
+/// `table` with its keys split at dots into nested tables, so that
+/// `"check.onSave": false` reads as `"check": { "onSave": false }`. The
+/// dotted keys are applied after the nested ones. Keys inside the values,
+/// such as the codes in `diagnostics.severityOverrides`, are left alone.
+fn expand_dotted_keys(table: &Value) -> Value {
+    let Some(map) = table.as_object() else {
+        return table.clone();
+    };
+    let mut expanded = Value::Object(Map::new());
+    let (dotted, plain): (Vec<_>, Vec<_>) = map.iter().partition(|(key, _)| key.contains('.'));
+    for (key, value) in plain.into_iter().chain(dotted) {
+        let nested = key
+            .rsplit('.')
+            .fold(value.clone(), |value, part| json!({ (part): value }));
+        merge_settings(&mut expanded, &nested);
+    }
+    expanded
+}
+
+/// Merges `overrides` into `settings`, table by table, so that a change to
+/// one setting keeps the others.
+pub fn merge_settings(settings: &mut Value, overrides: &Value) {
+    match (settings, overrides) {
+        (Value::Object(settings), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                merge_settings(settings.entry(key.clone()).or_insert(Value::Null), value);
             }
         }
+        (settings, overrides) => *settings = overrides.clone(),
+    }
+}
 
-        if let Some(check) = root.get("checkOnSave").and_then(|v| v.as_bool()) {
-            self.check_on_save = check;
+/// A setting that was left out, and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigIssue {
+    /// The full key, e.g. `check.features`.
+    pub key: String,
+    pub problem: String,
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` {}", self.key, self.problem)
+    }
+}
+
+/// Reads the keys of one table of settings, recording those of the wrong
+/// type in `issues`. `null` counts as unset. Keys that were never read are
+/// reported as unknown once it is dropped.
+struct Settings<'a, 'i> {
+    table: &'a Map<String, Value>,
+    /// The keys of the enclosing tables, e.g. `check.`.
+    prefix: String,
+    read: Vec<&'a str>,
+    issues: &'i mut Vec<ConfigIssue>,
+}
+
+impl<'a, 'i> Settings<'a, 'i> {
+    fn new(
+        table: &'a Map<String, Value>,
+        prefix: String,
+        issues: &'i mut Vec<ConfigIssue>,
+    ) -> Self {
+        Settings {
+            table,
+            prefix,
+            read: Vec::new(),
+            issues,
         }
+    }
+
+    fn issue(&mut self, key: &str, problem: impl Into<String>) {
+        self.issues.push(ConfigIssue {
+            key: format!("{}{key}", self.prefix),
+            problem: problem.into(),
+        });
+    }
+
+    fn get(&mut self, key: &'a str) -> Option<&'a Value> {
+        self.read.push(key);
+        self.table.get(key).filter(|value| !value.is_null())
+    }
 
-        if let Some(cmd) = root.get("checkCommand") {
-            if let Some(arr) = cmd.as_array() {
-                let mut next = Vec::new();
-                for item in arr {
-                    if let Some(s) = item.as_str() {
-                        next.push(s.to_string());
+    /// The value of `key` converted by `convert`, or None with an issue
+    /// saying it should be `expected`.
+    fn typed<T>(
+        &mut self,
+        key: &'a str,
+        expected: &str,
+        convert: impl FnOnce(&'a Value) -> Option<T>,
+    ) -> Option<T> {
+        let value = self.get(key)?;
+        let converted = convert(value);
+        if converted.is_none() {
+            self.issue(key, format!("should be {expected}, not {value}"));
+        }
+        converted
+    }
+
+    fn bool(&mut self, key: &'a str) -> Option<bool> {
+        self.typed(key, "`true` or `false`", Value::as_bool)
+    }
+
+    fn number(&mut self, key: &'a str) -> Option<usize> {
+        self.typed(key, "a whole number", |value| {
+            value.as_u64().and_then(|n| usize::try_from(n).ok())
+        })
+    }
+
+    fn strings(&mut self, key: &'a str) -> Option<Vec<String>> {
+        self.typed(key, "an array of strings", |value| {
+            value
+                .as_array()?
+                .iter()
+                .map(|item| item.as_str().map(str::to_string))
+                .collect()
+        })
+    }
+
+    /// A string, where an empty one or `null` unsets the setting.
+    fn optional_string(&mut self, key: &'a str) -> Option<Option<String>> {
+        if self.table.get(key).is_some_and(Value::is_null) {
+            self.read.push(key);
+            return Some(None);
+        }
+        self.typed(key, "a string", Value::as_str)
+            .map(|text| Some(text.to_string()).filter(|text| !text.is_empty()))
+    }
+
+    /// One of the `choices`, compared ignoring case.
+    fn choice<T: Clone>(&mut self, key: &'a str, choices: &[(&str, T)]) -> Option<T> {
+        let value = self.get(key)?;
+        let found = value.as_str().and_then(|text| {
+            choices
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(text))
+                .map(|(_, choice)| choice.clone())
+        });
+        if found.is_none() {
+            let names: Vec<String> = choices
+                .iter()
+                .map(|(name, _)| format!("`{name}`"))
+                .collect();
+            self.issue(
+                key,
+                format!("should be one of {}, not {value}", names.join(", ")),
+            );
+        }
+        found
+    }
+
+    /// A command line, where an empty one is left out.
+    fn command(&mut self, key: &'a str) -> Option<Vec<String>> {
+        let command = self.strings(key)?;
+        if command.is_empty() {
+            self.issue(key, "is empty, so the default command is used");
+            return None;
+        }
+        Some(command)
+    }
+
+    fn object(&mut self, key: &'a str) -> Option<&'a Map<String, Value>> {
+        self.typed(key, "an object", Value::as_object)
+    }
+
+    /// The nested table `key`, reporting into the same issues.
+    fn table(&mut self, key: &'a str) -> Option<Settings<'a, '_>> {
+        let table = self.object(key)?;
+        let prefix = format!("{}{key}.", self.prefix);
+        Some(Settings::new(table, prefix, self.issues))
+    }
+}
+
+impl Drop for Settings<'_, '_> {
+    fn drop(&mut self) {
+        let unknown: Vec<&String> = self
+            .table
+            .keys()
+            .filter(|key| !self.read.contains(&key.as_str()))
+            .collect();
+        for key in unknown {
+            self.issues.push(ConfigIssue {
+                key: format!("{}{key}", self.prefix),
+                problem: "is not a setting".to_string(),
+            });
+        }
+    }
+}
+
+const WORKSPACE_MODES: &[(&str, WorkspaceMode)] = &[
+    ("openFilesOnly", WorkspaceMode::OpenFilesOnly),
+    ("workspace", WorkspaceMode::Workspace),
+];
+
+impl WorkspaceConfig {
+    fn update_from_settings(&mut self, mut settings: Settings) {
+        if let Some(mode) = settings.choice("mode", WORKSPACE_MODES) {
+            self.mode = mode;
+        }
+        if let Some(dirs) = settings.strings("excludeDirs") {
+            self.exclude_dirs = dirs;
+        }
+        if let Some(max_files) = settings.number("maxFiles") {
+            self.max_files = max_files;
+        }
+    }
+}
+
+impl LimitsConfig {
+    fn update_from_settings(&mut self, mut settings: Settings) {
+        if let Some(size) = settings.number("maxAnalyzedFileSize") {
+            self.max_analyzed_file_size = size;
+        }
+        if let Some(size) = settings.number("maxMessageSize") {
+            self.max_message_size = size;
+        }
+    }
+}
+
+impl CheckConfig {
+    fn update_from_settings(&mut self, mut settings: Settings) {
+        if let Some(enabled) = settings.bool("onSave") {
+            self.on_save = enabled;
+        }
+        if let Some(command) = settings.command("command") {
+            self.command = Some(command);
+        }
+        if let Some(enabled) = settings.bool("allTargets") {
+            self.all_targets = enabled;
+        }
+        if let Some(features) = settings.strings("features") {
+            self.features = features;
+        }
+        if let Some(enabled) = settings.bool("noDefaultFeatures") {
+            self.no_default_features = enabled;
+        }
+        if let Some(triple) = settings.optional_string("targetTriple") {
+            self.target_triple = triple;
+        }
+        if let Some(env) = settings.object("extraEnv") {
+            self.extra_env = HashMap::new();
+            for (name, value) in env {
+                match value.as_str() {
+                    Some(value) => {
+                        self.extra_env.insert(name.clone(), value.to_string());
+                    }
+                    None => {
+                        settings.issue(&format!("extraEnv.{name}"), "should be a string");
                     }
-                }
-                if !next.is_empty() {
-                    self.check_command = next;
                 }
             }
         }
+        if let Some(enabled) = settings.bool("separateTargetDir") {
+            self.separate_target_dir = enabled;
+        }
+        if let Some(seconds) = settings.number("timeoutSeconds") {
+            self.timeout_seconds = seconds as u64;
+        }
+        if let Some(format) = settings.choice(
+            "outputFormat",
+            &[
+                ("cargoJson", OutputFormat::CargoJson),
+                ("regex", OutputFormat::Regex),
+            ],
+        ) {
+            self.output_format = format;
+        }
+        if let Some(pattern) = settings.optional_string("outputPattern") {
+            self.output_pattern = pattern;
+        }
+        if let Some(scope) = settings.choice(
+            "scope",
+            &[
+                ("workspace", CheckScope::Workspace),
+                ("package", CheckScope::Package),
+            ],
+        ) {
+            self.scope = scope;
+        }
+        if let Some(enabled) = settings.bool("standaloneFiles") {
+            self.standalone_files = enabled;
+        }
+    }
+}
+
+impl DiagnosticsConfig {
+    fn update_from_settings(&mut self, mut settings: Settings) {
+        if let Some(enabled) = settings.bool("openFilesOnly") {
+            self.open_files_only = enabled;
+        }
+        if let Some(enabled) = settings.bool("useRendered") {
+            self.use_rendered = enabled;
+        }
+        if let Some(max_lines) = settings.number("renderedMaxLines") {
+            self.rendered_max_lines = max_lines.max(1);
+        }
+        if let Some(overrides) = settings.object("severityOverrides") {
+            self.severity_overrides = HashMap::new();
+            for (code, level) in overrides {
+                let severity = match level.as_str().map(str::to_ascii_lowercase).as_deref() {
+                    Some("error") => Some(DiagnosticSeverity::ERROR),
+                    Some("warning") => Some(DiagnosticSeverity::WARNING),
+                    Some("info") => Some(DiagnosticSeverity::INFORMATION),
+                    Some("hint") => Some(DiagnosticSeverity::HINT),
+                    Some("off") => None,
+                    _ => {
+                        settings.issue(
+                            &format!("severityOverrides.{code}"),
+                            format!(
+                                "should be one of `error`, `warning`, `info`, `hint`, `off`, \
+                                 not {level}"
+                            ),
+                        );
+                        continue;
+                    }
+                };
+                self.severity_overrides.insert(code.clone(), severity);
+            }
+        }
+        if let Some(disabled) = settings.strings("disabled") {
+            self.disabled = disabled;
+        }
+        if let Some(globs) = settings.strings("excludePaths") {
+            self.exclude_paths = globs;
+        }
+    }
+
+    /// Whether `code`, or its bare form for `clippy::unwrap_used`, is listed
+    /// in `diagnostics.disabled`.
+    pub fn is_disabled(&self, code: &str) -> bool {
+        let bare = code.rsplit_once("::").map(|(_, bare)| bare);
+        self.disabled
+            .iter()
+            .any(|disabled| disabled == code || Some(disabled.as_str()) == bare)
+    }
+
+    /// Returns the override for `code`, matching `clippy::unwrap_used`
+    /// against both `clippy::unwrap_used` and `unwrap_used`.
+    pub fn severity_override(&self, code: &str) -> Option<Option<DiagnosticSeverity>> {
+        if let Some(severity) = self.severity_overrides.get(code) {
+            return Some(*severity);
+        }
+        let (_, bare) = code.rsplit_once("::")?;
+        self.severity_overrides.get(bare).copied()
+    }
+}
+
+impl HoverConfig {
+    fn update_from_settings(&mut self, mut settings: Settings) {
+        if let Some(format) = settings.choice(
+            "contentFormat",
+            &[
+                ("markdown", MarkupKind::Markdown),
+                ("plaintext", MarkupKind::PlainText),
+            ],
+        ) {
+            self.content_format = Some(format);
+        }
+        if let Some(max_lines) = settings.number("maxLines") {
+            self.max_lines = max_lines.max(1);
+        }
+        if let Some(enabled) = settings.bool("showDocs") {
+            self.show_docs = enabled;
+        }
+        if let Some(enabled) = settings.bool("keywords") {
+            self.keywords = enabled;
+        }
+        if let Some(enabled) = settings.bool("sysroot") {
+            self.sysroot = enabled;
+        }
+    }
+}
 
-        if let Some(level) = root.get("logLevel").and_then(|v| v.as_str()) {
-            self.log_level = match level.to_ascii_lowercase().as_str() {
-                "error" => LogLevel::Error,
-                "info" => LogLevel::Info,
-                "debug" => LogLevel::Debug,
-                _ => LogLevel::Warn,
-            };
+impl InlayHintsConfig {
+    fn update_from_settings(&mut self, mut settings: Settings) {
+        if let Some(hide) = settings.bool("hideObviousTypes") {
+            self.hide_obvious_types = hide;
+        }
+        if let Some(padding) = settings.bool("padding") {
+            self.padding = padding;
         }
+        if let Some(enabled) = settings.bool("bindingModeHints") {
+            self.binding_mode_hints = enabled;
+        }
+        if let Some(mut closing_brace) = settings.table("closingBraceHints")
+            && let Some(min_lines) = closing_brace.number("minLines")
+        {
+            self.closing_brace_min_lines = min_lines;
+        }
+        if let Some(enabled) = settings.bool("patternParameterHints") {
+            self.pattern_param_hints = enabled;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn issues(settings: Value) -> Vec<String> {
+        Config::from_settings(&settings)
+            .1
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn invalid_settings_are_reported_and_left_out() {
+        let cases = [
+            (
+                json!({ "checkComand": ["cargo", "clippy"] }),
+                "`checkComand` is not a setting",
+            ),
+            (
+                json!({ "checkOnSave": "yes" }),
+                "`checkOnSave` should be `true` or `false`, not \"yes\"",
+            ),
+            (
+                json!({ "checkCommand": [] }),
+                "`checkCommand` is empty, so the default command is used",
+            ),
+            (
+                json!({ "checkCommand": ["cargo", 1] }),
+                "`checkCommand` should be an array of strings, not [\"cargo\",1]",
+            ),
+            (
+                json!({ "workspaceMode": "everything" }),
+                "`workspaceMode` should be one of `openFilesOnly`, `workspace`, not \"everything\"",
+            ),
+            (
+                json!({ "check": { "features": "cli" } }),
+                "`check.features` should be an array of strings, not \"cli\"",
+            ),
+            (
+                json!({ "check": { "extraEnv": { "RUSTFLAGS": 1 } } }),
+                "`check.extraEnv.RUSTFLAGS` should be a string",
+            ),
+            (
+                json!({ "diagnostics": { "severityOverrides": { "dead_code": "loud" } } }),
+                "`diagnostics.severityOverrides.dead_code` should be one of `error`, `warning`, \
+                 `info`, `hint`, `off`, not \"loud\"",
+            ),
+            (
+                json!({ "inlayHints": { "closingBraceHints": { "minLine": 3 } } }),
+                "`inlayHints.closingBraceHints.minLine` is not a setting",
+            ),
+            (
+                json!({ "limits": { "maxMessageSize": -1 } }),
+                "`limits.maxMessageSize` should be a whole number, not -1",
+            ),
+            (
+                json!({ "hover": true }),
+                "`hover` should be an object, not true",
+            ),
+        ];
+        for (settings, expected) in cases {
+            assert_eq!(issues(settings.clone()), [expected], "{settings}");
+        }
+    }
+
+    #[test]
+    fn the_valid_settings_still_apply() {
+        let (config, found) = Config::from_settings(&json!({ "hitagi": {
+            "checkOnSave": false,
+            "checkComand": ["cargo", "clippy"],
+            "logLevel": "INFO",
+            "check": { "features": ["cli"], "targetTriple": null, "timeout": 5 },
+            "diagnostics": { "severityOverrides": { "dead_code": "hint", "unused": "loud" } },
+        } }));
+        assert_eq!(found.len(), 3, "{found:?}");
+        assert!(!config.check.on_save);
+        assert!(config.check.command.is_none());
+        assert!(matches!(config.log_level, LogLevel::Info));
+        assert_eq!(config.check.features, ["cli"]);
+        assert_eq!(
+            config.diagnostics.severity_override("dead_code"),
+            Some(Some(DiagnosticSeverity::HINT))
+        );
+        assert_eq!(config.diagnostics.severity_override("unused"), None);
+
+        // Unset values are not problems.
+        assert!(issues(json!({ "checkOnSave": null, "hover": { "maxLines": null } })).is_empty());
+    }
+
+    #[test]
+    fn every_spelling_of_a_setting_applies() {
+        let spellings = [
+            json!({ "checkOnSave": false, "inlayHints": { "closingBraceHints": { "minLines": 3 } } }),
+            json!({ "check": { "onSave": false }, "inlayHints.closingBraceHints.minLines": 3 }),
+            json!({ "check.onSave": false, "inlayHints.closingBraceHints": { "minLines": 3 } }),
+            json!({ "hitagi": { "check": { "onSave": false } },
+                    "hitagi.inlayHints.closingBraceHints.minLines": 3 }),
+            json!({ "hitagi.check.onSave": false,
+                    "hitagi.inlayHints.closingBraceHints": { "minLines": 3 } }),
+            json!({ "stapler": { "checkOnSave": false,
+                                 "inlayHints.closingBraceHints.minLines": 3 } }),
+        ];
+        for settings in spellings {
+            let (config, found) = Config::from_settings(&settings);
+            assert!(found.is_empty(), "{settings}: {found:?}");
+            assert!(!config.check.on_save, "{settings}");
+            assert_eq!(config.inlay_hints.closing_brace_min_lines, 3, "{settings}");
+        }
+
+        // Dotted keys apply over the nested ones, and the nested spelling
+        // over the flat one.
+        let (config, _) = Config::from_settings(&json!({
+            "check": { "onSave": true, "command": ["cargo", "clippy"] },
+            "check.onSave": false,
+            "checkCommand": ["cargo", "check"],
+        }));
+        assert!(!config.check.on_save);
+        assert_eq!(config.check.command.unwrap(), ["cargo", "clippy"]);
+        // Keys inside values are not split.
+        let (config, _) = Config::from_settings(&json!({
+            "check.extraEnv": { "RUST.LOG": "debug" },
+        }));
+        assert_eq!(config.check.extra_env["RUST.LOG"], "debug");
     }
 }
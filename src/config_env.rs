@@ -0,0 +1,210 @@
+//! Settings given through `HITAGI_*` environment variables, for setups such
+//! as containers where the editor's settings are hard to change. They are
+//! read once at startup and override both `hitagi.toml` and the editor.
+
+use serde_json::{Map, Value, json};
+
+/// The variables read, each with the setting it overrides.
+pub const VARIABLES: [(&str, &str); 5] = [
+    ("HITAGI_CHECK_COMMAND", "check.command"),
+    ("HITAGI_CHECK_ON_SAVE", "check.onSave"),
+    ("HITAGI_LOG_LEVEL", "logLevel"),
+    ("HITAGI_WORKSPACE_MODE", "workspace.mode"),
+    ("HITAGI_EXCLUDE_DIRS", "workspace.excludeDirs"),
+];
+
+/// The overrides found in the environment.
+#[derive(Debug, Clone, Default)]
+pub struct EnvSettings {
+    /// The variables that were set, with their values.
+    pub vars: Vec<(&'static str, String)>,
+    /// The settings they give, in the shape the editor sends.
+    pub settings: Value,
+    /// The variables that could not be read into a setting, and why.
+    pub issues: Vec<String>,
+}
+
+impl EnvSettings {
+    pub fn read() -> Self {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    /// The overrides from the variables `var` returns. Empty variables count
+    /// as unset.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let mut env = EnvSettings {
+            settings: Value::Object(Map::new()),
+            ..EnvSettings::default()
+        };
+        for (name, key) in VARIABLES {
+            let Some(text) = var(name).filter(|text| !text.trim().is_empty()) else {
+                continue;
+            };
+            let value = match name {
+                "HITAGI_CHECK_COMMAND" => match split_command(&text) {
+                    Ok(words) => json!(words),
+                    Err(err) => {
+                        env.issues.push(format!("`{name}` {err}"));
+                        continue;
+                    }
+                },
+                "HITAGI_CHECK_ON_SAVE" => parse_bool(&text).map_or(json!(text), Value::Bool),
+                "HITAGI_EXCLUDE_DIRS" => json!(
+                    text.split(':')
+                        .filter(|dir| !dir.is_empty())
+                        .collect::<Vec<_>>()
+                ),
+                _ => json!(text.trim()),
+            };
+            let slot = key
+                .split('.')
+                .fold(&mut env.settings, |table, part| &mut table[part]);
+            *slot = value;
+            env.vars.push((name, text));
+        }
+        env
+    }
+}
+
+/// `true`, `1`, `yes` or `on`, and their opposites, ignoring case. Other
+/// values are left to be reported as not a boolean.
+fn parse_bool(text: &str) -> Option<bool> {
+    match text.trim().to_ascii_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => Some(true),
+        "false" | "0" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+/// Splits `text` into words as a POSIX shell would, without expanding
+/// anything: whitespace separates words, single quotes keep their contents
+/// as they are, double quotes keep them except for `\"`, `\\`, `\$` and
+/// `` \` ``, and a backslash outside quotes escapes the next character.
+pub fn split_command(text: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    // Some once a word has started, so that `''` gives an empty word.
+    let mut word: Option<String> = None;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("has an unterminated `'`".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err("has an unterminated `\"`".to_string()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("has an unterminated `\"`".to_string()),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => return Err("ends with a `\\`".to_string()),
+            },
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_are_split_like_a_shell_would() {
+        let cases: [(&str, &[&str]); 7] = [
+            ("cargo check", &["cargo", "check"]),
+            (
+                "cargo clippy --features 'a b'",
+                &["cargo", "clippy", "--features", "a b"],
+            ),
+            (
+                r#"  cargo  "clippy" --features="a b"  "#,
+                &["cargo", "clippy", "--features=a b"],
+            ),
+            (
+                r#"sh -c "echo \"\$HOME\" \n""#,
+                &["sh", "-c", r#"echo "$HOME" \n"#],
+            ),
+            (r"path\ with\ spaces/cargo", &["path with spaces/cargo"]),
+            ("cargo '' check", &["cargo", "", "check"]),
+            ("", &[]),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(split_command(text).unwrap(), expected, "{text}");
+        }
+        assert_eq!(
+            split_command("cargo 'check").unwrap_err(),
+            "has an unterminated `'`"
+        );
+        assert_eq!(
+            split_command("cargo \"check").unwrap_err(),
+            "has an unterminated `\"`"
+        );
+        assert_eq!(split_command("cargo \\").unwrap_err(), "ends with a `\\`");
+    }
+
+    #[test]
+    fn variables_become_settings() {
+        let env = EnvSettings::from_vars(|name| {
+            let value = match name {
+                "HITAGI_CHECK_COMMAND" => "cargo clippy --features 'a b'",
+                "HITAGI_CHECK_ON_SAVE" => "0",
+                "HITAGI_LOG_LEVEL" => " debug ",
+                "HITAGI_WORKSPACE_MODE" => "",
+                "HITAGI_EXCLUDE_DIRS" => "vendor:crates/generated:",
+                _ => return None,
+            };
+            Some(value.to_string())
+        });
+        assert_eq!(
+            env.settings,
+            json!({
+                "check": { "command": ["cargo", "clippy", "--features", "a b"], "onSave": false },
+                "logLevel": "debug",
+                "workspace": { "excludeDirs": ["vendor", "crates/generated"] },
+            })
+        );
+        let names: Vec<&str> = env.vars.iter().map(|(name, _)| *name).collect();
+        assert_eq!(
+            names,
+            [
+                "HITAGI_CHECK_COMMAND",
+                "HITAGI_CHECK_ON_SAVE",
+                "HITAGI_LOG_LEVEL",
+                "HITAGI_EXCLUDE_DIRS"
+            ]
+        );
+        assert!(env.issues.is_empty());
+
+        let env = EnvSettings::from_vars(|name| {
+            (name == "HITAGI_CHECK_COMMAND").then(|| "cargo 'check".to_string())
+        });
+        assert_eq!(
+            env.issues,
+            ["`HITAGI_CHECK_COMMAND` has an unterminated `'`"]
+        );
+        assert!(env.vars.is_empty());
+    }
+}
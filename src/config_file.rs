@@ -0,0 +1,444 @@
+//! Settings committed to a project in `hitagi.toml`, or in
+//! `.hitagi/config.toml`, at the workspace root. The keys are those of the
+//! editor settings, e.g. `checkOnSave = false` or `features = ["cli"]` under
+//! `[check]`, and the file is read into the same JSON the editor sends.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::{Map, Value};
+
+/// The files looked for at the root, in order; the first one found is used.
+pub const FILE_NAMES: [&str; 2] = ["hitagi.toml", ".hitagi/config.toml"];
+
+/// Whether `path` is one of the configuration files of `root`.
+pub fn is_config_file(root: &Path, path: &Path) -> bool {
+    FILE_NAMES.iter().any(|name| root.join(name) == path)
+}
+
+/// Reads the configuration file of `root`, if there is one, along with its
+/// path. Settings under `[tool.hitagi]` are used when the file has that
+/// table, and the whole file otherwise.
+pub fn load(root: &Path) -> Option<(PathBuf, Result<Value, String>)> {
+    let path = FILE_NAMES
+        .iter()
+        .map(|name| root.join(name))
+        .find(|path| path.is_file())?;
+    let settings = fs::read_to_string(&path)
+        .map_err(|err| err.to_string())
+        .and_then(|text| parse(&text))
+        .map(
+            |mut settings| match settings.pointer_mut("/tool/hitagi").map(Value::take) {
+                Some(table) => table,
+                None => settings,
+            },
+        );
+    Some((path, settings))
+}
+
+/// Parses the subset of TOML configuration files need: tables, dotted keys,
+/// strings, integers, floats, booleans, arrays and inline tables. Errors say
+/// on which line they are and, when a value is wrong, for which key.
+pub fn parse(text: &str) -> Result<Value, String> {
+    let mut parser = Parser {
+        text,
+        pos: 0,
+        line: 1,
+    };
+    let mut root = Map::new();
+    let mut table: Vec<String> = Vec::new();
+    loop {
+        parser.skip_blank_lines();
+        match parser.peek() {
+            None => return Ok(Value::Object(root)),
+            Some('[') => {
+                parser.bump();
+                if parser.peek() == Some('[') {
+                    return Err(parser.error("arrays of tables are not supported"));
+                }
+                table = parser.key()?;
+                parser.skip_spaces();
+                parser.expect(']')?;
+                if !matches!(lookup(&mut root, &table), Some(Value::Object(_))) {
+                    insert(&mut root, &table, Value::Object(Map::new()))
+                        .map_err(|err| parser.error(&err))?;
+                }
+            }
+            Some(_) => {
+                let key = parser.key()?;
+                parser.skip_spaces();
+                parser.expect('=')?;
+                parser.skip_spaces();
+                let value = parser
+                    .value()
+                    .map_err(|err| format!("{err} in the value of `{}`", key.join(".")))?;
+                let path: Vec<String> = table.iter().chain(&key).cloned().collect();
+                insert(&mut root, &path, value).map_err(|err| parser.error(&err))?;
+            }
+        }
+        parser.end_of_line()?;
+    }
+}
+
+fn lookup<'a>(root: &'a mut Map<String, Value>, path: &[String]) -> Option<&'a mut Value> {
+    let (first, rest) = path.split_first()?;
+    rest.iter()
+        .try_fold(root.get_mut(first)?, |value, key| value.get_mut(key))
+}
+
+/// Sets `path` to `value`, creating the tables on the way.
+fn insert(root: &mut Map<String, Value>, path: &[String], value: Value) -> Result<(), String> {
+    let Some((last, parents)) = path.split_last() else {
+        return Err("empty key".to_string());
+    };
+    let mut table = root;
+    for (idx, key) in parents.iter().enumerate() {
+        let entry = table
+            .entry(key.clone())
+            .or_insert_with(|| Value::Object(Map::new()));
+        let Value::Object(next) = entry else {
+            return Err(format!("`{}` is not a table", parents[..=idx].join(".")));
+        };
+        table = next;
+    }
+    if table.contains_key(last) {
+        return Err(format!("`{}` is defined twice", path.join(".")));
+    }
+    table.insert(last.clone(), value);
+    Ok(())
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    line: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let ch = self.peek()?;
+        self.pos += ch.len_utf8();
+        if ch == '\n' {
+            self.line += 1;
+        }
+        Some(ch)
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("line {}: {message}", self.line)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.peek() {
+            Some(ch) if ch == expected => {
+                self.bump();
+                Ok(())
+            }
+            Some(ch) => Err(self.error(&format!("expected `{expected}`, found `{ch}`"))),
+            None => Err(self.error(&format!("expected `{expected}`"))),
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.bump();
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        if self.peek() == Some('#') {
+            while !matches!(self.peek(), None | Some('\n')) {
+                self.bump();
+            }
+        }
+    }
+
+    /// Skips whitespace, newlines and comments, e.g. between array items.
+    fn skip_blank_lines(&mut self) {
+        loop {
+            self.skip_spaces();
+            self.skip_comment();
+            match self.peek() {
+                Some('\n' | '\r') => {
+                    self.bump();
+                }
+                _ => return,
+            }
+        }
+    }
+
+    /// Requires the rest of the line to be blank or a comment.
+    fn end_of_line(&mut self) -> Result<(), String> {
+        self.skip_spaces();
+        self.skip_comment();
+        match self.peek() {
+            None => Ok(()),
+            Some('\r' | '\n') => {
+                self.bump();
+                Ok(())
+            }
+            Some(ch) => Err(self.error(&format!("unexpected `{ch}` after the value"))),
+        }
+    }
+
+    /// A dotted key such as `check.features`, with bare or quoted parts.
+    fn key(&mut self) -> Result<Vec<String>, String> {
+        let mut parts = Vec::new();
+        loop {
+            self.skip_spaces();
+            let part = match self.peek() {
+                Some('"') => self.basic_string()?,
+                Some('\'') => self.literal_string()?,
+                _ => {
+                    let start = self.pos;
+                    while self
+                        .peek()
+                        .is_some_and(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '_' | '-'))
+                    {
+                        self.bump();
+                    }
+                    if start == self.pos {
+                        return Err(self.error("expected a key"));
+                    }
+                    self.text[start..self.pos].to_string()
+                }
+            };
+            parts.push(part);
+            self.skip_spaces();
+            if self.peek() != Some('.') {
+                return Ok(parts);
+            }
+            self.bump();
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some('"') => self.basic_string().map(Value::String),
+            Some('\'') => self.literal_string().map(Value::String),
+            Some('[') => self.array(),
+            Some('{') => self.inline_table(),
+            Some(_) => self.scalar(),
+            None => Err(self.error("expected a value")),
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.bump();
+        let mut items = Vec::new();
+        loop {
+            self.skip_blank_lines();
+            if self.peek() == Some(']') {
+                self.bump();
+                return Ok(Value::Array(items));
+            }
+            items.push(self.value()?);
+            self.skip_blank_lines();
+            match self.peek() {
+                Some(',') => {
+                    self.bump();
+                }
+                Some(']') => {}
+                _ => return Err(self.error("expected `,` or `]` in an array")),
+            }
+        }
+    }
+
+    fn inline_table(&mut self) -> Result<Value, String> {
+        self.bump();
+        let mut table = Map::new();
+        self.skip_spaces();
+        if self.peek() == Some('}') {
+            self.bump();
+            return Ok(Value::Object(table));
+        }
+        loop {
+            let key = self.key()?;
+            self.skip_spaces();
+            self.expect('=')?;
+            self.skip_spaces();
+            let value = self.value()?;
+            insert(&mut table, &key, value).map_err(|err| self.error(&err))?;
+            self.skip_spaces();
+            match self.bump() {
+                Some(',') => {}
+                Some('}') => return Ok(Value::Object(table)),
+                _ => return Err(self.error("expected `,` or `}` in an inline table")),
+            }
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String, String> {
+        self.bump();
+        let mut out = String::new();
+        loop {
+            let Some(ch) = self.peek().filter(|ch| *ch != '\n') else {
+                return Err(self.error("unterminated string"));
+            };
+            self.bump();
+            match ch {
+                '"' => return Ok(out),
+                '\\' => {
+                    let escaped = match self.bump() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some(kind @ ('u' | 'U')) => {
+                            let len = if kind == 'u' { 4 } else { 8 };
+                            let digits = self.text.get(self.pos..self.pos + len).unwrap_or("");
+                            let ch = u32::from_str_radix(digits, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| self.error("invalid unicode escape"))?;
+                            self.pos += len;
+                            ch
+                        }
+                        _ => return Err(self.error("invalid escape in a string")),
+                    };
+                    out.push(escaped);
+                }
+                ch => out.push(ch),
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> Result<String, String> {
+        self.bump();
+        let start = self.pos;
+        loop {
+            match self.peek() {
+                Some('\'') => {
+                    let text = self.text[start..self.pos].to_string();
+                    self.bump();
+                    return Ok(text);
+                }
+                Some('\n') | None => return Err(self.error("unterminated string")),
+                Some(_) => {
+                    self.bump();
+                }
+            }
+        }
+    }
+
+    /// A boolean or a number, read up to the next delimiter.
+    fn scalar(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        while !matches!(
+            self.peek(),
+            None | Some(' ' | '\t' | '\r' | '\n' | ',' | ']' | '}' | '#')
+        ) {
+            self.bump();
+        }
+        let word = &self.text[start..self.pos];
+        match word {
+            "true" => return Ok(Value::Bool(true)),
+            "false" => return Ok(Value::Bool(false)),
+            _ => {}
+        }
+        let digits = word.replace('_', "");
+        if let Ok(int) = digits.parse::<i64>() {
+            return Ok(Value::from(int));
+        }
+        digits
+            .parse::<f64>()
+            .ok()
+            .filter(|float| float.is_finite())
+            .map(Value::from)
+            .ok_or_else(|| self.error(&format!("invalid value `{word}`")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    #[test]
+    fn settings_files_parse_into_the_settings_json() {
+        let text = r#"
+# Checked by CI as well.
+checkOnSave = true
+checkCommand = [
+    "cargo", "clippy",  # lints too
+    "--message-format=json",
+]
+limits.maxAnalyzedFileSize = 1_048_576
+
+[check]
+features = ["cli", 'tls']
+extraEnv = { RUSTFLAGS = "--cfg ci", "CARGO_TERM_COLOR" = "never" }
+
+[diagnostics.severityOverrides]
+"clippy::needless_return" = "hint"
+"#;
+        assert_eq!(
+            parse(text).unwrap(),
+            json!({
+                "checkOnSave": true,
+                "checkCommand": ["cargo", "clippy", "--message-format=json"],
+                "limits": { "maxAnalyzedFileSize": 1048576 },
+                "check": {
+                    "features": ["cli", "tls"],
+                    "extraEnv": { "RUSTFLAGS": "--cfg ci", "CARGO_TERM_COLOR": "never" },
+                },
+                "diagnostics": { "severityOverrides": { "clippy::needless_return": "hint" } },
+            })
+        );
+        assert_eq!(
+            parse("name = \"tab\\there \\u00e9\"").unwrap(),
+            json!({ "name": "tab\there é" })
+        );
+    }
+
+    #[test]
+    fn errors_name_the_line_and_the_key() {
+        assert_eq!(
+            parse("checkOnSave = true\ncheckOnSave = false\n").unwrap_err(),
+            "line 2: `checkOnSave` is defined twice"
+        );
+        assert_eq!(
+            parse("[check]\nfeatures = [\"a\" \"b\"]\n").unwrap_err(),
+            "line 2: expected `,` or `]` in an array in the value of `features`"
+        );
+        assert_eq!(
+            parse("checkOnSave = yes\n").unwrap_err(),
+            "line 1: invalid value `yes` in the value of `checkOnSave`"
+        );
+        assert_eq!(
+            parse("logLevel = \"info\n").unwrap_err(),
+            "line 1: unterminated string in the value of `logLevel`"
+        );
+        assert!(parse("[[check]]\n").is_err());
+        assert!(parse("checkOnSave = true false\n").is_err());
+    }
+
+    #[test]
+    fn the_hitagi_table_of_a_tool_file_is_used() {
+        let root = temp_dir("config");
+        fs::create_dir_all(root.join(".hitagi")).unwrap();
+        assert!(load(&root).is_none());
+
+        let tool = root.join(".hitagi/config.toml");
+        fs::write(&tool, "[tool.hitagi]\ncheckOnSave = false\n").unwrap();
+        let (path, settings) = load(&root).unwrap();
+        assert_eq!(path, tool);
+        assert_eq!(settings.unwrap(), json!({ "checkOnSave": false }));
+        assert!(is_config_file(&root, &tool));
+        assert!(!is_config_file(&root, &root.join("src/hitagi.toml")));
+
+        // `hitagi.toml` comes first.
+        fs::write(root.join("hitagi.toml"), "logLevel = \"debug\"\n").unwrap();
+        let (path, settings) = load(&root).unwrap();
+        assert_eq!(path, root.join("hitagi.toml"));
+        assert_eq!(settings.unwrap(), json!({ "logLevel": "debug" }));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,47 @@
+/// Matches a `/`-separated `path` against `pattern`, where `*` matches
+/// within a segment, `?` matches one character and a `**` segment matches
+/// any number of segments.
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('/').filter(|seg| !seg.is_empty()).collect();
+    let path: Vec<&str> = path.split('/').filter(|seg| !seg.is_empty()).collect();
+    match_segments(&pattern, &path)
+}
+
+fn match_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| match_segments(rest, &path[skip..])),
+        Some((segment, rest)) => path.split_first().is_some_and(|(name, path)| {
+            match_segment(segment.as_bytes(), name.as_bytes()) && match_segments(rest, path)
+        }),
+    }
+}
+
+fn match_segment(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| match_segment(rest, &name[skip..])),
+        Some((b'?', rest)) => !name.is_empty() && match_segment(rest, &name[1..]),
+        Some((ch, rest)) => name.first() == Some(ch) && match_segment(rest, &name[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::glob_match;
+
+    #[test]
+    fn globs_match_segments() {
+        assert!(glob_match("src/generated/*.rs", "src/generated/api.rs"));
+        assert!(!glob_match("src/generated/*.rs", "src/generated/v1/api.rs"));
+        assert!(glob_match("src/generated/**", "src/generated/v1/api.rs"));
+        assert!(glob_match(
+            "**/generated/**/*.rs",
+            "crates/a/generated/api.rs"
+        ));
+        assert!(glob_match("target/**", "target/debug/build/out.rs"));
+        assert!(glob_match("src/v?.rs", "src/v1.rs"));
+        assert!(!glob_match("src/v?.rs", "src/v10.rs"));
+        assert!(!glob_match("src/*.rs", "tests/main.rs"));
+    }
+}
@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use serde_json::Value;
+
+/// `cargo metadata` results keyed by the LSP root, including failures so
+/// that a root outside any workspace is not retried on every save.
+static WORKSPACES: OnceLock<Mutex<HashMap<PathBuf, Option<Arc<CargoWorkspace>>>>> = OnceLock::new();
+
+/// The directory a check of each saved file runs in, see `cargo_root`.
+static CARGO_ROOTS: OnceLock<Mutex<HashMap<PathBuf, Option<PathBuf>>>> = OnceLock::new();
+
+/// The cargo workspace containing an LSP root.
+#[derive(Debug)]
+pub struct CargoWorkspace {
+    /// The directory cargo runs rustc from, which relative span paths are
+    /// based on.
+    pub root: PathBuf,
+    pub members: Vec<Package>,
+}
+
+/// A workspace member.
+#[derive(Debug)]
+pub struct Package {
+    /// The name passed to `cargo check -p`.
+    pub name: String,
+    /// The directory of the member's `Cargo.toml`.
+    pub dir: PathBuf,
+}
+
+/// Returns the cargo workspace for `root`, running `cargo metadata` on first
+/// use.
+pub fn cargo_workspace(root: &Path) -> Option<Arc<CargoWorkspace>> {
+    let cache = WORKSPACES.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(workspace) = cache.lock().unwrap().get(root) {
+        return workspace.clone();
+    }
+    let workspace = load(root).map(Arc::new);
+    cache
+        .lock()
+        .unwrap()
+        .insert(root.to_path_buf(), workspace.clone());
+    workspace
+}
+
+/// Returns the directory to run cargo in for `file`: the nearest ancestor
+/// whose `Cargo.toml` declares a `[workspace]`, or else the nearest one with
+/// a `Cargo.toml`. The search stops at the root of the enclosing git
+/// repository.
+pub fn cargo_root(file: &Path) -> Option<PathBuf> {
+    let cache = CARGO_ROOTS.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(root) = cache.lock().unwrap().get(file) {
+        return root.clone();
+    }
+    let root = find_cargo_root(file);
+    cache
+        .lock()
+        .unwrap()
+        .insert(file.to_path_buf(), root.clone());
+    root
+}
+
+/// Forgets the cached workspaces and cargo roots, e.g. once a `Cargo.toml`
+/// changed.
+pub fn invalidate() {
+    if let Some(cache) = WORKSPACES.get() {
+        cache.lock().unwrap().clear();
+    }
+    if let Some(cache) = CARGO_ROOTS.get() {
+        cache.lock().unwrap().clear();
+    }
+}
+
+/// Whether `path` is a `Cargo.toml` or `Cargo.lock`, whose changes affect
+/// every check.
+pub fn is_cargo_manifest(path: &Path) -> bool {
+    matches!(
+        path.file_name().and_then(|name| name.to_str()),
+        Some("Cargo.toml" | "Cargo.lock")
+    )
+}
+
+fn find_cargo_root(file: &Path) -> Option<PathBuf> {
+    let mut package = None;
+    for dir in file.ancestors().skip(1) {
+        if let Ok(manifest) = fs::read_to_string(dir.join("Cargo.toml")) {
+            if manifest
+                .lines()
+                .any(|line| line.trim_start().starts_with("[workspace"))
+            {
+                return Some(dir.to_path_buf());
+            }
+            package.get_or_insert_with(|| dir.to_path_buf());
+        }
+        if dir.join(".git").exists() {
+            break;
+        }
+    }
+    package
+}
+
+fn load(root: &Path) -> Option<CargoWorkspace> {
+    let output = Command::new("cargo")
+        .args(["metadata", "--no-deps", "--format-version", "1"])
+        .current_dir(root)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse(&serde_json::from_slice(&output.stdout).ok()?)
+}
+
+fn parse(metadata: &Value) -> Option<CargoWorkspace> {
+    let root = PathBuf::from(metadata.get("workspace_root")?.as_str()?);
+    let member_ids: Vec<&str> = metadata
+        .get("workspace_members")
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|v| v.as_str())
+        .collect();
+    let members = metadata
+        .get("packages")
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+        .filter(|package| {
+            package
+                .get("id")
+                .and_then(|v| v.as_str())
+                .is_some_and(|id| member_ids.contains(&id))
+        })
+        .filter_map(|package| {
+            let manifest = Path::new(package.get("manifest_path")?.as_str()?);
+            Some(Package {
+                name: package.get("name")?.as_str()?.to_string(),
+                dir: manifest.parent()?.to_path_buf(),
+            })
+        })
+        .collect();
+    Some(CargoWorkspace { root, members })
+}
+
+impl CargoWorkspace {
+    /// Resolves a relative span path against the workspace root, or against
+    /// the member directory it exists in when it is not found there.
+    pub fn resolve(&self, file_name: &Path) -> PathBuf {
+        let full = self.root.join(file_name);
+        if full.exists() {
+            return full;
+        }
+        self.members
+            .iter()
+            .map(|member| member.dir.join(file_name))
+            .find(|path| path.exists())
+            .unwrap_or(full)
+    }
+
+    /// Returns the member whose directory contains `path`, the innermost
+    /// one for nested packages.
+    pub fn package_for(&self, path: &Path) -> Option<&Package> {
+        self.members
+            .iter()
+            .filter(|member| path.starts_with(&member.dir))
+            .max_by_key(|member| member.dir.components().count())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    #[test]
+    fn metadata_lists_workspace_members() {
+        let dir = temp_dir("metadata");
+        fs::create_dir_all(dir.join("crates/core/src")).unwrap();
+        fs::create_dir_all(dir.join("crates/cli/src")).unwrap();
+        fs::write(dir.join("crates/core/src/lib.rs"), "").unwrap();
+        fs::write(dir.join("crates/cli/src/main.rs"), "").unwrap();
+
+        let root = dir.to_string_lossy();
+        let metadata = serde_json::json!({
+            "packages": [
+                {
+                    "name": "core",
+                    "id": "path+file:///ws/crates/core#0.1.0",
+                    "manifest_path": format!("{}/crates/core/Cargo.toml", root),
+                },
+                {
+                    "name": "cli",
+                    "id": "path+file:///ws/crates/cli#0.1.0",
+                    "manifest_path": format!("{}/crates/cli/Cargo.toml", root),
+                },
+                {
+                    "name": "serde",
+                    "id": "registry+https://github.com/rust-lang/crates.io-index#serde@1.0.0",
+                    "manifest_path": "/registry/serde/Cargo.toml",
+                },
+            ],
+            "workspace_members": [
+                "path+file:///ws/crates/core#0.1.0",
+                "path+file:///ws/crates/cli#0.1.0",
+            ],
+            "workspace_root": root,
+        });
+        let workspace = parse(&metadata).unwrap();
+        assert_eq!(workspace.root, dir);
+        let names: Vec<&str> = workspace
+            .members
+            .iter()
+            .map(|member| member.name.as_str())
+            .collect();
+        assert_eq!(names, ["core", "cli"]);
+        assert_eq!(workspace.members[1].dir, dir.join("crates/cli"));
+
+        assert_eq!(
+            workspace.resolve(Path::new("crates/core/src/lib.rs")),
+            dir.join("crates/core/src/lib.rs")
+        );
+        assert_eq!(
+            workspace.resolve(Path::new("src/main.rs")),
+            dir.join("crates/cli/src/main.rs")
+        );
+        assert_eq!(
+            workspace.resolve(Path::new("src/gone.rs")),
+            dir.join("src/gone.rs")
+        );
+        let package = workspace.package_for(&dir.join("crates/core/src/lib.rs"));
+        assert_eq!(package.map(|package| package.name.as_str()), Some("core"));
+        assert!(workspace.package_for(&dir.join("README.md")).is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cargo_roots_prefer_workspaces_inside_the_repository() {
+        let dir = temp_dir("cargo-root");
+        let write = |path: &str, text: &str| {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        };
+        write("Cargo.toml", "[workspace]\nmembers = [\"repo/*\"]\n");
+        write("repo/.git/HEAD", "");
+        write("repo/tool/Cargo.toml", "[package]\nname = \"tool\"\n");
+        write("repo/tool/src/main.rs", "");
+        write(
+            "repo/ws/Cargo.toml",
+            "[workspace]\nmembers = [\"crates/*\"]\n",
+        );
+        write("repo/ws/crates/a/Cargo.toml", "[package]\nname = \"a\"\n");
+        write("repo/ws/crates/a/src/lib.rs", "");
+        write("repo/notes/scratch.rs", "");
+
+        assert_eq!(
+            cargo_root(&dir.join("repo/ws/crates/a/src/lib.rs")),
+            Some(dir.join("repo/ws"))
+        );
+        // The workspace above the repository is not considered.
+        assert_eq!(
+            cargo_root(&dir.join("repo/tool/src/main.rs")),
+            Some(dir.join("repo/tool"))
+        );
+        assert_eq!(cargo_root(&dir.join("repo/notes/scratch.rs")), None);
+
+        write("repo/notes/Cargo.toml", "[package]\nname = \"notes\"\n");
+        assert_eq!(cargo_root(&dir.join("repo/notes/scratch.rs")), None);
+        assert!(is_cargo_manifest(&dir.join("repo/notes/Cargo.toml")));
+        invalidate();
+        assert_eq!(
+            cargo_root(&dir.join("repo/notes/scratch.rs")),
+            Some(dir.join("repo/notes"))
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
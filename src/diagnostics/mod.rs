@@ -1,99 +1,820 @@
+mod glob;
+pub mod metadata;
+mod pattern;
+mod ranges;
+pub mod runner;
+
 use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use lsp_types::{Diagnostic, DiagnosticSeverity, Range, Uri};
-use serde_json::Value;
+use lsp_types::{
+    ClientCapabilities, CodeDescription, Diagnostic, DiagnosticRelatedInformation,
+    DiagnosticSeverity, DiagnosticTag, Location, NumberOrString, Range, TextEdit, Uri,
+};
+use serde_json::{Value, json};
 
+use crate::config::{CheckConfig, CheckScope, DiagnosticsConfig, OutputFormat};
 use crate::doc::position::lsp_position_from_span;
 use crate::doc::uri::path_to_uri;
+use glob::glob_match;
+use metadata::{CargoWorkspace, cargo_workspace};
+use pattern::{DEFAULT_PATTERN, output_regex, parse_captures};
+
+/// Diagnostic features the client declared support for.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ClientDiagnosticSupport {
+    /// The client shows `relatedInformation` alongside a diagnostic.
+    pub related_information: bool,
+    /// The client fades out code tagged `Unnecessary`.
+    pub unnecessary_tag: bool,
+    /// The client strikes through code tagged `Deprecated`.
+    pub deprecated_tag: bool,
+}
+
+impl ClientDiagnosticSupport {
+    pub fn from_capabilities(caps: &ClientCapabilities) -> Self {
+        let publish = caps
+            .text_document
+            .as_ref()
+            .and_then(|td| td.publish_diagnostics.as_ref());
+
+        let tags = publish
+            .and_then(|publish| publish.tag_support.as_ref())
+            .map(|support| support.value_set.as_slice())
+            .unwrap_or_default();
+
+        Self {
+            related_information: publish
+                .and_then(|publish| publish.related_information)
+                .unwrap_or(false),
+            unnecessary_tag: tags.contains(&DiagnosticTag::UNNECESSARY),
+            deprecated_tag: tags.contains(&DiagnosticTag::DEPRECATED),
+        }
+    }
+}
 
-pub fn run_check(root: &Path, command: &[String]) -> Result<HashMap<Uri, Vec<Diagnostic>>, String> {
-    let (program, args) = split_command(command)?;
+/// The cargo process of a running check, shared so that a newer save can
+/// stop it.
+#[derive(Default)]
+pub struct CheckProcess {
+    slot: Mutex<CheckSlot>,
+}
+
+#[derive(Default)]
+struct CheckSlot {
+    child: Option<Child>,
+    killed: bool,
+    timed_out: bool,
+    status: Option<ExitStatus>,
+}
 
-    let mut cmd = Command::new(program);
+impl CheckProcess {
+    /// Kills the process, or marks it so that it is killed as soon as it
+    /// starts. Returns `false` when the process could not be killed.
+    pub fn kill(&self) -> bool {
+        let mut slot = self.slot.lock().unwrap();
+        slot.killed = true;
+        match slot.child.as_mut() {
+            Some(child) => child.kill().is_ok(),
+            None => true,
+        }
+    }
+
+    fn is_killed(&self) -> bool {
+        self.slot.lock().unwrap().killed
+    }
+
+    /// Whether the process was killed for exceeding `check.timeoutSeconds`.
+    pub fn timed_out(&self) -> bool {
+        self.slot.lock().unwrap().timed_out
+    }
+
+    fn time_out(&self) {
+        self.slot.lock().unwrap().timed_out = true;
+        self.kill();
+    }
+
+    fn attach(&self, mut child: Child) {
+        let mut slot = self.slot.lock().unwrap();
+        if slot.killed {
+            let _ = child.kill();
+        }
+        slot.child = Some(child);
+    }
+
+    fn wait(&self) -> Option<ExitStatus> {
+        let child = self.slot.lock().unwrap().child.take();
+        let status = child.and_then(|mut child| child.wait().ok());
+        self.slot.lock().unwrap().status = status;
+        status
+    }
+
+    /// How the process exited, once it has.
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        self.slot.lock().unwrap().status
+    }
+}
+
+/// Runs `command` under `root` and parses cargo's JSON messages, or lines
+/// matching its pattern, as they are printed. `on_update` is called with all
+/// diagnostics of a file each time a new one is found for it; the complete
+/// map is returned once cargo exits. A check stopped through `process`
+/// reports no further updates and returns an error, as does a command that
+/// cannot be started or that fails without printing any compiler message,
+/// e.g. on a lockfile conflict.
+#[allow(clippy::mutable_key_type)]
+pub fn run_check(
+    root: &Path,
+    command: &CheckCommand,
+    support: ClientDiagnosticSupport,
+    config: &DiagnosticsConfig,
+    process: &CheckProcess,
+    mut on_update: impl FnMut(&Uri, &[Diagnostic]),
+) -> Result<HashMap<Uri, Vec<Diagnostic>>, String> {
+    let (program, args) = split_command(&command.args)?;
+    let regex = command.pattern.as_deref().map(output_regex).transpose()?;
+
+    let mut cmd = Command::new(&program);
     cmd.args(args);
-    if !has_message_format(command) {
+    if regex.is_none() && !has_message_format(&command.args) {
         cmd.arg("--message-format=json");
     }
-    cmd.current_dir(root);
+    cmd.envs(command.env.iter().map(|(name, value)| (name, value)));
+    cmd.current_dir(root)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
 
-    let output = cmd.output().map_err(|err| err.to_string())?;
-    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut child = cmd
+        .spawn()
+        .map_err(|err| format!("could not run `{}`: {}", program, err))?;
+    let stdout = child.stdout.take().ok_or("cargo stdout is not piped")?;
+    // Lines are read on their own thread so that a hung command, or a
+    // grandchild keeping the pipe open after a kill, cannot block the check.
+    let (lines_tx, lines_rx) = mpsc::channel();
+    // Cargo blocks once the stderr pipe fills up, so drain it concurrently,
+    // keeping the first lines to explain a failure. Its lines are parsed too:
+    // rustc prints its JSON diagnostics there, and patterns may match either
+    // stream.
+    let (stderr_tx, stderr_rx) = mpsc::channel();
+    if let Some(stderr) = child.stderr.take() {
+        let lines_tx = lines_tx.clone();
+        thread::spawn(move || {
+            let mut head = Vec::new();
+            for line in BufReader::new(stderr).split(b'\n') {
+                let Ok(line) = line else { break };
+                let line = String::from_utf8_lossy(&line).trim_end().to_string();
+                if head.len() < STDERR_LINES {
+                    head.push(line.clone());
+                }
+                let _ = lines_tx.send(line);
+            }
+            let _ = stderr_tx.send(head);
+        });
+    }
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            let Ok(line) = line else { break };
+            if lines_tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    process.attach(child);
 
+    let workspace = cargo_workspace(root);
+    let paths = SpanPaths::new(root, workspace.as_deref());
+    let deadline = command.timeout.map(|timeout| Instant::now() + timeout);
     let mut diagnostics: HashMap<Uri, Vec<Diagnostic>> = HashMap::new();
-
-    for line in stdout.lines() {
-        let value: Value = match serde_json::from_str(line) {
-            Ok(v) => v,
-            Err(_) => continue,
+    let mut compiler_messages = false;
+    loop {
+        if process.is_killed() {
+            break;
+        }
+        // Waking up regularly notices a kill even when a grandchild keeps the
+        // pipe open without printing anything.
+        let wait = deadline.map_or(KILL_POLL_INTERVAL, |deadline| {
+            deadline
+                .saturating_duration_since(Instant::now())
+                .min(KILL_POLL_INTERVAL)
+        });
+        let line = match lines_rx.recv_timeout(wait) {
+            Ok(line) => line,
+            Err(RecvTimeoutError::Timeout) => {
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    process.time_out();
+                    break;
+                }
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
         };
+        if process.is_killed() {
+            break;
+        }
+        let parsed = match &regex {
+            Some(regex) => {
+                let Some(captures) = regex.captures(&line) else {
+                    continue;
+                };
+                compiler_messages = true;
+                parse_captures(&paths, &captures, support, config)
+            }
+            None => {
+                let Ok(value) = serde_json::from_str::<Value>(&line) else {
+                    continue;
+                };
+                compiler_messages |= compiler_message(&value).is_some();
+                parse_message(&paths, &value, support, config)
+            }
+        };
+        if let Some((uri, diagnostic)) = parsed {
+            let file = diagnostics.entry(uri.clone()).or_default();
+            if merge_diagnostic(file, diagnostic) {
+                on_update(&uri, file);
+            }
+        }
+    }
+
+    let status = process.wait();
+    if let Some(scratch) = &command.scratch {
+        let _ = fs::remove_dir_all(scratch);
+    }
+    if process.timed_out() {
+        return Err("check timed out".to_string());
+    }
+    if process.is_killed() {
+        return Err("check cancelled".to_string());
+    }
+    match status {
+        Some(status) if !status.success() && !compiler_messages => {
+            let mut err = format!("`{}` failed ({})", command.args.join(" "), status);
+            // A grandchild may keep stderr open, so do not wait for it long.
+            let stderr = stderr_rx
+                .recv_timeout(Duration::from_secs(1))
+                .unwrap_or_default();
+            for line in stderr {
+                err.push('\n');
+                err.push_str(&line);
+            }
+            Err(err)
+        }
+        _ => Ok(diagnostics),
+    }
+}
+
+/// Adds `diagnostic` unless the file already has it, as happens when a file
+/// is compiled into several targets. Of two reports differing only in
+/// source, the one with a code is kept. Returns whether `file` changed.
+fn merge_diagnostic(file: &mut Vec<Diagnostic>, diagnostic: Diagnostic) -> bool {
+    let duplicate = file.iter().position(|existing| {
+        existing.range == diagnostic.range
+            && existing.severity == diagnostic.severity
+            && existing.message == diagnostic.message
+            && (existing.code == diagnostic.code
+                || (existing.source != diagnostic.source
+                    && (existing.code.is_none() || diagnostic.code.is_none())))
+    });
+    match duplicate {
+        None => {
+            file.push(diagnostic);
+            true
+        }
+        Some(idx) if file[idx].code.is_none() && diagnostic.code.is_some() => {
+            file[idx] = diagnostic;
+            true
+        }
+        Some(_) => false,
+    }
+}
+
+/// Returns the diagnostic of a cargo `compiler-message`, or a diagnostic
+/// printed by rustc itself, which is not wrapped in one.
+fn compiler_message(value: &Value) -> Option<&Value> {
+    match value.get("reason").and_then(|v| v.as_str()) {
+        Some("compiler-message") => value.get("message"),
+        Some(_) => None,
+        None => (value.get("$message_type").and_then(|v| v.as_str()) == Some("diagnostic"))
+            .then_some(value),
+    }
+}
+
+/// Converts one message of cargo's or rustc's JSON output into a diagnostic
+/// for the file of its primary span. Other messages yield `None`.
+fn parse_message(
+    paths: &SpanPaths,
+    value: &Value,
+    support: ClientDiagnosticSupport,
+    config: &DiagnosticsConfig,
+) -> Option<(Uri, Diagnostic)> {
+    let message = compiler_message(value)?;
+
+    let level = message
+        .get("level")
+        .and_then(|v| v.as_str())
+        .unwrap_or("error");
+    let msg_text = message
+        .get("message")
+        .and_then(|v| v.as_str())
+        .unwrap_or("rustc error");
+
+    let spans = match message.get("spans").and_then(|v| v.as_array()) {
+        Some(s) if !s.is_empty() => s,
+        _ => return None,
+    };
+
+    let span = spans
+        .iter()
+        .find(|span| span.get("is_primary").and_then(|v| v.as_bool()) == Some(true))
+        .unwrap_or(&spans[0]);
+
+    let file_name = span.get("file_name").and_then(|v| v.as_str())?;
+
+    let code = message
+        .get("code")
+        .and_then(|v| v.get("code"))
+        .and_then(|v| v.as_str());
+    let range = span_range(span);
+    if code.is_some_and(|code| config.is_disabled(code)) || is_excluded(paths, file_name, config) {
+        return None;
+    }
+    let severity = match code.and_then(|code| config.severity_override(code)) {
+        Some(None) => return None,
+        Some(severity) => severity,
+        None => map_severity(level),
+    };
+    let related_information = if support.related_information {
+        Some(related_information(paths, message, span, msg_text)).filter(|info| !info.is_empty())
+    } else {
+        None
+    };
+
+    let mut diagnostic = Diagnostic {
+        range,
+        severity,
+        code: code.map(|code| NumberOrString::String(code.to_string())),
+        code_description: code.and_then(code_description),
+        source: Some(diagnostic_source(code).to_string()),
+        message: diagnostic_message(message, msg_text, support, config),
+        related_information,
+        tags: diagnostic_tags(code, msg_text, support),
+        data: None,
+    };
+    set_fixes(&mut diagnostic, suggested_fixes(message, file_name));
+
+    Some((paths.uri(file_name)?, diagnostic))
+}
+
+/// Whether `file_name` matches one of the `diagnostics.excludePaths` globs.
+fn is_excluded(paths: &SpanPaths, file_name: &str, config: &DiagnosticsConfig) -> bool {
+    if config.exclude_paths.is_empty() {
+        return false;
+    }
+    let path = paths.resolve(file_name);
+    let relative = path.strip_prefix(paths.root).unwrap_or(&path);
+    let relative = relative.to_string_lossy().replace('\\', "/");
+    config
+        .exclude_paths
+        .iter()
+        .any(|glob| glob_match(glob, &relative))
+}
+
+/// How many lines of stderr are shown when a check command fails.
+const STDERR_LINES: usize = 5;
+
+/// How often a check waiting for output looks whether it was killed.
+const KILL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How many child notes are appended to a short message.
+const MAX_CHILD_NOTES: usize = 3;
+
+/// Returns the short message followed by its child notes, or the rendered
+/// text without colors when `diagnostics.useRendered` is set.
+fn diagnostic_message(
+    message: &Value,
+    msg_text: &str,
+    support: ClientDiagnosticSupport,
+    config: &DiagnosticsConfig,
+) -> String {
+    let rendered = message.get("rendered").and_then(|v| v.as_str());
+    match rendered {
+        Some(rendered) if config.use_rendered => {
+            let rendered = strip_ansi(rendered);
+            let lines: Vec<&str> = rendered.trim_end().lines().collect();
+            if lines.len() <= config.rendered_max_lines {
+                return lines.join("\n");
+            }
+            let mut out = lines[..config.rendered_max_lines].join("\n");
+            out.push_str("\n...");
+            out
+        }
+        _ => {
+            let mut out = msg_text.to_string();
+            for (level, text) in child_notes(message, support).take(MAX_CHILD_NOTES) {
+                out.push_str(&format!("\n{}: {}", level, text));
+            }
+            out
+        }
+    }
+}
+
+/// Returns the level and message of children worth appending to the main
+/// message: those that only carry suggestions are left out, as are those
+/// with spans when they are shown as related information instead.
+fn child_notes(
+    message: &Value,
+    support: ClientDiagnosticSupport,
+) -> impl Iterator<Item = (&str, &str)> {
+    message
+        .get("children")
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+        .filter(move |child| {
+            let mut spans = spans(child).peekable();
+            if spans.peek().is_none() {
+                return true;
+            }
+            let suggestion_only = spans.all(|span| {
+                span.get("suggested_replacement")
+                    .is_some_and(Value::is_string)
+            });
+            !suggestion_only && !support.related_information
+        })
+        .filter_map(|child| {
+            let level = child.get("level").and_then(|v| v.as_str())?;
+            let text = child.get("message").and_then(|v| v.as_str())?;
+            Some((level, text))
+        })
+        .filter(|(_, text)| !text.is_empty())
+}
 
-        if value.get("reason").and_then(|v| v.as_str()) != Some("compiler-message") {
+/// Removes ANSI escape sequences, e.g. the colors of `--message-format
+/// json-diagnostic-rendered-ansi`.
+fn strip_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\x1b' {
+            out.push(ch);
             continue;
         }
+        match chars.next() {
+            // CSI: parameters up to a final byte in `@`..=`~`.
+            Some('[') => {
+                for ch in chars.by_ref() {
+                    if ('@'..='~').contains(&ch) {
+                        break;
+                    }
+                }
+            }
+            // OSC: terminated by BEL or `ESC \`.
+            Some(']') => {
+                while let Some(ch) = chars.next() {
+                    if ch == '\x07' {
+                        break;
+                    }
+                    if ch == '\x1b' {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    out
+}
 
-        let message = match value.get("message") {
-            Some(v) => v,
-            None => continue,
-        };
+/// Links rustc error codes such as `E0308` to their explanation and clippy
+/// lints to their entry in the lint index.
+fn code_description(code: &str) -> Option<CodeDescription> {
+    let href = if let Some(lint) = code.strip_prefix("clippy::") {
+        format!(
+            "https://rust-lang.github.io/rust-clippy/master/index.html#{}",
+            lint
+        )
+    } else if code.len() == 5
+        && code.starts_with('E')
+        && code[1..].bytes().all(|b| b.is_ascii_digit())
+    {
+        format!("https://doc.rust-lang.org/error_codes/{}.html", code)
+    } else {
+        return None;
+    };
+    Some(CodeDescription {
+        href: Uri::from_str(&href).ok()?,
+    })
+}
 
-        let level = message
-            .get("level")
-            .and_then(|v| v.as_str())
-            .unwrap_or("error");
-        let msg_text = message
-            .get("message")
-            .and_then(|v| v.as_str())
-            .unwrap_or("rustc error");
+/// Lints whose code the client can fade out as unnecessary.
+const UNNECESSARY_LINTS: &[&str] = &[
+    "unused_variables",
+    "unused_imports",
+    "dead_code",
+    "unreachable_code",
+];
 
-        let spans = match message.get("spans").and_then(|v| v.as_array()) {
-            Some(s) if !s.is_empty() => s,
-            _ => continue,
-        };
+/// Tags the diagnostic by its lint, or by its message when it has no code,
+/// as output without codes does, keeping only the tags the client supports.
+fn diagnostic_tags(
+    code: Option<&str>,
+    message: &str,
+    support: ClientDiagnosticSupport,
+) -> Option<Vec<DiagnosticTag>> {
+    let tag = match code {
+        Some(code) if UNNECESSARY_LINTS.contains(&code) => DiagnosticTag::UNNECESSARY,
+        Some("deprecated") => DiagnosticTag::DEPRECATED,
+        Some(_) => return None,
+        None if message.starts_with("unused ") || message.starts_with("unreachable ") => {
+            DiagnosticTag::UNNECESSARY
+        }
+        None if message.starts_with("use of deprecated ") => DiagnosticTag::DEPRECATED,
+        None => return None,
+    };
+    let supported = match tag {
+        DiagnosticTag::UNNECESSARY => support.unnecessary_tag,
+        _ => support.deprecated_tag,
+    };
+    supported.then(|| vec![tag])
+}
 
-        let span = spans
-            .iter()
-            .find(|span| span.get("is_primary").and_then(|v| v.as_bool()) == Some(true))
-            .unwrap_or(&spans[0]);
+fn diagnostic_source(code: Option<&str>) -> &'static str {
+    match code {
+        Some(code) if code.starts_with("clippy::") => "clippy",
+        _ => "rustc",
+    }
+}
 
-        let file_name = match span.get("file_name").and_then(|v| v.as_str()) {
-            Some(name) => name,
-            None => continue,
-        };
+fn span_range(span: &Value) -> Range {
+    let start = lsp_position_from_span(
+        span.get("line_start").and_then(|v| v.as_u64()).unwrap_or(1) as u32,
+        span.get("column_start")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(1) as u32,
+    );
+    let end = lsp_position_from_span(
+        span.get("line_end").and_then(|v| v.as_u64()).unwrap_or(1) as u32,
+        span.get("column_end").and_then(|v| v.as_u64()).unwrap_or(1) as u32,
+    );
+    Range { start, end }
+}
 
-        let start = lsp_position_from_span(
-            span.get("line_start").and_then(|v| v.as_u64()).unwrap_or(1) as u32,
-            span.get("column_start")
-                .and_then(|v| v.as_u64())
-                .unwrap_or(1) as u32,
-        );
-        let end = lsp_position_from_span(
-            span.get("line_end").and_then(|v| v.as_u64()).unwrap_or(1) as u32,
-            span.get("column_end").and_then(|v| v.as_u64()).unwrap_or(1) as u32,
-        );
-
-        let range = Range { start, end };
-        let severity = map_severity(level);
-
-        let diagnostic = Diagnostic {
-            range,
-            severity,
-            code: None,
-            code_description: None,
-            source: Some("cargo".to_string()),
-            message: msg_text.to_string(),
-            related_information: None,
-            tags: None,
-            data: None,
+/// Collects the spans of `message` other than `primary`, labelled with their
+/// own `label` or the diagnostic text, and the spans of child messages,
+/// labelled with the child's text.
+fn related_information(
+    paths: &SpanPaths,
+    message: &Value,
+    primary: &Value,
+    msg_text: &str,
+) -> Vec<DiagnosticRelatedInformation> {
+    let mut related = Vec::new();
+    let mut push = |span: &Value, text: &str| {
+        let Some(uri) = span
+            .get("file_name")
+            .and_then(|v| v.as_str())
+            .and_then(|file_name| paths.uri(file_name))
+        else {
+            return;
         };
+        related.push(DiagnosticRelatedInformation {
+            location: Location {
+                uri,
+                range: span_range(span),
+            },
+            message: text.to_string(),
+        });
+    };
 
-        if let Some(uri) = uri_from_file(root, file_name) {
-            diagnostics.entry(uri).or_default().push(diagnostic);
+    for span in spans(message) {
+        if std::ptr::eq(span, primary) {
+            continue;
         }
+        let label = span.get("label").and_then(|v| v.as_str());
+        push(span, label.unwrap_or(msg_text));
     }
+    for child in message
+        .get("children")
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+    {
+        let text = child.get("message").and_then(|v| v.as_str()).unwrap_or("");
+        for span in spans(child) {
+            push(span, text);
+        }
+    }
+    related
+}
+
+/// The edits of the machine-applicable suggestions among the children of
+/// `message` that fall in `file_name`, e.g. removing an unneeded `mut`.
+fn suggested_fixes(message: &Value, file_name: &str) -> Vec<TextEdit> {
+    message
+        .get("children")
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+        .flat_map(spans)
+        .filter(|span| {
+            span.get("suggestion_applicability")
+                .and_then(|v| v.as_str())
+                == Some("MachineApplicable")
+                && span.get("file_name").and_then(|v| v.as_str()) == Some(file_name)
+        })
+        .filter_map(|span| {
+            Some(TextEdit {
+                range: span_range(span),
+                new_text: span.get("suggested_replacement")?.as_str()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// The fixes `parse_message` kept in the `data` of `diagnostic`.
+pub fn diagnostic_fixes(diagnostic: &Diagnostic) -> Vec<TextEdit> {
+    diagnostic
+        .data
+        .as_ref()
+        .and_then(|data| data.get("fixes"))
+        .and_then(|fixes| serde_json::from_value(fixes.clone()).ok())
+        .unwrap_or_default()
+}
+
+fn set_fixes(diagnostic: &mut Diagnostic, fixes: Vec<TextEdit>) {
+    diagnostic.data = (!fixes.is_empty()).then(|| json!({ "fixes": fixes }));
+}
+
+fn spans(message: &Value) -> impl Iterator<Item = &Value> {
+    message
+        .get("spans")
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+}
+
+/// A check command and the environment it runs with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckCommand {
+    pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+    /// How long the command may run before it is killed.
+    pub timeout: Option<Duration>,
+    /// The pattern matched against each line of output in the `regex`
+    /// output format; cargo's JSON messages are parsed when unset.
+    pub pattern: Option<String>,
+    /// The workspace member the check is limited to with `-p`.
+    pub package: Option<String>,
+    /// A directory for the command's output, removed once it exits.
+    pub scratch: Option<PathBuf>,
+}
+
+impl From<Vec<String>> for CheckCommand {
+    fn from(args: Vec<String>) -> Self {
+        CheckCommand {
+            args,
+            env: Vec::new(),
+            timeout: None,
+            pattern: None,
+            package: None,
+            scratch: None,
+        }
+    }
+}
+
+/// Returns the command run on save: `custom` as given, or else `cargo check`
+/// with the arguments selected by the `check.*` settings, limited to the
+/// package owning `saved` when `check.scope` is `package`.
+pub fn check_command(
+    custom: Option<&[String]>,
+    check: &CheckConfig,
+    root: &Path,
+    saved: Option<&Path>,
+) -> CheckCommand {
+    let package = match (custom, check.scope, saved) {
+        (None, CheckScope::Package, Some(saved)) => cargo_workspace(root)
+            .and_then(|workspace| Some(workspace.package_for(saved)?.name.clone())),
+        _ => None,
+    };
+    let mut args = check_args(custom, check);
+    if let Some(package) = &package {
+        args.push("-p".to_string());
+        args.push(package.clone());
+    }
+    CheckCommand {
+        args,
+        env: check_env(check, root),
+        timeout: (check.timeout_seconds > 0).then(|| Duration::from_secs(check.timeout_seconds)),
+        pattern: match check.output_format {
+            OutputFormat::CargoJson => None,
+            OutputFormat::Regex => Some(
+                check
+                    .output_pattern
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_PATTERN.to_string()),
+            ),
+        },
+        package,
+        scratch: None,
+    }
+}
 
-    Ok(diagnostics)
+/// Returns a `rustc` command checking `file` on its own when no ancestor
+/// directory has a `Cargo.toml` and `check.standaloneFiles` is enabled.
+pub fn standalone_command(file: &Path, check: &CheckConfig) -> Option<CheckCommand> {
+    if !check.standalone_files
+        || file
+            .ancestors()
+            .skip(1)
+            .any(|dir| dir.join("Cargo.toml").is_file())
+    {
+        return None;
+    }
+    let dir = file.parent()?;
+    let mut args = [
+        "rustc",
+        "--edition",
+        "2021",
+        "--error-format=json",
+        "--emit=metadata",
+    ]
+    .map(String::from)
+    .to_vec();
+    // Windows has no `/dev/null` to write the metadata to.
+    let scratch = cfg!(windows)
+        .then(|| std::env::temp_dir().join(format!("hitagi-rustc-{}", std::process::id())));
+    match &scratch {
+        Some(scratch) => {
+            args.push("--out-dir".to_string());
+            args.push(scratch.to_string_lossy().into_owned());
+        }
+        None => {
+            args.push("-o".to_string());
+            args.push("/dev/null".to_string());
+        }
+    }
+    args.push(file.to_string_lossy().into_owned());
+    Some(CheckCommand {
+        args,
+        env: check_env(check, dir),
+        timeout: (check.timeout_seconds > 0).then(|| Duration::from_secs(check.timeout_seconds)),
+        pattern: None,
+        package: None,
+        scratch,
+    })
+}
+
+fn check_args(custom: Option<&[String]>, check: &CheckConfig) -> Vec<String> {
+    if let Some(custom) = custom {
+        return custom.to_vec();
+    }
+    let message_format = match check.output_format {
+        OutputFormat::CargoJson => "--message-format=json",
+        OutputFormat::Regex => "--message-format=short",
+    };
+    let mut command: Vec<String> = ["cargo", "check", "-q", message_format]
+        .map(String::from)
+        .to_vec();
+    if check.all_targets {
+        command.push("--all-targets".to_string());
+    }
+    if !check.features.is_empty() {
+        command.push("--features".to_string());
+        command.push(check.features.join(","));
+    }
+    if check.no_default_features {
+        command.push("--no-default-features".to_string());
+    }
+    if let Some(triple) = &check.target_triple {
+        command.push("--target".to_string());
+        command.push(triple.clone());
+    }
+    command
+}
+
+/// Returns `check.extraEnv` sorted by name, after `CARGO_TARGET_DIR` when
+/// `check.separateTargetDir` is set so that an explicit value wins.
+fn check_env(check: &CheckConfig, root: &Path) -> Vec<(String, String)> {
+    let mut env = Vec::new();
+    if check.separate_target_dir {
+        env.push(("CARGO_TARGET_DIR".to_string(), "target/hitagi".to_string()));
+    }
+    let mut extra: Vec<_> = check.extra_env.iter().collect();
+    extra.sort();
+    let root = root.to_string_lossy();
+    for (name, value) in extra {
+        env.push((name.clone(), value.replace("${workspaceRoot}", &root)));
+    }
+    env
 }
 
 fn split_command(command: &[String]) -> Result<(String, Vec<String>), String> {
@@ -106,7 +827,9 @@ fn split_command(command: &[String]) -> Result<(String, Vec<String>), String> {
 }
 
 fn has_message_format(command: &[String]) -> bool {
-    command.iter().any(|arg| arg.contains("--message-format"))
+    command
+        .iter()
+        .any(|arg| arg.contains("--message-format") || arg.starts_with("--error-format"))
 }
 
 fn map_severity(level: &str) -> Option<DiagnosticSeverity> {
@@ -119,12 +842,784 @@ fn map_severity(level: &str) -> Option<DiagnosticSeverity> {
     }
 }
 
-fn uri_from_file(root: &Path, file_name: &str) -> Option<Uri> {
-    let path = PathBuf::from(file_name);
-    let full = if path.is_absolute() {
-        path
-    } else {
-        root.join(path)
-    };
-    path_to_uri(&full)
+/// Resolves the file names of spans, which cargo writes relative to the
+/// workspace root rather than to the LSP root.
+struct SpanPaths<'a> {
+    root: &'a Path,
+    workspace: Option<&'a CargoWorkspace>,
+}
+
+impl<'a> SpanPaths<'a> {
+    fn new(root: &'a Path, workspace: Option<&'a CargoWorkspace>) -> Self {
+        SpanPaths { root, workspace }
+    }
+
+    fn resolve(&self, file_name: &str) -> PathBuf {
+        let path = Path::new(file_name);
+        if path.is_absolute() {
+            return path.to_path_buf();
+        }
+        match self.workspace {
+            Some(workspace) => workspace.resolve(path),
+            None => self.root.join(path),
+        }
+    }
+
+    fn uri(&self, file_name: &str) -> Option<Uri> {
+        path_to_uri(&self.resolve(file_name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    const BORROW_CHECK: &str = include_str!("fixtures/borrow_check.json");
+    const NEEDLESS_RETURN: &str = include_str!("fixtures/needless_return.json");
+    const DUPLICATE_TARGETS: &str = include_str!("fixtures/duplicate_targets.json");
+    const TYPE_MISMATCH: &str = include_str!("fixtures/type_mismatch.json");
+    const UNUSED_VARIABLE: &str = include_str!("fixtures/unused_variable.json");
+    const DEPRECATED: &str = include_str!("fixtures/deprecated.json");
+    const UNUSED_MUT: &str = include_str!("fixtures/unused_mut.json");
+
+    #[allow(clippy::mutable_key_type)]
+    fn parse(
+        root: &Path,
+        output: &str,
+        support: ClientDiagnosticSupport,
+    ) -> HashMap<Uri, Vec<Diagnostic>> {
+        let mut diagnostics: HashMap<Uri, Vec<Diagnostic>> = HashMap::new();
+        for line in output.lines() {
+            if let Some((uri, diagnostic)) = parse_message(
+                &SpanPaths::new(root, None),
+                &serde_json::from_str(line).unwrap(),
+                support,
+                &DiagnosticsConfig::default(),
+            ) {
+                diagnostics.entry(uri).or_default().push(diagnostic);
+            }
+        }
+        diagnostics
+    }
+
+    /// Collapses a pretty-printed fixture into a single line of cargo output.
+    fn cargo_line(fixture: &str) -> String {
+        let value: Value = serde_json::from_str(fixture).unwrap();
+        value.to_string()
+    }
+
+    #[test]
+    #[allow(clippy::mutable_key_type)]
+    fn related_information_lists_secondary_spans() {
+        let root = Path::new("/project");
+        let support = ClientDiagnosticSupport {
+            related_information: true,
+            ..ClientDiagnosticSupport::default()
+        };
+        let diagnostics = parse(root, &cargo_line(BORROW_CHECK), support);
+        let uri = path_to_uri(&root.join("src/main.rs")).unwrap();
+        let [diagnostic] = diagnostics[&uri].as_slice() else {
+            panic!("expected one diagnostic");
+        };
+        assert_eq!(
+            diagnostic.message,
+            "cannot borrow `v` as mutable more than once at a time\n\
+             note: for more information, see the borrow checker chapter"
+        );
+        assert_eq!(diagnostic.range.start.line, 3);
+
+        let related: Vec<(u32, &str)> = diagnostic
+            .related_information
+            .iter()
+            .flatten()
+            .map(|info| (info.location.range.start.line, info.message.as_str()))
+            .collect();
+        assert_eq!(
+            related,
+            [
+                (2, "first mutable borrow occurs here"),
+                (4, "first borrow later used here"),
+                (2, "consider cloning the vector first"),
+            ]
+        );
+
+        let diagnostics = parse(
+            root,
+            &cargo_line(BORROW_CHECK),
+            ClientDiagnosticSupport::default(),
+        );
+        assert!(diagnostics[&uri][0].related_information.is_none());
+    }
+
+    #[test]
+    #[allow(clippy::mutable_key_type)]
+    fn child_notes_are_folded_into_the_message() {
+        let root = Path::new("/project");
+        let uri = path_to_uri(&root.join("src/main.rs")).unwrap();
+        let message = |support| {
+            let diagnostics = parse(root, &cargo_line(TYPE_MISMATCH), support);
+            diagnostics[&uri][0].message.clone()
+        };
+        assert_eq!(
+            message(ClientDiagnosticSupport::default()),
+            "mismatched types\n\
+             note: expected reference `&str`\n   found struct `String`\n\
+             note: function defined here"
+        );
+        assert_eq!(
+            message(ClientDiagnosticSupport {
+                related_information: true,
+                ..ClientDiagnosticSupport::default()
+            }),
+            "mismatched types\nnote: expected reference `&str`\n   found struct `String`"
+        );
+    }
+
+    #[test]
+    fn unused_and_deprecated_lints_are_tagged() {
+        let root = Path::new("/project");
+        let uri = path_to_uri(&root.join("src/main.rs")).unwrap();
+        let output = format!(
+            "{}\n{}\n{}\n",
+            cargo_line(UNUSED_VARIABLE),
+            cargo_line(DEPRECATED),
+            cargo_line(BORROW_CHECK)
+        );
+        let tags = |support| {
+            parse(root, &output, support)[&uri]
+                .iter()
+                .map(|diagnostic| diagnostic.tags.clone())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            tags(ClientDiagnosticSupport {
+                unnecessary_tag: true,
+                deprecated_tag: true,
+                ..ClientDiagnosticSupport::default()
+            }),
+            [
+                Some(vec![DiagnosticTag::UNNECESSARY]),
+                Some(vec![DiagnosticTag::DEPRECATED]),
+                None,
+            ]
+        );
+        assert_eq!(
+            tags(ClientDiagnosticSupport {
+                deprecated_tag: true,
+                ..ClientDiagnosticSupport::default()
+            }),
+            [None, Some(vec![DiagnosticTag::DEPRECATED]), None]
+        );
+        assert_eq!(tags(ClientDiagnosticSupport::default()), [None, None, None]);
+    }
+
+    #[test]
+    #[allow(clippy::mutable_key_type)]
+    fn machine_applicable_suggestions_are_kept_as_fixes() {
+        let root = Path::new("/project");
+        let uri = path_to_uri(&root.join("src/main.rs")).unwrap();
+        let diagnostics = parse(
+            root,
+            &cargo_line(UNUSED_MUT),
+            ClientDiagnosticSupport::default(),
+        );
+        let diagnostic = &diagnostics[&uri][0];
+        assert_eq!(
+            diagnostic_fixes(diagnostic),
+            [TextEdit {
+                range: Range {
+                    start: lsp_types::Position::new(1, 8),
+                    end: lsp_types::Position::new(1, 12),
+                },
+                new_text: String::new(),
+            }]
+        );
+        // The suggestion is applied rather than repeated in the message.
+        assert_eq!(
+            diagnostic.message,
+            "variable does not need to be mutable\n\
+             note: `#[warn(unused_mut)]` on by default"
+        );
+
+        let diagnostics = parse(
+            root,
+            &cargo_line(BORROW_CHECK),
+            ClientDiagnosticSupport::default(),
+        );
+        assert!(diagnostics[&uri][0].data.is_none());
+    }
+
+    #[test]
+    #[allow(clippy::mutable_key_type)]
+    fn codes_link_to_explanations() {
+        let root = Path::new("/project");
+        let uri = path_to_uri(&root.join("src/main.rs")).unwrap();
+        let output = format!(
+            "{}\n{}\n",
+            cargo_line(BORROW_CHECK),
+            cargo_line(NEEDLESS_RETURN)
+        );
+        let diagnostics = parse(root, &output, ClientDiagnosticSupport::default());
+        let summary: Vec<(Option<&str>, Option<&str>, Option<&str>)> = diagnostics[&uri]
+            .iter()
+            .map(|diagnostic| {
+                let code = match &diagnostic.code {
+                    Some(NumberOrString::String(code)) => Some(code.as_str()),
+                    _ => None,
+                };
+                let href = diagnostic
+                    .code_description
+                    .as_ref()
+                    .map(|description| description.href.as_str());
+                (code, href, diagnostic.source.as_deref())
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (
+                    Some("E0499"),
+                    Some("https://doc.rust-lang.org/error_codes/E0499.html"),
+                    Some("rustc")
+                ),
+                (
+                    Some("clippy::needless_return"),
+                    Some(
+                        "https://rust-lang.github.io/rust-clippy/master/index.html#needless_return"
+                    ),
+                    Some("clippy")
+                ),
+            ]
+        );
+        assert!(code_description("unused_variables").is_none());
+        assert_eq!(diagnostic_source(Some("unused_variables")), "rustc");
+    }
+
+    #[allow(clippy::mutable_key_type)]
+    #[cfg(not(windows))]
+    #[test]
+    fn run_check_reports_diagnostics_as_they_arrive() {
+        let root = std::env::temp_dir();
+        let command = CheckCommand::from(vec![
+            "printf".to_string(),
+            "%s\\n".to_string(),
+            cargo_line(BORROW_CHECK),
+            cargo_line(NEEDLESS_RETURN),
+        ]);
+        let mut updates = Vec::new();
+        let diagnostics = run_check(
+            &root,
+            &command,
+            ClientDiagnosticSupport::default(),
+            &DiagnosticsConfig::default(),
+            &CheckProcess::default(),
+            |uri, diagnostics| updates.push((uri.clone(), diagnostics.len())),
+        )
+        .unwrap();
+
+        let uri = path_to_uri(&root.join("src/main.rs")).unwrap();
+        assert_eq!(updates, [(uri.clone(), 1), (uri.clone(), 2)]);
+        assert_eq!(diagnostics[&uri].len(), 2);
+    }
+
+    #[allow(clippy::mutable_key_type)]
+    #[cfg(not(windows))]
+    #[test]
+    fn output_formats_parse_json_and_pattern_output() {
+        let root = std::env::temp_dir();
+        let uri = path_to_uri(&root.join("src/main.rs")).unwrap();
+        let check = |pattern: Option<&str>| {
+            let script = format!(
+                "printf '%s\\n' '{}'; echo 'src/main.rs:2:9: warning: unused variable: `x`' >&2",
+                cargo_line(NEEDLESS_RETURN)
+            );
+            let command = CheckCommand {
+                pattern: pattern.map(str::to_string),
+                ..CheckCommand::from(vec![
+                    "sh".to_string(),
+                    "-c".to_string(),
+                    script,
+                    "--message-format=json".to_string(),
+                ])
+            };
+            let diagnostics = run_check(
+                &root,
+                &command,
+                ClientDiagnosticSupport::default(),
+                &DiagnosticsConfig::default(),
+                &CheckProcess::default(),
+                |_, _| {},
+            )
+            .unwrap();
+            diagnostics[&uri]
+                .iter()
+                .map(|diagnostic| diagnostic.message.lines().next().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(check(None), ["unneeded `return` statement"]);
+        assert_eq!(check(Some(DEFAULT_PATTERN)), ["unused variable: `x`"]);
+    }
+
+    #[test]
+    fn rustc_diagnostics_parse_without_the_cargo_wrapper() {
+        let root = Path::new("/project");
+        let value: Value = serde_json::from_str(UNUSED_VARIABLE).unwrap();
+        let (uri, diagnostic) = parse_message(
+            &SpanPaths::new(root, None),
+            &value["message"],
+            ClientDiagnosticSupport::default(),
+            &DiagnosticsConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(uri, path_to_uri(&root.join("src/main.rs")).unwrap());
+        assert_eq!(diagnostic.message, "unused variable: `unused`");
+    }
+
+    #[allow(clippy::mutable_key_type)]
+    #[cfg(not(windows))]
+    #[test]
+    fn standalone_files_are_checked_with_rustc() {
+        let dir = temp_dir("standalone");
+        let file = dir.join("scratch.rs");
+        fs::write(&file, "fn main() {\n    let unused = 1;\n}\n").unwrap();
+
+        let mut check = CheckConfig::default();
+        let command = standalone_command(&file, &check).unwrap();
+        let diagnostics = run_check(
+            &dir,
+            &command,
+            ClientDiagnosticSupport::default(),
+            &DiagnosticsConfig::default(),
+            &CheckProcess::default(),
+            |_, _| {},
+        )
+        .unwrap();
+        let uri = path_to_uri(&file).unwrap();
+        let messages: Vec<&str> = diagnostics[&uri]
+            .iter()
+            .map(|diagnostic| diagnostic.message.lines().next().unwrap())
+            .collect();
+        assert_eq!(messages, ["unused variable: `unused`"]);
+
+        check.standalone_files = false;
+        assert!(standalone_command(&file, &check).is_none());
+        check.standalone_files = true;
+        fs::write(dir.join("Cargo.toml"), "").unwrap();
+        assert!(standalone_command(&file, &check).is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[allow(clippy::mutable_key_type)]
+    #[cfg(not(windows))]
+    #[test]
+    fn failing_commands_report_status_and_stderr() {
+        let root = std::env::temp_dir();
+        let check = |args: &[&str]| {
+            let command =
+                CheckCommand::from(args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>());
+            run_check(
+                &root,
+                &command,
+                ClientDiagnosticSupport::default(),
+                &DiagnosticsConfig::default(),
+                &CheckProcess::default(),
+                |_, _| {},
+            )
+        };
+
+        let err = check(&["carg", "check"]).unwrap_err();
+        assert!(err.starts_with("could not run `carg`: "), "{}", err);
+
+        let script = "echo 'error: failed to write Cargo.lock' >&2; exit 101";
+        let err = check(&["sh", "-c", script, "--message-format=json"]).unwrap_err();
+        assert_eq!(
+            err,
+            format!(
+                "`sh -c {} --message-format=json` failed (exit status: 101)\n\
+                 error: failed to write Cargo.lock",
+                script
+            )
+        );
+
+        // Compile errors make cargo fail too, but are shown as diagnostics.
+        let script = format!("printf '%s\\n' '{}'; exit 101", cargo_line(NEEDLESS_RETURN));
+        let diagnostics = check(&["sh", "-c", &script, "--message-format=json"]).unwrap();
+        assert_eq!(diagnostics.len(), 1);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn killed_checks_report_nothing() {
+        let process = CheckProcess::default();
+        assert!(process.kill());
+        let command = CheckCommand::from(vec!["printf".to_string(), cargo_line(BORROW_CHECK)]);
+        let mut updates = 0;
+        let result = run_check(
+            &std::env::temp_dir(),
+            &command,
+            ClientDiagnosticSupport::default(),
+            &DiagnosticsConfig::default(),
+            &process,
+            |_, _| updates += 1,
+        );
+        assert!(result.is_err());
+        assert_eq!(updates, 0);
+    }
+
+    #[test]
+    fn rendered_messages_are_stripped_and_capped() {
+        let message = serde_json::json!({
+            "message": "mismatched types",
+            "rendered": "\x1b[0m\x1b[1m\x1b[38;5;9merror[E0308]\x1b[0m: mismatched types\n \x1b[1m-->\x1b[0m src/main.rs:2:18\n  |\n2 |     let x: u8 = \"a\";\n  |                 ^^^ expected `u8`\n\x1b]8;;https://example.com\x1b\\link\x1b]8;;\x07\n",
+        });
+        let support = ClientDiagnosticSupport::default();
+        let mut config = DiagnosticsConfig::default();
+        assert_eq!(
+            diagnostic_message(&message, "mismatched types", support, &config),
+            "mismatched types"
+        );
+
+        config.use_rendered = true;
+        assert_eq!(
+            diagnostic_message(&message, "mismatched types", support, &config),
+            "error[E0308]: mismatched types\n --> src/main.rs:2:18\n  |\n2 |     let x: u8 = \"a\";\n  |                 ^^^ expected `u8`\nlink"
+        );
+
+        config.rendered_max_lines = 2;
+        assert_eq!(
+            diagnostic_message(&message, "mismatched types", support, &config),
+            "error[E0308]: mismatched types\n --> src/main.rs:2:18\n..."
+        );
+        let short = serde_json::json!({ "message": "unused variable" });
+        assert_eq!(
+            diagnostic_message(&short, "unused variable", support, &config),
+            "unused variable"
+        );
+    }
+
+    #[test]
+    fn severity_overrides_match_bare_and_namespaced_codes() {
+        let root = Path::new("/project");
+        let uri = path_to_uri(&root.join("src/main.rs")).unwrap();
+        let output = format!(
+            "{}\n{}\n",
+            cargo_line(BORROW_CHECK),
+            cargo_line(NEEDLESS_RETURN)
+        );
+        let severities = |overrides: &[(&str, Option<DiagnosticSeverity>)]| {
+            let config = DiagnosticsConfig {
+                severity_overrides: overrides
+                    .iter()
+                    .map(|(code, severity)| (code.to_string(), *severity))
+                    .collect(),
+                ..DiagnosticsConfig::default()
+            };
+            output
+                .lines()
+                .filter_map(|line| {
+                    parse_message(
+                        &SpanPaths::new(root, None),
+                        &serde_json::from_str(line).unwrap(),
+                        ClientDiagnosticSupport::default(),
+                        &config,
+                    )
+                })
+                .inspect(|(file, _)| assert_eq!(file, &uri))
+                .map(|(_, diagnostic)| diagnostic.severity)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            severities(&[]),
+            [
+                Some(DiagnosticSeverity::ERROR),
+                Some(DiagnosticSeverity::WARNING)
+            ]
+        );
+        assert_eq!(
+            severities(&[
+                ("E0499", Some(DiagnosticSeverity::HINT)),
+                ("needless_return", Some(DiagnosticSeverity::ERROR)),
+            ]),
+            [
+                Some(DiagnosticSeverity::HINT),
+                Some(DiagnosticSeverity::ERROR)
+            ]
+        );
+        assert_eq!(
+            severities(&[("clippy::needless_return", None)]),
+            [Some(DiagnosticSeverity::ERROR)]
+        );
+        assert_eq!(
+            severities(&[("needless_return", None), ("E0499", None)]),
+            []
+        );
+
+        let mut config = crate::config::Config::default();
+        config.update_from_settings(&serde_json::json!({
+            "diagnostics": {
+                "severityOverrides": { "dead_code": "Hint", "clippy::unwrap_used": "off", "E0308": "loud" }
+            }
+        }));
+        let diagnostics = &config.diagnostics;
+        assert_eq!(
+            diagnostics.severity_override("dead_code"),
+            Some(Some(DiagnosticSeverity::HINT))
+        );
+        assert_eq!(
+            diagnostics.severity_override("clippy::unwrap_used"),
+            Some(None)
+        );
+        assert_eq!(diagnostics.severity_override("E0308"), None);
+    }
+
+    #[test]
+    fn disabled_codes_and_excluded_paths_are_dropped() {
+        let root = Path::new("/project");
+        let generated = cargo_line(&BORROW_CHECK.replace("src/main.rs", "src/generated/api.rs"));
+        let absolute =
+            cargo_line(&BORROW_CHECK.replace("src/main.rs", "/project/src/generated/v1.rs"));
+        let output = [
+            cargo_line(BORROW_CHECK),
+            cargo_line(NEEDLESS_RETURN),
+            generated,
+            absolute,
+        ];
+        let files = |config: &DiagnosticsConfig| {
+            output
+                .iter()
+                .filter_map(|line| {
+                    parse_message(
+                        &SpanPaths::new(root, None),
+                        &serde_json::from_str(line).unwrap(),
+                        ClientDiagnosticSupport::default(),
+                        config,
+                    )
+                })
+                .map(|(uri, diagnostic)| (uri.path().to_string(), diagnostic.code))
+                .collect::<Vec<_>>()
+        };
+        let code = |code: &str| Some(NumberOrString::String(code.to_string()));
+
+        let mut config = DiagnosticsConfig::default();
+        assert_eq!(files(&config).len(), 4);
+        config.disabled = vec!["needless_return".to_string()];
+        config.exclude_paths = vec!["src/generated/**".to_string()];
+        assert_eq!(
+            files(&config),
+            [("/project/src/main.rs".to_string(), code("E0499"))]
+        );
+        config.disabled = vec!["E0499".to_string(), "clippy::needless_return".to_string()];
+        config.exclude_paths.clear();
+        assert!(files(&config).is_empty());
+    }
+
+    #[test]
+    fn check_command_adds_selected_targets() {
+        let mut check = CheckConfig::default();
+        assert_eq!(
+            check_args(None, &check),
+            ["cargo", "check", "-q", "--message-format=json"]
+        );
+
+        check.all_targets = true;
+        check.features = vec!["serde".to_string(), "cli".to_string()];
+        assert_eq!(
+            check_args(None, &check)[4..],
+            ["--all-targets", "--features", "serde,cli"]
+        );
+
+        check.all_targets = false;
+        check.features.clear();
+        check.no_default_features = true;
+        check.target_triple = Some("wasm32-unknown-unknown".to_string());
+        assert_eq!(
+            check_args(None, &check)[4..],
+            [
+                "--no-default-features",
+                "--target",
+                "wasm32-unknown-unknown"
+            ]
+        );
+
+        let custom = ["cargo", "clippy", "--message-format=json"].map(String::from);
+        assert_eq!(check_args(Some(&custom), &check), custom);
+    }
+
+    #[test]
+    fn check_env_substitutes_the_workspace_root() {
+        let mut check = CheckConfig::default();
+        let root = Path::new("/project");
+        assert!(check_command(None, &check, root, None).env.is_empty());
+
+        check.separate_target_dir = true;
+        check.extra_env = [
+            ("RUSTFLAGS", "--cfg hitagi"),
+            ("CARGO_TARGET_DIR", "${workspaceRoot}/target/lsp"),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+        let env = check_command(None, &check, root, None).env;
+        let env: Vec<(&str, &str)> = env
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        assert_eq!(
+            env,
+            [
+                ("CARGO_TARGET_DIR", "target/hitagi"),
+                ("CARGO_TARGET_DIR", "/project/target/lsp"),
+                ("RUSTFLAGS", "--cfg hitagi"),
+            ]
+        );
+    }
+
+    #[allow(clippy::mutable_key_type)]
+    #[cfg(not(windows))]
+    #[test]
+    fn run_check_sets_extra_env() {
+        let root = std::env::temp_dir();
+        let command = CheckCommand {
+            args: [
+                "sh",
+                "-c",
+                "printf '%s\\n' \"$HITAGI_LINE\"",
+                "--message-format=json",
+            ]
+            .map(String::from)
+            .to_vec(),
+            env: vec![("HITAGI_LINE".to_string(), cargo_line(BORROW_CHECK))],
+            timeout: None,
+            pattern: None,
+            package: None,
+            scratch: None,
+        };
+        let diagnostics = run_check(
+            &root,
+            &command,
+            ClientDiagnosticSupport::default(),
+            &DiagnosticsConfig::default(),
+            &CheckProcess::default(),
+            |_, _| {},
+        )
+        .unwrap();
+        assert_eq!(diagnostics.values().flatten().count(), 1);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn hung_checks_are_killed_after_the_timeout() {
+        let command = CheckCommand {
+            timeout: Some(Duration::from_millis(100)),
+            ..CheckCommand::from(
+                ["sh", "-c", "sleep 5", "--message-format=json"]
+                    .map(String::from)
+                    .to_vec(),
+            )
+        };
+        let process = CheckProcess::default();
+        let started = Instant::now();
+        let result = run_check(
+            &std::env::temp_dir(),
+            &command,
+            ClientDiagnosticSupport::default(),
+            &DiagnosticsConfig::default(),
+            &process,
+            |_, _| {},
+        );
+        assert!(result.is_err());
+        assert!(process.timed_out());
+        assert!(started.elapsed() < Duration::from_secs(4));
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn killed_checks_stop_while_a_grandchild_keeps_the_output_open() {
+        // `timeoutSeconds = 0`: only a kill can end the check early.
+        let command = CheckCommand::from(
+            ["sh", "-c", "sleep 5 & sleep 5", "--message-format=json"]
+                .map(String::from)
+                .to_vec(),
+        );
+        assert!(command.timeout.is_none());
+        let process = Arc::new(CheckProcess::default());
+        let killer = {
+            let process = Arc::clone(&process);
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(200));
+                process.kill();
+            })
+        };
+        let started = Instant::now();
+        let result = run_check(
+            &std::env::temp_dir(),
+            &command,
+            ClientDiagnosticSupport::default(),
+            &DiagnosticsConfig::default(),
+            &process,
+            |_, _| {},
+        );
+        killer.join().unwrap();
+        assert_eq!(result.unwrap_err(), "check cancelled");
+        assert!(started.elapsed() < Duration::from_secs(4));
+    }
+
+    #[allow(clippy::mutable_key_type)]
+    #[cfg(not(windows))]
+    #[test]
+    fn diagnostics_from_several_targets_are_deduplicated() {
+        let root = std::env::temp_dir();
+        let messages: Vec<Value> = serde_json::from_str(DUPLICATE_TARGETS).unwrap();
+        let mut args = vec!["printf".to_string(), "%s\\n".to_string()];
+        args.extend(messages.iter().map(Value::to_string));
+        let mut updates = 0;
+        let diagnostics = run_check(
+            &root,
+            &CheckCommand::from(args),
+            ClientDiagnosticSupport::default(),
+            &DiagnosticsConfig::default(),
+            &CheckProcess::default(),
+            |_, _| updates += 1,
+        )
+        .unwrap();
+        let uri = path_to_uri(&root.join("src/lib.rs")).unwrap();
+        assert_eq!(diagnostics[&uri].len(), 1);
+        assert_eq!(updates, 1);
+    }
+
+    #[test]
+    fn merging_prefers_the_report_with_a_code() {
+        let diagnostic = |source: &str, code: Option<&str>| Diagnostic {
+            range: Range::default(),
+            severity: Some(DiagnosticSeverity::WARNING),
+            code: code.map(|code| NumberOrString::String(code.to_string())),
+            source: Some(source.to_string()),
+            message: "unused variable: `x`".to_string(),
+            ..Diagnostic::default()
+        };
+        let mut file = Vec::new();
+        assert!(merge_diagnostic(&mut file, diagnostic("cargo", None)));
+        assert!(merge_diagnostic(
+            &mut file,
+            diagnostic("rustc", Some("unused_variables"))
+        ));
+        assert!(!merge_diagnostic(&mut file, diagnostic("cargo", None)));
+        assert!(!merge_diagnostic(
+            &mut file,
+            diagnostic("rustc", Some("unused_variables"))
+        ));
+        assert_eq!(file.len(), 1);
+        assert_eq!(file[0].source.as_deref(), Some("rustc"));
+
+        let mut other = diagnostic("rustc", Some("dead_code"));
+        other.message = "function `f` is never used".to_string();
+        assert!(merge_diagnostic(&mut file, other));
+        assert_eq!(file.len(), 2);
+    }
 }
@@ -0,0 +1,193 @@
+use lsp_types::{Diagnostic, NumberOrString, Range, Uri};
+use regex::{Captures, Regex};
+
+use super::{
+    ClientDiagnosticSupport, SpanPaths, diagnostic_source, diagnostic_tags, is_excluded,
+    map_severity,
+};
+use crate::config::DiagnosticsConfig;
+use crate::doc::position::lsp_position_from_span;
+
+/// Matches `rustc --error-format=short` and `cargo --message-format=short`
+/// output, e.g. `src/main.rs:2:9: warning: unused variable: `x``.
+pub const DEFAULT_PATTERN: &str = r"^(?P<file>[^:\s][^:]*):(?P<line>\d+):(?P<col>\d+): (?P<severity>error|warning|note|help)(?:\[(?P<code>[^\]]+)\])?: (?P<message>.+)$";
+
+/// Compiles `check.outputPattern`, which needs at least the `file`, `line`
+/// and `message` groups.
+pub fn output_regex(pattern: &str) -> Result<Regex, String> {
+    let regex =
+        Regex::new(pattern).map_err(|err| format!("invalid check.outputPattern: {}", err))?;
+    let names: Vec<&str> = regex.capture_names().flatten().collect();
+    for group in ["file", "line", "message"] {
+        if !names.contains(&group) {
+            return Err(format!(
+                "check.outputPattern has no `{}` group; it needs `file`, `line` and `message`",
+                group
+            ));
+        }
+    }
+    Ok(regex)
+}
+
+/// Builds a diagnostic from a line matched by the output pattern. `line`
+/// and the optional `col` are 1-based; `severity` defaults to an error and
+/// an optional `code` group is matched against `diagnostics.*` like lint
+/// names.
+pub fn parse_captures(
+    paths: &SpanPaths,
+    captures: &Captures,
+    support: ClientDiagnosticSupport,
+    config: &DiagnosticsConfig,
+) -> Option<(Uri, Diagnostic)> {
+    let file_name = captures.name("file")?.as_str().trim();
+    let number = |group: &str| {
+        captures
+            .name(group)
+            .and_then(|value| value.as_str().parse::<u32>().ok())
+    };
+    let line = number("line")?;
+    let col = number("col").unwrap_or(1);
+    let message = captures.name("message")?.as_str().trim();
+    let code = captures.name("code").map(|code| code.as_str());
+    if code.is_some_and(|code| config.is_disabled(code)) || is_excluded(paths, file_name, config) {
+        return None;
+    }
+    let severity = match code.and_then(|code| config.severity_override(code)) {
+        Some(None) => return None,
+        Some(severity) => severity,
+        None => {
+            let level = captures
+                .name("severity")
+                .map_or("error", |level| level.as_str());
+            map_severity(&level.to_ascii_lowercase())
+        }
+    };
+    let position = lsp_position_from_span(line, col);
+    let diagnostic = Diagnostic {
+        range: Range::new(position, position),
+        severity,
+        code: code.map(|code| NumberOrString::String(code.to_string())),
+        source: Some(diagnostic_source(code).to_string()),
+        message: message.to_string(),
+        tags: diagnostic_tags(code, message, support),
+        ..Diagnostic::default()
+    };
+    Some((paths.uri(file_name)?, diagnostic))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use lsp_types::{DiagnosticSeverity, Position};
+
+    use super::*;
+    use crate::doc::uri::path_to_uri;
+
+    fn parse(pattern: &str, output: &str) -> Vec<(Uri, Diagnostic)> {
+        let regex = output_regex(pattern).unwrap();
+        let paths = SpanPaths::new(Path::new("/project"), None);
+        output
+            .lines()
+            .filter_map(|line| regex.captures(line))
+            .filter_map(|captures| {
+                parse_captures(
+                    &paths,
+                    &captures,
+                    ClientDiagnosticSupport::default(),
+                    &DiagnosticsConfig::default(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn default_pattern_reads_short_rustc_output() {
+        let output = "\
+src/main.rs:2:9: warning: unused variable: `x`
+src/main.rs:4:17: error[E0499]: cannot borrow `v` as mutable more than once at a time
+error: aborting due to 1 previous error; 1 warning emitted
+";
+        let diagnostics = parse(DEFAULT_PATTERN, output);
+        let uri = path_to_uri(Path::new("/project/src/main.rs")).unwrap();
+        let summary: Vec<_> = diagnostics
+            .iter()
+            .map(|(file, diagnostic)| {
+                assert_eq!(file, &uri);
+                (
+                    diagnostic.range.start,
+                    diagnostic.severity,
+                    diagnostic.code.clone(),
+                    diagnostic.message.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (
+                    Position::new(1, 8),
+                    Some(DiagnosticSeverity::WARNING),
+                    None,
+                    "unused variable: `x`"
+                ),
+                (
+                    Position::new(3, 16),
+                    Some(DiagnosticSeverity::ERROR),
+                    Some(NumberOrString::String("E0499".to_string())),
+                    "cannot borrow `v` as mutable more than once at a time"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn custom_patterns_map_severity_and_default_the_column() {
+        let pattern = r"^\[(?P<severity>\w+)\] (?P<file>\S+) line (?P<line>\d+): (?P<message>.+)$";
+        let output = "\
+[WARNING] lib/gen.rs line 12: generated code is stale
+[ERROR] lib/api.rs line 3: missing field
+INFO: build took 2s
+";
+        let diagnostics = parse(pattern, output);
+        let summary: Vec<_> = diagnostics
+            .iter()
+            .map(|(file, diagnostic)| {
+                (
+                    file.path().to_string(),
+                    diagnostic.range.start,
+                    diagnostic.severity,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (
+                    "/project/lib/gen.rs".to_string(),
+                    Position::new(11, 0),
+                    Some(DiagnosticSeverity::WARNING)
+                ),
+                (
+                    "/project/lib/api.rs".to_string(),
+                    Position::new(2, 0),
+                    Some(DiagnosticSeverity::ERROR)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn patterns_need_the_required_groups() {
+        assert!(
+            output_regex("(")
+                .unwrap_err()
+                .starts_with("invalid check.outputPattern")
+        );
+        assert!(
+            output_regex(r"(?P<file>\S+):(?P<line>\d+)")
+                .unwrap_err()
+                .contains("no `message` group")
+        );
+    }
+}
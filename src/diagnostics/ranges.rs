@@ -0,0 +1,117 @@
+use lsp_types::Range;
+
+use crate::doc::position::{PositionEncoding, offset_to_position, position_to_offset};
+use crate::syntax::is_ident_continue;
+
+/// Widens ranges that editors render invisibly: an empty range covers the
+/// identifier or character at its position when `token` is set, and the
+/// rest of the line otherwise; a range from the start of one line to the
+/// start of the next covers that line's text.
+pub fn expand_range(range: Range, text: &str, token: bool, encoding: PositionEncoding) -> Range {
+    let whole_line = range.start.character == 0
+        && range.end.character == 0
+        && range.end.line == range.start.line + 1;
+    if range.start != range.end && !whole_line {
+        return range;
+    }
+    let Some(start) = position_to_offset(text, range.start, encoding) else {
+        return range;
+    };
+    let line_end = text[start..].find('\n').map_or(text.len(), |nl| start + nl);
+    let line = &text[start..line_end];
+
+    let (from, to) = if whole_line {
+        let content = line.trim();
+        if content.is_empty() {
+            return range;
+        }
+        let from = start + (line.len() - line.trim_start().len());
+        (from, from + content.len())
+    } else if token {
+        token_at(text, start)
+    } else {
+        (start, start + line.trim_end().len())
+    };
+    if from == to {
+        return range;
+    }
+    match (
+        offset_to_position(text, from, encoding),
+        offset_to_position(text, to, encoding),
+    ) {
+        (Some(start), Some(end)) => Range { start, end },
+        _ => range,
+    }
+}
+
+/// Returns the identifier around `offset`, or the single character there.
+fn token_at(text: &str, offset: usize) -> (usize, usize) {
+    let bytes = text.as_bytes();
+    if bytes.get(offset).is_some_and(|b| is_ident_continue(*b)) {
+        let from = text[..offset]
+            .bytes()
+            .rposition(|b| !is_ident_continue(b))
+            .map_or(0, |idx| idx + 1);
+        let to = text[offset..]
+            .bytes()
+            .position(|b| !is_ident_continue(b))
+            .map_or(text.len(), |idx| offset + idx);
+        return (from, to);
+    }
+    match text[offset..].chars().next() {
+        Some(ch) if ch != '\n' => (offset, offset + ch.len_utf8()),
+        _ => (offset, offset),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::Position;
+
+    use super::*;
+
+    fn line_range(line: u32, start: u32, end: u32) -> Range {
+        Range {
+            start: Position::new(line, start),
+            end: Position::new(line, end),
+        }
+    }
+
+    const TEXT: &str = "fn main() {\n    let unused = 1;\n    call(  );\n}\n";
+
+    #[test]
+    fn empty_ranges_cover_the_token() {
+        assert_eq!(
+            expand_range(line_range(1, 8, 8), TEXT, true, PositionEncoding::Utf16),
+            line_range(1, 8, 14)
+        );
+        assert_eq!(
+            expand_range(line_range(1, 11, 11), TEXT, true, PositionEncoding::Utf16),
+            line_range(1, 8, 14)
+        );
+        assert_eq!(
+            expand_range(line_range(1, 15, 15), TEXT, true, PositionEncoding::Utf16),
+            line_range(1, 15, 16)
+        );
+        assert_eq!(
+            expand_range(line_range(1, 8, 8), TEXT, false, PositionEncoding::Utf16),
+            line_range(1, 8, 19)
+        );
+        assert_eq!(
+            expand_range(line_range(1, 4, 10), TEXT, true, PositionEncoding::Utf16),
+            line_range(1, 4, 10)
+        );
+    }
+
+    #[test]
+    fn whole_line_ranges_cover_the_line_text() {
+        let range = Range {
+            start: Position::new(2, 0),
+            end: Position::new(3, 0),
+        };
+        assert_eq!(
+            expand_range(range, TEXT, true, PositionEncoding::Utf16),
+            line_range(2, 4, 13)
+        );
+    }
+}
@@ -0,0 +1,1099 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use lsp_types::{Diagnostic, MessageType, Range, Uri};
+
+use super::metadata::cargo_workspace;
+use super::ranges::expand_range;
+use super::{
+    CheckCommand, CheckProcess, ClientDiagnosticSupport, diagnostic_fixes, run_check, set_fixes,
+};
+use crate::config::DiagnosticsConfig;
+use crate::doc::file_cache;
+use crate::doc::position::{PositionEncoding, convert_position};
+use crate::doc::uri::{NormalizedUri, canonicalize, path_to_uri, uri_to_path};
+
+/// Everything a check needs, captured when the save arrives.
+pub struct CheckJob {
+    pub root: PathBuf,
+    pub command: CheckCommand,
+    pub support: ClientDiagnosticSupport,
+    /// Open documents with their version and text at the time of the save.
+    pub open_documents: HashMap<Uri, (i32, String)>,
+    /// The URIs the client opened files with, keyed by the URIs cargo may
+    /// report them under; see `DocumentStore::client_uris`.
+    pub client_uris: HashMap<NormalizedUri, Uri>,
+    pub config: DiagnosticsConfig,
+    pub encoding: PositionEncoding,
+}
+
+impl CheckJob {
+    /// The URI the client opened the file at `uri` with, which may be
+    /// spelled differently or go through a symlink, or `uri` itself when the
+    /// file is not open.
+    fn client_uri(&self, uri: Uri) -> Uri {
+        if let Some(client) = self.client_uris.get(&NormalizedUri::new(&uri)) {
+            return client.clone();
+        }
+        uri_to_path(&uri)
+            .and_then(|path| path_to_uri(&canonicalize(&path)))
+            .and_then(|real| self.client_uris.get(&NormalizedUri::new(&real)).cloned())
+            .unwrap_or(uri)
+    }
+
+    fn publishes(&self, uri: &Uri) -> bool {
+        !self.config.open_files_only || self.open_documents.contains_key(uri)
+    }
+
+    /// Groups the results of a check under the URIs the client opened the
+    /// files with, keeping the files diagnostics are published for.
+    #[allow(clippy::mutable_key_type)]
+    fn client_results(&self, map: HashMap<Uri, Vec<Diagnostic>>) -> HashMap<Uri, Vec<Diagnostic>> {
+        let mut map = map.into_iter().fold(
+            HashMap::<Uri, Vec<Diagnostic>>::new(),
+            |mut map, (uri, diagnostics)| {
+                map.entry(self.client_uri(uri))
+                    .or_default()
+                    .extend(diagnostics);
+                map
+            },
+        );
+        map.retain(|uri, _| self.publishes(uri));
+        map
+    }
+
+    /// Runs the check once on the current thread and returns the diagnostics
+    /// of each file as they would be published.
+    #[allow(clippy::mutable_key_type)]
+    pub fn run(&self) -> Result<HashMap<Uri, Vec<Diagnostic>>, String> {
+        let map = run_check(
+            &self.root,
+            &self.command,
+            self.support,
+            &self.config,
+            &CheckProcess::default(),
+            |_, _| {},
+        )?;
+        let mut map = self.client_results(map);
+        let mut disk = HashMap::new();
+        for (uri, diagnostics) in &mut map {
+            self.adjust_ranges(uri, diagnostics, &mut disk);
+        }
+        Ok(map)
+    }
+
+    fn version(&self, uri: &Uri) -> Option<i32> {
+        self.open_documents.get(uri).map(|(version, _)| *version)
+    }
+
+    /// Whether this check reports on `uri`: always for files under its root,
+    /// unless it is limited to a package and another workspace member owns
+    /// the file. Files under other roots, e.g. another workspace folder, are
+    /// left to their own checks.
+    fn owns(&self, uri: &Uri) -> bool {
+        if uri_to_path(uri).is_some_and(|path| !path.starts_with(&self.root)) {
+            return false;
+        }
+        let Some(package) = &self.command.package else {
+            return true;
+        };
+        let Some(workspace) = cargo_workspace(&self.root) else {
+            return true;
+        };
+        uri_to_path(uri)
+            .and_then(|path| {
+                workspace
+                    .package_for(&path)
+                    .map(|member| member.name == *package)
+            })
+            .unwrap_or(true)
+    }
+
+    /// The text of `uri`, and whether it is open: the open document, or the
+    /// file on disk, cached in `disk`, for files that are not open.
+    #[allow(clippy::mutable_key_type)]
+    fn source<'a>(
+        &'a self,
+        uri: &Uri,
+        disk: &'a mut HashMap<Uri, Option<Arc<str>>>,
+    ) -> Option<(&'a str, bool)> {
+        if let Some((_, text)) = self.open_documents.get(uri) {
+            return Some((text, true));
+        }
+        disk.entry(uri.clone())
+            .or_insert_with(|| uri_to_path(uri).and_then(|path| file_cache::get_text(&path)))
+            .as_deref()
+            .map(|text| (text, false))
+    }
+
+    /// Re-counts the character columns rustc reports in the negotiated
+    /// position encoding and widens empty ranges in `diagnostics`.
+    #[allow(clippy::mutable_key_type)]
+    fn adjust_ranges(
+        &self,
+        uri: &Uri,
+        diagnostics: &mut [Diagnostic],
+        disk: &mut HashMap<Uri, Option<Arc<str>>>,
+    ) {
+        for diagnostic in diagnostics {
+            for info in diagnostic.related_information.iter_mut().flatten() {
+                if let Some((text, _)) = self.source(&info.location.uri, disk) {
+                    info.location.range = from_characters(text, info.location.range, self.encoding);
+                }
+            }
+            let Some((text, open)) = self.source(uri, disk) else {
+                continue;
+            };
+            let range = from_characters(text, diagnostic.range, self.encoding);
+            diagnostic.range = expand_range(range, text, open, self.encoding);
+            let fixes = diagnostic_fixes(diagnostic)
+                .into_iter()
+                .map(|mut fix| {
+                    fix.range = from_characters(text, fix.range, self.encoding);
+                    fix
+                })
+                .collect();
+            set_fixes(diagnostic, fixes);
+        }
+    }
+}
+
+fn from_characters(text: &str, range: Range, encoding: PositionEncoding) -> Range {
+    let convert = |position| {
+        convert_position(text, position, PositionEncoding::Utf32, encoding).unwrap_or(position)
+    };
+    Range {
+        start: convert(range.start),
+        end: convert(range.end),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CheckStatus {
+    Idle,
+    Running,
+    /// Another save under the root being checked arrived during the run, so
+    /// its results are dropped and one more check of the root follows it.
+    RunningWithPending,
+}
+
+struct RunnerState {
+    status: CheckStatus,
+    /// The latest job of each root saved during a run, in the order the
+    /// roots were first saved.
+    pending: Vec<CheckJob>,
+    /// The root of the running check.
+    root: Option<PathBuf>,
+    process: Option<Arc<CheckProcess>>,
+    /// Files last published with a non-empty list, cleared once they drop
+    /// out of the results.
+    published: HashSet<Uri>,
+    /// The last failure reported to the user and when, so that a broken
+    /// command is not reported again on every save.
+    last_failure: Option<(String, Instant)>,
+    last_check: Option<CheckSummary>,
+}
+
+/// How the latest check that was not superseded went.
+#[derive(Debug, Clone)]
+pub struct CheckSummary {
+    pub finished: SystemTime,
+    pub duration: Duration,
+    /// None when the command could not be started or was stopped.
+    pub exit_code: Option<i32>,
+    pub error: Option<String>,
+}
+
+/// How long an unchanged check failure stays quiet after being reported.
+const FAILURE_REPORT_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Receives a file's diagnostics and, for open files, the document version
+/// they were computed for.
+type Publish = dyn Fn(Uri, Vec<Diagnostic>, Option<i32>) + Send + Sync;
+type Notify = dyn Fn(MessageType, String) + Send + Sync;
+
+/// Runs checks one at a time on a background thread. Saves during a run
+/// kill it and coalesce into a single follow-up check.
+pub struct CheckRunner {
+    state: Mutex<RunnerState>,
+    publish: Box<Publish>,
+    /// Reports problems the user should act on, e.g. a check timing out or
+    /// failing to run.
+    notify: Box<Notify>,
+    /// The id of the latest check started. A check whose id is older drops
+    /// its results instead of overwriting fresher ones.
+    generation: AtomicU64,
+    /// The id of the latest `request_after` call; earlier ones are dropped
+    /// when their delay ends.
+    debounce: AtomicU64,
+}
+
+impl CheckRunner {
+    pub fn new(
+        publish: impl Fn(Uri, Vec<Diagnostic>, Option<i32>) + Send + Sync + 'static,
+        notify: impl Fn(MessageType, String) + Send + Sync + 'static,
+    ) -> Self {
+        CheckRunner {
+            state: Mutex::new(RunnerState {
+                status: CheckStatus::Idle,
+                pending: Vec::new(),
+                root: None,
+                process: None,
+                published: HashSet::new(),
+                last_failure: None,
+                last_check: None,
+            }),
+            publish: Box::new(publish),
+            notify: Box::new(notify),
+            generation: AtomicU64::new(0),
+            debounce: AtomicU64::new(0),
+        }
+    }
+
+    /// Starts `job` when idle. Otherwise `job` is queued, replacing any job
+    /// queued before it for the same root, and the running check is killed
+    /// when it checks that root too. Checks of other roots, e.g. another
+    /// workspace folder, finish and are published first.
+    pub fn request(self: &Arc<Self>, job: CheckJob) {
+        let mut state = self.state.lock().unwrap();
+        match state.status {
+            CheckStatus::Idle => {
+                let process = Arc::new(CheckProcess::default());
+                state.status = CheckStatus::Running;
+                state.root = Some(job.root.clone());
+                state.process = Some(Arc::clone(&process));
+                drop(state);
+                let generation = self.next_generation();
+                let runner = Arc::clone(self);
+                thread::spawn(move || runner.run(job, process, generation));
+            }
+            CheckStatus::Running | CheckStatus::RunningWithPending => {
+                let same_root = state.root.as_ref() == Some(&job.root);
+                match state
+                    .pending
+                    .iter_mut()
+                    .find(|queued| queued.root == job.root)
+                {
+                    Some(queued) => *queued = job,
+                    None => state.pending.push(job),
+                }
+                if same_root {
+                    state.status = CheckStatus::RunningWithPending;
+                    if let Some(process) = &state.process {
+                        process.kill();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Requests `job` once `delay` has passed without another call, so that
+    /// a burst of changes, like saving `Cargo.toml` and then `Cargo.lock`,
+    /// results in a single check.
+    pub fn request_after(self: &Arc<Self>, job: CheckJob, delay: Duration) {
+        let id = self.debounce.fetch_add(1, Ordering::SeqCst) + 1;
+        let runner = Arc::clone(self);
+        thread::spawn(move || {
+            thread::sleep(delay);
+            if runner.debounce.load(Ordering::SeqCst) == id {
+                runner.request(job);
+            }
+        });
+    }
+
+    #[allow(clippy::mutable_key_type)]
+    fn run(&self, mut job: CheckJob, mut process: Arc<CheckProcess>, mut generation: u64) {
+        loop {
+            log_info!(
+                "running `{}` in {}",
+                job.command.args.join(" "),
+                job.root.display()
+            );
+            let started = Instant::now();
+            let mut disk = HashMap::new();
+            let on_update = |uri: &Uri, diagnostics: &[Diagnostic]| {
+                let uri = &job.client_uri(uri.clone());
+                if !job.publishes(uri) || !self.is_current(generation) {
+                    return;
+                }
+                // Adjusting may read the file from disk, which must not hold
+                // up the main loop waiting for the lock.
+                let mut diagnostics = diagnostics.to_vec();
+                job.adjust_ranges(uri, &mut diagnostics, &mut disk);
+                let mut state = self.state.lock().unwrap();
+                if state.status != CheckStatus::RunningWithPending && self.is_current(generation) {
+                    state.published.insert(uri.clone());
+                    (self.publish)(uri.clone(), diagnostics, job.version(uri));
+                }
+            };
+            let result = run_check(
+                &job.root,
+                &job.command,
+                job.support,
+                &job.config,
+                &process,
+                on_update,
+            );
+
+            let superseded = self.state.lock().unwrap().status == CheckStatus::RunningWithPending;
+            if superseded {
+                // The results describe code that has changed since.
+                log_info!("restarting the check for newer changes");
+            } else {
+                self.finish(&job, result, &process, generation, started, &mut disk);
+            }
+
+            let mut state = self.state.lock().unwrap();
+            // A newer job for the same root goes first; checks queued for
+            // other roots follow in order.
+            let idx = state
+                .pending
+                .iter()
+                .position(|next| next.root == job.root)
+                .filter(|_| state.status == CheckStatus::RunningWithPending)
+                .unwrap_or(0);
+            if idx >= state.pending.len() {
+                state.status = CheckStatus::Idle;
+                state.root = None;
+                state.process = None;
+                return;
+            }
+            job = state.pending.remove(idx);
+            process = Arc::new(CheckProcess::default());
+            state.status = CheckStatus::Running;
+            state.root = Some(job.root.clone());
+            state.process = Some(Arc::clone(&process));
+            generation = self.next_generation();
+        }
+    }
+
+    /// Records how the check of `job` went and publishes its results.
+    #[allow(clippy::mutable_key_type)]
+    fn finish(
+        &self,
+        job: &CheckJob,
+        result: Result<HashMap<Uri, Vec<Diagnostic>>, String>,
+        process: &CheckProcess,
+        generation: u64,
+        started: Instant,
+        disk: &mut HashMap<Uri, Option<Arc<str>>>,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        if !process.is_killed() || process.timed_out() {
+            state.last_check = Some(CheckSummary {
+                finished: SystemTime::now(),
+                duration: started.elapsed(),
+                exit_code: process.exit_status().and_then(|status| status.code()),
+                error: result.as_ref().err().cloned(),
+            });
+        }
+
+        let map = match result {
+            Ok(_) if !self.is_current(generation) => {
+                log_info!("dropping the results of a check that was overtaken");
+                return;
+            }
+            Ok(map) => map,
+            Err(_) if process.timed_out() => {
+                drop(state);
+                let seconds = job.command.timeout.unwrap_or_default().as_secs();
+                log_warn!("the check timed out after {seconds} seconds");
+                (self.notify)(
+                    MessageType::WARNING,
+                    format!(
+                        "hitagi: `{}` did not finish within {} seconds and was stopped. \
+                         Diagnostics were not updated; raise `check.timeoutSeconds` if the \
+                         check needs longer.",
+                        job.command.args.join(" "),
+                        seconds
+                    ),
+                );
+                return;
+            }
+            Err(_) if process.is_killed() => {
+                log_info!("the check was stopped");
+                return;
+            }
+            Err(err) => {
+                log_error!("the check failed: {err}");
+                let now = Instant::now();
+                let repeated = state.last_failure.as_ref().is_some_and(|(last, at)| {
+                    *last == err && now.duration_since(*at) < FAILURE_REPORT_INTERVAL
+                });
+                if repeated || !self.is_current(generation) {
+                    return;
+                }
+                state.last_failure = Some((err.clone(), now));
+                drop(state);
+                (self.notify)(MessageType::ERROR, format!("hitagi: {}", err));
+                return;
+            }
+        };
+        state.last_failure = None;
+        let published: Vec<Uri> = state.published.iter().cloned().collect();
+        drop(state);
+        log_info!(
+            "the check finished in {:?} with diagnostics for {} files",
+            started.elapsed(),
+            map.len()
+        );
+        let map = job.client_results(map);
+        // A check scoped to one package leaves the results of the other
+        // packages in place. Finding the package of a file may run
+        // `cargo metadata`, so it happens without the lock.
+        let cleared = job
+            .open_documents
+            .keys()
+            .chain(&published)
+            .filter(|uri| !map.contains_key(*uri) && job.owns(uri))
+            .cloned()
+            .collect::<HashSet<_>>();
+        let mut state = self.state.lock().unwrap();
+        state.published.retain(|uri| !cleared.contains(uri));
+        state.published.extend(map.keys().cloned());
+        drop(state);
+
+        for (uri, mut diagnostics) in map {
+            job.adjust_ranges(&uri, &mut diagnostics, disk);
+            let version = job.version(&uri);
+            self.publish_current(generation, uri, diagnostics, version);
+        }
+        for uri in cleared {
+            let version = job.version(&uri);
+            self.publish_current(generation, uri, Vec::new(), version);
+        }
+    }
+
+    pub fn last_check(&self) -> Option<CheckSummary> {
+        self.state.lock().unwrap().last_check.clone()
+    }
+
+    /// Whether no check is running or queued.
+    pub fn is_idle(&self) -> bool {
+        self.state.lock().unwrap().status == CheckStatus::Idle
+    }
+
+    fn next_generation(&self) -> u64 {
+        self.generation.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    fn is_current(&self, generation: u64) -> bool {
+        self.generation.load(Ordering::SeqCst) == generation
+    }
+
+    /// Publishes unless a newer check started since, which can happen
+    /// between this check going idle and its results going out.
+    fn publish_current(
+        &self,
+        generation: u64,
+        uri: Uri,
+        diagnostics: Vec<Diagnostic>,
+        version: Option<i32>,
+    ) {
+        if self.is_current(generation) {
+            (self.publish)(uri, diagnostics, version);
+        }
+    }
+
+    /// Clears the diagnostics published for `uri`, e.g. once it is closed.
+    pub fn clear(&self, uri: &Uri) {
+        let key = NormalizedUri::new(uri);
+        let mut state = self.state.lock().unwrap();
+        let published = state
+            .published
+            .iter()
+            .find(|published| NormalizedUri::new(published) == key)
+            .cloned();
+        if let Some(published) = published {
+            state.published.remove(&published);
+            drop(state);
+            (self.publish)(published, Vec::new(), None);
+        }
+    }
+}
+
+#[cfg(all(test, not(windows)))]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::str::FromStr;
+    use std::time::{Duration, Instant};
+
+    use lsp_types::Position;
+
+    use super::*;
+    use crate::config::{CheckConfig, CheckScope};
+    use crate::diagnostics::check_command;
+    use crate::doc::store::DocumentStore;
+    use crate::test_support::temp_dir;
+
+    fn wait_until(mut condition: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn saves_during_a_run_coalesce_into_one_follow_up() {
+        let root = temp_dir("runner");
+        let log = root.join("runs.log");
+        let runs = || fs::read_to_string(&log).unwrap_or_default().lines().count();
+
+        let runner = Arc::new(CheckRunner::new(|_, _, _| {}, |_, _| {}));
+        let job = || CheckJob {
+            root: root.clone(),
+            command: [
+                "sh",
+                "-c",
+                "echo run >> runs.log; sleep 0.3",
+                "--message-format=json",
+            ]
+            .map(String::from)
+            .to_vec()
+            .into(),
+            support: ClientDiagnosticSupport::default(),
+            open_documents: HashMap::new(),
+            client_uris: HashMap::new(),
+            encoding: PositionEncoding::Utf16,
+            config: DiagnosticsConfig::default(),
+        };
+
+        runner.request(job());
+        wait_until(|| runs() == 1);
+        for _ in 0..4 {
+            runner.request(job());
+        }
+        wait_until(|| runner.state.lock().unwrap().status == CheckStatus::Idle);
+        assert_eq!(runs(), 2);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn saves_in_other_folders_queue_behind_the_running_check() {
+        let dir = temp_dir("folders");
+        let log = dir.join("runs.log");
+        let runner = Arc::new(CheckRunner::new(|_, _, _| {}, |_, _| {}));
+        let job = |folder: &str| {
+            let root = dir.join(folder);
+            fs::create_dir_all(&root).unwrap();
+            let script = format!("sleep 0.3; echo {folder} >> ../runs.log");
+            CheckJob {
+                root,
+                command: vec![
+                    "sh".to_string(),
+                    "-c".to_string(),
+                    script,
+                    "--message-format=json".to_string(),
+                ]
+                .into(),
+                support: ClientDiagnosticSupport::default(),
+                open_documents: HashMap::new(),
+                client_uris: HashMap::new(),
+                encoding: PositionEncoding::Utf16,
+                config: DiagnosticsConfig::default(),
+            }
+        };
+
+        runner.request(job("a"));
+        runner.request(job("b"));
+        runner.request(job("b"));
+        wait_until(|| runner.is_idle());
+        assert_eq!(fs::read_to_string(&log).unwrap(), "a\nb\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn delayed_requests_debounce_into_one_check() {
+        let root = temp_dir("debounce");
+        let log = root.join("runs.log");
+        let runs = || fs::read_to_string(&log).unwrap_or_default().lines().count();
+
+        let runner = Arc::new(CheckRunner::new(|_, _, _| {}, |_, _| {}));
+        let job = || CheckJob {
+            root: root.clone(),
+            command: ["sh", "-c", "echo run >> runs.log", "--message-format=json"]
+                .map(String::from)
+                .to_vec()
+                .into(),
+            support: ClientDiagnosticSupport::default(),
+            open_documents: HashMap::new(),
+            client_uris: HashMap::new(),
+            encoding: PositionEncoding::Utf16,
+            config: DiagnosticsConfig::default(),
+        };
+
+        runner.request_after(job(), Duration::from_millis(100));
+        runner.request_after(job(), Duration::from_millis(100));
+        wait_until(|| runs() == 1);
+        thread::sleep(Duration::from_millis(200));
+        wait_until(|| runner.state.lock().unwrap().status == CheckStatus::Idle);
+        assert_eq!(runs(), 1);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn files_dropping_out_of_the_results_are_cleared() {
+        let published = Arc::new(Mutex::new(Vec::new()));
+        let runner = Arc::new(CheckRunner::new(
+            {
+                let published = Arc::clone(&published);
+                move |uri, diagnostics: Vec<Diagnostic>, _| {
+                    published.lock().unwrap().push((uri, diagnostics.len()));
+                }
+            },
+            |_, _| {},
+        ));
+
+        let root = std::env::temp_dir();
+        let fixture = include_str!("fixtures/borrow_check.json");
+        let line = |file: &str| {
+            let value: serde_json::Value =
+                serde_json::from_str(&fixture.replace("src/main.rs", file)).unwrap();
+            value.to_string()
+        };
+        let job = |files: &[&str]| {
+            let mut command = vec!["printf".to_string(), "%s\\n".to_string()];
+            command.extend(files.iter().map(|file| line(file)));
+            CheckJob {
+                root: root.clone(),
+                command: command.into(),
+                support: ClientDiagnosticSupport::default(),
+                open_documents: HashMap::new(),
+                client_uris: HashMap::new(),
+                encoding: PositionEncoding::Utf16,
+                config: DiagnosticsConfig {
+                    open_files_only: false,
+                    ..DiagnosticsConfig::default()
+                },
+            }
+        };
+        let run = |files: &[&str]| {
+            published.lock().unwrap().clear();
+            runner.request(job(files));
+            wait_until(|| runner.state.lock().unwrap().status == CheckStatus::Idle);
+            let mut last = published.lock().unwrap().clone();
+            // Keep only the latest publish of each file.
+            last.reverse();
+            last.sort_by(|a, b| a.0.as_str().cmp(b.0.as_str()));
+            last.dedup_by(|later, earlier| later.0 == earlier.0);
+            last
+        };
+        let main = path_to_uri(&root.join("src/main.rs")).unwrap();
+        let lib = path_to_uri(&root.join("src/lib.rs")).unwrap();
+
+        assert_eq!(
+            run(&["src/main.rs", "src/lib.rs"]),
+            [(lib.clone(), 1), (main.clone(), 1)]
+        );
+        assert_eq!(run(&["src/main.rs"]), [(lib.clone(), 0), (main.clone(), 1)]);
+
+        runner.clear(&main);
+        assert_eq!(published.lock().unwrap().last(), Some(&(main, 0)));
+    }
+
+    #[test]
+    fn timed_out_checks_warn_and_go_idle() {
+        let warnings = Arc::new(Mutex::new(Vec::new()));
+        let runner = Arc::new(CheckRunner::new(|_, _, _| {}, {
+            let warnings = Arc::clone(&warnings);
+            move |_, message| warnings.lock().unwrap().push(message)
+        }));
+        let command = CheckCommand {
+            timeout: Some(Duration::from_millis(100)),
+            ..["sh", "-c", "sleep 5", "--message-format=json"]
+                .map(String::from)
+                .to_vec()
+                .into()
+        };
+        runner.request(CheckJob {
+            root: std::env::temp_dir(),
+            command,
+            support: ClientDiagnosticSupport::default(),
+            open_documents: HashMap::new(),
+            client_uris: HashMap::new(),
+            encoding: PositionEncoding::Utf16,
+            config: DiagnosticsConfig::default(),
+        });
+        wait_until(|| runner.state.lock().unwrap().status == CheckStatus::Idle);
+        let warnings = warnings.lock().unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("`sh -c sleep 5 --message-format=json` did not finish"));
+    }
+
+    #[test]
+    fn failures_are_reported_once_until_a_check_succeeds() {
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let runner = Arc::new(CheckRunner::new(|_, _, _| {}, {
+            let messages = Arc::clone(&messages);
+            move |typ, message| messages.lock().unwrap().push((typ, message))
+        }));
+        let job = |args: &[&str]| CheckJob {
+            root: std::env::temp_dir(),
+            command: args
+                .iter()
+                .map(|arg| arg.to_string())
+                .collect::<Vec<_>>()
+                .into(),
+            support: ClientDiagnosticSupport::default(),
+            open_documents: HashMap::new(),
+            client_uris: HashMap::new(),
+            encoding: PositionEncoding::Utf16,
+            config: DiagnosticsConfig::default(),
+        };
+        let run = |args: &[&str]| {
+            runner.request(job(args));
+            wait_until(|| runner.state.lock().unwrap().status == CheckStatus::Idle);
+        };
+
+        run(&["carg", "check"]);
+        run(&["carg", "check"]);
+        assert_eq!(messages.lock().unwrap().len(), 1);
+        run(&["true"]);
+        let last = runner.last_check().unwrap();
+        assert_eq!((last.exit_code, last.error), (Some(0), None));
+        run(&["carg", "check"]);
+        let last = runner.last_check().unwrap();
+        assert_eq!(last.exit_code, None);
+        assert!(last.error.unwrap().starts_with("could not run `carg`"));
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 2);
+        assert!(messages.iter().all(|(typ, message)| {
+            *typ == MessageType::ERROR && message.starts_with("hitagi: could not run `carg`")
+        }));
+    }
+
+    #[test]
+    fn package_scoped_checks_keep_other_packages_results() {
+        let root = temp_dir("scope");
+        fs::write(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"a\", \"b\"]\nresolver = \"2\"\n",
+        )
+        .unwrap();
+        for name in ["a", "b"] {
+            fs::create_dir_all(root.join(name).join("src")).unwrap();
+            fs::write(
+                root.join(name).join("Cargo.toml"),
+                format!(
+                    "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"2024\"\n",
+                    name
+                ),
+            )
+            .unwrap();
+            fs::write(root.join(name).join("src/lib.rs"), "").unwrap();
+        }
+
+        let published = Arc::new(Mutex::new(Vec::new()));
+        let runner = Arc::new(CheckRunner::new(
+            {
+                let published = Arc::clone(&published);
+                move |uri: Uri, diagnostics: Vec<Diagnostic>, _| {
+                    published.lock().unwrap().push((uri, diagnostics.len()));
+                }
+            },
+            |_, _| {},
+        ));
+        let fixture = include_str!("fixtures/borrow_check.json");
+        let line = |file: &str| {
+            let value: serde_json::Value =
+                serde_json::from_str(&fixture.replace("src/main.rs", file)).unwrap();
+            value.to_string()
+        };
+        let job = |command: CheckCommand| CheckJob {
+            root: root.clone(),
+            command,
+            support: ClientDiagnosticSupport::default(),
+            open_documents: HashMap::new(),
+            client_uris: HashMap::new(),
+            encoding: PositionEncoding::Utf16,
+            config: DiagnosticsConfig {
+                open_files_only: false,
+                ..DiagnosticsConfig::default()
+            },
+        };
+        let run = |command: CheckCommand| {
+            published.lock().unwrap().clear();
+            runner.request(job(command));
+            wait_until(|| runner.state.lock().unwrap().status == CheckStatus::Idle);
+            published.lock().unwrap().clone()
+        };
+        let a = path_to_uri(&root.join("a/src/lib.rs")).unwrap();
+        let b = path_to_uri(&root.join("b/src/lib.rs")).unwrap();
+
+        let mut command = vec!["printf".to_string(), "%s\\n".to_string()];
+        command.extend([line("a/src/lib.rs"), line("b/src/lib.rs")]);
+        run(command.into());
+
+        let check = CheckConfig {
+            scope: CheckScope::Package,
+            ..CheckConfig::default()
+        };
+        let scoped = check_command(None, &check, &root, Some(&root.join("a/src/lib.rs")));
+        assert_eq!(scoped.args[scoped.args.len() - 2..], ["-p", "a"]);
+        // Package `a` is clean now; `b` was not checked.
+        let cleared = run(CheckCommand {
+            args: vec!["true".to_string()],
+            ..scoped
+        });
+        assert_eq!(cleared, [(a.clone(), 0)]);
+        assert_eq!(
+            runner.state.lock().unwrap().published,
+            HashSet::from([b.clone()])
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn stale_checks_do_not_overwrite_newer_results() {
+        let published = Arc::new(Mutex::new(Vec::new()));
+        let runner = Arc::new(CheckRunner::new(
+            {
+                let published = Arc::clone(&published);
+                move |uri: Uri, diagnostics: Vec<Diagnostic>, _| {
+                    published
+                        .lock()
+                        .unwrap()
+                        .push((uri.path().to_string(), diagnostics.len()));
+                }
+            },
+            |_, _| {},
+        ));
+
+        let root = std::env::temp_dir();
+        let fixture = include_str!("fixtures/borrow_check.json");
+        let job = |file: &str, delay: &str| {
+            let value: serde_json::Value =
+                serde_json::from_str(&fixture.replace("src/main.rs", file)).unwrap();
+            let script = format!("sleep {}; printf '%s\\n' \"$LINE\"", delay);
+            CheckJob {
+                root: root.clone(),
+                command: CheckCommand {
+                    env: vec![("LINE".to_string(), value.to_string())],
+                    ..vec![
+                        "sh".to_string(),
+                        "-c".to_string(),
+                        script,
+                        "--message-format=json".to_string(),
+                    ]
+                    .into()
+                },
+                support: ClientDiagnosticSupport::default(),
+                open_documents: HashMap::new(),
+                client_uris: HashMap::new(),
+                encoding: PositionEncoding::Utf16,
+                config: DiagnosticsConfig {
+                    open_files_only: false,
+                    ..DiagnosticsConfig::default()
+                },
+            }
+        };
+
+        // Two checks racing on detached threads: the older one is slower and
+        // finishes last.
+        let older = runner.next_generation();
+        let newer = runner.next_generation();
+        let slow = thread::spawn({
+            let runner = Arc::clone(&runner);
+            let job = job("src/slow.rs", "0.3");
+            move || runner.run(job, Arc::new(CheckProcess::default()), older)
+        });
+        let fast = thread::spawn({
+            let runner = Arc::clone(&runner);
+            let job = job("src/fast.rs", "0");
+            move || runner.run(job, Arc::new(CheckProcess::default()), newer)
+        });
+        fast.join().unwrap();
+        slow.join().unwrap();
+
+        let published = published.lock().unwrap();
+        let fast = root.join("src/fast.rs");
+        assert!(!published.is_empty());
+        assert!(
+            published
+                .iter()
+                .all(|(path, _)| Path::new(path) == fast.as_path())
+        );
+    }
+
+    #[test]
+    fn empty_spans_are_expanded_before_publishing() {
+        let published = Arc::new(Mutex::new(HashMap::new()));
+        let runner = Arc::new(CheckRunner::new(
+            {
+                let published = Arc::clone(&published);
+                move |uri: Uri, diagnostics: Vec<Diagnostic>, version| {
+                    let ranges: Vec<Range> = diagnostics.iter().map(|diag| diag.range).collect();
+                    published.lock().unwrap().insert(uri, (version, ranges));
+                }
+            },
+            |_, _| {},
+        ));
+
+        let root = temp_dir("ranges");
+        fs::create_dir_all(root.join("src")).unwrap();
+        let text = "fn main() {\n    let unused = 1;\n}\n";
+        fs::write(root.join("src/lib.rs"), text).unwrap();
+
+        let fixture = include_str!("fixtures/unused_variable.json");
+        let line = |file: &str| {
+            let value: serde_json::Value =
+                serde_json::from_str(&fixture.replace("src/main.rs", file)).unwrap();
+            value.to_string()
+        };
+        let main = path_to_uri(&root.join("src/main.rs")).unwrap();
+        let lib = path_to_uri(&root.join("src/lib.rs")).unwrap();
+        let mut command = vec!["printf".to_string(), "%s\\n".to_string()];
+        command.extend([line("src/main.rs"), line("src/lib.rs")]);
+        runner.request(CheckJob {
+            root: root.clone(),
+            command: command.into(),
+            support: ClientDiagnosticSupport::default(),
+            open_documents: HashMap::from([(main.clone(), (3, text.to_string()))]),
+            client_uris: HashMap::new(),
+            encoding: PositionEncoding::Utf16,
+            config: DiagnosticsConfig {
+                open_files_only: false,
+                ..DiagnosticsConfig::default()
+            },
+        });
+        wait_until(|| runner.state.lock().unwrap().status == CheckStatus::Idle);
+
+        let range = |start: u32, end: u32| Range {
+            start: Position::new(1, start),
+            end: Position::new(1, end),
+        };
+        let published = published.lock().unwrap();
+        // The open document covers the identifier; the file on disk falls
+        // back to the rest of the line.
+        // Only open documents have a version to report.
+        assert_eq!(published[&main], (Some(3), vec![range(8, 14)]));
+        assert_eq!(published[&lib], (None, vec![range(8, 19)]));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn diagnostics_follow_documents_opened_through_a_symlink() {
+        let published = Arc::new(Mutex::new(HashMap::new()));
+        let runner = Arc::new(CheckRunner::new(
+            {
+                let published = Arc::clone(&published);
+                move |uri: Uri, diagnostics: Vec<Diagnostic>, version| {
+                    published
+                        .lock()
+                        .unwrap()
+                        .insert(uri, (version, diagnostics.len()));
+                }
+            },
+            |_, _| {},
+        ));
+
+        let dir = temp_dir("symlink");
+        let real = dir.join("storage/work");
+        let link = dir.join("work");
+        fs::create_dir_all(real.join("src")).unwrap();
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+        let text = "fn main() {\n    let unused = 1;\n}\n";
+        fs::write(real.join("src/main.rs"), text).unwrap();
+
+        let uri = path_to_uri(&link.join("src/main.rs")).unwrap();
+        let mut docs = DocumentStore::new();
+        docs.open(lsp_types::TextDocumentItem {
+            uri: uri.clone(),
+            language_id: "rust".to_string(),
+            version: 2,
+            text: text.to_string(),
+        });
+        let fixture: serde_json::Value =
+            serde_json::from_str(include_str!("fixtures/unused_variable.json")).unwrap();
+        // cargo reports paths below the real workspace root.
+        runner.request(CheckJob {
+            root: real.clone(),
+            command: vec![
+                "printf".to_string(),
+                "%s\\n".to_string(),
+                fixture.to_string(),
+            ]
+            .into(),
+            support: ClientDiagnosticSupport::default(),
+            open_documents: docs.open_texts(),
+            client_uris: docs.client_uris(),
+            encoding: PositionEncoding::Utf16,
+            config: DiagnosticsConfig::default(),
+        });
+        wait_until(|| runner.state.lock().unwrap().status == CheckStatus::Idle);
+
+        let published = published.lock().unwrap();
+        assert_eq!(published.get(&uri), Some(&(Some(2), 1)), "{published:?}");
+        assert_eq!(published.len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn diagnostics_use_the_client_spelling_of_open_documents() {
+        let client = Uri::from_str("file://localhost/project/src/%6Dain.rs").unwrap();
+        let mut docs = DocumentStore::new();
+        docs.open(lsp_types::TextDocumentItem {
+            uri: client.clone(),
+            language_id: "rust".to_string(),
+            version: 1,
+            text: String::new(),
+        });
+        let job = CheckJob {
+            root: PathBuf::from("/project"),
+            command: Vec::new().into(),
+            support: ClientDiagnosticSupport::default(),
+            open_documents: docs.open_texts(),
+            client_uris: docs.client_uris(),
+            encoding: PositionEncoding::Utf16,
+            config: DiagnosticsConfig::default(),
+        };
+        let reported = path_to_uri(Path::new("/project/src/main.rs")).unwrap();
+        assert_eq!(job.client_uri(reported), client);
+        let other = path_to_uri(Path::new("/project/src/lib.rs")).unwrap();
+        assert_eq!(job.client_uri(other.clone()), other);
+    }
+
+    #[test]
+    fn character_columns_follow_the_negotiated_encoding() {
+        let uri = path_to_uri(Path::new("/project/src/main.rs")).unwrap();
+        let text = "fn main() {\n    let s = \"😀é\"; let unused = 1;\n}\n";
+        let chars = text.lines().nth(1).unwrap().chars().count();
+        let col = chars as u32 - "unused = 1;".chars().count() as u32;
+        let job = |encoding| CheckJob {
+            root: PathBuf::from("/project"),
+            command: Vec::new().into(),
+            support: ClientDiagnosticSupport::default(),
+            open_documents: HashMap::from([(uri.clone(), (1, text.to_string()))]),
+            client_uris: HashMap::new(),
+            encoding,
+            config: DiagnosticsConfig::default(),
+        };
+
+        // rustc counts `😀` and `é` as one column each.
+        for (encoding, extra) in [
+            (PositionEncoding::Utf32, 0),
+            (PositionEncoding::Utf16, 1),
+            (PositionEncoding::Utf8, 4),
+        ] {
+            let mut diagnostics = [Diagnostic {
+                range: Range::new(Position::new(1, col), Position::new(1, col)),
+                ..Diagnostic::default()
+            }];
+            job(encoding).adjust_ranges(&uri, &mut diagnostics, &mut HashMap::new());
+            assert_eq!(
+                diagnostics[0].range,
+                Range::new(
+                    Position::new(1, col + extra),
+                    Position::new(1, col + extra + 6)
+                )
+            );
+        }
+    }
+}
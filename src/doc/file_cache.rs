@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::SystemTime;
+
+/// Source text read from disk, shared by every feature that looks at files
+/// that are not open.
+static FILES: OnceLock<Mutex<FileCache>> = OnceLock::new();
+
+/// How much text the shared cache keeps before dropping the least recently
+/// used files.
+const MAX_CACHED_BYTES: usize = 64 * 1024 * 1024;
+
+/// Returns the text of `path`, reading it only when it is not cached or has
+/// changed on disk since.
+pub fn get_text(path: &Path) -> Option<Arc<str>> {
+    shared().lock().unwrap().get_text(path)
+}
+
+/// Drops the cached text of `path`, e.g. once the client reports a change.
+pub fn evict(path: &Path) {
+    shared().lock().unwrap().evict(path);
+}
+
+/// Drops every cached text, e.g. once files on disk are no longer looked at.
+pub fn clear() {
+    shared().lock().unwrap().clear();
+}
+
+/// Whether the text of `path` is cached, i.e. it was read and not dropped.
+pub fn contains(path: &Path) -> bool {
+    shared().lock().unwrap().entries.contains_key(path)
+}
+
+fn shared() -> &'static Mutex<FileCache> {
+    FILES.get_or_init(|| Mutex::new(FileCache::new(MAX_CACHED_BYTES)))
+}
+
+/// File contents keyed by path, invalidated when the modification time or
+/// size changes, and capped at `capacity` bytes with LRU eviction.
+#[derive(Debug)]
+pub struct FileCache {
+    entries: HashMap<PathBuf, Entry>,
+    capacity: usize,
+    bytes: usize,
+    /// Incremented on every access to order entries by recency.
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    text: Arc<str>,
+    modified: Option<SystemTime>,
+    len: u64,
+    last_used: u64,
+}
+
+impl FileCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            capacity,
+            bytes: 0,
+            clock: 0,
+        }
+    }
+
+    pub fn get_text(&mut self, path: &Path) -> Option<Arc<str>> {
+        self.clock += 1;
+        let Ok(meta) = fs::metadata(path) else {
+            self.evict(path);
+            return None;
+        };
+        let modified = meta.modified().ok();
+        if let Some(entry) = self.entries.get_mut(path)
+            && entry.modified == modified
+            && entry.len == meta.len()
+        {
+            entry.last_used = self.clock;
+            return Some(entry.text.clone());
+        }
+
+        self.evict(path);
+        let text: Arc<str> = match fs::read_to_string(path) {
+            Ok(text) => text.into(),
+            Err(err) => {
+                log_debug!("cannot read {}: {err}", path.display());
+                return None;
+            }
+        };
+        if text.len() <= self.capacity {
+            self.make_room(text.len());
+            self.bytes += text.len();
+            self.entries.insert(
+                path.to_path_buf(),
+                Entry {
+                    text: text.clone(),
+                    modified,
+                    len: meta.len(),
+                    last_used: self.clock,
+                },
+            );
+        }
+        Some(text)
+    }
+
+    pub fn evict(&mut self, path: &Path) {
+        if let Some(entry) = self.entries.remove(path) {
+            self.bytes -= entry.text.len();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.bytes = 0;
+    }
+
+    /// Drops the least recently used entries until `len` more bytes fit.
+    fn make_room(&mut self, len: usize) {
+        while self.bytes + len > self.capacity {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(path, _)| path.clone())
+            else {
+                return;
+            };
+            self.evict(&oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    #[test]
+    fn reads_are_cached_until_the_file_changes() {
+        let dir = temp_dir("files-changes");
+        let file = dir.join("lib.rs");
+        fs::write(&file, "fn a() {}").unwrap();
+        let mut cache = FileCache::new(1024);
+
+        let first = cache.get_text(&file).unwrap();
+        let second = cache.get_text(&file).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+
+        fs::write(&file, "fn changed() {}").unwrap();
+        assert_eq!(&*cache.get_text(&file).unwrap(), "fn changed() {}");
+
+        let cached = cache.get_text(&file).unwrap();
+        cache.evict(&file);
+        assert!(!Arc::ptr_eq(&cached, &cache.get_text(&file).unwrap()));
+
+        fs::remove_file(&file).unwrap();
+        assert!(cache.get_text(&file).is_none());
+        assert_eq!(cache.bytes, 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn least_recently_used_files_are_dropped_over_capacity() {
+        let dir = temp_dir("files-lru");
+        let [a, b, c, big] = ["a.rs", "b.rs", "c.rs", "big.rs"].map(|name| dir.join(name));
+        for file in [&a, &b, &c] {
+            fs::write(file, "0123456789").unwrap();
+        }
+        fs::write(&big, "x".repeat(100)).unwrap();
+        let mut cache = FileCache::new(25);
+
+        cache.get_text(&a).unwrap();
+        cache.get_text(&b).unwrap();
+        // Using `a` again makes `b` the oldest.
+        cache.get_text(&a).unwrap();
+        cache.get_text(&c).unwrap();
+        assert!(cache.entries.contains_key(&a));
+        assert!(!cache.entries.contains_key(&b));
+        assert!(cache.entries.contains_key(&c));
+        assert_eq!(cache.bytes, 20);
+
+        // Files larger than the whole cache are returned but not kept.
+        assert_eq!(cache.get_text(&big).unwrap().len(), 100);
+        assert!(!cache.entries.contains_key(&big));
+        assert_eq!(cache.bytes, 20);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+
+use lsp_types::Position;
+
+use super::position::PositionEncoding;
+
+/// Line starts and the multi-byte characters of a text, so that positions
+/// can be converted without walking the text from the beginning.
+#[derive(Debug, Clone)]
+pub struct LineIndex {
+    /// Byte offset of each line start; the first is always 0.
+    line_starts: Vec<usize>,
+    /// The non-ASCII characters of each line that has any, in order.
+    wide_chars: HashMap<u32, Vec<WideChar>>,
+    len: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct WideChar {
+    /// Byte offset from the start of the line.
+    start: usize,
+    ch: char,
+}
+
+impl LineIndex {
+    pub fn new(text: &str) -> Self {
+        let mut line_starts = vec![0];
+        let mut wide_chars: HashMap<u32, Vec<WideChar>> = HashMap::new();
+        let mut line_start = 0;
+        for (idx, ch) in text.char_indices() {
+            if ch == '\n' {
+                line_start = idx + 1;
+                line_starts.push(line_start);
+            } else if !ch.is_ascii() {
+                let line = (line_starts.len() - 1) as u32;
+                wide_chars.entry(line).or_default().push(WideChar {
+                    start: idx - line_start,
+                    ch,
+                });
+            }
+        }
+        Self {
+            line_starts,
+            wide_chars,
+            len: text.len(),
+        }
+    }
+
+    /// Same as `position::offset_to_position` for the indexed text.
+    pub fn offset_to_position(
+        &self,
+        offset: usize,
+        encoding: PositionEncoding,
+    ) -> Option<Position> {
+        if offset > self.len {
+            return None;
+        }
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let col = offset - self.line_starts[line];
+        let mut units = col as u32;
+        for wide in self.line_chars(line as u32) {
+            if wide.start >= col {
+                break;
+            }
+            let counted = (wide.ch.len_utf8()).min(col - wide.start);
+            units = units - counted as u32 + encoding.units(wide.ch);
+        }
+        Some(Position {
+            line: line as u32,
+            character: units,
+        })
+    }
+
+    /// Converts a position sent by the client, clamping it to the text:
+    /// columns inside a character snap to its start, columns past the end of
+    /// the line to the line end, and lines past the last line to the end of
+    /// the text. `position::position_to_offset` is the strict variant.
+    pub fn position_to_offset(&self, position: Position, encoding: PositionEncoding) -> usize {
+        let Some(&line_start) = self.line_starts.get(position.line as usize) else {
+            return self.len;
+        };
+        let line_end = self
+            .line_starts
+            .get(position.line as usize + 1)
+            .map_or(self.len, |next| next - 1);
+        let col = position.character;
+        let mut bytes = 0usize;
+        let mut units = 0u32;
+        for wide in self.line_chars(position.line) {
+            let ascii = (wide.start - bytes) as u32;
+            if col <= units + ascii {
+                return line_start + bytes + (col - units) as usize;
+            }
+            units += ascii + encoding.units(wide.ch);
+            if units > col {
+                return line_start + wide.start;
+            }
+            bytes = wide.start + wide.ch.len_utf8();
+        }
+        let rest = line_end - line_start - bytes;
+        line_start + bytes + rest.min((col - units) as usize)
+    }
+
+    fn line_chars(&self, line: u32) -> &[WideChar] {
+        self.wide_chars.get(&line).map_or(&[], Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::doc::position::{offset_to_position, position_to_offset};
+
+    const ENCODINGS: [PositionEncoding; 3] = [
+        PositionEncoding::Utf8,
+        PositionEncoding::Utf16,
+        PositionEncoding::Utf32,
+    ];
+
+    /// Lines mixing ASCII with two-, three- and four-byte characters, empty
+    /// lines and a missing final newline.
+    fn generated() -> String {
+        let pieces = ["fn", " ", "é", "😀", "\n", "中", "x", "\n\n", "𝔘", "\r\n"];
+        let mut seed = 7u64;
+        let mut text = String::new();
+        for _ in 0..2000 {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            text.push_str(pieces[(seed >> 33) as usize % pieces.len()]);
+        }
+        text.push_str("end");
+        text
+    }
+
+    #[test]
+    fn matches_the_free_functions_on_generated_text() {
+        let text = generated();
+        let index = LineIndex::new(&text);
+        for encoding in ENCODINGS {
+            for offset in 0..=text.len() + 1 {
+                assert_eq!(
+                    index.offset_to_position(offset, encoding),
+                    offset_to_position(&text, offset, encoding),
+                    "offset {offset} in {encoding:?}"
+                );
+            }
+            let lines = text.split('\n').count() as u32;
+            for line in 0..=lines {
+                for character in 0..40 {
+                    let position = Position::new(line, character);
+                    assert_eq!(
+                        index.position_to_offset(position, encoding),
+                        position_to_offset(&text, position, encoding).unwrap_or(text.len()),
+                        "{position:?} in {encoding:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn handles_empty_text() {
+        let index = LineIndex::new("");
+        assert_eq!(
+            index.offset_to_position(0, PositionEncoding::Utf16),
+            Some(Position::new(0, 0))
+        );
+        assert_eq!(
+            index.position_to_offset(Position::new(0, 3), PositionEncoding::Utf16),
+            0
+        );
+        assert_eq!(
+            index.position_to_offset(Position::new(1, 0), PositionEncoding::Utf16),
+            0
+        );
+    }
+
+    #[test]
+    fn client_positions_clamp_to_the_text() {
+        let text = "fn main() {}\nlet é = 1;\n";
+        let index = LineIndex::new(text);
+        let offset = |line, character| {
+            index.position_to_offset(Position::new(line, character), PositionEncoding::Utf16)
+        };
+        // Past the end of a line: the line end, before the newline.
+        assert_eq!(offset(0, 200), text.find('\n').unwrap());
+        assert_eq!(offset(1, 200), text.len() - 1);
+        // The empty line after the final newline, and lines past it.
+        assert_eq!(offset(2, 0), text.len());
+        assert_eq!(offset(2, 5), text.len());
+        assert_eq!(offset(3, 0), text.len());
+        assert_eq!(offset(999_999, 0), text.len());
+        assert_eq!(offset(u32::MAX, u32::MAX), text.len());
+
+        // The strict variant rejects lines that do not exist.
+        let strict =
+            |line| position_to_offset(text, Position::new(line, 0), PositionEncoding::Utf16);
+        assert_eq!(strict(2), Some(text.len()));
+        assert_eq!(strict(3), None);
+    }
+}
@@ -1,3 +1,7 @@
+pub mod file_cache;
+pub mod line_index;
 pub mod position;
+pub mod rope;
 pub mod store;
 pub mod uri;
+pub mod workspace;
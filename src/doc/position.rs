@@ -1,10 +1,60 @@
-use lsp_types::Position;
+use lsp_types::{ClientCapabilities, Position, PositionEncodingKind};
 
-pub fn position_to_offset(text: &str, position: Position) -> Option<usize> {
+/// The unit `Position::character` counts, negotiated with the client.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PositionEncoding {
+    Utf8,
+    #[default]
+    Utf16,
+    /// Characters, as in rustc's span columns.
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// Picks UTF-8 when the client offers it, and otherwise UTF-16, which
+    /// every client supports.
+    pub fn negotiate(caps: &ClientCapabilities) -> Self {
+        let offered = caps
+            .general
+            .as_ref()
+            .and_then(|general| general.position_encodings.as_deref())
+            .unwrap_or_default();
+        if offered.contains(&PositionEncodingKind::UTF8) {
+            PositionEncoding::Utf8
+        } else {
+            PositionEncoding::Utf16
+        }
+    }
+
+    pub fn kind(self) -> PositionEncodingKind {
+        match self {
+            PositionEncoding::Utf8 => PositionEncodingKind::UTF8,
+            PositionEncoding::Utf16 => PositionEncodingKind::UTF16,
+            PositionEncoding::Utf32 => PositionEncodingKind::UTF32,
+        }
+    }
+
+    /// How many units `ch` takes up.
+    pub fn units(self, ch: char) -> u32 {
+        match self {
+            PositionEncoding::Utf8 => ch.len_utf8() as u32,
+            PositionEncoding::Utf16 => ch.len_utf16() as u32,
+            PositionEncoding::Utf32 => 1,
+        }
+    }
+}
+
+/// Returns None for a line past the end of `text`; positions from the
+/// client go through `LineIndex::position_to_offset`, which clamps instead.
+pub fn position_to_offset(
+    text: &str,
+    position: Position,
+    encoding: PositionEncoding,
+) -> Option<usize> {
     let mut line_start = 0usize;
     for (idx, line) in text.split('\n').enumerate() {
         if idx as u32 == position.line {
-            let offset_in_line = utf16_col_to_byte_offset(line, position.character);
+            let offset_in_line = col_to_byte_offset(line, position.character, encoding);
             return Some(line_start + offset_in_line);
         }
         line_start += line.len() + 1;
@@ -13,14 +63,14 @@ pub fn position_to_offset(text: &str, position: Position) -> Option<usize> {
     None
 }
 
-fn utf16_col_to_byte_offset(line: &str, col: u32) -> usize {
-    let mut utf16_units = 0u32;
+fn col_to_byte_offset(line: &str, col: u32, encoding: PositionEncoding) -> usize {
+    let mut units = 0u32;
     for (byte_idx, ch) in line.char_indices() {
-        if utf16_units >= col {
+        if units >= col {
             return byte_idx;
         }
-        utf16_units += ch.len_utf16() as u32;
-        if utf16_units > col {
+        units += encoding.units(ch);
+        if units > col {
             return byte_idx;
         }
     }
@@ -28,6 +78,8 @@ fn utf16_col_to_byte_offset(line: &str, col: u32) -> usize {
     line.len()
 }
 
+/// Converts a 1-based rustc span line and column, which counts characters,
+/// to a position in characters; see `convert_position`.
 pub fn lsp_position_from_span(line: u32, column: u32) -> Position {
     Position {
         line: line.saturating_sub(1),
@@ -35,7 +87,11 @@ pub fn lsp_position_from_span(line: u32, column: u32) -> Position {
     }
 }
 
-pub fn offset_to_position(text: &str, offset: usize) -> Option<Position> {
+pub fn offset_to_position(
+    text: &str,
+    offset: usize,
+    encoding: PositionEncoding,
+) -> Option<Position> {
     if offset > text.len() {
         return None;
     }
@@ -50,7 +106,7 @@ pub fn offset_to_position(text: &str, offset: usize) -> Option<Position> {
             line = line.saturating_add(1);
             col = 0;
         } else {
-            col = col.saturating_add(ch.len_utf16() as u32);
+            col = col.saturating_add(encoding.units(ch));
         }
     }
 
@@ -59,3 +115,87 @@ pub fn offset_to_position(text: &str, offset: usize) -> Option<Position> {
         character: col,
     })
 }
+
+/// Re-counts the column of `position` from `from` units in `to` units.
+pub fn convert_position(
+    text: &str,
+    position: Position,
+    from: PositionEncoding,
+    to: PositionEncoding,
+) -> Option<Position> {
+    if from == to {
+        return Some(position);
+    }
+    offset_to_position(text, position_to_offset(text, position, from)?, to)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `😀` is four UTF-8 bytes and two UTF-16 units, `é` two bytes and one
+    // unit.
+    const TEXT: &str = "fn main() {\n    let s = \"😀é\"; call();\n}\n";
+
+    #[test]
+    fn emoji_columns_follow_the_encoding() {
+        let call = TEXT.find("call").unwrap();
+        let line_start = TEXT.find("    let").unwrap();
+        let bytes = (call - line_start) as u32;
+        let cases = [
+            (PositionEncoding::Utf8, bytes),
+            (PositionEncoding::Utf16, bytes - 3),
+            (PositionEncoding::Utf32, bytes - 4),
+        ];
+        for (encoding, col) in cases {
+            let position = Position::new(1, col);
+            assert_eq!(offset_to_position(TEXT, call, encoding), Some(position));
+            assert_eq!(position_to_offset(TEXT, position, encoding), Some(call));
+        }
+
+        let utf32 = Position::new(1, bytes - 4);
+        assert_eq!(
+            convert_position(TEXT, utf32, PositionEncoding::Utf32, PositionEncoding::Utf8),
+            Some(Position::new(1, bytes))
+        );
+    }
+
+    #[test]
+    fn columns_inside_a_character_snap_to_its_start() {
+        let emoji = TEXT.find('😀').unwrap();
+        let line_start = TEXT.find("    let").unwrap();
+        let col = (emoji - line_start) as u32;
+        for inside in 1..4 {
+            let position = Position::new(1, col + inside);
+            assert_eq!(
+                position_to_offset(TEXT, position, PositionEncoding::Utf8),
+                Some(emoji)
+            );
+        }
+        assert_eq!(
+            position_to_offset(TEXT, Position::new(1, col + 1), PositionEncoding::Utf16),
+            Some(emoji)
+        );
+    }
+
+    #[test]
+    fn utf8_is_negotiated_when_offered() {
+        let mut caps = ClientCapabilities::default();
+        assert_eq!(PositionEncoding::negotiate(&caps), PositionEncoding::Utf16);
+
+        caps.general = Some(lsp_types::GeneralClientCapabilities {
+            position_encodings: Some(vec![PositionEncodingKind::UTF32]),
+            ..Default::default()
+        });
+        assert_eq!(PositionEncoding::negotiate(&caps), PositionEncoding::Utf16);
+
+        caps.general = Some(lsp_types::GeneralClientCapabilities {
+            position_encodings: Some(vec![
+                PositionEncodingKind::UTF16,
+                PositionEncodingKind::UTF8,
+            ]),
+            ..Default::default()
+        });
+        assert_eq!(PositionEncoding::negotiate(&caps), PositionEncoding::Utf8);
+    }
+}
@@ -0,0 +1,306 @@
+use std::fmt;
+use std::ops::Range;
+
+use lsp_types::Position;
+
+use super::position::PositionEncoding;
+
+/// Chunks are split once they grow past twice this size and merged with a
+/// neighbour once they shrink below half of it.
+const CHUNK_SIZE: usize = 4 * 1024;
+
+/// Text stored as a list of chunks of a few KB, so that an edit only copies
+/// the chunks it touches instead of the whole tail of the text.
+#[derive(Debug, Clone, Default)]
+pub struct Rope {
+    chunks: Vec<Chunk>,
+    len: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Chunk {
+    text: String,
+    newlines: usize,
+}
+
+impl Chunk {
+    fn new(text: String) -> Self {
+        Self {
+            newlines: count_newlines(&text),
+            text,
+        }
+    }
+
+    fn update(&mut self) {
+        self.newlines = count_newlines(&self.text);
+    }
+}
+
+impl Rope {
+    pub fn new(text: &str) -> Self {
+        let mut rope = Rope::default();
+        rope.insert(0, text);
+        rope
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = &str> {
+        self.chunks.iter().map(|chunk| chunk.text.as_str())
+    }
+
+    /// Inserts `text` at byte `offset`, which must be a char boundary.
+    pub fn insert(&mut self, offset: usize, text: &str) {
+        assert!(offset <= self.len, "offset {offset} is past the end");
+        if text.is_empty() {
+            return;
+        }
+        if self.chunks.is_empty() {
+            self.chunks.push(Chunk::new(String::new()));
+        }
+        let (idx, local) = self.locate(offset);
+        let chunk = &mut self.chunks[idx];
+        chunk.text.insert_str(local, text);
+        chunk.update();
+        self.len += text.len();
+        self.split(idx);
+    }
+
+    /// Removes the bytes in `range`, whose ends must be char boundaries.
+    pub fn remove(&mut self, range: Range<usize>) {
+        assert!(
+            range.start <= range.end && range.end <= self.len,
+            "range {range:?} is out of bounds"
+        );
+        if range.is_empty() {
+            return;
+        }
+        let mut start = 0;
+        let mut first = None;
+        for (idx, chunk) in self.chunks.iter_mut().enumerate() {
+            let end = start + chunk.text.len();
+            if end > range.start && start < range.end {
+                let from = range.start.max(start) - start;
+                let to = range.end.min(end) - start;
+                chunk.text.replace_range(from..to, "");
+                chunk.update();
+                first.get_or_insert(idx);
+            }
+            if end >= range.end {
+                break;
+            }
+            start = end;
+        }
+        self.len -= range.len();
+        self.chunks.retain(|chunk| !chunk.text.is_empty());
+        if let Some(idx) = first {
+            self.merge(idx.min(self.chunks.len().saturating_sub(1)));
+        }
+    }
+
+    /// Converts a position sent by the client to a byte offset, clamping it
+    /// like `LineIndex::position_to_offset`, without materializing the text.
+    pub fn position_to_offset(&self, position: Position, encoding: PositionEncoding) -> usize {
+        let Some(line_start) = self.line_start(position.line as usize) else {
+            return self.len;
+        };
+        let mut units = 0u32;
+        let mut offset = line_start;
+        for ch in self.chars_from(line_start) {
+            if ch == '\n' || units >= position.character {
+                break;
+            }
+            units += encoding.units(ch);
+            if units > position.character {
+                break;
+            }
+            offset += ch.len_utf8();
+        }
+        offset
+    }
+
+    /// Returns the byte offset where `line` starts, if the text has it.
+    fn line_start(&self, line: usize) -> Option<usize> {
+        if line == 0 {
+            return Some(0);
+        }
+        let mut seen = 0;
+        let mut start = 0;
+        for chunk in &self.chunks {
+            if seen + chunk.newlines >= line {
+                let (idx, _) = chunk
+                    .text
+                    .match_indices('\n')
+                    .nth(line - seen - 1)
+                    .expect("counted newline");
+                return Some(start + idx + 1);
+            }
+            seen += chunk.newlines;
+            start += chunk.text.len();
+        }
+        None
+    }
+
+    fn chars_from(&self, offset: usize) -> impl Iterator<Item = char> + '_ {
+        let mut start = 0;
+        self.chunks.iter().flat_map(move |chunk| {
+            let from = offset.saturating_sub(start).min(chunk.text.len());
+            start += chunk.text.len();
+            chunk.text[from..].chars()
+        })
+    }
+
+    /// Returns the chunk containing byte `offset` and the offset within it;
+    /// the end of the text belongs to the last chunk.
+    fn locate(&self, offset: usize) -> (usize, usize) {
+        let mut start = 0;
+        for (idx, chunk) in self.chunks.iter().enumerate() {
+            let end = start + chunk.text.len();
+            if offset < end || idx + 1 == self.chunks.len() {
+                return (idx, offset - start);
+            }
+            start = end;
+        }
+        (0, 0)
+    }
+
+    /// Splits the chunk at `idx` into pieces of about `CHUNK_SIZE` bytes once
+    /// it has grown too large.
+    fn split(&mut self, idx: usize) {
+        if self.chunks[idx].text.len() <= 2 * CHUNK_SIZE {
+            return;
+        }
+        let text = std::mem::take(&mut self.chunks[idx].text);
+        let mut pieces = Vec::new();
+        let mut rest = text.as_str();
+        while rest.len() > CHUNK_SIZE {
+            let mut at = CHUNK_SIZE;
+            while !rest.is_char_boundary(at) {
+                at += 1;
+            }
+            let (piece, tail) = rest.split_at(at);
+            pieces.push(Chunk::new(piece.to_string()));
+            rest = tail;
+        }
+        pieces.push(Chunk::new(rest.to_string()));
+        self.chunks.splice(idx..=idx, pieces);
+    }
+
+    /// Merges the chunk at `idx` into its successor once it has become
+    /// small, so that many deletions do not leave tiny chunks behind.
+    fn merge(&mut self, idx: usize) {
+        if idx + 1 >= self.chunks.len() || self.chunks[idx].text.len() >= CHUNK_SIZE / 2 {
+            return;
+        }
+        let next = self.chunks.remove(idx + 1);
+        let chunk = &mut self.chunks[idx];
+        chunk.text.push_str(&next.text);
+        chunk.update();
+        self.split(idx);
+    }
+}
+
+impl fmt::Display for Rope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.chunks().try_for_each(|chunk| f.write_str(chunk))
+    }
+}
+
+fn count_newlines(text: &str) -> usize {
+    text.bytes().filter(|b| *b == b'\n').count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::doc::line_index::LineIndex;
+
+    /// A small deterministic generator, so failures can be reproduced.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self, bound: usize) -> usize {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 33) as usize % bound.max(1)
+        }
+
+        /// A char boundary in `text`.
+        fn boundary(&mut self, text: &str) -> usize {
+            let mut offset = self.next(text.len() + 1);
+            while !text.is_char_boundary(offset) {
+                offset -= 1;
+            }
+            offset
+        }
+    }
+
+    const PIECES: [&str; 6] = ["fn main() {}\n", "é", "😀", "\n", "let x = 1;", "中文\n"];
+
+    #[test]
+    fn random_edits_match_string_edits() {
+        let mut rng = Lcg(11);
+        let mut expected = String::new();
+        let mut rope = Rope::default();
+        for _ in 0..3000 {
+            if rng.next(3) == 0 && !expected.is_empty() {
+                let start = rng.boundary(&expected);
+                let mut end = (start + rng.next(3 * CHUNK_SIZE)).min(expected.len());
+                while !expected.is_char_boundary(end) {
+                    end -= 1;
+                }
+                expected.replace_range(start..end, "");
+                rope.remove(start..end);
+            } else {
+                let offset = rng.boundary(&expected);
+                let text = PIECES[rng.next(PIECES.len())].repeat(1 + rng.next(400));
+                expected.insert_str(offset, &text);
+                rope.insert(offset, &text);
+            }
+            assert_eq!(rope.len(), expected.len());
+        }
+        assert_eq!(rope.to_string(), expected);
+        assert!(rope.chunks.len() > 1);
+        assert!(
+            rope.chunks
+                .iter()
+                .all(|chunk| !chunk.text.is_empty() && chunk.text.len() <= 2 * CHUNK_SIZE)
+        );
+    }
+
+    #[test]
+    fn positions_match_the_line_index() {
+        let mut rng = Lcg(5);
+        let mut text = String::new();
+        while text.len() < 5 * CHUNK_SIZE {
+            text.push_str(PIECES[rng.next(PIECES.len())]);
+        }
+        let rope = Rope::new(&text);
+        let index = LineIndex::new(&text);
+        let lines = text.lines().count() as u32;
+        for encoding in [
+            PositionEncoding::Utf8,
+            PositionEncoding::Utf16,
+            PositionEncoding::Utf32,
+        ] {
+            for line in (0..lines + 2).step_by(7) {
+                for character in [0, 1, 2, 5, 11, 40] {
+                    let position = Position::new(line, character);
+                    assert_eq!(
+                        rope.position_to_offset(position, encoding),
+                        index.position_to_offset(position, encoding),
+                        "{position:?} in {encoding:?}"
+                    );
+                }
+            }
+        }
+    }
+}
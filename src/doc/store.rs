@@ -1,55 +1,821 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::SystemTime;
 
-use lsp_types::{TextDocumentItem, Uri};
+use lsp_types::{Position, TextDocumentContentChangeEvent, TextDocumentItem, Uri};
+
+use super::file_cache;
+use super::line_index::LineIndex;
+use super::position::PositionEncoding;
+use super::rope::Rope;
+use super::uri::{NormalizedUri, Scheme, canonicalize, path_to_uri, uri_to_path};
+use crate::config::{LimitsConfig, WorkspaceConfig, WorkspaceMode};
+
+/// Documents at least this large are kept in a `Rope`, so that applying an
+/// edit does not shift the whole tail of the text. Features still read it as
+/// one string; see `Document::text`.
+const ROPE_THRESHOLD: usize = 256 * 1024;
+
+/// The last revision given to a document. It is shared by every store, so a
+/// revision never stands for two different texts.
+static LAST_REVISION: AtomicU64 = AtomicU64::new(0);
+
+fn next_revision() -> u64 {
+    LAST_REVISION.fetch_add(1, Ordering::Relaxed) + 1
+}
 
 #[derive(Debug, Clone)]
 pub struct Document {
-    pub text: String,
+    /// The URI the client sent, which responses echo.
+    uri: Uri,
+    content: Content,
     pub version: i32,
+    /// The client's language id, e.g. `rust` or `toml`.
+    pub language_id: String,
+    /// The text of a rope, materialized on first use after a change.
+    text: OnceLock<String>,
+    /// Line starts of the text, built on first use after a change.
+    line_index: OnceLock<LineIndex>,
+    /// Set when the document is over `limits.maxAnalyzedFileSize`.
+    too_large: bool,
+    /// Bumped whenever the text or `too_large` changes; see `Revisions`.
+    revision: u64,
+    /// The URI of the file's real path when it is reached through a
+    /// symlink, resolved when the document is opened.
+    real_uri: Option<Uri>,
+    /// The modification time of the file when its text last matched the
+    /// document on save, so that an unchanged file is not read again.
+    saved_modified: Option<SystemTime>,
+}
+
+#[derive(Debug, Clone)]
+enum Content {
+    Plain(String),
+    Rope(Rope),
+}
+
+impl Content {
+    fn new(text: String) -> Self {
+        if text.len() < ROPE_THRESHOLD {
+            Content::Plain(text)
+        } else {
+            Content::Rope(Rope::new(&text))
+        }
+    }
+}
+
+impl Document {
+    fn new(uri: Uri, text: String, version: i32, language_id: String) -> Self {
+        Self {
+            real_uri: real_uri(&uri),
+            uri,
+            content: Content::new(text),
+            version,
+            language_id,
+            text: OnceLock::new(),
+            line_index: OnceLock::new(),
+            too_large: false,
+            revision: next_revision(),
+            saved_modified: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.content {
+            Content::Plain(text) => text.len(),
+            Content::Rope(rope) => rope.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether features should skip the document because of its size.
+    pub fn is_too_large(&self) -> bool {
+        self.too_large
+    }
+
+    /// The whole text. For a rope it is copied into one string on the first
+    /// call after a change, which the later calls share.
+    pub fn text(&self) -> &str {
+        match &self.content {
+            Content::Plain(text) => text,
+            Content::Rope(rope) => self.text.get_or_init(|| rope.to_string()),
+        }
+    }
+
+    pub fn line_index(&self) -> &LineIndex {
+        self.line_index.get_or_init(|| LineIndex::new(self.text()))
+    }
+
+    pub fn is_rust(&self) -> bool {
+        self.language_id == "rust"
+    }
+
+    /// Applies a `didChange` content change: the whole text when it has no
+    /// range, or an edit of the range otherwise.
+    fn apply(&mut self, change: TextDocumentContentChangeEvent, encoding: PositionEncoding) {
+        match change.range {
+            None => self.content = Content::new(change.text),
+            Some(range) => {
+                let start = self.offset(range.start, encoding);
+                let end = self.offset(range.end, encoding).max(start);
+                match &mut self.content {
+                    Content::Plain(text) => {
+                        text.replace_range(start..end, &change.text);
+                        if text.len() >= ROPE_THRESHOLD {
+                            self.content = Content::Rope(Rope::new(text));
+                        }
+                    }
+                    Content::Rope(rope) => {
+                        rope.remove(start..end);
+                        rope.insert(start, &change.text);
+                    }
+                }
+            }
+        }
+        self.text = OnceLock::new();
+        self.line_index = OnceLock::new();
+    }
+
+    fn replace_text(&mut self, text: String) {
+        self.content = Content::new(text);
+        self.text = OnceLock::new();
+        self.line_index = OnceLock::new();
+    }
+
+    fn offset(&self, position: Position, encoding: PositionEncoding) -> usize {
+        match &self.content {
+            Content::Plain(_) => self.line_index().position_to_offset(position, encoding),
+            Content::Rope(rope) => rope.position_to_offset(position, encoding),
+        }
+    }
 }
 
-#[derive(Debug, Default)]
+/// Open documents, found by any spelling of their URI. Each document is
+/// shared, so a clone of the store is a cheap snapshot that later changes
+/// leave untouched.
+#[derive(Debug, Clone)]
 pub struct DocumentStore {
-    docs: HashMap<Uri, Document>,
+    docs: HashMap<NormalizedUri, Arc<Document>>,
+    /// Documents larger than this many bytes are marked too large.
+    max_analyzed_size: usize,
+    /// Whether and which files that are not open features may read from
+    /// disk.
+    workspace: WorkspaceConfig,
+}
+
+impl Default for DocumentStore {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl DocumentStore {
     pub fn new() -> Self {
         Self {
             docs: HashMap::new(),
+            max_analyzed_size: LimitsConfig::default().max_analyzed_file_size,
+            workspace: WorkspaceConfig::default(),
         }
     }
 
     pub fn open(&mut self, item: TextDocumentItem) {
-        let doc = Document {
-            text: item.text,
-            version: item.version,
+        let key = NormalizedUri::new(&item.uri);
+        let mut doc = Document::new(item.uri, item.text, item.version, item.language_id);
+        doc.too_large = doc.len() > self.max_analyzed_size;
+        self.docs.insert(key, Arc::new(doc));
+    }
+
+    /// Changes the size over which documents are too large to analyze and
+    /// re-marks the open ones.
+    pub fn set_max_analyzed_size(&mut self, size: usize) {
+        self.max_analyzed_size = size;
+        for doc in self.docs.values_mut() {
+            if doc.too_large != (doc.len() > size) {
+                let doc = Arc::make_mut(doc);
+                doc.too_large = !doc.too_large;
+                doc.revision = next_revision();
+            }
+        }
+    }
+
+    pub fn set_workspace(&mut self, workspace: WorkspaceConfig) {
+        self.workspace = workspace;
+    }
+
+    pub fn workspace(&self) -> &WorkspaceConfig {
+        &self.workspace
+    }
+
+    /// Whether files that are not open may be read from disk, i.e. the
+    /// workspace mode is not `OpenFilesOnly`.
+    pub fn reads_disk(&self) -> bool {
+        self.workspace.mode == WorkspaceMode::Workspace
+    }
+
+    /// Applies the content changes of a `didChange` in order, unless the
+    /// stored version is already the same or newer, e.g. because a delayed
+    /// change arrived out of order.
+    pub fn change(
+        &mut self,
+        uri: Uri,
+        version: i32,
+        changes: Vec<TextDocumentContentChangeEvent>,
+        encoding: PositionEncoding,
+    ) -> Result<(), String> {
+        let key = NormalizedUri::new(&uri);
+        let doc = match self.docs.get_mut(&key) {
+            Some(doc) if version <= doc.version => {
+                return Err(format!(
+                    "ignoring change to {} with version {}; version {} is already applied",
+                    uri.as_str(),
+                    version,
+                    doc.version
+                ));
+            }
+            Some(doc) => Arc::make_mut(doc),
+            None => {
+                log_warn!("{} was changed before it was opened", uri.as_str());
+                let language_id = language_from_path(&uri);
+                let doc = Document::new(uri.clone(), String::new(), version, language_id);
+                Arc::make_mut(self.docs.entry(key.clone()).or_insert(Arc::new(doc)))
+            }
         };
-        self.docs.insert(item.uri, doc);
+        // Edits before a replacement of the whole text do not matter.
+        let replaced = changes
+            .iter()
+            .rposition(|change| change.range.is_none())
+            .unwrap_or(0);
+        let applied = panic::catch_unwind(AssertUnwindSafe(|| {
+            for change in changes.into_iter().skip(replaced) {
+                doc.apply(change, encoding);
+            }
+        }));
+        if applied.is_err() {
+            // The text may be half edited, so it is not kept.
+            self.docs.remove(&key);
+            return Err(format!(
+                "dropped {} because applying a change to it failed",
+                uri.as_str()
+            ));
+        }
+        doc.version = version;
+        doc.too_large = doc.len() > self.max_analyzed_size;
+        doc.revision = next_revision();
+        Ok(())
     }
 
-    pub fn change_full(&mut self, uri: Uri, version: i32, text: String) {
-        if let Some(doc) = self.docs.get_mut(&uri) {
-            doc.text = text;
-            doc.version = version;
-        } else {
-            self.docs.insert(uri, Document { text, version });
+    /// Brings `uri` in line with the text it was saved with: `text` when the
+    /// client included it, or else the file on disk when it was modified
+    /// outside the editor since the last save. Returns whether the text
+    /// changed.
+    pub fn save(&mut self, uri: &Uri, text: Option<String>) -> bool {
+        let Some(doc) = self.docs.get_mut(&NormalizedUri::new(uri)) else {
+            return false;
+        };
+        let text = match text {
+            Some(text) => text,
+            None => {
+                let Some(path) = uri_to_path(uri) else {
+                    return false;
+                };
+                let modified = fs::metadata(&path).and_then(|meta| meta.modified()).ok();
+                if modified.is_some() && modified == doc.saved_modified {
+                    return false;
+                }
+                let Ok(text) = fs::read_to_string(&path) else {
+                    return false;
+                };
+                Arc::make_mut(doc).saved_modified = modified;
+                text
+            }
+        };
+        if text == doc.text() {
+            return false;
         }
+        let doc = Arc::make_mut(doc);
+        doc.replace_text(text);
+        doc.too_large = doc.len() > self.max_analyzed_size;
+        doc.revision = next_revision();
+        true
     }
 
     pub fn close(&mut self, uri: &Uri) {
-        self.docs.remove(uri);
+        self.docs.remove(&NormalizedUri::new(uri));
     }
 
     pub fn get(&self, uri: &Uri) -> Option<&Document> {
-        self.docs.get(uri)
+        self.docs.get(&NormalizedUri::new(uri)).map(Arc::as_ref)
     }
 
-    pub fn open_urls(&self) -> Vec<Uri> {
-        self.docs.keys().cloned().collect()
+    /// The open document `uri`, or else the file on disk, for requests about
+    /// files the client did not open. The file is not added to the store.
+    pub fn get_or_read(&self, uri: &Uri) -> Option<Cow<'_, Document>> {
+        if let Some(doc) = self.get(uri) {
+            return Some(Cow::Borrowed(doc));
+        }
+        if Scheme::of(uri) != Scheme::File || !self.reads_disk() {
+            return None;
+        }
+        let text = file_cache::get_text(&uri_to_path(uri)?)?;
+        let mut doc = Document::new(uri.clone(), text.to_string(), 0, language_from_path(uri));
+        doc.too_large = doc.len() > self.max_analyzed_size;
+        Some(Cow::Owned(doc))
+    }
+
+    /// The version of `uri` along with its text, so that results computed
+    /// from it can say which version they describe.
+    pub fn get_versioned(&self, uri: &Uri) -> Option<(i32, &str)> {
+        self.get(uri).map(|doc| (doc.version, doc.text()))
+    }
+
+    /// The open documents that are files on disk, which are the ones
+    /// diagnostics can be reported for.
+    #[allow(clippy::mutable_key_type)]
+    pub fn open_texts(&self) -> HashMap<Uri, (i32, String)> {
+        self.iter()
+            .filter(|(uri, _)| Scheme::of(uri) == Scheme::File)
+            .filter_map(|(uri, _)| {
+                let (version, text) = self.get_versioned(uri)?;
+                Some((uri.clone(), (version, text.to_string())))
+            })
+            .collect()
     }
 
+    /// The open documents with the URIs the client opened them with.
     pub fn iter(&self) -> impl Iterator<Item = (&Uri, &Document)> {
-        self.docs.iter()
+        self.docs.values().map(|doc| (&doc.uri, doc.as_ref()))
+    }
+
+    /// Maps each open file, by its own URI and by its real path when it is
+    /// reached through a symlink, to the URI the client opened it with.
+    pub fn client_uris(&self) -> HashMap<NormalizedUri, Uri> {
+        let mut uris = HashMap::new();
+        for (key, doc) in self.docs.iter() {
+            if Scheme::of(&doc.uri) != Scheme::File {
+                continue;
+            }
+            uris.insert(key.clone(), doc.uri.clone());
+            if let Some(real) = &doc.real_uri {
+                uris.insert(NormalizedUri::new(real), doc.uri.clone());
+            }
+        }
+        uris
+    }
+
+    /// The revision of every open document, to pass to `changed_since` later.
+    pub fn revisions(&self) -> Revisions {
+        Revisions(
+            self.docs
+                .iter()
+                .map(|(key, doc)| (key.clone(), (doc.uri.clone(), doc.revision)))
+                .collect(),
+        )
+    }
+
+    /// Documents opened, changed or closed since `since` was taken. A closed
+    /// document is listed too, so consumers can drop what they derived from
+    /// it; `get` tells the cases apart.
+    pub fn changed_since(&self, since: &Revisions) -> Vec<Uri> {
+        let changed = self
+            .docs
+            .iter()
+            .filter(|(key, doc)| {
+                since.0.get(*key).map(|(_, revision)| *revision) != Some(doc.revision)
+            })
+            .map(|(_, doc)| doc.uri.clone());
+        let closed = since
+            .0
+            .iter()
+            .filter(|(key, _)| !self.docs.contains_key(*key))
+            .map(|(_, (uri, _))| uri.clone());
+        changed.chain(closed).collect()
+    }
+}
+
+/// The revisions of the open documents at some point, which consumers that
+/// cache results per document keep to find out what changed since.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Revisions(HashMap<NormalizedUri, (Uri, u64)>);
+
+/// The URI of the real path of `uri`, if it differs because of a symlink.
+fn real_uri(uri: &Uri) -> Option<Uri> {
+    let real = path_to_uri(&canonicalize(&uri_to_path(uri)?))?;
+    (NormalizedUri::new(&real) != NormalizedUri::new(uri)).then_some(real)
+}
+
+/// Infers the language of a document changed before it was opened.
+fn language_from_path(uri: &Uri) -> String {
+    if uri.path().as_str().ends_with(".rs") {
+        "rust".to_string()
+    } else {
+        String::new()
+    }
+}
+
+/// A `DocumentStore` shared between the main loop, which applies changes,
+/// and workers, which read snapshots. Snapshots share the documents instead
+/// of holding the lock, so a long computation never delays a change.
+#[derive(Debug, Clone)]
+pub struct SharedDocuments {
+    store: Arc<RwLock<DocumentStore>>,
+}
+
+impl Default for SharedDocuments {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SharedDocuments {
+    pub fn new() -> Self {
+        Self {
+            store: Arc::new(RwLock::new(DocumentStore::new())),
+        }
+    }
+
+    pub fn open(&self, item: TextDocumentItem) {
+        self.write().open(item);
+    }
+
+    pub fn change(
+        &self,
+        uri: Uri,
+        version: i32,
+        changes: Vec<TextDocumentContentChangeEvent>,
+        encoding: PositionEncoding,
+    ) -> Result<(), String> {
+        self.write().change(uri, version, changes, encoding)
+    }
+
+    pub fn save(&self, uri: &Uri, text: Option<String>) -> bool {
+        self.write().save(uri, text)
+    }
+
+    pub fn close(&self, uri: &Uri) {
+        self.write().close(uri);
+    }
+
+    pub fn set_max_analyzed_size(&self, size: usize) {
+        self.write().set_max_analyzed_size(size);
+    }
+
+    pub fn set_workspace(&self, workspace: WorkspaceConfig) {
+        self.write().set_workspace(workspace);
+    }
+
+    pub fn is_too_large(&self, uri: &Uri) -> bool {
+        let store = self.read();
+        store.get(uri).is_some_and(Document::is_too_large)
+    }
+
+    /// The documents as they are now; later changes do not affect it.
+    pub fn snapshot(&self) -> DocumentStore {
+        self.read().clone()
+    }
+
+    // A panic while the lock is held does not leave the store half updated
+    // (see `DocumentStore::change`), so it stays usable afterwards.
+    fn read(&self) -> RwLockReadGuard<'_, DocumentStore> {
+        self.store.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, DocumentStore> {
+        self.store.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use lsp_types::Range;
+
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    fn full(text: &str) -> Vec<TextDocumentContentChangeEvent> {
+        vec![TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: text.to_string(),
+        }]
+    }
+
+    fn edit(
+        (start_line, start_col): (u32, u32),
+        (end_line, end_col): (u32, u32),
+        text: &str,
+    ) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: Some(Range::new(
+                Position::new(start_line, start_col),
+                Position::new(end_line, end_col),
+            )),
+            range_length: None,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn stale_changes_are_ignored() {
+        let uri = Uri::from_str("file:///project/src/main.rs").unwrap();
+        let mut docs = DocumentStore::new();
+        docs.open(TextDocumentItem {
+            uri: uri.clone(),
+            language_id: "rust".to_string(),
+            version: 1,
+            text: "fn a() {}".to_string(),
+        });
+
+        assert!(
+            docs.change(uri.clone(), 3, full("fn c() {}"), PositionEncoding::Utf16)
+                .is_ok()
+        );
+        assert!(
+            docs.change(uri.clone(), 2, full("fn b() {}"), PositionEncoding::Utf16)
+                .is_err()
+        );
+        assert!(
+            docs.change(uri.clone(), 3, full("fn b() {}"), PositionEncoding::Utf16)
+                .is_err()
+        );
+        assert_eq!(docs.get_versioned(&uri), Some((3, "fn c() {}")));
+
+        assert!(
+            docs.change(uri.clone(), 4, full("fn d() {}"), PositionEncoding::Utf16)
+                .is_ok()
+        );
+        assert_eq!(docs.get_versioned(&uri), Some((4, "fn d() {}")));
+        assert_eq!(docs.open_texts()[&uri], (4, "fn d() {}".to_string()));
+    }
+
+    #[test]
+    fn snapshots_are_unaffected_by_later_changes() {
+        let uri = Uri::from_str("file:///project/src/lib.rs").unwrap();
+        let shared = SharedDocuments::new();
+        shared.open(TextDocumentItem {
+            uri: uri.clone(),
+            language_id: "rust".to_string(),
+            version: 1,
+            text: "fn a() {}".to_string(),
+        });
+        let before = shared.snapshot();
+
+        // Workers take their own snapshots from other threads.
+        let worker = {
+            let shared = shared.clone();
+            std::thread::spawn(move || shared.snapshot())
+        };
+        shared
+            .change(uri.clone(), 2, full("fn b() {}"), PositionEncoding::Utf16)
+            .unwrap();
+        worker.join().unwrap();
+
+        assert_eq!(before.get_versioned(&uri), Some((1, "fn a() {}")));
+        let after = shared.snapshot();
+        assert_eq!(after.get_versioned(&uri), Some((2, "fn b() {}")));
+        assert!(after.get(&uri).unwrap().is_rust());
+
+        shared.close(&uri);
+        assert!(shared.snapshot().get(&uri).is_none());
+        assert!(after.get(&uri).is_some());
+    }
+
+    #[test]
+    fn incremental_changes_apply_in_order() {
+        let uri = Uri::from_str("file:///project/src/edit.rs").unwrap();
+        let small = "fn main() {\n    let s = \"😀\";\n}\n".to_string();
+        let large = small.clone() + &"// 😀 padding\n".repeat(ROPE_THRESHOLD / 10);
+        for text in [small, large] {
+            let last_line = text.lines().count() as u32 - 1;
+            let mut docs = DocumentStore::new();
+            docs.open(TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "rust".to_string(),
+                version: 1,
+                text: text.clone(),
+            });
+            let changes = vec![
+                // Replaces `😀`, two UTF-16 units, on line 1.
+                edit((1, 13), (1, 15), "é"),
+                // Inserts at the start of the text, then at the end of the
+                // shifted last line.
+                edit((0, 0), (0, 0), "use std::fmt;\n"),
+                edit((last_line + 1, 200), (last_line + 1, 200), " // end"),
+            ];
+            docs.change(uri.clone(), 2, changes, PositionEncoding::Utf16)
+                .unwrap();
+
+            let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+            lines[1] = lines[1].replacen('😀', "é", 1);
+            lines.insert(0, "use std::fmt;".to_string());
+            lines[last_line as usize + 1].push_str(" // end");
+            let expected = lines.join("\n") + "\n";
+            let doc = docs.get(&uri).unwrap();
+            let is_rope = matches!(doc.content, Content::Rope(_));
+            assert_eq!(is_rope, text.len() >= ROPE_THRESHOLD);
+            assert_eq!(doc.version, 2);
+            assert_eq!(doc.text(), expected);
+            assert_eq!(
+                doc.line_index()
+                    .position_to_offset(Position::new(1, 0), PositionEncoding::Utf16),
+                "use std::fmt;\n".len()
+            );
+        }
+    }
+
+    #[test]
+    fn documents_over_the_limit_are_marked_too_large() {
+        let uri = Uri::from_str("file:///project/src/big.rs").unwrap();
+        let shared = SharedDocuments::new();
+        shared.set_max_analyzed_size(16);
+        shared.open(TextDocumentItem {
+            uri: uri.clone(),
+            language_id: "rust".to_string(),
+            version: 1,
+            text: "fn a() {}".to_string(),
+        });
+        assert!(!shared.is_too_large(&uri));
+
+        shared
+            .change(
+                uri.clone(),
+                2,
+                full("fn main() { loop {} }"),
+                PositionEncoding::Utf16,
+            )
+            .unwrap();
+        assert!(shared.is_too_large(&uri));
+        // The text is still kept, so raising the limit brings it back.
+        let snapshot = shared.snapshot();
+        assert_eq!(
+            snapshot.get_versioned(&uri),
+            Some((2, "fn main() { loop {} }"))
+        );
+
+        shared.set_max_analyzed_size(1024);
+        assert!(!shared.is_too_large(&uri));
+        assert!(snapshot.get(&uri).unwrap().is_too_large());
+    }
+
+    #[test]
+    fn saves_replace_text_that_fell_behind() {
+        let dir = temp_dir("save");
+        let path = dir.join("main.rs");
+        fs::write(&path, "fn a() {}").unwrap();
+        let uri = path_to_uri(&path).unwrap();
+        let mut docs = DocumentStore::new();
+        docs.open(TextDocumentItem {
+            uri: uri.clone(),
+            language_id: "rust".to_string(),
+            version: 1,
+            text: "fn a() {}".to_string(),
+        });
+
+        // Nothing changed outside the editor.
+        let opened = docs.revisions();
+        assert!(!docs.save(&uri, None));
+        assert!(!docs.save(&uri, Some("fn a() {}".to_string())));
+        assert!(docs.changed_since(&opened).is_empty());
+
+        // The text included in the save wins.
+        assert!(docs.save(&uri, Some("fn b() {}".to_string())));
+        assert_eq!(docs.get_versioned(&uri), Some((1, "fn b() {}")));
+        assert_eq!(docs.changed_since(&opened), std::slice::from_ref(&uri));
+
+        // Without it, a file rewritten on disk is read again.
+        fs::write(&path, "fn c() {}").unwrap();
+        let saved = docs.revisions();
+        assert!(docs.save(&uri, None));
+        assert_eq!(docs.get_versioned(&uri), Some((1, "fn c() {}")));
+        assert_eq!(docs.changed_since(&saved), std::slice::from_ref(&uri));
+        assert!(!docs.save(&uri, None));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn changed_since_lists_opened_changed_and_closed_documents() {
+        let [a, b, c] = ["a", "b", "c"]
+            .map(|name| Uri::from_str(&format!("file:///project/src/{name}.rs")).unwrap());
+        let mut docs = DocumentStore::new();
+        let open = |docs: &mut DocumentStore, uri: &Uri| {
+            docs.open(TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "rust".to_string(),
+                version: 1,
+                text: "fn a() {}".to_string(),
+            });
+        };
+        let changed = |docs: &DocumentStore, since: &Revisions| {
+            let mut uris: Vec<String> = docs
+                .changed_since(since)
+                .iter()
+                .map(|uri| uri.as_str().rsplit('/').next().unwrap().to_string())
+                .collect();
+            uris.sort();
+            uris
+        };
+
+        let empty = docs.revisions();
+        open(&mut docs, &a);
+        open(&mut docs, &b);
+        assert_eq!(changed(&docs, &empty), ["a.rs", "b.rs"]);
+
+        let opened = docs.revisions();
+        assert!(changed(&docs, &opened).is_empty());
+        docs.change(a.clone(), 2, full("fn b() {}"), PositionEncoding::Utf16)
+            .unwrap();
+        // A rejected change leaves the revision alone.
+        assert!(
+            docs.change(b.clone(), 1, full("fn c() {}"), PositionEncoding::Utf16)
+                .is_err()
+        );
+        docs.close(&b);
+        open(&mut docs, &c);
+        assert_eq!(changed(&docs, &opened), ["a.rs", "b.rs", "c.rs"]);
+        assert!(docs.get(&b).is_none());
+
+        // Reopening with the same text is still a new revision.
+        let reopened = docs.revisions();
+        open(&mut docs, &c);
+        assert_eq!(changed(&docs, &reopened), ["c.rs"]);
+
+        // So is a document crossing the size limit.
+        let resized = docs.revisions();
+        docs.set_max_analyzed_size(4);
+        assert_eq!(changed(&docs, &resized), ["a.rs", "c.rs"]);
+        assert_eq!(changed(&docs, &docs.revisions()), Vec::<String>::new());
+        assert_eq!(changed(&DocumentStore::new(), &resized), ["a.rs", "c.rs"]);
+    }
+
+    #[test]
+    fn drive_letter_spellings_find_the_same_document() {
+        let mut docs = DocumentStore::new();
+        let vscode = Uri::from_str("file:///c%3A/project/src/main.rs").unwrap();
+        let native = Uri::from_str("file:///C:/project/src/main.rs").unwrap();
+        docs.open(TextDocumentItem {
+            uri: vscode.clone(),
+            language_id: "rust".to_string(),
+            version: 1,
+            text: "fn a() {}".to_string(),
+        });
+        assert_eq!(docs.get_versioned(&native), Some((1, "fn a() {}")));
+
+        docs.change(
+            native.clone(),
+            2,
+            full("fn b() {}"),
+            PositionEncoding::Utf16,
+        )
+        .unwrap();
+        assert_eq!(docs.get_versioned(&vscode), Some((2, "fn b() {}")));
+        // The document keeps the URI it was opened with.
+        let keys: Vec<&str> = docs.iter().map(|(uri, _)| uri.as_str()).collect();
+        assert_eq!(keys, ["file:///c%3A/project/src/main.rs"]);
+
+        docs.close(&native);
+        assert!(docs.get(&vscode).is_none());
+    }
+
+    #[test]
+    fn equivalent_uris_find_the_same_document() {
+        let spellings = [
+            "file:///home/me/my%20crate/src/main.rs",
+            "file:///home/me/my%20crate/src/./main.rs",
+            "file:///home/%6de/my%20crate/src/main.rs",
+            "file:///home/%6De/my%20crate/tests/../src/main.rs",
+            "file://localhost/home/me/my%20crate/src/main.rs",
+        ]
+        .map(|uri| Uri::from_str(uri).unwrap());
+        let mut docs = DocumentStore::new();
+        docs.open(TextDocumentItem {
+            uri: spellings[2].clone(),
+            language_id: "rust".to_string(),
+            version: 1,
+            text: "fn a() {}".to_string(),
+        });
+        for (version, uri) in (2..).zip(&spellings) {
+            let text = format!("fn v{version}() {{}}");
+            docs.change(uri.clone(), version, full(&text), PositionEncoding::Utf16)
+                .unwrap();
+            for other in &spellings {
+                assert_eq!(docs.get_versioned(other), Some((version, text.as_str())));
+            }
+        }
+        assert_eq!(docs.iter().count(), 1);
+        assert_eq!(
+            docs.open_texts().keys().collect::<Vec<_>>(),
+            [&spellings[2]]
+        );
+
+        docs.close(&spellings[4]);
+        assert!(docs.get(&spellings[0]).is_none());
     }
 }
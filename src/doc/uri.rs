@@ -1,21 +1,56 @@
-use std::path::{Path, PathBuf};
+use std::borrow::Cow;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
 
 use lsp_types::Uri;
 
+/// What a document URI refers to, as far as paths are concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    /// A file on disk.
+    File,
+    /// A new buffer that has not been saved yet, e.g. `untitled:Untitled-1`.
+    Untitled,
+    /// Anything else, e.g. `git:` for a diff view or
+    /// `vscode-notebook-cell:`, often another view of a file on disk.
+    Other,
+}
+
+impl Scheme {
+    pub fn of(uri: &Uri) -> Self {
+        let scheme = uri
+            .as_str()
+            .split_once(':')
+            .map_or("", |(scheme, _)| scheme);
+        if scheme.eq_ignore_ascii_case("file") {
+            Scheme::File
+        } else if scheme.eq_ignore_ascii_case("untitled") {
+            Scheme::Untitled
+        } else {
+            Scheme::Other
+        }
+    }
+}
+
+/// Converts a `file` URI to a path. The scheme is matched
+/// case-insensitively, the authority may be missing (`file:/tmp/a.rs`),
+/// `localhost` or a UNC host, and a query or fragment is ignored.
 pub fn uri_to_path(uri: &Uri) -> Option<PathBuf> {
-    let raw = uri.as_str();
-    let rest = raw.strip_prefix("file://")?;
-    let (authority, path_part) = if rest.starts_with('/') {
-        ("", rest.to_string())
-    } else {
-        let mut parts = rest.splitn(2, '/');
-        let authority = parts.next().unwrap_or("");
-        let path = parts
-            .next()
-            .map(|p| format!("/{}", p))
-            .unwrap_or_else(|| "/".to_string());
-        (authority, path)
+    let (scheme, rest) = uri.as_str().split_once(':')?;
+    if !scheme.eq_ignore_ascii_case("file") {
+        return None;
+    }
+    let rest = rest.split(['?', '#']).next().unwrap_or_default();
+    let (authority, path_part) = match rest.strip_prefix("//") {
+        // `file://C:/src` puts the drive where the authority belongs.
+        Some(rest) if split_drive(rest).is_some() => ("", format!("/{}", rest)),
+        Some(rest) => match rest.find('/') {
+            Some(idx) => (&rest[..idx], rest[idx..].to_string()),
+            None => (rest, "/".to_string()),
+        },
+        None if rest.starts_with('/') => ("", rest.to_string()),
+        None => ("", format!("/{}", rest)),
     };
 
     let combined = if authority.is_empty() || authority.eq_ignore_ascii_case("localhost") {
@@ -27,12 +62,10 @@ pub fn uri_to_path(uri: &Uri) -> Option<PathBuf> {
     let decoded = percent_decode(&combined)?;
     let mut path = decoded;
 
-    if cfg!(windows) {
-        if path.starts_with('/') {
-            let bytes = path.as_bytes();
-            if bytes.len() > 2 && bytes[2] == b':' {
-                path = path[1..].to_string();
-            }
+    if cfg!(windows) && path.starts_with('/') {
+        let bytes = path.as_bytes();
+        if bytes.len() > 2 && bytes[2] == b':' {
+            path = path[1..].to_string();
         }
     }
 
@@ -46,16 +79,15 @@ pub fn path_to_uri(path: &Path) -> Option<Uri> {
     let path_str = path.to_string_lossy();
     let mut normalized = path_str.replace('\\', "/");
     if cfg!(windows) {
-        if normalized.starts_with("//") {
-            let unc = &normalized[2..];
+        if let Some(unc) = normalized.strip_prefix("//") {
             let mut parts = unc.splitn(2, '/');
             let host = parts.next().unwrap_or("");
             let rest = parts.next().unwrap_or("");
             if host.is_empty() {
                 return None;
             }
-            let host_enc = percent_encode(host);
-            let rest_enc = percent_encode(&format!("/{}", rest));
+            let host_enc = percent_encode(host.as_bytes());
+            let rest_enc = percent_encode(format!("/{}", rest).as_bytes());
             let uri_str = format!("file://{}{}", host_enc, rest_enc);
             return Uri::from_str(&uri_str).ok();
         }
@@ -69,10 +101,151 @@ pub fn path_to_uri(path: &Path) -> Option<Uri> {
         normalized = format!("/{}", normalized);
     }
 
-    let encoded = percent_encode(&normalized);
+    let encoded = percent_encode(normalized.as_bytes());
     let encoded = encoded.strip_prefix('/').unwrap_or(&encoded);
-    let uri_str = format!("file:///{}", encoded);
-    Uri::from_str(&uri_str).ok()
+    let uri = Uri::from_str(&format!("file:///{}", encoded)).ok()?;
+    Some(normalize_uri(&uri).into_owned())
+}
+
+/// Resolves symlinks in `path`, so that a file reached through a symlinked
+/// directory compares equal to its real path. Paths that do not exist, e.g.
+/// deleted files, are only normalized lexically.
+pub fn canonicalize(path: &Path) -> PathBuf {
+    match fs::canonicalize(path) {
+        Ok(real) => strip_verbatim_prefix(real),
+        Err(_) => normalize_lexically(path),
+    }
+}
+
+/// Turns `\\?\C:\src` from `fs::canonicalize` on Windows back into
+/// `C:\src`, which `path_to_uri` understands.
+fn strip_verbatim_prefix(path: PathBuf) -> PathBuf {
+    if cfg!(windows)
+        && let Some(rest) = path.to_str().and_then(|path| path.strip_prefix(r"\\?\"))
+        && rest.as_bytes().get(1) == Some(&b':')
+    {
+        return PathBuf::from(rest);
+    }
+    path
+}
+
+/// Removes `.` components and resolves `..` against the preceding
+/// component, without looking at the file system.
+pub fn normalize_lexically(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match out.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    out.pop();
+                }
+                // `..` at the root is the root.
+                Some(Component::RootDir | Component::Prefix(_)) => {}
+                _ => out.push(".."),
+            },
+            component => out.push(component),
+        }
+    }
+    out
+}
+
+/// A URI spelled canonically, for comparing URIs and keying maps by them.
+/// Spellings of the same file that differ in escapes (`%7E` or `~`, `%7e`
+/// or `%7E`), dot segments, a trailing `/` or the case of a drive letter
+/// normalize to the same value. Responses should still use the URI the
+/// client sent.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NormalizedUri(String);
+
+impl NormalizedUri {
+    pub fn new(uri: &Uri) -> Self {
+        if Scheme::of(uri) == Scheme::File
+            && let Some(path) = uri_to_path(uri)
+        {
+            return NormalizedUri(format!("file://{}", normalize_file_path(&path)));
+        }
+        NormalizedUri(lowercase_escapes(uri.as_str()))
+    }
+}
+
+/// Re-encodes a decoded file path with `/` separators, a leading `/` and a
+/// lowercase drive letter.
+fn normalize_file_path(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    // UNC paths keep their `//host` prefix.
+    let mut path = if path.starts_with("//") {
+        path
+    } else {
+        normalize_lexically(Path::new(&path))
+            .to_string_lossy()
+            .replace('\\', "/")
+    };
+    if !path.starts_with('/') {
+        path.insert(0, '/');
+    }
+    let bytes = path.as_bytes();
+    if bytes.len() > 2 && bytes[1].is_ascii_alphabetic() && bytes[2] == b':' {
+        path[1..2].make_ascii_lowercase();
+    }
+    lowercase_escapes(&percent_encode(path.as_bytes()))
+}
+
+fn lowercase_escapes(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut escape = 0;
+    for ch in input.chars() {
+        if ch == '%' {
+            escape = 2;
+            out.push(ch);
+        } else if escape > 0 {
+            escape -= 1;
+            out.push(ch.to_ascii_lowercase());
+        } else {
+            out.push(ch);
+        }
+    }
+    out
+}
+
+/// Spells `file` URIs with a Windows drive letter the same way whichever
+/// client produced them: `file:///C%3A%5CUsers` and `file:///C:/Users` both
+/// become `file:///c:/Users`, so they can be compared and used as keys.
+/// Other URIs are returned as they are.
+pub fn normalize_uri(uri: &Uri) -> Cow<'_, Uri> {
+    let Some(path) = uri.as_str().strip_prefix("file:///") else {
+        return Cow::Borrowed(uri);
+    };
+    let Some((drive, rest)) = split_drive(path) else {
+        return Cow::Borrowed(uri);
+    };
+    let rest = rest.replace("%5C", "/").replace("%5c", "/");
+    let normalized = format!("file:///{}:{}", drive.to_ascii_lowercase(), rest);
+    if normalized == uri.as_str() {
+        return Cow::Borrowed(uri);
+    }
+    match Uri::from_str(&normalized) {
+        Ok(normalized) => Cow::Owned(normalized),
+        Err(_) => Cow::Borrowed(uri),
+    }
+}
+
+/// Splits a drive letter followed by `:` or `%3A` off the start of a URI
+/// path, if the rest is empty or starts with a separator.
+fn split_drive(path: &str) -> Option<(char, &str)> {
+    let drive = path.chars().next().filter(char::is_ascii_alphabetic)?;
+    let rest = &path[1..];
+    let rest = match rest.strip_prefix(':') {
+        Some(rest) => rest,
+        None if rest.get(..3)?.eq_ignore_ascii_case("%3A") => &rest[3..],
+        None => return None,
+    };
+    let separated = rest.is_empty()
+        || rest.starts_with('/')
+        || rest
+            .get(..3)
+            .is_some_and(|sep| sep.eq_ignore_ascii_case("%5C"));
+    separated.then_some((drive, rest))
 }
 
 fn percent_decode(input: &str) -> Option<String> {
@@ -100,23 +273,24 @@ fn percent_decode(input: &str) -> Option<String> {
     String::from_utf8(out).ok()
 }
 
-fn percent_encode(input: &str) -> String {
-    let mut out = String::new();
-    for b in input.as_bytes() {
-        let ch = *b as char;
-        if is_unreserved(ch) || ch == '/' {
-            out.push(ch);
+/// Encodes every byte but unreserved ones and `/`, so each byte of a
+/// multi-byte UTF-8 character becomes its own `%XX`.
+fn percent_encode(input: &[u8]) -> String {
+    let mut out = String::with_capacity(input.len());
+    for &b in input {
+        if is_unreserved(b) || b == b'/' {
+            out.push(b as char);
         } else {
             out.push('%');
-            out.push(to_hex((b >> 4) & 0x0f));
+            out.push(to_hex(b >> 4));
             out.push(to_hex(b & 0x0f));
         }
     }
     out
 }
 
-fn is_unreserved(ch: char) -> bool {
-    ch.is_ascii_alphanumeric() || matches!(ch, '-' | '.' | '_' | '~')
+fn is_unreserved(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~')
 }
 
 fn to_hex(value: u8) -> char {
@@ -138,7 +312,9 @@ fn from_hex(b: u8) -> Option<u8> {
 
 #[cfg(test)]
 mod tests {
-    use super::{path_to_uri, uri_to_path};
+    use super::{
+        NormalizedUri, Scheme, normalize_lexically, normalize_uri, path_to_uri, uri_to_path,
+    };
     use lsp_types::Uri;
     use std::path::Path;
     use std::str::FromStr;
@@ -166,4 +342,167 @@ mod tests {
         let path = uri_to_path(&uri).unwrap();
         assert_eq!(path, Path::new("/tmp/foo.rs"));
     }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn non_ascii_paths_round_trip() {
+        let cases = [
+            (
+                "/tmp/テスト.rs",
+                "file:///tmp/%E3%83%86%E3%82%B9%E3%83%88.rs",
+            ),
+            ("/tmp/😀/lib.rs", "file:///tmp/%F0%9F%98%80/lib.rs"),
+            // `e` followed by a combining acute accent.
+            ("/tmp/cafe\u{301}.rs", "file:///tmp/cafe%CC%81.rs"),
+        ];
+        for (path, expected) in cases {
+            let uri = path_to_uri(Path::new(path)).unwrap();
+            assert_eq!(uri.as_str(), expected);
+            assert_eq!(uri_to_path(&uri).unwrap(), Path::new(path));
+
+            // Clients may use lowercase hex digits.
+            let lower = Uri::from_str(&expected.to_lowercase()).unwrap();
+            assert_eq!(
+                uri_to_path(&lower)
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .to_lowercase(),
+                path.to_lowercase()
+            );
+        }
+    }
+
+    #[test]
+    fn windows_drive_letters_are_normalized() {
+        let normalize = |uri: &str| {
+            normalize_uri(&Uri::from_str(uri).unwrap())
+                .as_str()
+                .to_string()
+        };
+        for uri in [
+            "file:///c%3A/Users/me/main.rs",
+            "file:///C%3a/Users/me/main.rs",
+            "file:///C:/Users/me/main.rs",
+            "file:///c:%5CUsers%5cme%5Cmain.rs",
+        ] {
+            assert_eq!(normalize(uri), "file:///c:/Users/me/main.rs", "{uri}");
+        }
+        // Only the drive letter is case-insensitive.
+        assert_eq!(
+            normalize("file:///D:/Users/Me/Main.rs"),
+            "file:///d:/Users/Me/Main.rs"
+        );
+        assert_eq!(normalize("file:///c%3A"), "file:///c:");
+        for unchanged in [
+            "file:///tmp/main.rs",
+            "file:///cd/main.rs",
+            "file:///c%3Ax/main.rs",
+            "file://server/share/main.rs",
+            "untitled:Untitled-1",
+        ] {
+            assert_eq!(normalize(unchanged), unchanged);
+        }
+    }
+
+    #[test]
+    fn dot_segments_are_removed_lexically() {
+        let cases = [
+            ("/work/./src/../src/main.rs", "/work/src/main.rs"),
+            ("/work/src/../../lib.rs", "/lib.rs"),
+            ("/../work/main.rs", "/work/main.rs"),
+            ("src/../../main.rs", "../main.rs"),
+            ("./src/./main.rs", "src/main.rs"),
+            ("/work/src/", "/work/src"),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(
+                normalize_lexically(Path::new(path)),
+                Path::new(expected),
+                "{path}"
+            );
+        }
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn uri_to_path_accepts_file_uri_variants() {
+        let cases = [
+            ("file:///tmp/foo.rs", "/tmp/foo.rs"),
+            ("FILE:///tmp/foo.rs", "/tmp/foo.rs"),
+            ("File://localhost/tmp/foo.rs", "/tmp/foo.rs"),
+            ("file://LOCALHOST/tmp/foo.rs", "/tmp/foo.rs"),
+            ("file:/tmp/foo.rs", "/tmp/foo.rs"),
+            ("file:tmp/foo.rs", "/tmp/foo.rs"),
+            ("file:///tmp/foo.rs?line=3", "/tmp/foo.rs"),
+            ("file:///tmp/foo.rs#L3", "/tmp/foo.rs"),
+            ("file:///tmp/a%23b.rs?x#y", "/tmp/a#b.rs"),
+            ("file:///C:/x/main.rs", "/C:/x/main.rs"),
+            ("file://C:/x/main.rs", "/C:/x/main.rs"),
+            ("file:///c%3A/x/main.rs#frag", "/c:/x/main.rs"),
+            ("file://server/share/foo.rs", "//server/share/foo.rs"),
+            ("file://server", "//server/"),
+        ];
+        for (uri, expected) in cases {
+            let path = uri_to_path(&Uri::from_str(uri).unwrap());
+            assert_eq!(path.as_deref(), Some(Path::new(expected)), "{uri}");
+        }
+        for other in ["untitled:Untitled-1", "https://example.com/foo.rs"] {
+            assert_eq!(uri_to_path(&Uri::from_str(other).unwrap()), None, "{other}");
+        }
+    }
+
+    #[test]
+    fn schemes_are_classified() {
+        let cases = [
+            ("file:///tmp/foo.rs", Scheme::File),
+            ("FILE:///tmp/foo.rs", Scheme::File),
+            ("untitled:Untitled-1", Scheme::Untitled),
+            (
+                "git:/tmp/foo.rs?%7B%22ref%22%3A%22HEAD%22%7D",
+                Scheme::Other,
+            ),
+            (
+                "vscode-notebook-cell:/tmp/nb.ipynb#W0sZmlsZQ%3D%3D",
+                Scheme::Other,
+            ),
+        ];
+        for (uri, scheme) in cases {
+            assert_eq!(Scheme::of(&Uri::from_str(uri).unwrap()), scheme, "{uri}");
+        }
+    }
+
+    #[test]
+    fn equivalent_spellings_normalize_the_same() {
+        let normalize = |uri: &str| NormalizedUri::new(&Uri::from_str(uri).unwrap());
+        let expected = normalize("file:///home/me/my%20crate/src/main.rs");
+        for uri in [
+            "file:///home/me/my%20crate/src/main.rs",
+            "FILE:///home/me/my%20crate/src/main.rs",
+            "file://localhost/home/me/my%20crate/src/main.rs",
+            "file:///home/me/my%20crate/src/./main.rs",
+            "file:///home/me/my%20crate/tests/../src/main.rs",
+            "file:///home/me/my%20crate/src/main.rs/",
+            "file:///home/%6De/my%20crate/src/main.rs",
+            "file:///home/me/my%20crate/src/main.rs#L1",
+        ] {
+            assert_eq!(normalize(uri), expected, "{uri}");
+        }
+        assert_ne!(normalize("file:///home/me/my%20crate/src/lib.rs"), expected);
+
+        assert_eq!(normalize("file:///a/~b"), normalize("file:///a/%7eb"));
+        assert_eq!(normalize("file:///a/%C3%A9"), normalize("file:///a/%c3%a9"));
+        assert_eq!(
+            normalize("file:///c%3A/Users/x.rs"),
+            normalize("file:///C:/Users/x.rs")
+        );
+        assert_ne!(
+            normalize("file:///C:/Users/x.rs"),
+            normalize("file:///C:/users/x.rs")
+        );
+        assert_eq!(
+            normalize("untitled:Untitled%2D1"),
+            normalize("untitled:Untitled%2d1")
+        );
+    }
 }
@@ -0,0 +1,148 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use lsp_types::Uri;
+
+use crate::config::WorkspaceConfig;
+use crate::doc::file_cache;
+use crate::doc::store::{Document, DocumentStore};
+use crate::doc::uri::{Scheme, path_to_uri, uri_to_path};
+
+/// Whether the open document `uri` contributes definitions to the
+/// workspace. Oversized documents are left out, and so are views such as
+/// `git:` diffs, which would duplicate the definitions of the file on disk.
+pub fn is_indexed(uri: &Uri, doc: &Document) -> bool {
+    doc.is_rust() && !doc.is_too_large() && Scheme::of(uri) != Scheme::Other
+}
+
+/// Where the walk over the workspace reads directories and files.
+pub trait SourceFs {
+    /// The entries of `dir`, each with whether it is a directory.
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<(PathBuf, bool)>>;
+
+    fn read(&self, path: &Path) -> Option<Arc<str>>;
+}
+
+/// The files on disk, read through the file cache.
+pub struct Disk;
+
+impl SourceFs for Disk {
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<(PathBuf, bool)>> {
+        Ok(fs::read_dir(dir)?
+            .flatten()
+            .map(|entry| {
+                let path = entry.path();
+                let is_dir = path.is_dir();
+                (path, is_dir)
+            })
+            .collect())
+    }
+
+    fn read(&self, path: &Path) -> Option<Arc<str>> {
+        file_cache::get_text(path)
+    }
+}
+
+/// Visits every indexed open Rust document, then every `.rs` file under `root` that
+/// is not open, so unsaved edits take precedence over the copy on disk. Stops
+/// at the first `Break` and returns its value. Nothing is read from disk when
+/// `docs` is limited to the open files, and otherwise the walk follows the
+/// `workspace` settings of `docs`.
+pub fn visit_sources<B>(
+    docs: &DocumentStore,
+    root: Option<&Path>,
+    visit: impl FnMut(Option<&Uri>, &str) -> ControlFlow<B>,
+) -> Option<B> {
+    visit_sources_with_progress(docs, root, &Disk, visit, |_, _| {})
+}
+
+/// `visit_sources` reading from `fs`, telling `progress` after each file
+/// under `root` how many were visited out of those found so far.
+/// Directories are read as the walk reaches them, so the number found grows
+/// along the way.
+pub fn visit_sources_with_progress<B>(
+    docs: &DocumentStore,
+    root: Option<&Path>,
+    fs: &dyn SourceFs,
+    mut visit: impl FnMut(Option<&Uri>, &str) -> ControlFlow<B>,
+    mut progress: impl FnMut(usize, usize),
+) -> Option<B> {
+    let mut open_paths = HashSet::new();
+    for (uri, doc) in docs.iter().filter(|(_, doc)| doc.is_rust()) {
+        if is_indexed(uri, doc)
+            && let ControlFlow::Break(found) = visit(Some(uri), doc.text())
+        {
+            return Some(found);
+        }
+        // Open files shadow their copy on disk, even when left out above.
+        if Scheme::of(uri) == Scheme::File
+            && let Some(path) = uri_to_path(uri)
+        {
+            open_paths.insert(path);
+        }
+    }
+
+    let root = root.filter(|_| docs.reads_disk())?;
+    let config = docs.workspace();
+    let mut stack = vec![root.to_path_buf()];
+    let (mut visited, mut found) = (0, 0);
+    while let Some(dir) = stack.pop() {
+        let entries = match fs.read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+
+        let mut sources = Vec::new();
+        for (path, is_dir) in entries {
+            if is_dir {
+                if !should_skip_dir(root, &path, config) {
+                    stack.push(path);
+                }
+            } else if is_rust_source(&path, &open_paths) {
+                sources.push(path);
+            }
+        }
+        found += sources.len();
+        for path in sources {
+            if visited == config.max_files {
+                log_info!(
+                    "stopped reading the workspace after workspace.maxFiles ({}) files",
+                    config.max_files
+                );
+                return None;
+            }
+            visited += 1;
+            if let Some(text) = fs.read(&path)
+                && let ControlFlow::Break(found) = visit(path_to_uri(&path).as_ref(), &text)
+            {
+                return Some(found);
+            }
+            progress(visited, found);
+        }
+    }
+
+    None
+}
+
+fn is_rust_source(path: &Path, open_paths: &HashSet<PathBuf>) -> bool {
+    path.extension().and_then(|s| s.to_str()) == Some("rs") && !open_paths.contains(path)
+}
+
+/// Whether the walk leaves out `path`: build output, git internals and the
+/// directories in `workspace.excludeDirs`, given by name or by path
+/// relative to `root`.
+fn should_skip_dir(root: &Path, path: &Path, config: &WorkspaceConfig) -> bool {
+    let name = path.file_name().and_then(|s| s.to_str());
+    if matches!(name, Some("target") | Some(".git")) {
+        return true;
+    }
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    config.exclude_dirs.iter().any(|excluded| {
+        let excluded = excluded.trim_end_matches('/');
+        name == Some(excluded) || relative == Path::new(excluded)
+    })
+}
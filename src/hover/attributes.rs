@@ -0,0 +1,165 @@
+use crate::syntax::Token;
+
+/// Well-known derive macros, shown when hovering a name inside `derive(...)`.
+const DERIVES: &[(&str, &str)] = &[
+    (
+        "Debug",
+        "Implements `fmt::Debug`, so the value can be printed with `{:?}` and `{:#?}`.",
+    ),
+    (
+        "Clone",
+        "Implements `Clone` by cloning every field, giving the type an explicit `.clone()`.",
+    ),
+    (
+        "Copy",
+        "Marks the type as copied bit for bit instead of moved. Requires `Clone` and every field to be `Copy`.",
+    ),
+    (
+        "PartialEq",
+        "Implements `==` and `!=` by comparing fields in order; enums also compare variants.",
+    ),
+    (
+        "Eq",
+        "Marks `==` as a full equivalence relation, so every value equals itself. Requires `PartialEq`.",
+    ),
+    (
+        "PartialOrd",
+        "Implements `<`, `<=`, `>` and `>=` by comparing fields lexicographically in declaration order.",
+    ),
+    (
+        "Ord",
+        "Implements a total order by comparing fields lexicographically. Requires `Eq` and `PartialOrd`.",
+    ),
+    (
+        "Hash",
+        "Implements `Hash` by hashing every field, so the type can be a `HashMap` key.",
+    ),
+    (
+        "Default",
+        "Implements `Default` with the default of every field; enums use the variant marked `#[default]`.",
+    ),
+    (
+        "Serialize",
+        "Implements serde's `Serialize`, customizable with `#[serde(...)]` attributes.",
+    ),
+    (
+        "Deserialize",
+        "Implements serde's `Deserialize`, customizable with `#[serde(...)]` attributes.",
+    ),
+];
+
+/// Built-in attributes, shown when hovering the attribute name.
+const ATTRIBUTES: &[(&str, &str, &str)] = &[
+    (
+        "allow",
+        "Silences the listed lints for the annotated item.",
+        "#[allow(dead_code)]",
+    ),
+    (
+        "cfg",
+        "Compiles the item only when the configuration predicate holds.",
+        "#[cfg(target_os = \"linux\")]",
+    ),
+    (
+        "cfg_attr",
+        "Applies the attributes after the predicate only when it holds.",
+        "#[cfg_attr(test, derive(Debug))]",
+    ),
+    (
+        "deny",
+        "Turns the listed lints into errors for the annotated item.",
+        "#[deny(missing_docs)]",
+    ),
+    (
+        "derive",
+        "Generates trait implementations for a struct, enum or union.",
+        "#[derive(Debug, Clone, PartialEq)]",
+    ),
+    (
+        "inline",
+        "Suggests inlining the function at call sites. `#[inline(always)]` and `#[inline(never)]` make the hint stronger.",
+        "#[inline]",
+    ),
+    (
+        "must_use",
+        "Warns when the returned value or a value of the type is ignored.",
+        "#[must_use]",
+    ),
+    (
+        "test",
+        "Marks a function as a unit test, run by `cargo test` and compiled only in test builds.",
+        "#[test]",
+    ),
+    (
+        "warn",
+        "Reports the listed lints as warnings for the annotated item.",
+        "#[warn(unused_results)]",
+    ),
+];
+
+/// Returns the markdown documentation for the derive macro `name`.
+pub fn derive_doc(name: &str) -> Option<String> {
+    let (_, description) = DERIVES.iter().find(|(derive, _)| *derive == name)?;
+    Some(format!(
+        "```rust\n#[derive({})]\n```\n\n{}",
+        name, description
+    ))
+}
+
+/// Returns the markdown documentation for the built-in attribute `name`.
+pub fn attribute_doc(name: &str) -> Option<String> {
+    let (_, description, example) = ATTRIBUTES
+        .iter()
+        .find(|(attribute, _, _)| *attribute == name)?;
+    Some(format!(
+        "`#[{}]` attribute\n\n{}\n\n```rust\n{}\n```",
+        name, description, example
+    ))
+}
+
+/// Where the hovered identifier sits inside an attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttributePosition {
+    /// The attribute name, e.g. `inline` in `#[inline]`.
+    Name,
+    /// A macro listed in `derive(...)`.
+    Derive,
+}
+
+/// Returns how the identifier at `idx` is used when it sits between `#[` (or
+/// `#![`) and the matching `]`, or `None` outside attributes.
+pub fn attribute_position(tokens: &[Token], idx: usize) -> Option<AttributePosition> {
+    let mut depth = 0usize;
+    let mut group = None;
+    for open in (0..idx).rev() {
+        let tok = &tokens[open];
+        if tok.is_punct(')') || tok.is_punct(']') {
+            depth += 1;
+        } else if tok.is_punct('(') || tok.is_punct('[') {
+            if depth > 0 {
+                depth -= 1;
+                continue;
+            }
+            if tok.is_punct('(') {
+                group.get_or_insert(open);
+                continue;
+            }
+            let hash = match open.checked_sub(1).map(|prev| &tokens[prev]) {
+                Some(prev) if prev.is_punct('!') => open.checked_sub(2),
+                _ => open.checked_sub(1),
+            };
+            if !hash.is_some_and(|hash| tokens[hash].is_punct('#')) {
+                return None;
+            }
+            if group.is_none() && open + 1 == idx {
+                return Some(AttributePosition::Name);
+            }
+            let group = group?;
+            return (group >= 1 && tokens[group - 1].is_ident("derive"))
+                .then_some(AttributePosition::Derive);
+        } else if depth == 0 && (tok.is_punct(';') || tok.is_punct('{') || tok.is_punct('}')) {
+            return None;
+        }
+    }
+    None
+}
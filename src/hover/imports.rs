@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+use crate::syntax::{Token, TokenKind};
+
+/// Maps each name brought into scope by a `use` declaration to the full path
+/// it refers to, e.g. `Widget` to `foo::Thing` for `use foo::Thing as
+/// Widget;`. Glob imports and `as _` are skipped.
+pub fn imports(tokens: &[Token]) -> HashMap<&str, Vec<&str>> {
+    let mut imports = HashMap::new();
+    for (idx, tok) in tokens.iter().enumerate() {
+        if tok.is_ident("use") {
+            let mut start = idx + 1;
+            if tokens
+                .get(start)
+                .is_some_and(|tok| matches!(tok.kind, TokenKind::DoubleColon))
+            {
+                start += 1;
+            }
+            parse_tree(tokens, start, Vec::new(), &mut imports);
+        }
+    }
+    imports
+}
+
+/// Parses one use tree starting at `idx` below `prefix` and returns the index
+/// just past it.
+fn parse_tree<'a>(
+    tokens: &'a [Token],
+    mut idx: usize,
+    mut path: Vec<&'a str>,
+    imports: &mut HashMap<&'a str, Vec<&'a str>>,
+) -> usize {
+    loop {
+        let Some(tok) = tokens.get(idx) else {
+            return idx;
+        };
+        if tok.is_punct('{') {
+            idx += 1;
+            loop {
+                if tokens.get(idx).is_some_and(|tok| tok.is_punct('}')) {
+                    return idx + 1;
+                }
+                let next = parse_tree(tokens, idx, path.clone(), imports);
+                if next == idx {
+                    return idx;
+                }
+                idx = next;
+                if tokens.get(idx).is_some_and(|tok| tok.is_punct(',')) {
+                    idx += 1;
+                }
+            }
+        }
+        let Some(name) = tok.ident() else {
+            // A glob, or the end of a malformed tree.
+            return if tok.is_punct('*') { idx + 1 } else { idx };
+        };
+        path.push(name);
+        idx += 1;
+        if tokens
+            .get(idx)
+            .is_some_and(|tok| matches!(tok.kind, TokenKind::DoubleColon))
+        {
+            idx += 1;
+            continue;
+        }
+
+        if name == "self" {
+            path.pop();
+        }
+        let mut local = path.last().copied();
+        if tokens.get(idx).is_some_and(|tok| tok.is_ident("as")) {
+            local = tokens.get(idx + 1).and_then(Token::ident);
+            idx += 2;
+        }
+        if let Some(local) = local
+            && local != "_"
+        {
+            imports.insert(local, path);
+        }
+        return idx;
+    }
+}
@@ -0,0 +1,194 @@
+/// Short explanations of Rust keywords, each with a tiny example.
+const KEYWORDS: &[(&str, &str, &str)] = &[
+    (
+        "as",
+        "Casts between primitive types, or renames an item in a `use` declaration.",
+        "let byte = 300_i32 as u8;\nuse std::io::Result as IoResult;",
+    ),
+    (
+        "async",
+        "Makes a function, block or closure return a `Future` instead of running immediately. The body only runs when the future is awaited or polled.",
+        "async fn fetch() -> u32 { 42 }",
+    ),
+    (
+        "await",
+        "Suspends the current `async` context until the future is ready and yields its output.",
+        "let value = fetch().await;",
+    ),
+    (
+        "break",
+        "Exits a loop early. `loop` blocks can produce a value through `break`, and labels select which loop to leave.",
+        "let found = loop { break 7; };",
+    ),
+    (
+        "const",
+        "Declares a compile-time constant, a `const fn` callable in constant contexts, or a const generic parameter.",
+        "const MAX: usize = 64;\nfn first<const N: usize>(xs: [u8; N]) -> u8 { xs[0] }",
+    ),
+    (
+        "continue",
+        "Skips the rest of the current loop iteration and starts the next one.",
+        "for x in 0..10 { if x % 2 == 0 { continue; } }",
+    ),
+    (
+        "crate",
+        "Refers to the root of the current crate in paths, or restricts visibility with `pub(crate)`.",
+        "use crate::config::Config;",
+    ),
+    (
+        "dyn",
+        "Marks a trait object type, whose methods are dispatched at runtime through a vtable.",
+        "let shapes: Vec<Box<dyn Shape>> = Vec::new();",
+    ),
+    (
+        "else",
+        "The branch taken when an `if` condition is false, or the fallback of a `let ... else` statement.",
+        "let Some(x) = opt else { return; };",
+    ),
+    (
+        "enum",
+        "Defines a type that is exactly one of several variants, each of which may carry data.",
+        "enum Shape { Circle(f64), Rect { w: f64, h: f64 } }",
+    ),
+    (
+        "extern",
+        "Links to foreign code: declares functions from another ABI, or specifies the ABI of a Rust function.",
+        "extern \"C\" { fn abs(x: i32) -> i32; }",
+    ),
+    ("false", "The boolean value `false`.", "let done = false;"),
+    (
+        "fn",
+        "Declares a function, or names a function pointer type.",
+        "fn add(a: i32, b: i32) -> i32 { a + b }",
+    ),
+    (
+        "for",
+        "Loops over the items of an iterator. Also introduces higher-ranked bounds and the trait in `impl Trait for Type`.",
+        "for (i, x) in xs.iter().enumerate() { println!(\"{i}: {x}\"); }",
+    ),
+    (
+        "if",
+        "Branches on a boolean condition. `if` is an expression, so both branches can produce a value.",
+        "let sign = if x < 0 { -1 } else { 1 };",
+    ),
+    (
+        "impl",
+        "Implements inherent methods or a trait for a type. In argument and return position, `impl Trait` names some type implementing the trait.",
+        "impl Display for Point { /* ... */ }\nfn evens() -> impl Iterator<Item = u32> { (0..).step_by(2) }",
+    ),
+    (
+        "in",
+        "Separates the pattern from the iterator in a `for` loop.",
+        "for x in 0..3 {}",
+    ),
+    (
+        "let",
+        "Introduces a new binding. The pattern can destructure the value, and bindings are immutable unless marked `mut`.",
+        "let (x, mut y) = (1, 2);",
+    ),
+    (
+        "loop",
+        "Repeats a block forever until a `break` or `return`. A `loop` can produce a value through `break`.",
+        "let n = loop { break 3; };",
+    ),
+    (
+        "match",
+        "Compares a value against a series of patterns and runs the arm of the first one that matches. Matches must be exhaustive.",
+        "match opt { Some(x) if x > 0 => x, Some(_) | None => 0 }",
+    ),
+    (
+        "mod",
+        "Declares a module, either inline or loaded from `name.rs` or `name/mod.rs`.",
+        "mod config;\nmod tests { }",
+    ),
+    (
+        "move",
+        "Makes a closure or async block take ownership of the variables it captures instead of borrowing them.",
+        "std::thread::spawn(move || println!(\"{data:?}\"));",
+    ),
+    (
+        "mut",
+        "Marks a binding or reference as mutable.",
+        "let mut count = 0;\nfn push(v: &mut Vec<u8>) {}",
+    ),
+    (
+        "pub",
+        "Makes an item visible outside its module, optionally limited with `pub(crate)`, `pub(super)` or `pub(in path)`.",
+        "pub struct Config { pub name: String }",
+    ),
+    (
+        "ref",
+        "Binds by reference in a pattern instead of moving the matched value.",
+        "if let Some(ref name) = user.name { println!(\"{name}\"); }",
+    ),
+    (
+        "return",
+        "Returns a value from the enclosing function early.",
+        "if list.is_empty() { return None; }",
+    ),
+    (
+        "self",
+        "The receiver of a method, or the current module in a path.",
+        "fn len(&self) -> usize { self.items.len() }\nuse self::store::Store;",
+    ),
+    (
+        "Self",
+        "The implementing type inside a trait or `impl` block.",
+        "impl Point { fn origin() -> Self { Self { x: 0, y: 0 } } }",
+    ),
+    (
+        "static",
+        "Declares a global variable with a fixed address for the whole program, or the `'static` lifetime.",
+        "static GREETING: &str = \"hello\";",
+    ),
+    (
+        "struct",
+        "Defines a type with named fields, positional fields, or no fields at all.",
+        "struct Point { x: i32, y: i32 }\nstruct Meters(f64);",
+    ),
+    (
+        "super",
+        "Refers to the parent module in a path.",
+        "use super::Config;",
+    ),
+    (
+        "trait",
+        "Defines a set of methods, types and constants that types can implement.",
+        "trait Shape { fn area(&self) -> f64; }",
+    ),
+    ("true", "The boolean value `true`.", "let ready = true;"),
+    (
+        "type",
+        "Declares a type alias, or an associated type in a trait.",
+        "type Result<T> = std::result::Result<T, Error>;",
+    ),
+    (
+        "unsafe",
+        "Marks code whose safety the compiler cannot check: unsafe blocks, functions that require invariants from the caller, or traits with such requirements.",
+        "let value = unsafe { *ptr };",
+    ),
+    (
+        "use",
+        "Brings paths into scope so they can be referred to by a shorter name.",
+        "use std::collections::{HashMap, HashSet};",
+    ),
+    (
+        "where",
+        "Introduces bounds on generic parameters after the signature.",
+        "fn print_all<T>(items: &[T]) where T: Debug { }",
+    ),
+    (
+        "while",
+        "Loops as long as a condition holds, or, with `while let`, as long as a pattern matches.",
+        "while let Some(top) = stack.pop() { }",
+    ),
+];
+
+/// Returns the markdown documentation for `name` if it is a keyword.
+pub fn keyword_doc(name: &str) -> Option<String> {
+    let (_, description, example) = KEYWORDS.iter().find(|(keyword, _, _)| *keyword == name)?;
+    Some(format!(
+        "`{}` keyword\n\n{}\n\n```rust\n{}\n```",
+        name, description, example
+    ))
+}
@@ -1,26 +1,407 @@
-use lsp_types::{Hover, HoverContents, MarkupContent, MarkupKind, Position, Uri};
+mod attributes;
+mod imports;
+mod keywords;
+mod modules;
+mod primitives;
+mod sysroot;
 
-use crate::doc::position::position_to_offset;
+use std::ops::ControlFlow;
+use std::path::Path;
+
+use lsp_types::{
+    ClientCapabilities, Hover, HoverContents, MarkupContent, MarkupKind, Position, Range, Uri,
+};
+
+use crate::config::HoverConfig;
+use crate::doc::line_index::LineIndex;
+use crate::doc::position::PositionEncoding;
 use crate::doc::store::DocumentStore;
+use crate::doc::uri::{path_to_uri, uri_to_path};
+use crate::doc::workspace::visit_sources;
+use crate::inlay::{generic_param_at, resolve_field};
+use crate::syntax::{
+    Token, TokenKind, collapse_whitespace, find_matching_brace, find_matching_paren,
+    impl_self_type, lex,
+};
 
-pub fn hover(docs: &DocumentStore, uri: &Uri, position: Position) -> Option<Hover> {
-    let doc = docs.get(uri)?;
-    let offset = position_to_offset(&doc.text, position)?;
-    let ident = extract_ident_at(&doc.text, offset)?;
-    let snippet = find_definition(docs, &ident)?;
+/// Returns the configured hover format, or else the one the client prefers,
+/// defaulting to markdown.
+pub fn content_format(caps: &ClientCapabilities, config: &HoverConfig) -> MarkupKind {
+    if let Some(format) = &config.content_format {
+        return format.clone();
+    }
+    caps.text_document
+        .as_ref()
+        .and_then(|td| td.hover.as_ref())
+        .and_then(|hover| hover.content_format.as_ref())
+        .and_then(|formats| formats.first().cloned())
+        .unwrap_or(MarkupKind::Markdown)
+}
 
-    let contents = HoverContents::Markup(MarkupContent {
-        kind: MarkupKind::Markdown,
-        value: format!("```rust\n{}\n```", snippet),
-    });
+pub fn hover(
+    docs: &DocumentStore,
+    root: Option<&Path>,
+    uri: &Uri,
+    position: Position,
+    config: &HoverConfig,
+    format: MarkupKind,
+    encoding: PositionEncoding,
+) -> Option<Hover> {
+    let doc = docs.get_or_read(uri).filter(|doc| !doc.is_too_large())?;
+    let text = doc.text();
+    let lines = doc.line_index();
+    let offset = lines.position_to_offset(position, encoding);
+    let tokens = lex(text);
+    let finish = |value: String, (start, end): (usize, usize)| {
+        let value = match format {
+            MarkupKind::PlainText => plain_text(&value),
+            MarkupKind::Markdown => value,
+        };
+        Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: format.clone(),
+                value,
+            }),
+            range: span_range(lines, start, end, encoding),
+        }
+    };
+
+    if config.keywords
+        && let Some(span) = keyword_at(&tokens, offset)
+        && let Some(doc) = keywords::keyword_doc(&text[span.0..span.1])
+    {
+        return Some(finish(doc, span));
+    }
+    if let Some(span) = unit_at(&tokens, offset) {
+        let doc = primitives::primitive_doc("()")?;
+        return Some(finish(doc, span));
+    }
+    if let Some(idx) = ident_token(&tokens, offset)
+        && let Some(position) = attributes::attribute_position(&tokens, idx)
+        && let Some(value) = attribute_hover(docs, root, tokens[idx].ident()?, position, config)
+    {
+        return Some(finish(value, (tokens[idx].start, tokens[idx].end)));
+    }
 
-    Some(Hover {
-        contents,
-        range: None,
+    if let Some(idx) = tokens.iter().position(|tok| {
+        tok.start <= offset
+            && offset <= tok.end
+            && (tok.ident().is_some() || matches!(tok.kind, TokenKind::Lifetime(_)))
+    }) && let Some(param) = generic_param_at(text, &tokens, idx)
+    {
+        let mut snippet = param.decl;
+        if !param.where_bounds.is_empty() {
+            snippet.push_str("\nwhere");
+            for bound in &param.where_bounds {
+                snippet.push_str(&format!("\n    {},", bound));
+            }
+        }
+        let definition = Definition::new(Some(uri), text, snippet, param.start, false);
+        let span = (tokens[idx].start, tokens[idx].end);
+        return Some(finish(
+            definitions_markdown(&[definition], root, config),
+            span,
+        ));
+    }
+
+    let span = ident_at(text, offset)?;
+    let ident = &text[span.0..span.1];
+    let imports = imports::imports(&tokens);
+    if let Some(definition) = modules::module_definition(docs, uri, &tokens, offset, &imports) {
+        return Some(finish(
+            definitions_markdown(std::slice::from_ref(&definition), root, config),
+            span,
+        ));
+    }
+    let context = member_context(text, &tokens, offset);
+    if context == MemberContext::Method
+        && let Some(idx) = ident_token(&tokens, offset)
+        && !tokens.get(idx + 1).is_some_and(|next| next.is_punct('('))
+        && let Some(field) = resolve_field(docs, root, text, &tokens, idx, encoding)
+    {
+        let definition = Definition {
+            snippet: format!("{}::{}: {}", field.owner, field.name, field.ty),
+            line: field
+                .location
+                .as_ref()
+                .map_or(0, |location| location.range.start.line as usize),
+            uri: field.location.map(|location| location.uri),
+            fallback: false,
+            docs: field.docs,
+            origin: None,
+        };
+        return Some(finish(
+            definitions_markdown(std::slice::from_ref(&definition), root, config),
+            span,
+        ));
+    }
+    if context == MemberContext::None
+        && let Some(path) = imports.get(ident)
+    {
+        let definitions = find_imported(docs, root, uri, path, config);
+        if !definitions.is_empty() {
+            return Some(finish(
+                definitions_markdown(&definitions, root, config),
+                span,
+            ));
+        }
+    }
+    let definitions = find_definitions(docs, root, ident, context, config);
+    if !definitions.is_empty() {
+        return Some(finish(
+            definitions_markdown(&definitions, root, config),
+            span,
+        ));
+    }
+    // Checked after the workspace so that an item named like a primitive or
+    // a std item wins.
+    if let Some(doc) = primitives::primitive_doc(ident) {
+        return Some(finish(doc, span));
+    }
+    if !config.sysroot {
+        return None;
+    }
+    let definition = sysroot::std_definition(ident, context, config)?;
+    Some(finish(
+        definitions_markdown(std::slice::from_ref(&definition), root, config),
+        span,
+    ))
+}
+
+/// Describes a built-in attribute or derive. An unknown derive falls back to
+/// its `derive_*` proc macro function or the trait of the same name in the
+/// workspace.
+fn attribute_hover(
+    docs: &DocumentStore,
+    root: Option<&Path>,
+    name: &str,
+    position: attributes::AttributePosition,
+    config: &HoverConfig,
+) -> Option<String> {
+    match position {
+        attributes::AttributePosition::Name => attributes::attribute_doc(name),
+        attributes::AttributePosition::Derive => {
+            if let Some(doc) = attributes::derive_doc(name) {
+                return Some(doc);
+            }
+            let macro_name = format!("derive_{}", snake_case(name));
+            let mut definitions =
+                find_definitions(docs, root, &macro_name, MemberContext::None, config);
+            if definitions.is_empty() {
+                let trait_keyword = format!("trait {}", name);
+                definitions = find_definitions(docs, root, name, MemberContext::None, config);
+                definitions.retain(|definition| definition.snippet.contains(&trait_keyword));
+            }
+            (!definitions.is_empty()).then(|| definitions_markdown(&definitions, root, config))
+        }
+    }
+}
+
+/// Converts `PartialEq` to `partial_eq`.
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (i, ch) in name.char_indices() {
+        if ch.is_ascii_uppercase() {
+            if i > 0 {
+                snake.push('_');
+            }
+            snake.push(ch.to_ascii_lowercase());
+        } else {
+            snake.push(ch);
+        }
+    }
+    snake
+}
+
+fn span_range(
+    lines: &LineIndex,
+    start: usize,
+    end: usize,
+    encoding: PositionEncoding,
+) -> Option<Range> {
+    Some(Range {
+        start: lines.offset_to_position(start, encoding)?,
+        end: lines.offset_to_position(end, encoding)?,
     })
 }
 
-fn extract_ident_at(text: &str, offset: usize) -> Option<String> {
+/// Most candidates listed when a hovered name is ambiguous.
+const MAX_CANDIDATES: usize = 5;
+
+/// A definition found for the hovered identifier.
+#[derive(Debug, Clone)]
+struct Definition {
+    snippet: String,
+    uri: Option<Uri>,
+    /// Zero-based line of the declaring keyword.
+    line: usize,
+    /// An `impl` header or a member inside an `impl` block, only shown when
+    /// no other item has the name.
+    fallback: bool,
+    /// Documentation shown below the snippet.
+    docs: Option<String>,
+    /// The library crate of a definition outside the workspace, e.g. `std`.
+    origin: Option<&'static str>,
+}
+
+impl Definition {
+    fn new(uri: Option<&Uri>, text: &str, snippet: String, offset: usize, fallback: bool) -> Self {
+        Definition {
+            snippet,
+            uri: uri.cloned(),
+            line: text[..offset].matches('\n').count(),
+            fallback,
+            docs: None,
+            origin: None,
+        }
+    }
+
+    /// Returns `path:line` with a one-based line.
+    fn location(&self, root: Option<&Path>) -> Option<String> {
+        let uri = self.uri.as_ref()?;
+        Some(format!("{}:{}", display_path(uri, root), self.line + 1))
+    }
+}
+
+/// Renders a single definition compactly, or lists every candidate with its
+/// location when the name is ambiguous.
+fn definitions_markdown(
+    definitions: &[Definition],
+    root: Option<&Path>,
+    config: &HoverConfig,
+) -> String {
+    let [definition] = definitions else {
+        let mut blocks: Vec<String> = definitions
+            .iter()
+            .take(MAX_CANDIDATES)
+            .map(|definition| match definition.location(root) {
+                Some(location) => format!("`{}`\n```rust\n{}\n```", location, definition.snippet),
+                None => format!("```rust\n{}\n```", definition.snippet),
+            })
+            .collect();
+        if definitions.len() > MAX_CANDIDATES {
+            blocks.push(format!("…and {} more", definitions.len() - MAX_CANDIDATES));
+        }
+        return blocks.join("\n\n");
+    };
+
+    let mut value = format!("```rust\n{}\n```", definition.snippet);
+    let location = match definition.origin {
+        Some(origin) => Some(format!("({})", origin)),
+        None => definition
+            .location(root)
+            .map(|location| format!("— defined in {}", location)),
+    };
+    if let Some(location) = location {
+        value.push_str(&format!("\n\n*{}*", location));
+    }
+    if config.show_docs
+        && let Some(docs) = &definition.docs
+    {
+        value.push_str("\n\n");
+        value.push_str(docs);
+    }
+    value
+}
+
+/// Converts rendered markdown to plain text by dropping code fences, inline
+/// code backticks and emphasis markers. Code inside fences is kept as is.
+fn plain_text(markdown: &str) -> String {
+    let mut lines = Vec::new();
+    let mut in_code = false;
+    for line in markdown.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+        } else if in_code {
+            lines.push(line.to_string());
+        } else {
+            lines.push(strip_inline_markup(line));
+        }
+    }
+    lines.join("\n")
+}
+
+/// Removes backticks and the `*` of `*emphasis*` and `**strong**`, keeping
+/// a `*` with whitespace or word characters on both sides, as in `a * b`.
+fn strip_inline_markup(line: &str) -> String {
+    let chars: Vec<char> = line.chars().collect();
+    let mut out = String::with_capacity(line.len());
+    for (i, &ch) in chars.iter().enumerate() {
+        if ch == '`' {
+            continue;
+        }
+        if ch == '*' {
+            let prev = i.checked_sub(1).map(|prev| chars[prev]);
+            let next = chars.get(i + 1).copied();
+            let opens = prev.is_none_or(|prev| prev.is_whitespace() || prev == '*')
+                && next.is_some_and(|next| !next.is_whitespace());
+            let closes = prev.is_some_and(|prev| !prev.is_whitespace())
+                && next.is_none_or(|next| !next.is_alphanumeric());
+            if opens || closes {
+                continue;
+            }
+        }
+        out.push(ch);
+    }
+    out
+}
+
+/// Returns the path of `uri` relative to `root` with `/` separators, or the
+/// full path or URI when it lies elsewhere.
+fn display_path(uri: &Uri, root: Option<&Path>) -> String {
+    let Some(path) = uri_to_path(uri) else {
+        return uri.as_str().to_string();
+    };
+    match root.and_then(|root| path.strip_prefix(root).ok()) {
+        Some(relative) => relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"),
+        None => path.display().to_string(),
+    }
+}
+
+/// Returns the span of the word under `offset` if it may be a keyword. Raw
+/// identifiers such as `r#match` and words inside strings or comments never
+/// count.
+fn keyword_at(tokens: &[Token], offset: usize) -> Option<(usize, usize)> {
+    let tok = tokens
+        .iter()
+        .find(|tok| tok.start <= offset && offset <= tok.end)?;
+    match tok.kind {
+        TokenKind::Ident(_) => Some((tok.start, tok.end)),
+        _ => None,
+    }
+}
+
+/// Returns the span of the `()` under `offset` when it names the unit type
+/// or value rather than the argument list of a call.
+fn unit_at(tokens: &[Token], offset: usize) -> Option<(usize, usize)> {
+    let idx = tokens
+        .iter()
+        .position(|tok| tok.start <= offset && offset < tok.end)?;
+    let open = match &tokens[idx] {
+        tok if tok.is_punct('(') => idx,
+        tok if tok.is_punct(')') && idx > 0 => idx - 1,
+        _ => return None,
+    };
+    let close = tokens.get(open + 1)?;
+    if !tokens[open].is_punct('(') || !close.is_punct(')') {
+        return None;
+    }
+    let is_call = open > 0 && {
+        let prev = &tokens[open - 1];
+        prev.ident().is_some_and(|_| !prev.is_keyword())
+            || prev.is_punct(')')
+            || prev.is_punct(']')
+            || prev.is_punct('>')
+            || prev.is_punct('!')
+    };
+    (!is_call).then_some((tokens[open].start, close.end))
+}
+
+fn ident_at(text: &str, offset: usize) -> Option<(usize, usize)> {
     if text.is_empty() {
         return None;
     }
@@ -50,102 +431,1880 @@ fn extract_ident_at(text: &str, offset: usize) -> Option<String> {
         }
     }
 
-    if start == end {
-        return None;
-    }
-
-    std::str::from_utf8(&bytes[start..end])
-        .ok()
-        .map(|s| s.to_string())
+    (start != end).then_some((start, end))
 }
 
 fn is_ident_char(b: u8) -> bool {
     b == b'_' || (b as char).is_ascii_alphanumeric()
 }
 
-fn find_definition(docs: &DocumentStore, ident: &str) -> Option<String> {
-    const KEYWORDS: [&str; 8] = [
-        "fn", "struct", "enum", "type", "const", "mod", "trait", "impl",
-    ];
+/// How the hovered identifier is used, so that `impl` members can be
+/// preferred over free items of the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MemberContext<'a> {
+    /// Not after a `.` or a `Type::` path.
+    None,
+    /// After a `.`, e.g. `vec.push(x)`.
+    Method,
+    /// A call through a type path, e.g. `Config::new()`.
+    Associated(&'a str),
+    /// A macro invocation such as `my_macro!(x)`, or the name in
+    /// `macro_rules! my_macro`.
+    Macro,
+}
 
-    for (_uri, doc) in docs.iter() {
-        for line in doc.text.lines() {
-            let mut trimmed = line.trim_start();
-            if trimmed.starts_with("//") || trimmed.starts_with("/*") {
-                continue;
-            }
+/// Returns the index of the identifier token under `offset`.
+fn ident_token(tokens: &[Token], offset: usize) -> Option<usize> {
+    tokens
+        .iter()
+        .position(|tok| tok.start <= offset && offset <= tok.end && tok.ident().is_some())
+}
+
+fn member_context<'a>(text: &'a str, tokens: &[Token], offset: usize) -> MemberContext<'a> {
+    let Some(idx) = ident_token(tokens, offset) else {
+        return MemberContext::None;
+    };
+    let is_invocation = tokens.get(idx + 1).is_some_and(|next| next.is_punct('!'))
+        && !tokens.get(idx + 2).is_some_and(|next| next.is_punct('='));
+    let is_macro_name =
+        idx >= 2 && tokens[idx - 1].is_punct('!') && tokens[idx - 2].is_ident("macro_rules");
+    if is_invocation || is_macro_name {
+        return MemberContext::Macro;
+    }
+    if idx == 0 {
+        return MemberContext::None;
+    }
+    let prev = &tokens[idx - 1];
+    if prev.is_punct('.') {
+        return MemberContext::Method;
+    }
+    if matches!(prev.kind, TokenKind::DoubleColon)
+        && tokens.get(idx + 1).is_some_and(|next| next.is_punct('('))
+        && idx >= 2
+        && tokens[idx - 2].ident().is_some()
+    {
+        let owner = &tokens[idx - 2];
+        return MemberContext::Associated(&text[owner.start..owner.end]);
+    }
+    MemberContext::None
+}
+
+/// Collects every definition of `ident`, sorted by path and line. Macros
+/// only match `macro_rules!` definitions. `impl` members are searched first
+/// when the context calls for them; otherwise, or when none match, every
+/// item with the name is a candidate.
+fn find_definitions(
+    docs: &DocumentStore,
+    root: Option<&Path>,
+    ident: &str,
+    context: MemberContext,
+    config: &HoverConfig,
+) -> Vec<Definition> {
+    let collect = |find: &dyn Fn(Option<&Uri>, &str) -> Vec<Definition>| {
+        let mut definitions = Vec::new();
+        visit_sources::<()>(docs, root, |uri, text| {
+            definitions.extend(find(uri, text));
+            ControlFlow::Continue(())
+        });
+        definitions
+    };
+    let owner = match context {
+        MemberContext::None => None,
+        MemberContext::Method => Some(None),
+        MemberContext::Associated(owner) => Some(Some(owner)),
+        MemberContext::Macro => {
+            let mut definitions = collect(&|uri, text| find_macros(uri, text, ident, config));
+            sort_by_location(&mut definitions);
+            return definitions;
+        }
+    };
 
-            trimmed = strip_pub_prefix(trimmed);
-
-            for keyword in &KEYWORDS {
-                if let Some(rest) = trimmed.strip_prefix(keyword) {
-                    let is_space = rest
-                        .chars()
-                        .next()
-                        .map(|c| c.is_whitespace())
-                        .unwrap_or(false);
-                    if !is_space {
-                        continue;
-                    }
-                    let rest = rest.trim_start();
-                    let name = take_ident(rest);
-                    if let Some(name) = name {
-                        if name == ident {
-                            return Some(line.trim().to_string());
-                        }
-                    }
-                }
+    let mut definitions = Vec::new();
+    if let Some(owner) = owner {
+        definitions = collect(&|uri, text| find_impl_members(uri, text, ident, owner, config));
+    }
+    if definitions.is_empty() {
+        definitions = collect(&|uri, text| find_definitions_in(uri, text, ident, config));
+        if definitions.iter().any(|definition| !definition.fallback) {
+            definitions.retain(|definition| !definition.fallback);
+        }
+    }
+    sort_by_location(&mut definitions);
+    definitions
+}
+
+/// Finds the target of an import, preferring definitions in the module the
+/// path names over others with the same name.
+fn find_imported(
+    docs: &DocumentStore,
+    root: Option<&Path>,
+    uri: &Uri,
+    path: &[&str],
+    config: &HoverConfig,
+) -> Vec<Definition> {
+    if let Some(module) = modules::resolve_module(docs, uri, path) {
+        return vec![module];
+    }
+    let Some((name, parents)) = path.split_last() else {
+        return Vec::new();
+    };
+    let mut definitions = find_definitions(docs, root, name, MemberContext::None, config);
+    let file = modules::module_file(docs, uri, parents).and_then(|file| path_to_uri(&file));
+    if file.is_some() && definitions.iter().any(|definition| definition.uri == file) {
+        definitions.retain(|definition| definition.uri == file);
+    }
+    definitions
+}
+
+fn sort_by_location(definitions: &mut [Definition]) {
+    definitions.sort_by(|a, b| {
+        let key = |definition: &Definition| {
+            (
+                definition.uri.as_ref().map(|uri| uri.as_str().to_string()),
+                definition.line,
+            )
+        };
+        key(a).cmp(&key(b))
+    });
+}
+
+/// Finds every `macro_rules! ident` and renders its doc comments and
+/// attributes, the matcher of its first rule and a marker for the rest.
+fn find_macros(
+    uri: Option<&Uri>,
+    text: &str,
+    ident: &str,
+    config: &HoverConfig,
+) -> Vec<Definition> {
+    let mut definitions = Vec::new();
+    let tokens = lex(text);
+    for (idx, tok) in tokens.iter().enumerate() {
+        if !tok.is_ident("macro_rules")
+            || !tokens.get(idx + 1).is_some_and(|bang| bang.is_punct('!'))
+            || tokens.get(idx + 2).and_then(Token::ident) != Some(ident)
+        {
+            continue;
+        }
+        let open = idx + 3;
+        let Some(close) = matching_group(&tokens, open) else {
+            continue;
+        };
+
+        let mut rules = Vec::new();
+        let mut rule = open + 1;
+        while rule < close
+            && let Some(matcher_close) = matching_group(&tokens, rule)
+        {
+            rules.push(collapse_whitespace(
+                &text[tokens[rule].start..tokens[matcher_close].end],
+            ));
+            // Skip `=>`, the transcriber and an optional `;`.
+            let Some(body_close) = matching_group(&tokens, matcher_close + 3) else {
+                break;
+            };
+            rule = body_close + 1;
+            if tokens.get(rule).is_some_and(|semi| semi.is_punct(';')) {
+                rule += 1;
             }
         }
+
+        let line_start = text[..tok.start].rfind('\n').map_or(0, |nl| nl + 1);
+        let mut lines: Vec<String> = leading_attributes(text, line_start)
+            .into_iter()
+            .map(str::to_string)
+            .collect();
+        lines.push(format!("macro_rules! {} {{", ident));
+        if let Some(first) = rules.first() {
+            lines.push(format!("    {} => {{ ... }};", first));
+        }
+        if rules.len() > 1 {
+            lines.push("    ...".to_string());
+        }
+        lines.push("}".to_string());
+        let snippet = truncate_lines(&lines.join("\n"), config.max_lines);
+        definitions.push(Definition::new(uri, text, snippet, tok.start, false));
     }
+    definitions
+}
 
-    None
+/// Returns the index closing the `(`, `[` or `{` group opened at `open`.
+fn matching_group(tokens: &[Token], open: usize) -> Option<usize> {
+    match tokens.get(open)?.kind {
+        TokenKind::Punct('(') => find_matching_paren(tokens, open),
+        TokenKind::Punct('[') => matching_bracket(tokens, open),
+        TokenKind::Punct('{') => find_matching_brace(tokens, open),
+        _ => None,
+    }
+}
+
+/// Returns the doc comment and attribute lines directly above `line_start`.
+fn leading_attributes(text: &str, line_start: usize) -> Vec<&str> {
+    let mut lines: Vec<&str> = text[..line_start]
+        .lines()
+        .rev()
+        .map(str::trim)
+        .take_while(|line| line.starts_with("///") || line.starts_with("#["))
+        .collect();
+    lines.reverse();
+    lines
 }
 
-fn strip_pub_prefix(line: &str) -> &str {
-    let trimmed = line.trim_start();
-    if let Some(rest) = trimmed.strip_prefix("pub") {
-        let rest = rest.trim_start();
-        if rest.starts_with('(') {
-            if let Some(idx) = rest.find(')') {
-                return rest[idx + 1..].trim_start();
+/// Finds every `fn ident` declared directly inside an `impl` block,
+/// optionally only in blocks whose self type is named `owner`.
+fn find_impl_members(
+    uri: Option<&Uri>,
+    text: &str,
+    ident: &str,
+    owner: Option<&str>,
+    config: &HoverConfig,
+) -> Vec<Definition> {
+    let mut definitions = Vec::new();
+    let tokens = lex(text);
+    for (idx, tok) in tokens.iter().enumerate() {
+        if !tok.is_ident("impl") || item_start(&tokens, idx).is_none() {
+            continue;
+        }
+        let Some(open) = tokens[idx..]
+            .iter()
+            .position(|tok| tok.is_punct('{'))
+            .map(|pos| idx + pos)
+        else {
+            continue;
+        };
+        if let Some(owner) = owner
+            && impl_self_type(&tokens[idx + 1..open]) != Some(owner)
+        {
+            continue;
+        }
+        let Some(close) = find_matching_brace(&tokens, open) else {
+            continue;
+        };
+
+        let mut depth = 0i32;
+        for member in open + 1..close {
+            let tok = &tokens[member];
+            if tok.is_punct('{') {
+                depth += 1;
+            } else if tok.is_punct('}') {
+                depth -= 1;
+            } else if depth == 0
+                && tok.is_ident("fn")
+                && tokens.get(member + 1).and_then(Token::ident) == Some(ident)
+                && let Some(item_start) = item_start(&tokens, member)
+                && let Some(snippet) = item_snippet(text, item_start, tok.start, config)
+            {
+                definitions.push(Definition::new(uri, text, snippet, tok.start, true));
             }
-            return rest;
         }
-        if rest
-            .chars()
-            .next()
-            .map(|c| c.is_whitespace())
-            .unwrap_or(false)
+    }
+    definitions
+}
+
+/// Returns the offset where the item whose keyword is at `idx` begins,
+/// including its visibility and qualifiers such as `const` or `unsafe`, or
+/// `None` when the keyword does not begin an item, e.g. `impl Trait` in
+/// argument position.
+fn item_start(tokens: &[Token], idx: usize) -> Option<usize> {
+    let mut start = idx;
+    while let Some(prev) = start.checked_sub(1).map(|prev| &tokens[prev]) {
+        if [
+            "pub", "const", "unsafe", "async", "extern", "default", "auto",
+        ]
+        .iter()
+        .any(|qualifier| prev.is_ident(qualifier))
+        {
+            start -= 1;
+        } else if prev.is_punct(')')
+            && let Some(open) = tokens[..start].iter().rposition(|tok| tok.is_punct('('))
+            && open > 0
+            && tokens[open - 1].is_ident("pub")
         {
-            return rest.trim_start();
+            // `pub(crate)` or `pub(in path)`.
+            start = open - 1;
+        } else if prev.is_punct('}')
+            || prev.is_punct('{')
+            || prev.is_punct(';')
+            || prev.is_punct(']')
+        {
+            break;
+        } else {
+            return None;
         }
-        return trimmed;
     }
+    Some(tokens[start].start)
+}
 
-    trimmed
+/// Returns every item named `ident` in `text`, matching an item keyword
+/// token followed by the name so that strings and comments are ignored.
+/// `impl` headers and members of `impl` blocks are marked as fallbacks.
+fn find_definitions_in(
+    uri: Option<&Uri>,
+    text: &str,
+    ident: &str,
+    config: &HoverConfig,
+) -> Vec<Definition> {
+    const KEYWORDS: [&str; 9] = [
+        "fn", "struct", "enum", "type", "const", "static", "mod", "trait", "impl",
+    ];
+
+    let mut definitions = Vec::new();
+    let tokens = lex(text);
+    for (idx, tok) in tokens.iter().enumerate() {
+        let Some(keyword) = KEYWORDS.iter().find(|keyword| tok.is_ident(keyword)) else {
+            continue;
+        };
+        let mut name = idx + 1;
+        if *keyword == "static" && tokens.get(name).is_some_and(|tok| tok.is_ident("mut")) {
+            name += 1;
+        }
+        if tokens.get(name).and_then(Token::ident) != Some(ident) {
+            continue;
+        }
+        let Some(item_start) = item_start(&tokens, idx) else {
+            continue;
+        };
+
+        let snippet = item_snippet(text, item_start, tok.start, config).unwrap_or_else(|| {
+            let line_end = text[item_start..]
+                .find('\n')
+                .map_or(text.len(), |nl| item_start + nl);
+            text[item_start..line_end].trim_end().to_string()
+        });
+        definitions.push(Definition::new(
+            uri,
+            text,
+            snippet,
+            tok.start,
+            *keyword == "impl",
+        ));
+    }
+
+    if !definitions.is_empty() {
+        let members: Vec<usize> = find_impl_members(uri, text, ident, None, config)
+            .iter()
+            .map(|member| member.line)
+            .collect();
+        for definition in &mut definitions {
+            definition.fallback |= members.contains(&definition.line);
+        }
+    }
+    definitions
 }
 
-fn take_ident(s: &str) -> Option<String> {
-    let mut chars = s.char_indices();
-    let Some((idx, first)) = chars.next() else {
+/// Extracts a `fn` signature up to its body, a whole `struct`/`enum`/`trait`
+/// declaration, or a `const`/`static` with its initializer, starting at
+/// `item_start`. Returns `None` for other items, which are rendered as their
+/// declaring line.
+fn item_snippet(
+    text: &str,
+    item_start: usize,
+    keyword_start: usize,
+    config: &HoverConfig,
+) -> Option<String> {
+    let rest = &text[keyword_start..];
+    let tokens = lex(rest);
+    let keyword = tokens.first()?.ident()?;
+    if !matches!(
+        keyword,
+        "fn" | "struct" | "enum" | "trait" | "const" | "static"
+    ) {
         return None;
+    }
+    // Initializers may hold struct literals and comparisons, so only `;`
+    // outside every bracket ends a `const` or `static`.
+    let is_value = matches!(keyword, "const" | "static");
+
+    let mut depth = 0i32;
+    let mut end = None;
+    let mut term = None;
+    for (idx, tok) in tokens.iter().enumerate() {
+        if tok.is_punct('(') || tok.is_punct('[') || (tok.is_punct('<') && !is_value) {
+            depth += 1;
+        } else if tok.is_punct(')') || tok.is_punct(']') || (tok.is_punct('>') && !is_value) {
+            depth -= 1;
+        } else if is_value && tok.is_punct('{') {
+            depth += 1;
+        } else if is_value && tok.is_punct('}') {
+            depth -= 1;
+        } else if depth == 0 && tok.is_punct(';') {
+            end = Some(tok.end);
+            term = Some(idx);
+            break;
+        } else if depth == 0 && tok.is_punct('{') {
+            end = if keyword == "fn" {
+                Some(tok.start)
+            } else {
+                find_matching_brace(&tokens, idx).map(|close| tokens[close].end)
+            };
+            term = Some(idx);
+            break;
+        }
+    }
+
+    let line_start = text[..item_start].rfind('\n').map_or(0, |nl| nl + 1);
+    let line = &text[line_start..item_start];
+    let indent = line.len() - line.trim_start().len();
+    let prefix = &text[item_start..keyword_start];
+    match keyword {
+        "struct" => return render_struct(prefix, rest, &tokens, term?, config),
+        "enum" => return render_enum(prefix, rest, &tokens, term?, config),
+        "trait" => return render_trait(prefix, rest, &tokens, term?, config),
+        _ => {}
+    }
+    let snippet = &text[item_start..keyword_start + end?];
+    Some(truncate_lines(
+        &dedent(snippet.trim_end(), indent),
+        config.max_lines,
+    ))
+}
+
+/// Renders a struct from its tokens so that field doc comments and
+/// attributes are dropped, e.g. `struct Meters(f64);` or one field per line
+/// for a braced body. `term` is the `{` opening the body or the `;` ending the
+/// declaration.
+fn render_struct(
+    prefix: &str,
+    rest: &str,
+    tokens: &[Token],
+    term: usize,
+    config: &HoverConfig,
+) -> Option<String> {
+    let header = |end: usize| collapse_whitespace(&format!("{}{}", prefix, &rest[..end]));
+
+    if tokens[term].is_punct('{') {
+        let close = find_matching_brace(tokens, term)?;
+        let fields = split_fields(rest, tokens, term + 1, close)
+            .into_iter()
+            .map(|field| format!("{},", field))
+            .collect::<Vec<_>>();
+        let header = header(tokens[term].start);
+        return Some(render_body(&header, &fields, "field", config));
+    }
+
+    let mut angle_depth = 0i32;
+    let open = tokens[..term].iter().position(|tok| {
+        if tok.is_punct('<') {
+            angle_depth += 1;
+        } else if tok.is_punct('>') {
+            angle_depth -= 1;
+        }
+        angle_depth == 0 && tok.is_punct('(')
+    });
+    let Some(open) = open else {
+        return Some(header(tokens[term].end));
     };
-    if !(first == '_' || first.is_ascii_alphabetic()) {
+    let close = find_matching_paren(tokens, open)?;
+    let fields = split_fields(rest, tokens, open + 1, close);
+    let tail = collapse_whitespace(&rest[tokens[close].end..tokens[term].start]);
+    let tail = if tail.is_empty() {
+        String::new()
+    } else {
+        format!(" {}", tail)
+    };
+    Some(format!(
+        "{}({}){};",
+        header(tokens[open].start),
+        fields.join(", "),
+        tail
+    ))
+}
+
+/// Renders an enum with one variant per line. Struct variants with more than
+/// a few fields are collapsed to `Variant { .. }`.
+fn render_enum(
+    prefix: &str,
+    rest: &str,
+    tokens: &[Token],
+    open: usize,
+    config: &HoverConfig,
+) -> Option<String> {
+    const MAX_VARIANT_FIELDS: usize = 3;
+
+    if !tokens[open].is_punct('{') {
         return None;
     }
-    let mut end = idx + first.len_utf8();
-    for (idx, ch) in chars {
-        if ch == '_' || ch.is_ascii_alphanumeric() {
-            end = idx + ch.len_utf8();
-        } else {
+    let close = find_matching_brace(tokens, open)?;
+    let variants = field_spans(tokens, open + 1, close)
+        .into_iter()
+        .map(|(first, end)| {
+            if tokens[first].ident().is_some()
+                && first + 1 < end
+                && tokens[first + 1].is_punct('{')
+                && let Some(body_close) = find_matching_brace(tokens, first + 1)
+                && field_spans(tokens, first + 2, body_close).len() > MAX_VARIANT_FIELDS
+            {
+                let name = &rest[tokens[first].start..tokens[first].end];
+                return format!("{} {{ .. }},", name);
+            }
+            let variant = collapse_whitespace(&rest[tokens[first].start..tokens[end - 1].end]);
+            format!("{},", variant)
+        })
+        .collect::<Vec<_>>();
+
+    let header = collapse_whitespace(&format!("{}{}", prefix, &rest[..tokens[open].start]));
+    Some(render_body(&header, &variants, "variant", config))
+}
+
+/// Renders a trait's associated items as signatures, one per line, with the
+/// bodies of provided methods elided to `{ ... }`.
+fn render_trait(
+    prefix: &str,
+    rest: &str,
+    tokens: &[Token],
+    open: usize,
+    config: &HoverConfig,
+) -> Option<String> {
+    if !tokens[open].is_punct('{') {
+        return None;
+    }
+    let close = find_matching_brace(tokens, open)?;
+    let mut items = Vec::new();
+    let mut idx = open + 1;
+    while idx < close {
+        while idx + 1 < close && tokens[idx].is_punct('#') && tokens[idx + 1].is_punct('[') {
+            idx = matching_bracket(tokens, idx + 1).map_or(close, |end| end + 1);
+        }
+        if idx >= close {
             break;
         }
+
+        let start = idx;
+        let is_fn = tokens[start..close]
+            .iter()
+            .find(|tok| {
+                tok.is_ident("fn") || tok.is_punct('(') || tok.is_punct(';') || tok.is_punct(':')
+            })
+            .is_some_and(|tok| tok.is_ident("fn"));
+        let mut depth = 0i32;
+        let mut item = None;
+        while idx < close {
+            let tok = &tokens[idx];
+            if tok.is_punct('{') && depth == 0 && is_fn {
+                let signature = collapse_whitespace(&rest[tokens[start].start..tok.start]);
+                item = Some(format!("{} {{ ... }}", signature.trim_end_matches(',')));
+                idx = find_matching_brace(tokens, idx).unwrap_or(close);
+                break;
+            } else if tok.is_punct(';') && depth == 0 {
+                item = Some(collapse_whitespace(&rest[tokens[start].start..tok.end]));
+                break;
+            } else if tok.is_punct('(')
+                || tok.is_punct('[')
+                || tok.is_punct('{')
+                || tok.is_punct('<')
+            {
+                depth += 1;
+            } else if tok.is_punct(')')
+                || tok.is_punct(']')
+                || tok.is_punct('}')
+                || tok.is_punct('>')
+            {
+                depth -= 1;
+            }
+            idx += 1;
+        }
+        items.extend(item);
+        idx += 1;
     }
 
-    if end == 0 {
-        None
+    let header = collapse_whitespace(&format!("{}{}", prefix, &rest[..tokens[open].start]));
+    Some(render_body(&header, &items, "item", config))
+}
+
+/// Renders `header { ... }` one item per line, replacing the items past
+/// `hover.maxLines` with a count of the omitted `noun`.
+fn render_body(header: &str, items: &[String], noun: &str, config: &HoverConfig) -> String {
+    let shown = if items.len() + 2 > config.max_lines {
+        config.max_lines.saturating_sub(1).max(1).min(items.len())
     } else {
-        Some(s[..end].to_string())
+        items.len()
+    };
+    let mut out = format!("{} {{\n", header);
+    for item in &items[..shown] {
+        out.push_str(&format!("    {}\n", item));
+    }
+    let omitted = items.len() - shown;
+    if omitted > 0 {
+        let plural = if omitted == 1 { "" } else { "s" };
+        out.push_str(&format!("    // ... {} more {}{}\n", omitted, noun, plural));
+    }
+    out.push('}');
+    out
+}
+
+/// Splits the tokens between `start` and `end` at top-level commas and
+/// returns each field's source with attributes removed.
+fn split_fields(rest: &str, tokens: &[Token], start: usize, end: usize) -> Vec<String> {
+    field_spans(tokens, start, end)
+        .into_iter()
+        .map(|(first, end)| collapse_whitespace(&rest[tokens[first].start..tokens[end - 1].end]))
+        .collect()
+}
+
+/// Returns the token range of each comma-separated item between `start` and
+/// `end`, skipping leading attributes. Angle brackets in a discriminant such
+/// as `A = 1 << 2` are not treated as nesting.
+fn field_spans(tokens: &[Token], start: usize, end: usize) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut depth = 0i32;
+    let mut in_expr = false;
+    let mut field_start = start;
+    for idx in start..=end {
+        let tok = &tokens[idx];
+        let angle = !in_expr && (tok.is_punct('<') || tok.is_punct('>'));
+        if idx < end
+            && (tok.is_punct('(')
+                || tok.is_punct('[')
+                || tok.is_punct('{')
+                || (angle && tok.is_punct('<')))
+        {
+            depth += 1;
+        } else if idx < end
+            && (tok.is_punct(')')
+                || tok.is_punct(']')
+                || tok.is_punct('}')
+                || (angle && tok.is_punct('>')))
+        {
+            depth -= 1;
+        } else if depth == 0 && tok.is_punct('=') {
+            in_expr = true;
+        } else if idx == end || (depth == 0 && tok.is_punct(',')) {
+            let mut first = field_start;
+            while first + 1 < idx && tokens[first].is_punct('#') && tokens[first + 1].is_punct('[')
+            {
+                first = matching_bracket(tokens, first + 1).map_or(idx, |close| close + 1);
+            }
+            if first < idx {
+                spans.push((first, idx));
+            }
+            field_start = idx + 1;
+            in_expr = false;
+        }
+    }
+    spans
+}
+
+fn matching_bracket(tokens: &[Token], open: usize) -> Option<usize> {
+    let mut depth = 0i32;
+    for (idx, tok) in tokens.iter().enumerate().skip(open) {
+        if tok.is_punct('[') {
+            depth += 1;
+        } else if tok.is_punct(']') {
+            depth -= 1;
+            if depth == 0 {
+                return Some(idx);
+            }
+        }
+    }
+    None
+}
+
+/// Removes up to `indent` leading whitespace bytes from every line after the
+/// first, which has already been trimmed.
+fn dedent(snippet: &str, indent: usize) -> String {
+    let mut lines = snippet.lines();
+    let mut out = lines.next().unwrap_or_default().to_string();
+    for line in lines {
+        let strip = line
+            .bytes()
+            .take(indent)
+            .take_while(|b| b.is_ascii_whitespace())
+            .count();
+        out.push('\n');
+        out.push_str(&line[strip..]);
+    }
+    out
+}
+
+/// Keeps the first `max_lines` lines, marking the cut with `...` and keeping
+/// the closing brace of a truncated body.
+fn truncate_lines(snippet: &str, max_lines: usize) -> String {
+    let lines: Vec<&str> = snippet.lines().collect();
+    if lines.len() <= max_lines {
+        return snippet.to_string();
+    }
+    let mut out = lines[..max_lines].join("\n");
+    out.push_str("\n    ...");
+    if snippet.ends_with('}') {
+        out.push_str("\n}");
+    }
+    out
+}
+
+/// Strips attributes written before an item on the same line, e.g.
+/// `#[no_mangle]`.
+fn strip_attributes(line: &str) -> &str {
+    let mut rest = line;
+    while let Some(attr) = rest.strip_prefix("#[") {
+        let mut depth = 1;
+        let Some(close) = attr.find(|c| {
+            match c {
+                '[' => depth += 1,
+                ']' => depth -= 1,
+                _ => {}
+            }
+            depth == 0
+        }) else {
+            return rest;
+        };
+        rest = attr[close + 1..].trim_start();
+    }
+    rest
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use lsp_types::TextDocumentItem;
+
+    use super::*;
+    use crate::config::Config;
+    use crate::doc::position::offset_to_position;
+    use crate::doc::uri::path_to_uri;
+    use crate::test_support::temp_dir;
+
+    fn fixture_dir(name: &str) -> PathBuf {
+        let dir = temp_dir(&format!("hover-{name}"));
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(
+            dir.join("src/util.rs"),
+            "/// Helper.\npub fn helper(x: i32) -> i32 {\n    x\n}\n",
+        )
+        .unwrap();
+        dir
+    }
+
+    fn open(docs: &mut DocumentStore, path: &Path, text: &str) -> Uri {
+        let uri = path_to_uri(path).unwrap();
+        docs.open(TextDocumentItem {
+            uri: uri.clone(),
+            language_id: "rust".to_string(),
+            version: 1,
+            text: text.to_string(),
+        });
+        uri
+    }
+
+    fn snippet(text: &str, ident: &str, config: &HoverConfig) -> Option<String> {
+        find_definitions_in(None, text, ident, config)
+            .into_iter()
+            .next()
+            .map(|definition| definition.snippet)
+    }
+
+    fn hover_text(hover: Hover) -> String {
+        match hover.contents {
+            HoverContents::Markup(markup) => markup.value,
+            other => panic!("unexpected hover contents: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn hover_finds_definitions_in_unopened_files() {
+        let dir = fixture_dir("unopened");
+        let mut docs = DocumentStore::new();
+        let uri = open(
+            &mut docs,
+            &dir.join("src/main.rs"),
+            "fn main() { helper(1); }",
+        );
+
+        let config = HoverConfig::default();
+        let position = Position::new(0, 14);
+        let result = hover(
+            &docs,
+            Some(&dir),
+            &uri,
+            position,
+            &config,
+            MarkupKind::Markdown,
+            PositionEncoding::Utf16,
+        );
+        assert_eq!(
+            hover_text(result.unwrap()),
+            "```rust\npub fn helper(x: i32) -> i32\n```\n\n*— defined in src/util.rs:2*"
+        );
+        let result = hover(
+            &docs,
+            Some(&dir),
+            &uri,
+            position,
+            &config,
+            MarkupKind::PlainText,
+            PositionEncoding::Utf16,
+        );
+        assert_eq!(
+            hover_text(result.unwrap()),
+            "pub fn helper(x: i32) -> i32\n\n— defined in src/util.rs:2"
+        );
+        let result = hover(
+            &docs,
+            None,
+            &uri,
+            position,
+            &config,
+            MarkupKind::Markdown,
+            PositionEncoding::Utf16,
+        );
+        assert!(result.is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn hover_helper(docs: &DocumentStore, dir: &Path, format: MarkupKind) -> String {
+        let main = path_to_uri(&dir.join("src/main.rs")).unwrap();
+        let result = hover(
+            docs,
+            Some(dir),
+            &main,
+            Position::new(0, 14),
+            &HoverConfig::default(),
+            format,
+            PositionEncoding::Utf16,
+        );
+        hover_text(result.unwrap())
+    }
+
+    #[test]
+    fn definitions_in_other_files_give_their_relative_path_and_line() {
+        let dir = fixture_dir("defined-in");
+        fs::create_dir_all(dir.join("src/nested")).unwrap();
+        fs::write(
+            dir.join("src/nested/deep.rs"),
+            "// Deep.\n\n\npub fn deep() {}\n",
+        )
+        .unwrap();
+        let mut docs = DocumentStore::new();
+        open(
+            &mut docs,
+            &dir.join("src/main.rs"),
+            "fn main() { helper(deep()); }",
+        );
+
+        let text = hover_helper(&docs, &dir, MarkupKind::Markdown);
+        assert!(text.ends_with("\n\n*— defined in src/util.rs:2*"), "{text}");
+        let main = path_to_uri(&dir.join("src/main.rs")).unwrap();
+        let result = hover(
+            &docs,
+            Some(&dir),
+            &main,
+            Position::new(0, 21),
+            &HoverConfig::default(),
+            MarkupKind::Markdown,
+            PositionEncoding::Utf16,
+        );
+        assert_eq!(
+            hover_text(result.unwrap()),
+            "```rust\npub fn deep()\n```\n\n*— defined in src/nested/deep.rs:4*"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn definitions_in_unsaved_buffers_give_the_buffer_line() {
+        let dir = fixture_dir("defined-in-buffer");
+        let mut docs = DocumentStore::new();
+        open(
+            &mut docs,
+            &dir.join("src/main.rs"),
+            "fn main() { helper(1); }",
+        );
+        // On disk `helper` is on line 2; the buffer moved it down.
+        open(
+            &mut docs,
+            &dir.join("src/util.rs"),
+            "use std::fmt;\n\n\n\npub fn helper(x: i32) -> i32 {\n    x\n}\n",
+        );
+
+        let text = hover_helper(&docs, &dir, MarkupKind::Markdown);
+        assert!(text.ends_with("*— defined in src/util.rs:5*"), "{text}");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn plaintext_definition_lines_have_no_emphasis() {
+        let dir = fixture_dir("defined-in-plaintext");
+        let mut docs = DocumentStore::new();
+        open(
+            &mut docs,
+            &dir.join("src/main.rs"),
+            "fn main() { helper(1); }",
+        );
+
+        let text = hover_helper(&docs, &dir, MarkupKind::PlainText);
+        assert_eq!(text.lines().last(), Some("— defined in src/util.rs:2"));
+        assert!(!text.contains('*') && !text.contains('`'), "{text}");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hover_reads_files_that_were_never_opened() {
+        let dir = fixture_dir("never-opened");
+        let main = dir.join("src/main.rs");
+        fs::write(&main, "fn main() { helper(1); }").unwrap();
+        let docs = DocumentStore::new();
+
+        let result = hover(
+            &docs,
+            Some(&dir),
+            &path_to_uri(&main).unwrap(),
+            Position::new(0, 14),
+            &HoverConfig::default(),
+            MarkupKind::PlainText,
+            PositionEncoding::Utf16,
+        );
+        assert_eq!(
+            hover_text(result.unwrap()),
+            "pub fn helper(x: i32) -> i32\n\n— defined in src/util.rs:2"
+        );
+        assert_eq!(docs.iter().count(), 0);
+
+        let missing = path_to_uri(&dir.join("src/missing.rs")).unwrap();
+        let result = hover(
+            &docs,
+            Some(&dir),
+            &missing,
+            Position::new(0, 0),
+            &HoverConfig::default(),
+            MarkupKind::PlainText,
+            PositionEncoding::Utf16,
+        );
+        assert!(result.is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hover_clamps_positions_past_the_text() {
+        let mut docs = DocumentStore::new();
+        let src = "fn helper() {}\nstatic F: fn() = helper\nstatic G: fn() = helper";
+        let uri = open(&mut docs, Path::new("/clamp/main.rs"), src);
+        let config = HoverConfig::default();
+        let text = |position| {
+            hover(
+                &docs,
+                None,
+                &uri,
+                position,
+                &config,
+                MarkupKind::PlainText,
+                PositionEncoding::Utf16,
+            )
+            .map(hover_text)
+        };
+
+        let expected = Some("fn helper()\n\n— defined in /clamp/main.rs:1".to_string());
+        assert_eq!(text(Position::new(1, 200)), expected);
+        assert_eq!(text(Position::new(2, u32::MAX)), expected);
+        assert_eq!(text(Position::new(999_999, 0)), expected);
+    }
+
+    #[test]
+    fn hover_skips_oversized_documents() {
+        let mut docs = DocumentStore::new();
+        let src = "fn helper() {}\nstatic F: fn() = helper\n";
+        let uri = open(&mut docs, Path::new("/large/main.rs"), src);
+        let config = HoverConfig::default();
+        let hover_at = |docs: &DocumentStore| {
+            hover(
+                docs,
+                None,
+                &uri,
+                Position::new(1, 18),
+                &config,
+                MarkupKind::PlainText,
+                PositionEncoding::Utf16,
+            )
+        };
+        assert!(hover_at(&docs).is_some());
+
+        docs.set_max_analyzed_size(src.len() - 1);
+        let started = std::time::Instant::now();
+        assert!(hover_at(&docs).is_none());
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn hover_prefers_unsaved_open_documents() {
+        let dir = fixture_dir("unsaved");
+        let mut docs = DocumentStore::new();
+        let uri = open(
+            &mut docs,
+            &dir.join("src/main.rs"),
+            "fn main() { helper(1); }",
+        );
+        open(
+            &mut docs,
+            &dir.join("src/util.rs"),
+            "pub fn helper(x: u8) {}\n",
+        );
+
+        let config = HoverConfig::default();
+        let result = hover(
+            &docs,
+            Some(&dir),
+            &uri,
+            Position::new(0, 14),
+            &config,
+            MarkupKind::Markdown,
+            PositionEncoding::Utf16,
+        );
+        assert!(
+            hover_text(result.unwrap())
+                .ends_with("pub fn helper(x: u8)\n```\n\n*— defined in src/util.rs:1*")
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hover_ignores_strings_and_comments() {
+        let config = HoverConfig::default();
+        let src = "fn main() {
+    let msg = \"fn hover_me() {}\";
+    /* struct Foo;
+       /* nested */ enum Foo {} */
+}
+// fn hover_me(x: u8) {}
+#[inline] pub(crate) const unsafe fn hover_me(x: u32) {}
+fn takes(x: impl Foo) {}
+impl Foo for u8 {}";
+
+        assert_eq!(
+            snippet(src, "hover_me", &config).unwrap(),
+            "pub(crate) const unsafe fn hover_me(x: u32)"
+        );
+        assert_eq!(snippet(src, "Foo", &config).unwrap(), "impl Foo for u8 {}");
+    }
+
+    #[test]
+    fn hover_renders_multi_line_fn_signatures() {
+        let src = "impl Runner {
+    pub fn run_check(
+        root: &Path,
+        command: &[String],
+    ) -> Result<Vec<String>, String> {
+        todo!()
+    }
+}";
+        let snippet = snippet(src, "run_check", &HoverConfig::default());
+        assert_eq!(
+            snippet.as_deref(),
+            Some(
+                "pub fn run_check(
+    root: &Path,
+    command: &[String],
+) -> Result<Vec<String>, String>"
+            )
+        );
+    }
+
+    #[test]
+    fn hover_renders_struct_and_enum_bodies() {
+        let src = "struct Pair<T> {
+    left: T,
+    right: T,
+}
+struct Meters(f64);
+enum Color {
+    Red,
+    Green,
+    Blue,
+    Custom(u8, u8, u8),
+}";
+        let config = HoverConfig {
+            max_lines: 4,
+            ..HoverConfig::default()
+        };
+        assert_eq!(
+            snippet(src, "Pair", &config).as_deref(),
+            Some("struct Pair<T> {\n    left: T,\n    right: T,\n}")
+        );
+        assert_eq!(
+            snippet(src, "Meters", &config).as_deref(),
+            Some("struct Meters(f64);")
+        );
+        assert_eq!(
+            snippet(src, "Color", &config).as_deref(),
+            Some("enum Color {\n    Red,\n    Green,\n    Blue,\n    // ... 1 more variant\n}")
+        );
+    }
+
+    #[test]
+    fn hover_renders_struct_fields() {
+        let src = "/// A cache.
+pub struct Cache<K, V: Clone> {
+    /// Stored entries.
+    pub entries: HashMap<K, Vec<V>>,
+    #[serde(default)]
+    capacity: usize,
+    hits: Option<(K, V)>,
+}
+pub(crate) struct Wrapper<T>(
+    /// The inner value.
+    pub T,
+    String,
+) where T: Copy;
+struct Marker;
+struct Big { a: u8, b: u8, c: u8, d: u8, e: u8 }";
+        let config = HoverConfig::default();
+        assert_eq!(
+            snippet(src, "Cache", &config).as_deref(),
+            Some(
+                "pub struct Cache<K, V: Clone> {
+    pub entries: HashMap<K, Vec<V>>,
+    capacity: usize,
+    hits: Option<(K, V)>,
+}"
+            )
+        );
+        assert_eq!(
+            snippet(src, "Wrapper", &config).as_deref(),
+            Some("pub(crate) struct Wrapper<T>(pub T, String) where T: Copy;")
+        );
+        assert_eq!(
+            snippet(src, "Marker", &config).as_deref(),
+            Some("struct Marker;")
+        );
+
+        let config = HoverConfig {
+            max_lines: 3,
+            ..HoverConfig::default()
+        };
+        assert_eq!(
+            snippet(src, "Big", &config).as_deref(),
+            Some("struct Big {\n    a: u8,\n    b: u8,\n    // ... 3 more fields\n}")
+        );
+    }
+
+    #[test]
+    fn hover_renders_enum_variants() {
+        let src = "pub enum Shape<T> {
+    /// No shape.
+    Empty,
+    Red = 1,
+    Flag = 1 << 2,
+    Point(T, T),
+    #[allow(dead_code)]
+    Rect { w: T, h: T },
+    Mesh {
+        vertices: Vec<T>,
+        indices: Vec<u32>,
+        normals: Vec<T>,
+        name: String,
+    },
+}";
+        let config = HoverConfig::default();
+        assert_eq!(
+            snippet(src, "Shape", &config).as_deref(),
+            Some(
+                "pub enum Shape<T> {
+    Empty,
+    Red = 1,
+    Flag = 1 << 2,
+    Point(T, T),
+    Rect { w: T, h: T },
+    Mesh { .. },
+}"
+            )
+        );
+    }
+
+    #[test]
+    fn hover_renders_const_and_static_values() {
+        let config = HoverConfig::default();
+        let src = "const MAX_RETRIES: u32 = 5;
+pub const LIMITS: Limits = Limits {
+    max: if A < B { 1 } else { 2 },
+};
+#[no_mangle] pub static GREETING: &str = \"hi; there\";
+static mut COUNTER: [u8; 2] = [0; 2];";
+
+        assert_eq!(
+            snippet(src, "MAX_RETRIES", &config).unwrap(),
+            "const MAX_RETRIES: u32 = 5;"
+        );
+        assert_eq!(
+            snippet(src, "LIMITS", &config).unwrap(),
+            "pub const LIMITS: Limits = Limits {\n    max: if A < B { 1 } else { 2 },\n};"
+        );
+        assert_eq!(
+            snippet(src, "GREETING", &config).unwrap(),
+            "pub static GREETING: &str = \"hi; there\";"
+        );
+        assert_eq!(
+            snippet(src, "COUNTER", &config).unwrap(),
+            "static mut COUNTER: [u8; 2] = [0; 2];"
+        );
+
+        let config = HoverConfig {
+            max_lines: 2,
+            ..HoverConfig::default()
+        };
+        assert_eq!(
+            snippet(src, "LIMITS", &config).unwrap(),
+            "pub const LIMITS: Limits = Limits {\n    max: if A < B { 1 } else { 2 },\n    ..."
+        );
+    }
+
+    #[test]
+    fn hover_renders_trait_items() {
+        let src = "pub trait Store: Send + Sync where Self: Sized {
+    /// The key type.
+    type Key: Eq + Hash;
+    const CAPACITY: usize = 16;
+
+    fn get(&self, key: &Self::Key) -> Option<String>;
+
+    #[inline]
+    fn contains(&self, key: &Self::Key) -> bool {
+        self.get(key).is_some()
+    }
+
+    fn insert_all<I>(&mut self, items: I)
+    where
+        I: IntoIterator<Item = (Self::Key, String)>,
+    {
+        for (key, value) in items {
+            self.insert(key, value);
+        }
+    }
+}";
+        let config = HoverConfig::default();
+        assert_eq!(
+            snippet(src, "Store", &config).as_deref(),
+            Some(
+                "pub trait Store: Send + Sync where Self: Sized {
+    type Key: Eq + Hash;
+    const CAPACITY: usize = 16;
+    fn get(&self, key: &Self::Key) -> Option<String>;
+    fn contains(&self, key: &Self::Key) -> bool { ... }
+    fn insert_all<I>(&mut self, items: I) where I: IntoIterator<Item = (Self::Key, String)> { ... }
+}"
+            )
+        );
+
+        let methods = (0..10)
+            .map(|i| {
+                format!(
+                    "    fn method{}(&self) -> u32 {{\n        {}\n    }}\n",
+                    i, i
+                )
+            })
+            .collect::<String>();
+        let src = format!("trait Many {{\n{}}}", methods);
+        let snippet = snippet(&src, "Many", &config).unwrap();
+        assert_eq!(snippet.lines().count(), 12);
+        assert!(snippet.contains("    fn method9(&self) -> u32 { ... }\n}"));
+    }
+
+    #[test]
+    fn hover_explains_keywords() {
+        let mut docs = DocumentStore::new();
+        let src = "fn main() { match r#match { _ => \"match\" } }";
+        let uri = open(&mut docs, Path::new("/keywords/main.rs"), src);
+        let at = |needle: &str| Position::new(0, src.find(needle).unwrap() as u32 + 1);
+
+        let config = HoverConfig::default();
+        let result = hover(
+            &docs,
+            None,
+            &uri,
+            at("match r#"),
+            &config,
+            MarkupKind::Markdown,
+            PositionEncoding::Utf16,
+        );
+        assert!(hover_text(result.unwrap()).starts_with("`match` keyword"));
+        assert!(
+            hover(
+                &docs,
+                None,
+                &uri,
+                at("r#match"),
+                &config,
+                MarkupKind::Markdown,
+                PositionEncoding::Utf16
+            )
+            .is_none()
+        );
+        assert!(
+            hover(
+                &docs,
+                None,
+                &uri,
+                at("\"match"),
+                &config,
+                MarkupKind::Markdown,
+                PositionEncoding::Utf16
+            )
+            .is_none()
+        );
+
+        let config = HoverConfig {
+            keywords: false,
+            ..HoverConfig::default()
+        };
+        assert!(
+            hover(
+                &docs,
+                None,
+                &uri,
+                at("match r#"),
+                &config,
+                MarkupKind::Markdown,
+                PositionEncoding::Utf16
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn hover_describes_primitives_unless_shadowed() {
+        let mut docs = DocumentStore::new();
+        let src = "type i128 = Wide;\nstruct r#usize;\nfn run(x: u32, y: i128, z: usize) -> () { f(); Ok(()) }";
+        let uri = open(&mut docs, Path::new("/primitives/main.rs"), src);
+        let at = |needle: &str| {
+            offset_to_position(src, src.rfind(needle).unwrap(), PositionEncoding::Utf16).unwrap()
+        };
+        let config = HoverConfig::default();
+        let text = |position| {
+            hover(
+                &docs,
+                None,
+                &uri,
+                position,
+                &config,
+                MarkupKind::Markdown,
+                PositionEncoding::Utf16,
+            )
+            .map(hover_text)
+        };
+
+        let u32_doc = text(at("u32")).unwrap();
+        assert!(u32_doc.starts_with("```rust\nu32\n```"));
+        assert!(u32_doc.contains("Range: `0..=4294967295`"));
+        assert_eq!(
+            text(at("i128")).as_deref(),
+            Some("```rust\ntype i128 = Wide;\n```\n\n*— defined in /primitives/main.rs:1*")
+        );
+        assert_eq!(
+            text(at("usize")).as_deref(),
+            Some("```rust\nstruct r#usize;\n```\n\n*— defined in /primitives/main.rs:2*")
+        );
+
+        assert!(text(at("() {")).unwrap().contains("The unit type"));
+        assert!(text(at("())")).unwrap().contains("The unit type"));
+        assert!(text(at("(); O")).is_none());
+    }
+
+    #[test]
+    fn hover_prefers_impl_members_by_context() {
+        let mut docs = DocumentStore::new();
+        let src = "fn push(item: i32) {}
+fn new() -> u8 { 0 }
+struct Stack;
+impl Stack {
+    pub fn push(&mut self, value: u8) {}
+}
+impl<T> Default for Queue<T> where T: Copy {
+    fn new() -> Queue<T> { Queue }
+}
+fn main(stack: Stack) { stack.push(1); push(2); Queue::new(); Other::new(); }";
+        let uri = open(&mut docs, Path::new("/members/main.rs"), src);
+        let at = |needle: &str| {
+            let offset = src.rfind(needle).unwrap() + needle.len() - 1;
+            offset_to_position(src, offset, PositionEncoding::Utf16).unwrap()
+        };
+        let config = HoverConfig::default();
+        let text = |position| {
+            hover(
+                &docs,
+                None,
+                &uri,
+                position,
+                &config,
+                MarkupKind::Markdown,
+                PositionEncoding::Utf16,
+            )
+            .map(hover_text)
+        };
+
+        let fenced = |code: &str, line: u32| {
+            Some(format!(
+                "```rust\n{}\n```\n\n*— defined in /members/main.rs:{}*",
+                code, line
+            ))
+        };
+        assert_eq!(
+            text(at(".push")),
+            fenced("pub fn push(&mut self, value: u8)", 5)
+        );
+        assert_eq!(text(at(" push")), fenced("fn push(item: i32)", 1));
+        assert_eq!(text(at("Queue::new")), fenced("fn new() -> Queue<T>", 8));
+        assert_eq!(text(at("Other::new")), fenced("fn new() -> u8", 2));
+    }
+
+    #[test]
+    fn hover_renders_macro_rules() {
+        let mut docs = DocumentStore::new();
+        let src = "/// Squares a value.
+#[macro_export]
+macro_rules! square {
+    ($x:expr) => {
+        $x * $x
+    };
+    ($x:expr, $($rest:tt)*) => { square!($x) };
+}
+fn square() {}
+fn main() { let ok = square!(2); if ok != true {} }";
+        let uri = open(&mut docs, Path::new("/macros/main.rs"), src);
+        let config = HoverConfig::default();
+        let text = |offset: usize| {
+            let position = offset_to_position(src, offset, PositionEncoding::Utf16).unwrap();
+            hover(
+                &docs,
+                None,
+                &uri,
+                position,
+                &config,
+                MarkupKind::Markdown,
+                PositionEncoding::Utf16,
+            )
+            .map(hover_text)
+        };
+
+        let expected = "```rust
+/// Squares a value.
+#[macro_export]
+macro_rules! square {
+    ($x:expr) => { ... };
+    ...
+}
+```
+
+*— defined in /macros/main.rs:3*";
+        assert_eq!(text(src.rfind("square!").unwrap()).unwrap(), expected);
+        assert_eq!(text(src.find("square {").unwrap()).unwrap(), expected);
+        assert_eq!(
+            text(src.find("fn square").unwrap() + 3).unwrap(),
+            "```rust\nfn square()\n```\n\n*— defined in /macros/main.rs:9*"
+        );
+        assert!(text(src.find("ok !=").unwrap()).is_none());
+    }
+
+    #[test]
+    fn hover_resolves_module_paths() {
+        let dir = fixture_dir("modules");
+        fs::create_dir_all(dir.join("src/doc")).unwrap();
+        fs::write(dir.join("src/main.rs"), "mod doc;\n").unwrap();
+        fs::write(dir.join("src/doc/mod.rs"), "pub mod store;\n").unwrap();
+        fs::write(
+            dir.join("src/doc/store.rs"),
+            "//! Open document storage.\n//!\n//! Keyed by URI.\npub struct DocumentStore;\n",
+        )
+        .unwrap();
+        let mut docs = DocumentStore::new();
+        let src = "mod doc;
+/// Inline helpers.
+pub mod util {
+    pub fn helper() {}
+}
+use crate::doc::store::DocumentStore;
+fn main() { doc::store::open(); util::helper(); self::doc::Thing; }";
+        let uri = open(&mut docs, &dir.join("src/main.rs"), src);
+        let config = HoverConfig::default();
+        let text = |needle: &str| {
+            let position =
+                offset_to_position(src, src.rfind(needle).unwrap(), PositionEncoding::Utf16)
+                    .unwrap();
+            hover(
+                &docs,
+                Some(&dir),
+                &uri,
+                position,
+                &config,
+                MarkupKind::Markdown,
+                PositionEncoding::Utf16,
+            )
+            .map(hover_text)
+        };
+
+        let store = "```rust\nmod store\n```\n\n*— defined in src/doc/store.rs:1*\n\n\
+                     Open document storage.\n\nKeyed by URI.";
+        assert_eq!(text("store::Doc").unwrap(), store);
+        assert_eq!(text("store::open").unwrap(), store);
+        assert_eq!(
+            text("doc::Thing").unwrap(),
+            "```rust\nmod doc\n```\n\n*— defined in src/doc/mod.rs:1*"
+        );
+        assert_eq!(text("doc;").unwrap(), text("doc::Thing").unwrap());
+        assert_eq!(
+            text("util::").unwrap(),
+            "```rust\npub mod util\n```\n\n*— defined in src/main.rs:3*\n\nInline helpers."
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hover_resolves_through_use_statements() {
+        let dir = fixture_dir("imports");
+        fs::create_dir_all(dir.join("src/doc")).unwrap();
+        fs::write(dir.join("src/main.rs"), "").unwrap();
+        fs::write(dir.join("src/doc/mod.rs"), "pub mod store;\n").unwrap();
+        fs::write(dir.join("src/doc/store.rs"), "pub struct DocumentStore;\n").unwrap();
+        fs::write(dir.join("src/other.rs"), "struct DocumentStore(u8);\n").unwrap();
+        fs::write(dir.join("src/foo.rs"), "\npub struct Thing;\n").unwrap();
+        let mut docs = DocumentStore::new();
+        let src = "use crate::doc::store::DocumentStore;
+use crate::{foo::{self as bar, Thing as Widget}, doc::*};
+fn main(s: DocumentStore, w: Widget) { bar::run(); }";
+        let uri = open(&mut docs, &dir.join("src/main.rs"), src);
+        let config = HoverConfig::default();
+        let text = |needle: &str| {
+            let position =
+                offset_to_position(src, src.rfind(needle).unwrap(), PositionEncoding::Utf16)
+                    .unwrap();
+            hover(
+                &docs,
+                Some(&dir),
+                &uri,
+                position,
+                &config,
+                MarkupKind::Markdown,
+                PositionEncoding::Utf16,
+            )
+            .map(hover_text)
+        };
+
+        let store = "```rust\npub struct DocumentStore;\n```\n\n*— defined in src/doc/store.rs:1*";
+        assert_eq!(text("DocumentStore,").unwrap(), store);
+        assert_eq!(text("DocumentStore;").unwrap(), store);
+        let thing = "```rust\npub struct Thing;\n```\n\n*— defined in src/foo.rs:2*";
+        assert_eq!(text("Widget)").unwrap(), thing);
+        assert_eq!(text("Widget}").unwrap(), thing);
+        assert_eq!(
+            text("bar::").unwrap(),
+            "```rust\nmod foo\n```\n\n*— defined in src/foo.rs:1*"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hover_resolves_struct_fields() {
+        let mut docs = DocumentStore::new();
+        let src = "pub struct Document {
+    /// Full text of the document.
+    #[serde(default)]
+    pub text: String,
+    pub(crate) meta: Meta<u8>,
+}
+struct Meta<T> { version: T }
+fn text() {}
+impl Document {
+    fn len(&self) -> usize { self.text.len() }
+}
+fn show(doc: &Document, other: Unknown) {
+    let copy: Document = doc.clone();
+    doc.text; copy.meta.version; other.text; doc.text();
+}";
+        let uri = open(&mut docs, Path::new("/fields/main.rs"), src);
+        let config = HoverConfig::default();
+        let text = |needle: &str| {
+            // Hover the first character after the last `.` of `needle`.
+            let offset = src.rfind(needle).unwrap() + needle.rfind('.').unwrap() + 1;
+            let position = offset_to_position(src, offset, PositionEncoding::Utf16).unwrap();
+            hover(
+                &docs,
+                None,
+                &uri,
+                position,
+                &config,
+                MarkupKind::Markdown,
+                PositionEncoding::Utf16,
+            )
+            .map(hover_text)
+        };
+
+        let field = "```rust\nDocument::text: String\n```\n\n\
+                     *— defined in /fields/main.rs:4*\n\nFull text of the document.";
+        assert_eq!(text("doc.text;").unwrap(), field);
+        assert_eq!(text("self.text").unwrap(), field);
+        assert_eq!(
+            text("meta.version").unwrap(),
+            "```rust\nMeta::version: T\n```\n\n*— defined in /fields/main.rs:7*"
+        );
+        let function = "```rust\nfn text()\n```\n\n*— defined in /fields/main.rs:8*";
+        assert_eq!(text("other.text;").unwrap(), function);
+        assert_eq!(text("doc.text()").unwrap(), function);
+    }
+
+    #[test]
+    fn hover_resolves_generic_params_in_scope() {
+        let mut docs = DocumentStore::new();
+        let src = "struct T;
+fn convert<T: Into<String>>(value: T) -> String where T: Clone, Vec<T>: Debug { value.into() }
+impl<'a, T> Wrapper<'a, T> {
+    fn get<U>(&'a self, other: U) -> &'a T where U: Copy { other; self.0 }
+}
+fn plain(value: T) {}";
+        let uri = open(&mut docs, Path::new("/generics/main.rs"), src);
+        let config = HoverConfig::default();
+        let text = |needle: &str| {
+            let position =
+                offset_to_position(src, src.find(needle).unwrap(), PositionEncoding::Utf16)
+                    .unwrap();
+            hover(
+                &docs,
+                None,
+                &uri,
+                position,
+                &config,
+                MarkupKind::Markdown,
+                PositionEncoding::Utf16,
+            )
+            .map(hover_text)
+        };
+
+        assert_eq!(
+            text("T) -> String").unwrap(),
+            "```rust\nT: Into<String>\nwhere\n    T: Clone,\n```\n\n\
+             *— defined in /generics/main.rs:2*"
+        );
+        assert_eq!(
+            text("U: Copy").unwrap(),
+            "```rust\nU\nwhere\n    U: Copy,\n```\n\n*— defined in /generics/main.rs:4*"
+        );
+        assert_eq!(
+            text("T where U").unwrap(),
+            "```rust\nT\n```\n\n*— defined in /generics/main.rs:3*"
+        );
+        assert_eq!(
+            text("'a T").unwrap(),
+            "```rust\n'a\n```\n\n*— defined in /generics/main.rs:3*"
+        );
+        assert_eq!(
+            text("T) {}").unwrap(),
+            "```rust\nstruct T;\n```\n\n*— defined in /generics/main.rs:1*"
+        );
+    }
+
+    #[test]
+    fn hover_describes_attributes_and_derives() {
+        let mut docs = DocumentStore::new();
+        let src = "#[derive(Debug, Clone, Builder, Named)]
+#[cfg_attr(test, derive(PartialEq))]
+struct Config;
+#[inline]
+#[allow(dead_code)]
+fn run() {}
+pub fn derive_builder(input: TokenStream) -> TokenStream { input }
+pub trait Named {}";
+        let uri = open(&mut docs, Path::new("/attrs/main.rs"), src);
+        let config = HoverConfig::default();
+        let text = |needle: &str| {
+            let position =
+                offset_to_position(src, src.find(needle).unwrap(), PositionEncoding::Utf16)
+                    .unwrap();
+            hover(
+                &docs,
+                None,
+                &uri,
+                position,
+                &config,
+                MarkupKind::Markdown,
+                PositionEncoding::Utf16,
+            )
+            .map(hover_text)
+        };
+
+        assert!(
+            text("Debug")
+                .unwrap()
+                .starts_with("```rust\n#[derive(Debug)]\n```\n\nImplements `fmt::Debug`")
+        );
+        assert!(text("PartialEq").unwrap().contains("`!=`"));
+        assert!(text("inline").unwrap().starts_with("`#[inline]` attribute"));
+        assert!(text("allow").unwrap().starts_with("`#[allow]` attribute"));
+        assert_eq!(
+            text("Builder").unwrap(),
+            "```rust\npub fn derive_builder(input: TokenStream) -> TokenStream\n```\n\n\
+             *— defined in /attrs/main.rs:7*"
+        );
+        assert_eq!(
+            text("Named)").unwrap(),
+            "```rust\npub trait Named {\n}\n```\n\n*— defined in /attrs/main.rs:8*"
+        );
+        assert!(text("dead_code").is_none());
+    }
+
+    #[test]
+    fn hover_lists_ambiguous_candidates() {
+        let mut docs = DocumentStore::new();
+        let main = "fn main() { init(); Point::origin(); }";
+        let uri = open(&mut docs, Path::new("/ambiguous/main.rs"), main);
+        open(
+            &mut docs,
+            Path::new("/ambiguous/b.rs"),
+            "struct Point;\nimpl Point {}\npub fn init() {}\n",
+        );
+        open(
+            &mut docs,
+            Path::new("/ambiguous/a.rs"),
+            "fn init(verbose: bool) {}\n",
+        );
+        let config = HoverConfig::default();
+        let text = |docs: &DocumentStore, needle: &str, format| {
+            let position =
+                offset_to_position(main, main.find(needle).unwrap(), PositionEncoding::Utf16)
+                    .unwrap();
+            hover(
+                docs,
+                None,
+                &uri,
+                position,
+                &config,
+                format,
+                PositionEncoding::Utf16,
+            )
+            .map(hover_text)
+        };
+
+        assert_eq!(
+            text(&docs, "init", MarkupKind::Markdown).unwrap(),
+            "`/ambiguous/a.rs:1`\n```rust\nfn init(verbose: bool)\n```\n\n\
+             `/ambiguous/b.rs:3`\n```rust\npub fn init()\n```"
+        );
+        assert_eq!(
+            text(&docs, "init", MarkupKind::PlainText).unwrap(),
+            "/ambiguous/a.rs:1\nfn init(verbose: bool)\n\n/ambiguous/b.rs:3\npub fn init()"
+        );
+        assert_eq!(
+            text(&docs, "Point", MarkupKind::Markdown).unwrap(),
+            "```rust\nstruct Point;\n```\n\n*— defined in /ambiguous/b.rs:1*"
+        );
+
+        for n in 0..6 {
+            open(
+                &mut docs,
+                Path::new(&format!("/ambiguous/more{}.rs", n)),
+                "fn init() {}\n",
+            );
+        }
+        let many = text(&docs, "init", MarkupKind::Markdown).unwrap();
+        assert_eq!(many.matches("```rust").count(), 5);
+        assert!(many.ends_with("fn init()\n```\n\n…and 3 more"));
+    }
+
+    #[test]
+    fn hover_content_format_follows_settings() {
+        let mut caps: ClientCapabilities = serde_json::from_value(serde_json::json!({
+            "textDocument": { "hover": { "contentFormat": ["plaintext", "markdown"] } }
+        }))
+        .unwrap();
+        let mut config = Config::default();
+        assert_eq!(content_format(&caps, &config.hover), MarkupKind::PlainText);
+        config.update_from_settings(&serde_json::json!({
+            "hover": { "contentFormat": "markdown", "showDocs": false }
+        }));
+        assert_eq!(content_format(&caps, &config.hover), MarkupKind::Markdown);
+        assert!(!config.hover.show_docs);
+        config.update_from_settings(&serde_json::json!({ "hover": { "contentFormat": "auto" } }));
+        caps.text_document = None;
+        assert_eq!(content_format(&caps, &config.hover), MarkupKind::Markdown);
+    }
+
+    #[test]
+    fn hover_show_docs_and_plaintext() {
+        let mut docs = DocumentStore::new();
+        let src = "struct Config {
+    /// The **maximum** number of `lines`, *not* bytes; a * b.
+    max: usize,
+}
+fn show(config: Config) { config.max; match config {} }";
+        let uri = open(&mut docs, Path::new("/plain/main.rs"), src);
+        let text = |needle: &str, config: &HoverConfig, format| {
+            let position =
+                offset_to_position(src, src.rfind(needle).unwrap(), PositionEncoding::Utf16)
+                    .unwrap();
+            hover(
+                &docs,
+                None,
+                &uri,
+                position,
+                config,
+                format,
+                PositionEncoding::Utf16,
+            )
+            .map(hover_text)
+        };
+
+        let mut config = HoverConfig::default();
+        assert_eq!(
+            text("max;", &config, MarkupKind::Markdown).unwrap(),
+            "```rust\nConfig::max: usize\n```\n\n*— defined in /plain/main.rs:3*\n\n\
+             The **maximum** number of `lines`, *not* bytes; a * b."
+        );
+        assert_eq!(
+            text("max;", &config, MarkupKind::PlainText).unwrap(),
+            "Config::max: usize\n\n— defined in /plain/main.rs:3\n\n\
+             The maximum number of lines, not bytes; a * b."
+        );
+        assert!(
+            text("match", &config, MarkupKind::PlainText)
+                .unwrap()
+                .starts_with("match keyword\n\n")
+        );
+
+        config.show_docs = false;
+        assert_eq!(
+            text("max;", &config, MarkupKind::PlainText).unwrap(),
+            "Config::max: usize\n\n— defined in /plain/main.rs:3"
+        );
+    }
+
+    #[test]
+    fn hover_range_follows_the_encoding() {
+        let mut docs = DocumentStore::new();
+        let src = "fn helper() {}\nfn main() { let s = \"😀é\"; helper(); }";
+        let uri = open(&mut docs, Path::new("/range/main.rs"), src);
+        let line_start = src.find("fn main").unwrap();
+        let byte_col = (src.rfind("helper").unwrap() - line_start) as u32;
+        let config = HoverConfig::default();
+
+        // `😀` is four bytes but two UTF-16 units and `é` is two bytes but one unit.
+        for (encoding, col) in [
+            (PositionEncoding::Utf16, byte_col - 3),
+            (PositionEncoding::Utf8, byte_col),
+        ] {
+            let position = Position::new(1, col + 2);
+            let result = hover(
+                &docs,
+                None,
+                &uri,
+                position,
+                &config,
+                MarkupKind::Markdown,
+                encoding,
+            );
+            assert_eq!(
+                result.unwrap().range,
+                Some(Range::new(Position::new(1, col), Position::new(1, col + 6)))
+            );
+
+            let result = hover(
+                &docs,
+                None,
+                &uri,
+                Position::new(1, 0),
+                &config,
+                MarkupKind::Markdown,
+                encoding,
+            );
+            assert_eq!(
+                result.unwrap().range,
+                Some(Range::new(Position::new(1, 0), Position::new(1, 2)))
+            );
+        }
     }
 }
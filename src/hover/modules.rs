@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use lsp_types::Uri;
+
+use super::{Definition, ident_token, leading_attributes};
+use crate::doc::file_cache;
+use crate::doc::store::DocumentStore;
+use crate::doc::uri::{path_to_uri, uri_to_path};
+use crate::syntax::{Token, TokenKind, lex};
+
+/// A module reached while walking a path: the directory its child files live
+/// in and the file that declares its items.
+struct Module {
+    dir: PathBuf,
+    file: PathBuf,
+}
+
+/// Resolves the identifier at `offset` when it names a module, either as a
+/// non-final segment of a path such as `crate::doc::store::DocumentStore` or
+/// in a `mod store;` declaration. A path starting with an imported name is
+/// expanded to the full import path first.
+pub fn module_definition(
+    docs: &DocumentStore,
+    uri: &Uri,
+    tokens: &[Token],
+    offset: usize,
+    imports: &HashMap<&str, Vec<&str>>,
+) -> Option<Definition> {
+    let idx = ident_token(tokens, offset)?;
+    let is_segment = tokens
+        .get(idx + 1)
+        .is_some_and(|next| matches!(next.kind, TokenKind::DoubleColon));
+    let is_declaration = idx >= 1
+        && tokens[idx - 1].is_ident("mod")
+        && tokens.get(idx + 1).is_some_and(|next| next.is_punct(';'));
+    if !is_segment && !is_declaration {
+        return None;
+    }
+
+    let mut first = idx;
+    while first >= 2
+        && matches!(tokens[first - 1].kind, TokenKind::DoubleColon)
+        && tokens[first - 2].ident().is_some()
+    {
+        first -= 2;
+    }
+    let mut segments: Vec<&str> = tokens[first..=idx]
+        .iter()
+        .step_by(2)
+        .filter_map(Token::ident)
+        .collect();
+    if let Some(full) = imports.get(segments[0]) {
+        segments.splice(..1, full.iter().copied());
+    }
+    resolve_module(docs, uri, &segments)
+}
+
+/// Resolves `segments` to a module, walking from the file at `uri`, or from
+/// the crate root for paths starting with `crate`.
+pub fn resolve_module(docs: &DocumentStore, uri: &Uri, segments: &[&str]) -> Option<Definition> {
+    let (last, parents) = segments.split_last()?;
+    if matches!(*last, "crate" | "self" | "super") {
+        return None;
+    }
+    let module = walk(docs, uri, parents)?;
+
+    match child_module(docs, &module, last)? {
+        Child::File(child) => {
+            let text = read_source(docs, &child.file)?;
+            Some(Definition {
+                snippet: format!("mod {}", last),
+                uri: path_to_uri(&child.file),
+                line: 0,
+                fallback: false,
+                docs: inner_docs(&text),
+                origin: None,
+            })
+        }
+        Child::Inline(definition) => Some(definition),
+    }
+}
+
+/// Returns the file declaring the items of the module at `segments`.
+pub fn module_file(docs: &DocumentStore, uri: &Uri, segments: &[&str]) -> Option<PathBuf> {
+    walk(docs, uri, segments).map(|module| module.file)
+}
+
+fn walk(docs: &DocumentStore, uri: &Uri, segments: &[&str]) -> Option<Module> {
+    let path = uri_to_path(uri)?;
+    let mut module = current_module(&path)?;
+    for segment in segments {
+        module = match *segment {
+            "crate" => crate_root(docs, &path)?,
+            "self" => module,
+            "super" => parent_module(docs, &module)?,
+            name => match child_module(docs, &module, name)? {
+                Child::File(child) => child,
+                Child::Inline(_) => Module {
+                    dir: module.dir.join(name),
+                    file: module.file,
+                },
+            },
+        };
+    }
+    Some(module)
+}
+
+enum Child {
+    File(Module),
+    /// `mod name { ... }` written inside the parent's file.
+    Inline(Definition),
+}
+
+fn child_module(docs: &DocumentStore, parent: &Module, name: &str) -> Option<Child> {
+    if let Some(text) = read_source(docs, &parent.file)
+        && let Some(definition) = inline_module(&text, &parent.file, name)
+    {
+        return Some(Child::Inline(definition));
+    }
+
+    let dir = parent.dir.join(name);
+    [parent.dir.join(format!("{}.rs", name)), dir.join("mod.rs")]
+        .into_iter()
+        .find(|file| exists(docs, file))
+        .map(|file| Child::File(Module { dir, file }))
+}
+
+fn inline_module(text: &str, file: &Path, name: &str) -> Option<Definition> {
+    let tokens = lex(text);
+    let idx = tokens.windows(3).position(|window| {
+        window[0].is_ident("mod") && window[1].ident() == Some(name) && window[2].is_punct('{')
+    })?;
+    let keyword = &tokens[idx];
+    let line_start = text[..keyword.start].rfind('\n').map_or(0, |nl| nl + 1);
+    let declaration = &text[line_start..tokens[idx + 2].start];
+    let docs: Vec<&str> = leading_attributes(text, line_start)
+        .into_iter()
+        .filter_map(|line| line.strip_prefix("///"))
+        .map(|line| line.strip_prefix(' ').unwrap_or(line))
+        .collect();
+
+    Some(Definition {
+        snippet: declaration.trim().to_string(),
+        uri: path_to_uri(file),
+        line: text[..keyword.start].matches('\n').count(),
+        fallback: false,
+        docs: (!docs.is_empty()).then(|| docs.join("\n")),
+        origin: None,
+    })
+}
+
+/// Returns the `//!` comment at the top of a module file.
+fn inner_docs(text: &str) -> Option<String> {
+    let lines: Vec<&str> = text
+        .lines()
+        .map(str::trim)
+        .map_while(|line| line.strip_prefix("//!"))
+        .map(|line| line.strip_prefix(' ').unwrap_or(line))
+        .collect();
+    (!lines.is_empty()).then(|| lines.join("\n"))
+}
+
+/// The module declared by `file`, e.g. `src/doc/` for both `src/doc.rs` and
+/// `src/doc/mod.rs`.
+fn current_module(file: &Path) -> Option<Module> {
+    let parent = file.parent()?;
+    let dir = match file.file_name()?.to_str()? {
+        "mod.rs" | "main.rs" | "lib.rs" => parent.to_path_buf(),
+        _ => parent.join(file.file_stem()?),
+    };
+    Some(Module {
+        dir,
+        file: file.to_path_buf(),
+    })
+}
+
+/// The nearest enclosing directory with a `lib.rs` or `main.rs`.
+fn crate_root(docs: &DocumentStore, file: &Path) -> Option<Module> {
+    file.ancestors().skip(1).find_map(|dir| {
+        ["lib.rs", "main.rs"]
+            .into_iter()
+            .map(|name| dir.join(name))
+            .find(|root| exists(docs, root))
+            .map(|root| Module {
+                dir: dir.to_path_buf(),
+                file: root,
+            })
+    })
+}
+
+fn parent_module(docs: &DocumentStore, module: &Module) -> Option<Module> {
+    let dir = module.dir.parent()?;
+    let file = [
+        dir.join("mod.rs"),
+        dir.with_extension("rs"),
+        dir.join("lib.rs"),
+        dir.join("main.rs"),
+    ]
+    .into_iter()
+    .find(|file| exists(docs, file))?;
+    Some(Module {
+        dir: dir.to_path_buf(),
+        file,
+    })
+}
+
+/// Whether `file` is open, or on disk when files that are not open are
+/// looked at.
+fn exists(docs: &DocumentStore, file: &Path) -> bool {
+    path_to_uri(file).is_some_and(|uri| docs.get(&uri).is_some())
+        || (docs.reads_disk() && file.is_file())
+}
+
+/// Reads `file` from the open documents first so unsaved edits win.
+fn read_source(docs: &DocumentStore, file: &Path) -> Option<Arc<str>> {
+    match path_to_uri(file).and_then(|uri| docs.get(&uri)) {
+        Some(doc) => Some(doc.text().into()),
+        None if docs.reads_disk() => file_cache::get_text(file),
+        None => None,
+    }
+}
@@ -0,0 +1,139 @@
+/// Built-in primitive types shown when hovering a type name that no
+/// workspace item shadows.
+struct Primitive {
+    name: &'static str,
+    description: &'static str,
+    /// Size in bytes; `None` when it depends on the target or the type is
+    /// unsized.
+    size: Option<u32>,
+    /// Inclusive bounds of integer types.
+    range: Option<(&'static str, &'static str)>,
+}
+
+const PRIMITIVES: &[Primitive] = &[
+    Primitive {
+        name: "bool",
+        description: "A boolean, either `true` or `false`.",
+        size: Some(1),
+        range: None,
+    },
+    Primitive {
+        name: "char",
+        description: "A Unicode scalar value, any code point except surrogates.",
+        size: Some(4),
+        range: None,
+    },
+    Primitive {
+        name: "str",
+        description: "A UTF-8 encoded string slice, usually seen behind a reference as `&str`.",
+        size: None,
+        range: None,
+    },
+    Primitive {
+        name: "f32",
+        description: "A 32-bit IEEE 754 floating point number.",
+        size: Some(4),
+        range: None,
+    },
+    Primitive {
+        name: "f64",
+        description: "A 64-bit IEEE 754 floating point number.",
+        size: Some(8),
+        range: None,
+    },
+    Primitive {
+        name: "i8",
+        description: "An 8-bit signed integer.",
+        size: Some(1),
+        range: Some(("-128", "127")),
+    },
+    Primitive {
+        name: "i16",
+        description: "A 16-bit signed integer.",
+        size: Some(2),
+        range: Some(("-32768", "32767")),
+    },
+    Primitive {
+        name: "i32",
+        description: "A 32-bit signed integer, the default for integer literals.",
+        size: Some(4),
+        range: Some(("-2147483648", "2147483647")),
+    },
+    Primitive {
+        name: "i64",
+        description: "A 64-bit signed integer.",
+        size: Some(8),
+        range: Some(("-9223372036854775808", "9223372036854775807")),
+    },
+    Primitive {
+        name: "i128",
+        description: "A 128-bit signed integer.",
+        size: Some(16),
+        range: Some((
+            "-170141183460469231731687303715884105728",
+            "170141183460469231731687303715884105727",
+        )),
+    },
+    Primitive {
+        name: "isize",
+        description: "A pointer-sized signed integer; same range as `i64` on 64-bit targets.",
+        size: None,
+        range: None,
+    },
+    Primitive {
+        name: "u8",
+        description: "An 8-bit unsigned integer.",
+        size: Some(1),
+        range: Some(("0", "255")),
+    },
+    Primitive {
+        name: "u16",
+        description: "A 16-bit unsigned integer.",
+        size: Some(2),
+        range: Some(("0", "65535")),
+    },
+    Primitive {
+        name: "u32",
+        description: "A 32-bit unsigned integer.",
+        size: Some(4),
+        range: Some(("0", "4294967295")),
+    },
+    Primitive {
+        name: "u64",
+        description: "A 64-bit unsigned integer.",
+        size: Some(8),
+        range: Some(("0", "18446744073709551615")),
+    },
+    Primitive {
+        name: "u128",
+        description: "A 128-bit unsigned integer.",
+        size: Some(16),
+        range: Some(("0", "340282366920938463463374607431768211455")),
+    },
+    Primitive {
+        name: "usize",
+        description: "A pointer-sized unsigned integer used for indexing and lengths; same range as `u64` on 64-bit targets.",
+        size: None,
+        range: None,
+    },
+    Primitive {
+        name: "()",
+        description: "The unit type, whose only value is `()`. Returned by functions without a return type.",
+        size: Some(0),
+        range: None,
+    },
+];
+
+/// Returns the markdown documentation for `name` if it is a primitive type.
+pub fn primitive_doc(name: &str) -> Option<String> {
+    let primitive = PRIMITIVES.iter().find(|primitive| primitive.name == name)?;
+    let mut value = format!("```rust\n{}\n```\n\n{}", name, primitive.description);
+    if let Some(size) = primitive.size {
+        let unit = if size == 1 { "byte" } else { "bytes" };
+        value.push_str(&format!("\n\nSize: {} {}", size, unit));
+    }
+    if let Some((min, max)) = primitive.range {
+        value.push_str(&format!("\n\nRange: `{}..={}`", min, max));
+    }
+    Some(value)
+}
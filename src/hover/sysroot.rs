@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use super::{Definition, MemberContext, find_impl_members, item_snippet, strip_attributes};
+use crate::config::HoverConfig;
+
+/// Library crates searched in order, so that `std` wins over the `core` item
+/// it re-exports.
+const CRATES: [&str; 3] = ["std", "alloc", "core"];
+
+static SYSROOT: OnceLock<Option<Sysroot>> = OnceLock::new();
+
+/// Finds `ident` among the public items of the standard library sources
+/// installed with `rust-src`. The index is built on first use and kept for
+/// the life of the process.
+pub fn std_definition(
+    ident: &str,
+    context: MemberContext,
+    config: &HoverConfig,
+) -> Option<Definition> {
+    SYSROOT
+        .get_or_init(|| library_dir().map(|dir| Sysroot::build(&dir)))
+        .as_ref()?
+        .find(ident, context, config)
+}
+
+fn library_dir() -> Option<PathBuf> {
+    let output = Command::new("rustc")
+        .args(["--print", "sysroot"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let sysroot = String::from_utf8(output.stdout).ok()?;
+    let dir = Path::new(sysroot.trim()).join("lib/rustlib/src/rust/library");
+    dir.is_dir().then_some(dir)
+}
+
+/// A public item declared in the standard library.
+struct Item {
+    crate_name: &'static str,
+    file: usize,
+    line_start: usize,
+    item_start: usize,
+    keyword_start: usize,
+    is_fn: bool,
+    /// Whether the item is indented, i.e. inside an `impl`, trait or inline
+    /// module.
+    nested: bool,
+}
+
+/// A hovered name and, for `Owner::name`, its owner.
+type Lookup = (String, Option<String>);
+
+struct Sysroot {
+    files: Vec<PathBuf>,
+    items: HashMap<String, Vec<Item>>,
+    /// Sources read so far, indexed like `files`.
+    sources: Mutex<HashMap<usize, Arc<str>>>,
+    /// Results of earlier lookups, including misses.
+    found: Mutex<HashMap<Lookup, Option<Definition>>>,
+}
+
+impl Sysroot {
+    /// Indexes the `pub` items of every crate in [`CRATES`] under `library`.
+    fn build(library: &Path) -> Self {
+        let mut sysroot = Sysroot {
+            files: Vec::new(),
+            items: HashMap::new(),
+            sources: Mutex::new(HashMap::new()),
+            found: Mutex::new(HashMap::new()),
+        };
+        for crate_name in CRATES {
+            let mut files = Vec::new();
+            collect_sources(&library.join(crate_name).join("src"), &mut files);
+            files.sort();
+            for path in files {
+                if let Ok(text) = fs::read_to_string(&path) {
+                    sysroot.index_file(crate_name, path, &text);
+                }
+            }
+        }
+        sysroot
+    }
+
+    fn index_file(&mut self, crate_name: &'static str, path: PathBuf, text: &str) {
+        let file = self.files.len();
+        self.files.push(path);
+
+        let mut line_start = 0usize;
+        for line in text.split('\n') {
+            let start = line_start;
+            line_start += line.len() + 1;
+            let trimmed = strip_attributes(line.trim_start());
+            let Some(rest) = trimmed.strip_prefix("pub ") else {
+                continue;
+            };
+            let rest = strip_qualifiers(rest.trim_start());
+            let Some((keyword, rest)) = rest.split_once(char::is_whitespace) else {
+                continue;
+            };
+            if !matches!(
+                keyword,
+                "fn" | "struct" | "enum" | "union" | "trait" | "type"
+            ) {
+                continue;
+            }
+            let name: String = rest
+                .trim_start()
+                .chars()
+                .take_while(|c| *c == '_' || c.is_ascii_alphanumeric())
+                .collect();
+            if name.is_empty() {
+                continue;
+            }
+            let keyword_start = start + line.len() - keyword.len() - rest.len() - 1;
+            self.items.entry(name).or_default().push(Item {
+                crate_name,
+                file,
+                line_start: start,
+                item_start: start + line.len() - trimmed.len(),
+                keyword_start,
+                is_fn: keyword == "fn",
+                nested: line.starts_with(char::is_whitespace),
+            });
+        }
+    }
+
+    /// Free items match a plain name and `impl` members of `owner` match
+    /// `Owner::name`. Method calls are skipped since the receiver type is
+    /// unknown.
+    fn find(
+        &self,
+        ident: &str,
+        context: MemberContext,
+        config: &HoverConfig,
+    ) -> Option<Definition> {
+        let owner = match context {
+            MemberContext::None => None,
+            MemberContext::Associated(owner) => Some(owner),
+            MemberContext::Method | MemberContext::Macro => return None,
+        };
+        let key = (ident.to_string(), owner.map(str::to_string));
+        if let Some(found) = self.found.lock().unwrap().get(&key) {
+            return found.clone();
+        }
+
+        let candidates = self.items.get(ident).map(Vec::as_slice).unwrap_or(&[]);
+        let item = match owner {
+            None => candidates
+                .iter()
+                .filter(|item| !item.nested)
+                .min_by_key(|item| item.is_fn),
+            Some(owner) => {
+                let owner_files: Vec<usize> = self
+                    .items
+                    .get(owner)
+                    .into_iter()
+                    .flatten()
+                    .filter(|item| !item.is_fn)
+                    .map(|item| item.file)
+                    .collect();
+                candidates.iter().find(|item| {
+                    item.is_fn
+                        && owner_files.contains(&item.file)
+                        && self.source(item.file).is_some_and(|text| {
+                            !find_impl_members(None, &text, ident, Some(owner), config).is_empty()
+                        })
+                })
+            }
+        };
+        let found = item.and_then(|item| self.render(item, config));
+        self.found.lock().unwrap().insert(key, found.clone());
+        found
+    }
+
+    fn render(&self, item: &Item, config: &HoverConfig) -> Option<Definition> {
+        let text = self.source(item.file)?;
+        let snippet = item_snippet(&text, item.item_start, item.keyword_start, config)?;
+        Some(Definition {
+            snippet,
+            uri: None,
+            line: text[..item.keyword_start].matches('\n').count(),
+            fallback: false,
+            docs: first_paragraph(&text, item.line_start),
+            origin: Some(item.crate_name),
+        })
+    }
+
+    fn source(&self, file: usize) -> Option<Arc<str>> {
+        let mut sources = self.sources.lock().unwrap();
+        if let Some(text) = sources.get(&file) {
+            return Some(text.clone());
+        }
+        let text: Arc<str> = fs::read_to_string(&self.files[file]).ok()?.into();
+        sources.insert(file, text.clone());
+        Some(text)
+    }
+}
+
+fn collect_sources(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            if path.file_name().and_then(|s| s.to_str()) != Some("tests") {
+                collect_sources(&path, files);
+            }
+        } else if path.extension().and_then(|s| s.to_str()) == Some("rs") {
+            files.push(path);
+        }
+    }
+}
+
+/// Skips the qualifiers that may precede `fn` or `trait`, e.g. `const
+/// unsafe` or `extern "C"`.
+fn strip_qualifiers(mut rest: &str) -> &str {
+    loop {
+        let next = ["const ", "unsafe ", "async ", "auto "]
+            .iter()
+            .find_map(|qualifier| rest.strip_prefix(qualifier))
+            .or_else(|| {
+                let abi = rest.strip_prefix("extern \"")?;
+                Some(&abi[abi.find('"')? + 1..])
+            });
+        match next {
+            Some(next) => rest = next.trim_start(),
+            None => return rest,
+        }
+    }
+}
+
+/// Returns the first paragraph of the doc comment above `line_start`,
+/// skipping attributes between the comment and the item.
+fn first_paragraph(text: &str, line_start: usize) -> Option<String> {
+    let mut docs = Vec::new();
+    let mut in_attribute = false;
+    for line in text[..line_start].lines().rev().map(str::trim) {
+        if let Some(doc) = line.strip_prefix("///") {
+            docs.push(doc.strip_prefix(' ').unwrap_or(doc));
+        } else if !docs.is_empty() {
+            break;
+        } else if line.starts_with("#[") {
+            in_attribute = false;
+        } else if in_attribute || line.ends_with(']') {
+            // The tail of an attribute spanning several lines.
+            in_attribute = true;
+        } else {
+            break;
+        }
+    }
+    docs.reverse();
+    let paragraph: Vec<&str> = docs
+        .into_iter()
+        .skip_while(|line| line.is_empty())
+        .take_while(|line| !line.is_empty())
+        .collect();
+    (!paragraph.is_empty()).then(|| paragraph.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    #[test]
+    fn sysroot_finds_items_with_docs() {
+        let library = temp_dir("sysroot");
+        fs::create_dir_all(library.join("alloc/src/vec")).unwrap();
+        fs::create_dir_all(library.join("core/src/iter")).unwrap();
+        fs::write(
+            library.join("alloc/src/vec/mod.rs"),
+            "/// A contiguous growable array type.
+///
+/// More details.
+#[stable(feature = \"rust1\", since = \"1.0.0\")]
+#[rustc_on_unimplemented(
+    label = \"not a vector\"
+)]
+pub struct Vec<T> {
+    buf: RawVec<T>,
+}
+
+impl<T> Vec<T> {
+    /// Appends an element to the back of a collection.
+    #[inline]
+    pub fn push(&mut self, value: T) {
+        self.buf.push(value);
+    }
+}
+",
+        )
+        .unwrap();
+        fs::write(
+            library.join("core/src/iter/traits.rs"),
+            "pub unsafe auto trait Iterator {\n    fn next(&mut self);\n}\npub(crate) fn hidden() {}\n",
+        )
+        .unwrap();
+
+        let sysroot = Sysroot::build(&library);
+        let config = HoverConfig::default();
+        let vec = sysroot.find("Vec", MemberContext::None, &config).unwrap();
+        assert_eq!(vec.snippet, "pub struct Vec<T> {\n    buf: RawVec<T>,\n}");
+        assert_eq!(
+            vec.docs.as_deref(),
+            Some("A contiguous growable array type.")
+        );
+        assert_eq!(vec.origin, Some("alloc"));
+
+        let push = sysroot
+            .find("push", MemberContext::Associated("Vec"), &config)
+            .unwrap();
+        assert_eq!(push.snippet, "pub fn push(&mut self, value: T)");
+        assert_eq!(
+            push.docs.as_deref(),
+            Some("Appends an element to the back of a collection.")
+        );
+        assert!(sysroot.find("push", MemberContext::None, &config).is_none());
+        assert!(
+            sysroot
+                .find("push", MemberContext::Method, &config)
+                .is_none()
+        );
+
+        let iterator = sysroot
+            .find("Iterator", MemberContext::None, &config)
+            .unwrap();
+        assert_eq!(iterator.origin, Some("core"));
+        assert!(
+            iterator
+                .snippet
+                .starts_with("pub unsafe auto trait Iterator {")
+        );
+        assert!(
+            sysroot
+                .find("hidden", MemberContext::None, &config)
+                .is_none()
+        );
+        fs::remove_dir_all(&library).unwrap();
+    }
+}
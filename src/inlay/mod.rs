@@ -1,100 +1,447 @@
-use std::collections::{HashMap, HashSet};
-use std::fs;
-use std::path::{Path, PathBuf};
-
-use lsp_types::{InlayHint, InlayHintKind, InlayHintLabel, Position, Range, Uri};
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::Instant;
+
+use lsp_types::{
+    ClientCapabilities, InlayHint, InlayHintKind, InlayHintLabel, InlayHintLabelPart,
+    InlayHintTooltip, Location, MarkupContent, MarkupKind, Position, Range, TextEdit, Uri,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::config::InlayHintsConfig;
+use crate::doc::line_index::LineIndex;
+use crate::doc::position::PositionEncoding;
+use crate::doc::store::{DocumentStore, Revisions};
+use crate::doc::uri::NormalizedUri;
+use crate::doc::workspace::{Disk, SourceFs, is_indexed, visit_sources_with_progress};
+use crate::syntax::{
+    Token, TokenKind, collapse_whitespace, doc_comment_above, find_matching_angle,
+    find_matching_angle_backward, find_matching_brace, find_matching_paren, impl_self_type,
+    is_ident_continue, is_keyword, lex,
+};
+
+/// Inlay hint features that depend on what the client advertised at
+/// initialization.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ClientHintSupport {
+    /// The client can render label parts that jump to a location.
+    pub label_location: bool,
+    /// The client can fill in tooltips lazily through `inlayHint/resolve`.
+    pub resolve_tooltip: bool,
+}
 
-use crate::doc::position::offset_to_position;
-use crate::doc::store::DocumentStore;
-use crate::doc::uri::uri_to_path;
+impl ClientHintSupport {
+    pub fn from_capabilities(caps: &ClientCapabilities) -> Self {
+        let properties = caps
+            .text_document
+            .as_ref()
+            .and_then(|td| td.inlay_hint.as_ref())
+            .and_then(|hint| hint.resolve_support.as_ref())
+            .map(|resolve| resolve.properties.as_slice())
+            .unwrap_or_default();
+
+        Self {
+            label_location: properties.iter().any(|prop| prop == "label.location"),
+            resolve_tooltip: properties.iter().any(|prop| prop == "tooltip"),
+        }
+    }
+}
 
 pub fn inlay_hints(
     docs: &DocumentStore,
     root: Option<&Path>,
     uri: &Uri,
     range: Range,
+    config: &InlayHintsConfig,
+    support: ClientHintSupport,
+    encoding: PositionEncoding,
 ) -> Vec<InlayHint> {
-    let doc = match docs.get(uri) {
-        Some(doc) => doc,
-        None => return Vec::new(),
+    let doc = match docs.get_or_read(uri) {
+        Some(doc) if !doc.is_too_large() => doc,
+        _ => return Vec::new(),
     };
 
-    let index = WorkspaceIndex::build(docs, root);
+    let index = WorkspaceIndex::build(docs, root, encoding);
+    let ctx = HintContext {
+        index: &index,
+        config,
+        support,
+        encoding,
+        lines: doc.line_index(),
+    };
     let mut hints = Vec::new();
-    hints.extend(local_var_type_hints(&doc.text, &index));
-    hints.extend(arg_name_hints(&doc.text, &index));
-    hints.extend(const_generic_hints(&doc.text, &index));
-    hints.extend(chained_expr_type_hints(&doc.text, &index));
+    hints.extend(local_var_type_hints(doc.text(), &ctx));
+    hints.extend(arg_name_hints(doc.text(), &ctx));
+    hints.extend(const_generic_hints(doc.text(), &ctx));
+    hints.extend(chained_expr_type_hints(doc.text(), &ctx));
+    if config.binding_mode_hints {
+        hints.extend(binding_mode_hints(doc.text(), &ctx));
+    }
+    hints.extend(closing_brace_hints(doc.text(), &ctx));
 
     hints.retain(|hint| position_in_range(hint.position, range));
-    hints.sort_by(|a, b| position_cmp(a.position, b.position));
+    sort_and_dedup(&mut hints);
     hints
 }
 
-#[derive(Debug, Default)]
-struct WorkspaceIndex {
-    fn_defs: HashMap<String, Vec<FunctionSig>>,
-    method_defs: HashMap<String, Vec<FunctionSig>>,
-    generics: HashMap<String, Vec<Vec<GenericParam>>>,
-    type_names: HashMap<String, usize>,
+/// Orders hints by position, then kind (parameter hints first), then label,
+/// and drops exact duplicates produced by overlapping passes.
+fn sort_and_dedup(hints: &mut Vec<InlayHint>) {
+    hints.sort_by(|a, b| {
+        position_cmp(a.position, b.position)
+            .then_with(|| kind_rank(a.kind).cmp(&kind_rank(b.kind)))
+            .then_with(|| label_text(&a.label).cmp(&label_text(&b.label)))
+    });
+    hints.dedup_by(|a, b| {
+        a.position == b.position && a.kind == b.kind && label_text(&a.label) == label_text(&b.label)
+    });
 }
 
-impl WorkspaceIndex {
-    fn build(docs: &DocumentStore, root: Option<&Path>) -> Self {
-        let mut index = WorkspaceIndex::default();
-        let mut open_paths = HashSet::new();
+fn kind_rank(kind: Option<InlayHintKind>) -> u8 {
+    match kind {
+        Some(InlayHintKind::PARAMETER) => 0,
+        Some(InlayHintKind::TYPE) => 1,
+        _ => 2,
+    }
+}
+
+fn label_text(label: &InlayHintLabel) -> String {
+    match label {
+        InlayHintLabel::String(value) => value.clone(),
+        InlayHintLabel::LabelParts(parts) => parts.iter().map(|part| part.value.as_str()).collect(),
+    }
+}
+
+/// Fills in a tooltip that was deferred to `inlayHint/resolve`.
+pub fn resolve_inlay_hint(
+    docs: &DocumentStore,
+    root: Option<&Path>,
+    mut hint: InlayHint,
+    encoding: PositionEncoding,
+) -> InlayHint {
+    if hint.tooltip.is_some() {
+        return hint;
+    }
+    let Some(data) = hint
+        .data
+        .take()
+        .and_then(|data| serde_json::from_value::<TooltipData>(data).ok())
+    else {
+        return hint;
+    };
+
+    let index = WorkspaceIndex::build(docs, root, encoding);
+    let code = match &data {
+        TooltipData::Function { name } => index.unique_fn(name).map(|sig| sig.signature.clone()),
+        TooltipData::Method { name } => index.unique_method(name).map(|sig| sig.signature.clone()),
+        TooltipData::Generic { owner, index: idx } => index
+            .unique_generics(owner)
+            .and_then(|generics| generics.get(*idx))
+            .map(|param| param.decl.clone()),
+    };
+    hint.tooltip = code.map(|code| code_tooltip(&code));
+    hint
+}
+
+/// Identifies the definition a hint's tooltip is built from, stored in
+/// `InlayHint::data` when the tooltip is resolved lazily.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+enum TooltipData {
+    Function { name: String },
+    Method { name: String },
+    Generic { owner: String, index: usize },
+}
+
+fn code_tooltip(code: &str) -> InlayHintTooltip {
+    InlayHintTooltip::MarkupContent(MarkupContent {
+        kind: MarkupKind::Markdown,
+        value: format!("```rust\n{}\n```", code),
+    })
+}
+
+struct HintContext<'a> {
+    index: &'a WorkspaceIndex,
+    config: &'a InlayHintsConfig,
+    support: ClientHintSupport,
+    encoding: PositionEncoding,
+    /// Line starts of the document the hints are for.
+    lines: &'a LineIndex,
+}
 
-        for (uri, doc) in docs.iter() {
-            index.add_source(&doc.text);
-            if let Some(path) = uri_to_path(uri) {
-                open_paths.insert(path);
+impl HintContext<'_> {
+    fn position(&self, offset: usize) -> Option<Position> {
+        self.lines.offset_to_position(offset, self.encoding)
+    }
+
+    /// Builds `: Ty`, linking each unique type name in `ty` to its definition
+    /// when the client can follow label locations.
+    fn type_label(&self, ty: &str) -> InlayHintLabel {
+        let text = format!(": {}", ty);
+        if !self.support.label_location {
+            return InlayHintLabel::String(text);
+        }
+
+        let mut parts = Vec::new();
+        let mut plain = ": ".to_string();
+        for segment in split_ident_runs(ty) {
+            match self.index.unique_type_location(segment) {
+                Some(location) => {
+                    if !plain.is_empty() {
+                        parts.push(label_part(std::mem::take(&mut plain), None));
+                    }
+                    parts.push(label_part(segment.to_string(), Some(location.clone())));
+                }
+                None => plain.push_str(segment),
             }
         }
 
-        if let Some(root) = root {
-            index.add_workspace(root, &open_paths);
+        if parts.is_empty() {
+            return InlayHintLabel::String(text);
         }
+        if !plain.is_empty() {
+            parts.push(label_part(plain, None));
+        }
+        InlayHintLabel::LabelParts(parts)
+    }
 
-        index
+    /// Attaches `code` as a tooltip, or defers it to `inlayHint/resolve` via
+    /// `data` when the client supports that.
+    fn set_tooltip(&self, hint: &mut InlayHint, code: &str, data: TooltipData) {
+        if self.support.resolve_tooltip {
+            hint.data = serde_json::to_value(data).ok();
+        } else {
+            hint.tooltip = Some(code_tooltip(code));
+        }
     }
 
-    fn add_workspace(&mut self, root: &Path, open_paths: &HashSet<PathBuf>) {
-        let mut stack = vec![root.to_path_buf()];
-        while let Some(dir) = stack.pop() {
-            let entries = match fs::read_dir(&dir) {
-                Ok(entries) => entries,
-                Err(_) => continue,
-            };
+    fn param_label(&self, name: &str, location: Option<&Location>) -> InlayHintLabel {
+        match location {
+            Some(location) if self.support.label_location => InlayHintLabel::LabelParts(vec![
+                label_part(name.to_string(), Some(location.clone())),
+                label_part(":".to_string(), None),
+            ]),
+            _ => InlayHintLabel::String(format!("{}:", name)),
+        }
+    }
+}
 
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if path.is_dir() {
-                    if should_skip_dir(&path) {
-                        continue;
-                    }
-                    stack.push(path);
-                } else if path.extension().and_then(|s| s.to_str()) == Some("rs") {
-                    if open_paths.contains(&path) {
-                        continue;
-                    }
-                    if let Ok(text) = fs::read_to_string(&path) {
-                        self.add_source(&text);
-                    }
+fn label_part(value: String, location: Option<Location>) -> InlayHintLabelPart {
+    InlayHintLabelPart {
+        value,
+        tooltip: None,
+        location,
+        command: None,
+    }
+}
+
+/// Splits `text` into alternating identifier and non-identifier runs.
+fn split_ident_runs(text: &str) -> Vec<&str> {
+    let mut runs = Vec::new();
+    let mut start = 0usize;
+    let mut in_ident = None;
+    for (idx, b) in text.bytes().enumerate() {
+        let is_ident = is_ident_continue(b);
+        if in_ident.is_some_and(|prev| prev != is_ident) {
+            runs.push(&text[start..idx]);
+            start = idx;
+        }
+        in_ident = Some(is_ident);
+    }
+    if start < text.len() {
+        runs.push(&text[start..]);
+    }
+    runs
+}
+
+/// The definitions of each open document, kept between requests so that
+/// only documents changed since the last request are parsed again.
+static OPEN_DEFS: OnceLock<Mutex<OpenDefs>> = OnceLock::new();
+
+/// The size of the latest workspace index built.
+static LAST_INDEX: Mutex<Option<IndexStats>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy)]
+pub struct IndexStats {
+    pub open_files: usize,
+    pub other_files: usize,
+    pub definitions: usize,
+}
+
+/// The size of the workspace index inlay hints last looked things up in.
+pub fn index_stats() -> Option<IndexStats> {
+    *LAST_INDEX.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Builds the workspace index once without using it, so that the files it
+/// reads from disk are cached before the first request needs them.
+/// `progress` is told how many files on disk were indexed out of those
+/// found so far.
+pub fn build_index(
+    docs: &DocumentStore,
+    root: Option<&Path>,
+    encoding: PositionEncoding,
+    progress: impl FnMut(usize, usize),
+) -> IndexStats {
+    WorkspaceIndex::build_with_progress(docs, root, &Disk, encoding, progress).1
+}
+
+#[derive(Debug, Default)]
+struct OpenDefs {
+    revisions: Revisions,
+    encoding: PositionEncoding,
+    indexes: HashMap<NormalizedUri, WorkspaceIndex>,
+}
+
+impl OpenDefs {
+    fn update(&mut self, docs: &DocumentStore, encoding: PositionEncoding) {
+        if encoding != self.encoding {
+            *self = OpenDefs {
+                encoding,
+                ..OpenDefs::default()
+            };
+        }
+        for uri in docs.changed_since(&self.revisions) {
+            let doc = docs.get(&uri).filter(|doc| is_indexed(&uri, doc));
+            match doc {
+                Some(doc) => {
+                    let mut index = WorkspaceIndex {
+                        encoding,
+                        ..WorkspaceIndex::default()
+                    };
+                    index.add_source(Some(&uri), doc.text());
+                    self.indexes.insert(NormalizedUri::new(&uri), index);
+                }
+                None => {
+                    self.indexes.remove(&NormalizedUri::new(&uri));
                 }
             }
         }
+        self.revisions = docs.revisions();
+    }
+}
+
+#[derive(Debug, Default)]
+struct WorkspaceIndex {
+    fn_defs: HashMap<String, Vec<FunctionSig>>,
+    method_defs: HashMap<String, Vec<FunctionSig>>,
+    generics: HashMap<String, Vec<Vec<GenericParam>>>,
+    type_defs: HashMap<String, Vec<Option<Location>>>,
+    struct_fields: HashMap<String, Vec<Vec<FieldDef>>>,
+    /// The unit definition locations are counted in.
+    encoding: PositionEncoding,
+}
+
+impl WorkspaceIndex {
+    fn build(docs: &DocumentStore, root: Option<&Path>, encoding: PositionEncoding) -> Self {
+        Self::build_with_progress(docs, root, &Disk, encoding, |_, _| {}).0
+    }
+
+    /// Indexes the open documents, then the files `fs` finds under `root`.
+    fn build_with_progress(
+        docs: &DocumentStore,
+        root: Option<&Path>,
+        fs: &dyn SourceFs,
+        encoding: PositionEncoding,
+        progress: impl FnMut(usize, usize),
+    ) -> (Self, IndexStats) {
+        let started = Instant::now();
+        let mut other_files = 0;
+        let mut index = WorkspaceIndex {
+            encoding,
+            ..WorkspaceIndex::default()
+        };
+        let open_files = {
+            let defs = OPEN_DEFS.get_or_init(Default::default);
+            let mut open = defs.lock().unwrap_or_else(|poisoned| {
+                // A request panicked while updating the cache; start over.
+                defs.clear_poison();
+                let mut open = poisoned.into_inner();
+                *open = OpenDefs::default();
+                open
+            });
+            open.update(docs, encoding);
+            for defs in open.indexes.values() {
+                index.merge(defs);
+            }
+            open.indexes.len()
+        };
+        let visit = |uri: Option<&Uri>, text: &str| {
+            // Open documents were merged above.
+            if uri.is_none_or(|uri| docs.get(uri).is_none()) {
+                index.add_source(uri, text);
+                other_files += 1;
+            }
+            ControlFlow::<()>::Continue(())
+        };
+        visit_sources_with_progress(docs, root, fs, visit, progress);
+        let stats = IndexStats {
+            open_files,
+            other_files,
+            definitions: index.definitions(),
+        };
+        log_debug!(
+            "indexed {open_files} open and {other_files} other files with {} definitions in {:?}",
+            stats.definitions,
+            started.elapsed()
+        );
+        *LAST_INDEX.lock().unwrap_or_else(PoisonError::into_inner) = Some(stats);
+        (index, stats)
+    }
+
+    fn definitions(&self) -> usize {
+        fn count<T>(defs: &HashMap<String, Vec<T>>) -> usize {
+            defs.values().map(Vec::len).sum()
+        }
+        count(&self.fn_defs) + count(&self.method_defs) + count(&self.type_defs)
+    }
+
+    fn merge(&mut self, other: &WorkspaceIndex) {
+        fn extend<T: Clone>(into: &mut HashMap<String, Vec<T>>, from: &HashMap<String, Vec<T>>) {
+            for (name, items) in from {
+                into.entry(name.clone())
+                    .or_default()
+                    .extend_from_slice(items);
+            }
+        }
+        extend(&mut self.fn_defs, &other.fn_defs);
+        extend(&mut self.method_defs, &other.method_defs);
+        extend(&mut self.generics, &other.generics);
+        extend(&mut self.type_defs, &other.type_defs);
+        extend(&mut self.struct_fields, &other.struct_fields);
     }
 
-    fn add_source(&mut self, text: &str) {
+    fn add_source(&mut self, uri: Option<&Uri>, text: &str) {
         let tokens = lex(text);
-        self.collect_defs(text, &tokens);
+        self.collect_defs(uri, text, &tokens);
     }
 
-    fn collect_defs(&mut self, text: &str, tokens: &[Token]) {
+    fn collect_defs(&mut self, uri: Option<&Uri>, text: &str, tokens: &[Token]) {
+        let encoding = self.encoding;
+        let lines = LineIndex::new(text);
+        let locate = |start: usize, end: usize| -> Option<Location> {
+            let uri = uri?;
+            let range = Range {
+                start: lines.offset_to_position(start, encoding)?,
+                end: lines.offset_to_position(end, encoding)?,
+            };
+            Some(Location {
+                uri: uri.clone(),
+                range,
+            })
+        };
+
         let mut i = 0;
         while i < tokens.len() {
             if tokens[i].is_ident("fn") {
-                if let Some((name, sig, next_i)) = parse_fn_def(text, tokens, i) {
+                if let Some((name, mut sig, next_i)) = parse_fn_def(text, tokens, i) {
+                    let name_tok = &tokens[i + 1];
+                    sig.location = locate(name_tok.start, name_tok.end);
+                    for param in &mut sig.params {
+                        param.location = locate(param.start, param.end);
+                    }
                     self.add_fn(&name, sig.clone());
                     self.add_generics(&name, sig.generics.clone());
                     if sig.has_self {
@@ -104,23 +451,36 @@ impl WorkspaceIndex {
                             return_type: sig.return_type.clone(),
                             generics: sig.generics.clone(),
                             has_self: false,
+                            signature: sig.signature.clone(),
+                            location: sig.location.clone(),
                         };
                         self.add_method(&name, method_sig);
                     }
                     i = next_i;
                     continue;
                 }
-            } else if tokens[i].is_ident("struct")
+            } else if (tokens[i].is_ident("struct")
                 || tokens[i].is_ident("enum")
                 || tokens[i].is_ident("trait")
-                || tokens[i].is_ident("type")
+                || tokens[i].is_ident("type"))
+                && let Some((name, generics, next_i)) = parse_type_def(text, tokens, i)
             {
-                if let Some((name, generics, next_i)) = parse_type_def(tokens, i) {
-                    self.add_generics(&name, generics);
-                    *self.type_names.entry(name).or_insert(0) += 1;
-                    i = next_i;
-                    continue;
+                self.add_generics(&name, generics);
+                if tokens[i].is_ident("struct") {
+                    let mut fields = parse_struct_fields(text, tokens, next_i);
+                    for field in &mut fields {
+                        field.location = locate(field.start, field.end);
+                    }
+                    self.struct_fields
+                        .entry(name.clone())
+                        .or_default()
+                        .push(fields);
                 }
+                let name_tok = &tokens[i + 1];
+                let location = locate(name_tok.start, name_tok.end);
+                self.type_defs.entry(name).or_default().push(location);
+                i = next_i;
+                continue;
             }
             i += 1;
         }
@@ -177,296 +537,155 @@ impl WorkspaceIndex {
         })
     }
 
+    /// Returns the field `name` of the struct `owner` if `owner` is declared
+    /// once.
+    fn unique_field(&self, owner: &str, name: &str) -> Option<&FieldDef> {
+        match self.struct_fields.get(owner).map(Vec::as_slice) {
+            Some([fields]) => fields.iter().find(|field| field.name == name),
+            _ => None,
+        }
+    }
+
     fn is_unique_type(&self, name: &str) -> bool {
-        self.type_names.get(name).copied().unwrap_or(0) == 1
+        self.type_defs.get(name).map(Vec::len).unwrap_or(0) == 1
+    }
+
+    fn unique_type_location(&self, name: &str) -> Option<&Location> {
+        match self.type_defs.get(name).map(Vec::as_slice) {
+            Some([location]) => location.as_ref(),
+            _ => None,
+        }
     }
 }
 
 #[derive(Debug, Clone)]
 struct FunctionSig {
-    params: Vec<String>,
+    params: Vec<Param>,
     return_type: Option<String>,
     generics: Vec<GenericParam>,
     has_self: bool,
-}
-
-#[derive(Debug, Clone, PartialEq, Eq)]
-enum GenericParamKind {
-    Const,
-    Type,
-    Lifetime,
+    /// Reconstructed `fn name<..>(..) -> Ret` header.
+    signature: String,
+    location: Option<Location>,
 }
 
 #[derive(Debug, Clone)]
-struct GenericParam {
+struct Param {
     name: String,
-    kind: GenericParamKind,
-}
-
-#[derive(Debug, Clone)]
-struct Token {
-    kind: TokenKind,
+    /// Condensed source of a destructuring pattern; `name` is then the first
+    /// binding inside it rather than a binding for the whole argument.
+    pattern: Option<String>,
+    /// Declared type of the parameter; `None` for `self` receivers.
+    ty: Option<String>,
     start: usize,
     end: usize,
+    location: Option<Location>,
 }
 
+/// A named field of a braced struct.
 #[derive(Debug, Clone)]
-enum TokenKind {
-    Ident(String),
-    Lifetime(String),
-    Number,
-    Punct(char),
-    DoubleColon,
-    Arrow,
+struct FieldDef {
+    name: String,
+    ty: String,
+    docs: Option<String>,
+    start: usize,
+    end: usize,
+    location: Option<Location>,
 }
 
-impl Token {
-    fn is_ident(&self, value: &str) -> bool {
-        matches!(&self.kind, TokenKind::Ident(name) if name == value)
-    }
+/// Parses the named fields of the struct whose name and generics end before
+/// `idx`. Tuple and unit structs have none.
+fn parse_struct_fields(text: &str, tokens: &[Token], idx: usize) -> Vec<FieldDef> {
+    let Some(open) = tokens[idx.min(tokens.len())..]
+        .iter()
+        .position(|tok| tok.is_punct('{') || tok.is_punct('(') || tok.is_punct(';'))
+        .map(|pos| idx + pos)
+        .filter(|&open| tokens[open].is_punct('{'))
+    else {
+        return Vec::new();
+    };
+    let Some(close) = find_matching_brace(tokens, open) else {
+        return Vec::new();
+    };
 
-    fn ident(&self) -> Option<&str> {
-        match &self.kind {
-            TokenKind::Ident(name) => Some(name.as_str()),
-            _ => None,
+    let mut fields = Vec::new();
+    let mut start = open + 1;
+    let mut depth = 0i32;
+    for i in open + 1..close {
+        let tok = &tokens[i];
+        if tok.is_punct('(') || tok.is_punct('[') || tok.is_punct('<') || tok.is_punct('{') {
+            depth += 1;
+        } else if tok.is_punct(')') || tok.is_punct(']') || tok.is_punct('>') || tok.is_punct('}') {
+            depth -= 1;
+        } else if tok.is_punct(',') && depth == 0 {
+            fields.extend(parse_field(text, &tokens[start..i]));
+            start = i + 1;
         }
     }
-
-    fn is_punct(&self, ch: char) -> bool {
-        matches!(self.kind, TokenKind::Punct(value) if value == ch)
-    }
+    fields.extend(parse_field(text, &tokens[start..close]));
+    fields
 }
 
-fn lex(text: &str) -> Vec<Token> {
-    let bytes = text.as_bytes();
-    let mut tokens = Vec::new();
-    let mut i = 0usize;
-
-    while i < bytes.len() {
-        let b = bytes[i];
-        if b.is_ascii_whitespace() {
-            i += 1;
-            continue;
-        }
-
-        if b == b'/' && i + 1 < bytes.len() {
-            if bytes[i + 1] == b'/' {
-                i += 2;
-                while i < bytes.len() && bytes[i] != b'\n' {
-                    i += 1;
-                }
-                continue;
-            }
-            if bytes[i + 1] == b'*' {
-                i += 2;
-                while i + 1 < bytes.len() {
-                    if bytes[i] == b'*' && bytes[i + 1] == b'/' {
-                        i += 2;
-                        break;
-                    }
-                    i += 1;
-                }
-                continue;
-            }
-        }
-
-        if let Some(next) = skip_string_literal(bytes, i) {
-            i = next;
-            continue;
-        }
-
-        if b == b'\'' {
-            let (token, next) = lex_lifetime_or_char(text, bytes, i);
-            if let Some(token) = token {
-                tokens.push(token);
-            }
-            i = next;
-            continue;
-        }
-
-        if is_ident_start(b) {
-            let start = i;
-            i += 1;
-            while i < bytes.len() && is_ident_continue(bytes[i]) {
-                i += 1;
-            }
-            let ident = &text[start..i];
-            tokens.push(Token {
-                kind: TokenKind::Ident(ident.to_string()),
-                start,
-                end: i,
-            });
-            continue;
+fn parse_field(text: &str, tokens: &[Token]) -> Option<FieldDef> {
+    let mut i = 0;
+    while tokens.get(i)?.is_punct('#') {
+        let open = i + 1;
+        if !tokens.get(open)?.is_punct('[') {
+            return None;
         }
-
-        if b.is_ascii_digit() {
-            let start = i;
-            i += 1;
-            while i < bytes.len() {
-                let ch = bytes[i];
-                if ch.is_ascii_alphanumeric() || ch == b'_' || ch == b'.' {
-                    i += 1;
-                } else {
+        let mut depth = 0i32;
+        i = open;
+        loop {
+            if tokens.get(i)?.is_punct('[') {
+                depth += 1;
+            } else if tokens[i].is_punct(']') {
+                depth -= 1;
+                if depth == 0 {
                     break;
                 }
             }
-            tokens.push(Token {
-                kind: TokenKind::Number,
-                start,
-                end: i,
-            });
-            continue;
-        }
-
-        if b == b':' && i + 1 < bytes.len() && bytes[i + 1] == b':' {
-            tokens.push(Token {
-                kind: TokenKind::DoubleColon,
-                start: i,
-                end: i + 2,
-            });
-            i += 2;
-            continue;
-        }
-
-        if b == b'-' && i + 1 < bytes.len() && bytes[i + 1] == b'>' {
-            tokens.push(Token {
-                kind: TokenKind::Arrow,
-                start: i,
-                end: i + 2,
-            });
-            i += 2;
-            continue;
+            i += 1;
         }
-
-        tokens.push(Token {
-            kind: TokenKind::Punct(b as char),
-            start: i,
-            end: i + 1,
-        });
         i += 1;
     }
-
-    tokens
-}
-
-fn skip_string_literal(bytes: &[u8], idx: usize) -> Option<usize> {
-    let len = bytes.len();
-    if idx >= len {
-        return None;
-    }
-
-    if bytes[idx] == b'"' {
-        return Some(skip_normal_string(bytes, idx + 1));
-    }
-
-    if bytes[idx] == b'b' {
-        if idx + 1 < len && bytes[idx + 1] == b'"' {
-            return Some(skip_normal_string(bytes, idx + 2));
-        }
-        if idx + 1 < len && bytes[idx + 1] == b'r' {
-            if let Some(next) = skip_raw_string(bytes, idx + 2) {
-                return Some(next);
-            }
-        }
-    }
-
-    if bytes[idx] == b'r' {
-        if let Some(next) = skip_raw_string(bytes, idx + 1) {
-            return Some(next);
-        }
-    }
-
-    None
-}
-
-fn skip_normal_string(bytes: &[u8], mut idx: usize) -> usize {
-    while idx < bytes.len() {
-        if bytes[idx] == b'\\' {
-            idx = idx.saturating_add(2);
-            continue;
-        }
-        if bytes[idx] == b'"' {
-            return idx + 1;
+    if tokens.get(i)?.is_ident("pub") {
+        i += 1;
+        if tokens.get(i)?.is_punct('(') {
+            i = find_matching_paren(tokens, i)? + 1;
         }
-        idx += 1;
-    }
-    bytes.len()
-}
-
-fn skip_raw_string(bytes: &[u8], mut idx: usize) -> Option<usize> {
-    let len = bytes.len();
-    let mut hashes = 0usize;
-    while idx < len && bytes[idx] == b'#' {
-        hashes += 1;
-        idx += 1;
     }
-    if idx >= len || bytes[idx] != b'"' {
+    let name_tok = tokens.get(i)?;
+    let name = name_tok.ident()?;
+    if !tokens.get(i + 1)?.is_punct(':') {
         return None;
     }
-    idx += 1;
-
-    while idx < len {
-        if bytes[idx] == b'"' {
-            let mut j = idx + 1;
-            let mut matched = 0usize;
-            while matched < hashes && j < len && bytes[j] == b'#' {
-                matched += 1;
-                j += 1;
-            }
-            if matched == hashes {
-                return Some(j);
-            }
-        }
-        idx += 1;
-    }
-
-    Some(len)
-}
-
-fn lex_lifetime_or_char(text: &str, bytes: &[u8], idx: usize) -> (Option<Token>, usize) {
-    let len = bytes.len();
-    if idx + 1 >= len {
-        return (None, idx + 1);
-    }
-    let next = bytes[idx + 1];
-    if is_ident_start(next) {
-        let mut j = idx + 1;
-        while j < len && is_ident_continue(bytes[j]) {
-            j += 1;
-        }
-        if j < len && bytes[j] == b'\'' {
-            return (None, j + 1);
-        }
-        let name = &text[idx + 1..j];
-        let token = Token {
-            kind: TokenKind::Lifetime(name.to_string()),
-            start: idx,
-            end: j,
-        };
-        return (Some(token), j);
-    }
-
-    let mut j = idx + 1;
-    while j < len {
-        if bytes[j] == b'\\' {
-            j = j.saturating_add(2);
-            continue;
-        }
-        if bytes[j] == b'\'' {
-            return (None, j + 1);
-        }
-        j += 1;
-    }
-
-    (None, len)
+    let ty_start = tokens.get(i + 2)?.start;
+    let ty_end = tokens.last()?.end;
+    Some(FieldDef {
+        name: name.to_string(),
+        ty: collapse_whitespace(&text[ty_start..ty_end]),
+        docs: doc_comment_above(text, name_tok.start),
+        start: name_tok.start,
+        end: name_tok.end,
+        location: None,
+    })
 }
 
-fn is_ident_start(b: u8) -> bool {
-    b == b'_' || (b as char).is_ascii_alphabetic()
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum GenericParamKind {
+    Const,
+    Type,
+    Lifetime,
 }
 
-fn is_ident_continue(b: u8) -> bool {
-    b == b'_' || (b as char).is_ascii_alphanumeric()
+#[derive(Debug, Clone)]
+struct GenericParam {
+    name: String,
+    kind: GenericParamKind,
+    /// Source text of the declaration, e.g. `const N: usize`.
+    decl: String,
+    /// Offset of the declaration.
+    start: usize,
 }
 
 fn parse_fn_def(text: &str, tokens: &[Token], idx: usize) -> Option<(String, FunctionSig, usize)> {
@@ -478,11 +697,14 @@ fn parse_fn_def(text: &str, tokens: &[Token], idx: usize) -> Option<(String, Fun
     i += 1;
 
     let mut generics = Vec::new();
-    if i < tokens.len() && tokens[i].is_punct('<') {
-        if let Some((parsed, next_i)) = parse_generics(tokens, i) {
-            generics = parsed;
-            i = next_i;
-        }
+    let mut generics_text = "";
+    if i < tokens.len()
+        && tokens[i].is_punct('<')
+        && let Some((parsed, next_i)) = parse_generics(text, tokens, i)
+    {
+        generics = parsed;
+        generics_text = &text[tokens[i].start..tokens[next_i - 1].end];
+        i = next_i;
     }
 
     if i >= tokens.len() || !tokens[i].is_punct('(') {
@@ -490,22 +712,42 @@ fn parse_fn_def(text: &str, tokens: &[Token], idx: usize) -> Option<(String, Fun
     }
 
     let close_idx = find_matching_paren(tokens, i)?;
-    let params = parse_params(tokens, i + 1, close_idx);
-    let has_self = params.first().map(|name| name == "self").unwrap_or(false);
+    let params_text = collapse_whitespace(&text[tokens[i].end..tokens[close_idx].start]);
+    let params = parse_params(text, tokens, i + 1, close_idx);
+    let has_self = params
+        .first()
+        .map(|param| param.name == "self")
+        .unwrap_or(false);
 
     let return_type = parse_return_type(text, tokens, close_idx + 1);
+    let mut signature = format!(
+        "fn {}{}({})",
+        name,
+        collapse_whitespace(generics_text),
+        params_text.trim_end_matches(',')
+    );
+    if let Some(ret) = &return_type {
+        signature.push_str(" -> ");
+        signature.push_str(&collapse_whitespace(ret));
+    }
 
     let sig = FunctionSig {
         params,
         return_type,
         generics,
         has_self,
+        signature,
+        location: None,
     };
 
     Some((name, sig, close_idx + 1))
 }
 
-fn parse_type_def(tokens: &[Token], idx: usize) -> Option<(String, Vec<GenericParam>, usize)> {
+fn parse_type_def(
+    text: &str,
+    tokens: &[Token],
+    idx: usize,
+) -> Option<(String, Vec<GenericParam>, usize)> {
     let mut i = idx + 1;
     if i >= tokens.len() {
         return None;
@@ -514,26 +756,32 @@ fn parse_type_def(tokens: &[Token], idx: usize) -> Option<(String, Vec<GenericPa
     i += 1;
 
     let mut generics = Vec::new();
-    if i < tokens.len() && tokens[i].is_punct('<') {
-        if let Some((parsed, next_i)) = parse_generics(tokens, i) {
-            generics = parsed;
-            i = next_i;
-        }
+    if i < tokens.len()
+        && tokens[i].is_punct('<')
+        && let Some((parsed, next_i)) = parse_generics(text, tokens, i)
+    {
+        generics = parsed;
+        i = next_i;
     }
 
     Some((name, generics, i))
 }
 
-fn parse_generics(tokens: &[Token], idx: usize) -> Option<(Vec<GenericParam>, usize)> {
+fn parse_generics(text: &str, tokens: &[Token], idx: usize) -> Option<(Vec<GenericParam>, usize)> {
     if !tokens[idx].is_punct('<') {
         return None;
     }
     let end_idx = find_matching_angle(tokens, idx)?;
-    let params = parse_generic_params(tokens, idx + 1, end_idx);
+    let params = parse_generic_params(text, tokens, idx + 1, end_idx);
     Some((params, end_idx + 1))
 }
 
-fn parse_generic_params(tokens: &[Token], start: usize, end: usize) -> Vec<GenericParam> {
+fn parse_generic_params(
+    text: &str,
+    tokens: &[Token],
+    start: usize,
+    end: usize,
+) -> Vec<GenericParam> {
     let mut params = Vec::new();
     let mut current = Vec::new();
     let mut angle_depth = 0i32;
@@ -541,8 +789,7 @@ fn parse_generic_params(tokens: &[Token], start: usize, end: usize) -> Vec<Gener
     let mut bracket_depth = 0i32;
     let mut brace_depth = 0i32;
 
-    for idx in start..end {
-        let tok = &tokens[idx];
+    for tok in &tokens[start..end] {
         match tok.kind {
             TokenKind::Punct('<') => {
                 angle_depth += 1;
@@ -590,7 +837,7 @@ fn parse_generic_params(tokens: &[Token], start: usize, end: usize) -> Vec<Gener
                     && bracket_depth == 0
                     && brace_depth == 0 =>
             {
-                if let Some(param) = parse_generic_param(&current) {
+                if let Some(param) = parse_generic_param(text, &current) {
                     params.push(param);
                 }
                 current.clear();
@@ -599,16 +846,23 @@ fn parse_generic_params(tokens: &[Token], start: usize, end: usize) -> Vec<Gener
         }
     }
 
-    if !current.is_empty() {
-        if let Some(param) = parse_generic_param(&current) {
-            params.push(param);
-        }
+    if !current.is_empty()
+        && let Some(param) = parse_generic_param(text, &current)
+    {
+        params.push(param);
     }
 
     params
 }
 
-fn parse_generic_param(tokens: &[Token]) -> Option<GenericParam> {
+fn parse_generic_param(text: &str, tokens: &[Token]) -> Option<GenericParam> {
+    let (decl, start) = match (tokens.first(), tokens.last()) {
+        (Some(first), Some(last)) => (
+            collapse_whitespace(&text[first.start..last.end]),
+            first.start,
+        ),
+        _ => return None,
+    };
     let mut iter = tokens.iter();
     while let Some(tok) = iter.next() {
         match &tok.kind {
@@ -616,23 +870,29 @@ fn parse_generic_param(tokens: &[Token]) -> Option<GenericParam> {
                 return Some(GenericParam {
                     name: name.clone(),
                     kind: GenericParamKind::Lifetime,
+                    decl,
+                    start,
                 });
             }
             TokenKind::Ident(name) if name == "const" => {
                 for tok in iter {
-                    if let TokenKind::Ident(param) = &tok.kind {
+                    if let Some(param) = tok.ident() {
                         return Some(GenericParam {
-                            name: param.clone(),
+                            name: param.to_string(),
                             kind: GenericParamKind::Const,
+                            decl,
+                            start,
                         });
                     }
                 }
                 return None;
             }
-            TokenKind::Ident(name) => {
+            TokenKind::Ident(name) | TokenKind::RawIdent(name) => {
                 return Some(GenericParam {
                     name: name.clone(),
                     kind: GenericParamKind::Type,
+                    decl,
+                    start,
                 });
             }
             _ => {}
@@ -641,15 +901,163 @@ fn parse_generic_param(tokens: &[Token]) -> Option<GenericParam> {
     None
 }
 
-fn parse_params(tokens: &[Token], start: usize, end: usize) -> Vec<String> {
-    let mut params = Vec::new();
+/// A generic parameter in scope at some offset, with the bounds its item
+/// states in a `where` clause.
+pub struct GenericDecl {
+    /// The declaration with its inline bounds, e.g. `T: Into<String>`.
+    pub decl: String,
+    pub where_bounds: Vec<String>,
+    /// Offset of the declaration.
+    pub start: usize,
+}
+
+/// Generic parameters declared by an item together with its `where`
+/// predicates and its span, so parameters can be resolved by scope.
+struct ItemGenerics {
+    params: Vec<GenericParam>,
+    where_bounds: Vec<String>,
+    start: usize,
+    end: usize,
+}
+
+/// Resolves the identifier or lifetime token at `idx` to the generic
+/// parameter of the innermost enclosing item that declares it.
+pub fn generic_param_at(text: &str, tokens: &[Token], idx: usize) -> Option<GenericDecl> {
+    let tok = tokens.get(idx)?;
+    let (name, is_lifetime) = match &tok.kind {
+        TokenKind::Lifetime(name) => (name.as_str(), true),
+        TokenKind::Ident(name) => (name.as_str(), false),
+        _ => return None,
+    };
+    if idx > 0
+        && (tokens[idx - 1].is_punct('.') || matches!(tokens[idx - 1].kind, TokenKind::DoubleColon))
+    {
+        return None;
+    }
+
+    item_generics(text, tokens)
+        .into_iter()
+        .filter(|item| item.start <= tok.start && tok.start < item.end)
+        .filter_map(|item| {
+            let param = item.params.iter().find(|param| {
+                param.name == name && (param.kind == GenericParamKind::Lifetime) == is_lifetime
+            })?;
+            let prefix = if is_lifetime {
+                format!("'{}", name)
+            } else {
+                name.to_string()
+            };
+            let where_bounds = item
+                .where_bounds
+                .iter()
+                .filter(|bound| {
+                    bound
+                        .strip_prefix(&prefix)
+                        .is_some_and(|rest| rest.trim_start().starts_with(':'))
+                })
+                .cloned()
+                .collect();
+            Some((
+                item.end - item.start,
+                GenericDecl {
+                    decl: param.decl.clone(),
+                    where_bounds,
+                    start: param.start,
+                },
+            ))
+        })
+        .min_by_key(|(len, _)| *len)
+        .map(|(_, decl)| decl)
+}
+
+fn item_generics(text: &str, tokens: &[Token]) -> Vec<ItemGenerics> {
+    let mut items = Vec::new();
+    for (idx, tok) in tokens.iter().enumerate() {
+        let Some(keyword) = tok.ident() else {
+            continue;
+        };
+        let open = match keyword {
+            "impl" => idx + 1,
+            "fn" | "struct" | "enum" | "union" | "trait" | "type" => idx + 2,
+            _ => continue,
+        };
+        if !tokens.get(open).is_some_and(|tok| tok.is_punct('<')) {
+            continue;
+        }
+        let Some((params, next)) = parse_generics(text, tokens, open) else {
+            continue;
+        };
+
+        let mut depth = 0i32;
+        let mut where_start = None;
+        let mut end = None;
+        for (i, tok) in tokens.iter().enumerate().skip(next) {
+            if tok.is_punct('(') || tok.is_punct('[') || tok.is_punct('<') {
+                depth += 1;
+            } else if tok.is_punct(')') || tok.is_punct(']') || tok.is_punct('>') {
+                depth -= 1;
+            } else if depth == 0 && tok.is_ident("where") {
+                where_start = Some(i + 1);
+            } else if depth == 0 && (tok.is_punct('{') || tok.is_punct(';')) {
+                end = Some(i);
+                break;
+            }
+        }
+        let Some(end) = end else {
+            continue;
+        };
+        let where_bounds = where_start.map_or_else(Vec::new, |start| {
+            split_top_level(tokens, start, end)
+                .into_iter()
+                .map(|(first, last)| {
+                    collapse_whitespace(&text[tokens[first].start..tokens[last].end])
+                })
+                .collect()
+        });
+        let item_end = if tokens[end].is_punct('{') {
+            find_matching_brace(tokens, end).map_or(text.len(), |close| tokens[close].end)
+        } else {
+            tokens[end].end
+        };
+        items.push(ItemGenerics {
+            params,
+            where_bounds,
+            start: tok.start,
+            end: item_end,
+        });
+    }
+    items
+}
+
+/// Splits `tokens[start..end]` at commas outside brackets and returns the
+/// first and last token index of each non-empty part.
+fn split_top_level(tokens: &[Token], start: usize, end: usize) -> Vec<(usize, usize)> {
+    let mut parts = Vec::new();
+    let mut part_start = start;
+    let mut depth = 0i32;
+    for (i, tok) in tokens.iter().enumerate().take(end + 1).skip(start) {
+        if i < end && (tok.is_punct('(') || tok.is_punct('[') || tok.is_punct('<')) {
+            depth += 1;
+        } else if i < end && (tok.is_punct(')') || tok.is_punct(']') || tok.is_punct('>')) {
+            depth -= 1;
+        } else if i == end || (depth == 0 && tok.is_punct(',')) {
+            if part_start < i {
+                parts.push((part_start, i - 1));
+            }
+            part_start = i + 1;
+        }
+    }
+    parts
+}
+
+fn parse_params(text: &str, tokens: &[Token], start: usize, end: usize) -> Vec<Param> {
+    let mut params = Vec::new();
     let mut current = Vec::new();
     let mut paren_depth = 0i32;
     let mut bracket_depth = 0i32;
     let mut brace_depth = 0i32;
 
-    for idx in start..end {
-        let tok = &tokens[idx];
+    for tok in &tokens[start..end] {
         match tok.kind {
             TokenKind::Punct('(') => {
                 paren_depth += 1;
@@ -682,8 +1090,8 @@ fn parse_params(tokens: &[Token], start: usize, end: usize) -> Vec<String> {
                 current.push(tok.clone());
             }
             TokenKind::Punct(',') if paren_depth == 0 && bracket_depth == 0 && brace_depth == 0 => {
-                if let Some(name) = parse_param_name(&current) {
-                    params.push(name);
+                if let Some(param) = parse_param_name(text, &current) {
+                    params.push(param);
                 }
                 current.clear();
             }
@@ -691,22 +1099,53 @@ fn parse_params(tokens: &[Token], start: usize, end: usize) -> Vec<String> {
         }
     }
 
-    if !current.is_empty() {
-        if let Some(name) = parse_param_name(&current) {
-            params.push(name);
-        }
+    if !current.is_empty()
+        && let Some(param) = parse_param_name(text, &current)
+    {
+        params.push(param);
     }
 
     params
 }
 
-fn parse_param_name(tokens: &[Token]) -> Option<String> {
+fn parse_param_name(text: &str, tokens: &[Token]) -> Option<Param> {
+    let ty = top_level_colon(tokens).and_then(|colon| {
+        let first = tokens.get(colon + 1)?;
+        let last = tokens.last()?;
+        Some(collapse_whitespace(&text[first.start..last.end]))
+    });
+    if let Some(pattern) = destructuring_pattern(tokens) {
+        let first = pattern.first()?;
+        let last = pattern.last()?;
+        let name = pattern.iter().find_map(|tok| match tok.ident() {
+            Some("mut" | "ref") | None => None,
+            Some(name) => Some(name.to_string()),
+        })?;
+        return Some(Param {
+            name,
+            pattern: Some(collapse_whitespace(&text[first.start..last.end])),
+            ty,
+            start: first.start,
+            end: last.end,
+            location: None,
+        });
+    }
+
     for tok in tokens {
         match &tok.kind {
             TokenKind::Ident(name) if name == "mut" || name == "ref" || name == "const" => {
                 continue;
             }
-            TokenKind::Ident(name) => return Some(name.clone()),
+            TokenKind::Ident(name) | TokenKind::RawIdent(name) => {
+                return Some(Param {
+                    name: name.clone(),
+                    pattern: None,
+                    ty,
+                    start: tok.start,
+                    end: tok.end,
+                    location: None,
+                });
+            }
             TokenKind::Lifetime(_) => continue,
             _ => continue,
         }
@@ -714,6 +1153,43 @@ fn parse_param_name(tokens: &[Token]) -> Option<String> {
     None
 }
 
+/// Returns the pattern tokens of a parameter that destructures its argument,
+/// e.g. `(x1, y1): Point` or `Point { x, y }: Point`.
+fn destructuring_pattern(tokens: &[Token]) -> Option<&[Token]> {
+    let colon = top_level_colon(tokens)?;
+    let pattern = &tokens[..colon];
+    let skip = pattern
+        .iter()
+        .take_while(|tok| tok.is_punct('&') || tok.is_ident("mut") || tok.is_ident("ref"))
+        .count();
+    let rest = &pattern[skip..];
+    let first = rest.first()?;
+    let is_destructuring = first.is_punct('(')
+        || first.is_punct('[')
+        || (first.ident().is_some()
+            && rest.get(1).is_some_and(|next| {
+                next.is_punct('(')
+                    || next.is_punct('{')
+                    || matches!(next.kind, TokenKind::DoubleColon)
+            }));
+    is_destructuring.then_some(pattern)
+}
+
+/// Returns the index of the `:` separating a parameter's pattern from its
+/// type.
+fn top_level_colon(tokens: &[Token]) -> Option<usize> {
+    let mut depth = 0i32;
+    tokens.iter().position(|tok| {
+        match tok.kind {
+            TokenKind::Punct('(') | TokenKind::Punct('[') | TokenKind::Punct('{') => depth += 1,
+            TokenKind::Punct(')') | TokenKind::Punct(']') | TokenKind::Punct('}') => depth -= 1,
+            TokenKind::Punct(':') => return depth == 0,
+            _ => {}
+        }
+        false
+    })
+}
+
 fn parse_return_type(text: &str, tokens: &[Token], start: usize) -> Option<String> {
     if start >= tokens.len() {
         return None;
@@ -750,22 +1226,16 @@ fn parse_return_type(text: &str, tokens: &[Token], start: usize) -> Option<Strin
                 break;
             }
             TokenKind::Punct('<') => angle_depth += 1,
-            TokenKind::Punct('>') => {
-                if angle_depth > 0 {
-                    angle_depth -= 1;
-                }
+            TokenKind::Punct('>') if angle_depth > 0 => {
+                angle_depth -= 1;
             }
             TokenKind::Punct('(') => paren_depth += 1,
-            TokenKind::Punct(')') => {
-                if paren_depth > 0 {
-                    paren_depth -= 1;
-                }
+            TokenKind::Punct(')') if paren_depth > 0 => {
+                paren_depth -= 1;
             }
             TokenKind::Punct('[') => bracket_depth += 1,
-            TokenKind::Punct(']') => {
-                if bracket_depth > 0 {
-                    bracket_depth -= 1;
-                }
+            TokenKind::Punct(']') if bracket_depth > 0 => {
+                bracket_depth -= 1;
             }
             _ => {}
         }
@@ -780,175 +1250,119 @@ fn parse_return_type(text: &str, tokens: &[Token], start: usize) -> Option<Strin
     }
 }
 
-fn find_matching_paren(tokens: &[Token], idx: usize) -> Option<usize> {
-    let mut depth = 0i32;
-    for i in idx..tokens.len() {
-        match tokens[i].kind {
-            TokenKind::Punct('(') => depth += 1,
-            TokenKind::Punct(')') => {
-                depth -= 1;
-                if depth == 0 {
-                    return Some(i);
-                }
-            }
-            _ => {}
-        }
-    }
-    None
-}
+fn local_var_type_hints(text: &str, ctx: &HintContext) -> Vec<InlayHint> {
+    let tokens = lex(text);
+    let locals = LocalTypes::new(text, &tokens, ctx.index);
+    let mut hints = Vec::new();
 
-fn find_matching_angle(tokens: &[Token], idx: usize) -> Option<usize> {
-    let mut depth = 0i32;
-    for i in idx..tokens.len() {
-        match tokens[i].kind {
-            TokenKind::Punct('<') => depth += 1,
-            TokenKind::Punct('>') => {
-                depth -= 1;
-                if depth == 0 {
-                    return Some(i);
-                }
-            }
-            _ => {}
+    let mut i = 0usize;
+    while i < tokens.len() {
+        if tokens[i].is_ident("let")
+            && let Some(binding) = parse_let_binding(text, &tokens, i)
+            && binding.ty.is_none()
+            && let Some(ty) = infer_type(binding.init, ctx.index, &locals, tokens[i].start)
+            && !(ctx.config.hide_obvious_types && is_obvious_type(binding.init, &ty))
+            && let Some(position) = ctx.position(binding.name_end)
+        {
+            hints.push(binding_type_hint(position, &ty, ctx));
         }
+        i += 1;
     }
-    None
+
+    hints
 }
 
-fn find_matching_angle_backward(tokens: &[Token], idx: usize) -> Option<usize> {
-    let mut depth = 0i32;
-    let mut i = idx;
-    loop {
-        match tokens[i].kind {
-            TokenKind::Punct('>') => depth += 1,
-            TokenKind::Punct('<') => {
-                depth -= 1;
-                if depth == 0 {
-                    return Some(i);
-                }
-            }
-            _ => {}
-        }
-        if i == 0 {
-            break;
-        }
-        i -= 1;
-    }
-    None
+/// A `let` statement binding a single identifier to an initializer.
+struct LetBinding<'a> {
+    name: &'a str,
+    name_end: usize,
+    /// The type annotation, if any.
+    ty: Option<&'a str>,
+    init: &'a str,
+    /// Offset of the `;` ending the statement, or the end of the text.
+    end: usize,
 }
 
-fn local_var_type_hints(text: &str, index: &WorkspaceIndex) -> Vec<InlayHint> {
-    let tokens = lex(text);
-    let mut hints = Vec::new();
+fn parse_let_binding<'a>(text: &'a str, tokens: &[Token], idx: usize) -> Option<LetBinding<'a>> {
+    if idx > 0
+        && let Some(prev) = tokens[idx - 1].ident()
+        && matches!(prev, "if" | "while" | "match" | "for")
+    {
+        return None;
+    }
 
-    let mut i = 0usize;
-    while i < tokens.len() {
-        if tokens[i].is_ident("let") {
-            if i > 0 {
-                if let Some(prev) = tokens[i - 1].ident() {
-                    if matches!(prev, "if" | "while" | "match" | "for") {
-                        i += 1;
-                        continue;
-                    }
-                }
-            }
+    let mut j = idx + 1;
+    if j < tokens.len() && tokens[j].is_ident("mut") {
+        j += 1;
+    }
+    let var_token = tokens.get(j)?;
+    var_token.ident()?;
+    let name = &text[var_token.start..var_token.end];
+    if name == "_" {
+        return None;
+    }
+    j += 1;
 
-            let mut j = i + 1;
-            if j < tokens.len() && tokens[j].is_ident("mut") {
-                j += 1;
-            }
-            let var_token = match tokens.get(j) {
-                Some(tok) => tok,
-                None => {
-                    i += 1;
-                    continue;
-                }
-            };
-            let var_name = match var_token.ident() {
-                Some(name) => name,
-                None => {
-                    i += 1;
-                    continue;
-                }
-            };
-            if var_name == "_" {
-                i += 1;
-                continue;
+    let mut colon_idx = None;
+    let mut eq_idx = None;
+    let mut depth = 0i32;
+    while j < tokens.len() {
+        let tok = &tokens[j];
+        match tok.kind {
+            TokenKind::Punct('(') | TokenKind::Punct('[') | TokenKind::Punct('{') => depth += 1,
+            TokenKind::Punct(')') | TokenKind::Punct(']') | TokenKind::Punct('}') if depth > 0 => {
+                depth -= 1;
             }
-            let var_end = var_token.end;
-            j += 1;
-
-            let mut has_type = false;
-            let mut eq_idx = None;
-            let mut depth = 0i32;
-            while j < tokens.len() {
-                let tok = &tokens[j];
-                match tok.kind {
-                    TokenKind::Punct('(') | TokenKind::Punct('[') | TokenKind::Punct('{') => {
-                        depth += 1
-                    }
-                    TokenKind::Punct(')') | TokenKind::Punct(']') | TokenKind::Punct('}') => {
-                        if depth > 0 {
-                            depth -= 1;
-                        }
-                    }
-                    TokenKind::Punct(':') if depth == 0 => has_type = true,
-                    TokenKind::Punct('=') if depth == 0 => {
-                        eq_idx = Some(j);
-                        break;
-                    }
-                    TokenKind::Punct(';') if depth == 0 => break,
-                    _ => {}
-                }
-                j += 1;
+            TokenKind::Punct(':') if depth == 0 => {
+                colon_idx.get_or_insert(j);
             }
-
-            if has_type {
-                i += 1;
-                continue;
+            TokenKind::Punct('=') if depth == 0 => {
+                eq_idx = Some(j);
+                break;
             }
+            TokenKind::Punct(';') if depth == 0 => break,
+            _ => {}
+        }
+        j += 1;
+    }
+    let eq_idx = eq_idx?;
 
-            let Some(eq_idx) = eq_idx else {
-                i += 1;
-                continue;
-            };
-
-            let mut k = eq_idx + 1;
-            let mut depth = 0i32;
-            let mut end_offset = text.len();
-            while k < tokens.len() {
-                let tok = &tokens[k];
-                match tok.kind {
-                    TokenKind::Punct('(') | TokenKind::Punct('[') | TokenKind::Punct('{') => {
-                        depth += 1
-                    }
-                    TokenKind::Punct(')') | TokenKind::Punct(']') | TokenKind::Punct('}') => {
-                        if depth > 0 {
-                            depth -= 1;
-                        }
-                    }
-                    TokenKind::Punct(';') if depth == 0 => {
-                        end_offset = tok.start;
-                        break;
-                    }
-                    _ => {}
-                }
-                k += 1;
+    let mut k = eq_idx + 1;
+    let mut depth = 0i32;
+    let mut end_offset = text.len();
+    while k < tokens.len() {
+        let tok = &tokens[k];
+        match tok.kind {
+            TokenKind::Punct('(') | TokenKind::Punct('[') | TokenKind::Punct('{') => depth += 1,
+            TokenKind::Punct(')') | TokenKind::Punct(']') | TokenKind::Punct('}') if depth > 0 => {
+                depth -= 1;
             }
-
-            let expr = text[tokens[eq_idx].end..end_offset].trim();
-            if let Some(ty) = infer_type(expr, index) {
-                if let Some(position) = offset_to_position(text, var_end) {
-                    hints.push(type_hint(position, &ty));
-                }
+            TokenKind::Punct(';') if depth == 0 => {
+                end_offset = tok.start;
+                break;
             }
+            _ => {}
         }
-        i += 1;
+        k += 1;
     }
 
-    hints
+    Some(LetBinding {
+        name,
+        name_end: var_token.end,
+        ty: colon_idx.map(|colon| text[tokens[colon].end..tokens[eq_idx].start].trim()),
+        init: text[tokens[eq_idx].end..end_offset].trim(),
+        end: end_offset,
+    })
 }
 
-fn infer_type(expr: &str, index: &WorkspaceIndex) -> Option<String> {
+/// Infers the type of `expr`, consulting `locals` for bindings visible at
+/// `offset`.
+fn infer_type(
+    expr: &str,
+    index: &WorkspaceIndex,
+    locals: &LocalTypes,
+    offset: usize,
+) -> Option<String> {
     let trimmed = expr.trim();
     if trimmed.is_empty() {
         return None;
@@ -970,23 +1384,148 @@ fn infer_type(expr: &str, index: &WorkspaceIndex) -> Option<String> {
         return Some(num);
     }
 
+    if let [tok] = lex(trimmed).as_slice()
+        && tok.ident().is_some()
+        && let Some(ty) = locals.type_of(trimmed, offset)
+    {
+        return Some(ty.to_string());
+    }
+
     if let Some(ty) = infer_struct_literal(trimmed, index) {
         return Some(ty);
     }
 
+    if let Some(ty) = infer_known_method(trimmed, index, locals, offset) {
+        return ty;
+    }
+
     infer_from_call(trimmed, index)
 }
 
+/// Infers std methods whose result type follows from the receiver alone,
+/// e.g. `clone` or `to_string`. Returns `None` when `expr` is not such a
+/// call; otherwise workspace methods of the same name are never consulted,
+/// even if the receiver's type is unknown.
+fn infer_known_method(
+    expr: &str,
+    index: &WorkspaceIndex,
+    locals: &LocalTypes,
+    offset: usize,
+) -> Option<Option<String>> {
+    let tokens = lex(expr);
+    let [.., dot, method, open, close] = tokens.as_slice() else {
+        return None;
+    };
+    if !dot.is_punct('.') || !open.is_punct('(') || !close.is_punct(')') {
+        return None;
+    }
+    let receiver = || infer_type(&expr[..dot.start], index, locals, offset);
+    let ty = match method.ident()? {
+        "to_string" => Some("String".to_string()),
+        "as_str" => Some("&str".to_string()),
+        "clone" => receiver().map(|ty| cloned_type(&ty)),
+        "to_owned" => receiver().map(|ty| owned_type(&ty)),
+        "to_vec" => receiver().and_then(|ty| {
+            let elem = ty.strip_prefix('&')?.trim_start().strip_prefix('[')?;
+            Some(format!("Vec<{}>", elem.strip_suffix(']')?.trim()))
+        }),
+        _ => return None,
+    };
+    Some(ty)
+}
+
+/// Result of `clone` on a receiver of type `ty`: references to `Clone` types
+/// auto-deref, while `&str` and slices only copy the reference.
+fn cloned_type(ty: &str) -> String {
+    match ty.strip_prefix('&') {
+        Some(rest) => {
+            let rest = rest.strip_prefix("mut ").unwrap_or(rest).trim_start();
+            if rest == "str" || rest.starts_with('[') {
+                ty.to_string()
+            } else {
+                rest.to_string()
+            }
+        }
+        None => ty.to_string(),
+    }
+}
+
+/// Result of `to_owned` on a receiver of type `ty`.
+fn owned_type(ty: &str) -> String {
+    let Some(rest) = ty.strip_prefix('&') else {
+        return ty.to_string();
+    };
+    let rest = rest.strip_prefix("mut ").unwrap_or(rest).trim_start();
+    if rest == "str" {
+        return "String".to_string();
+    }
+    match rest
+        .strip_prefix('[')
+        .and_then(|elem| elem.strip_suffix(']'))
+    {
+        Some(elem) if !elem.contains(';') => format!("Vec<{}>", elem.trim()),
+        _ => rest.to_string(),
+    }
+}
+
+/// Returns true when `ty` is spelled out as a segment of the initializer's
+/// leading path, e.g. `Foo { .. }`, `Foo(..)`, `Foo::new()` or
+/// `config::Config::default()`.
+fn is_obvious_type(expr: &str, ty: &str) -> bool {
+    let base = ty.split('<').next().unwrap_or(ty).trim();
+    if base.is_empty() {
+        return false;
+    }
+
+    let tokens = lex(expr);
+    let is_path_sep = |idx: usize| {
+        tokens
+            .get(idx)
+            .is_some_and(|tok| matches!(tok.kind, TokenKind::DoubleColon))
+    };
+
+    let mut i = 0usize;
+    while let Some(ident) = tokens.get(i).and_then(|tok| tok.ident()) {
+        if ident == base {
+            return true;
+        }
+        i += 1;
+        if is_path_sep(i) {
+            i += 1;
+        }
+        if tokens.get(i).is_some_and(|tok| tok.is_punct('<')) {
+            let Some(end) = find_matching_angle(&tokens, i) else {
+                break;
+            };
+            i = end + 1;
+            if !is_path_sep(i) {
+                break;
+            }
+            i += 1;
+        } else if !is_path_sep(i - 1) {
+            break;
+        }
+    }
+
+    false
+}
+
 fn infer_string_literal(text: &str) -> Option<String> {
-    if text.starts_with("b\"") || text.starts_with("br\"") || text.starts_with("br#") {
+    if text.starts_with("b\"") || text.strip_prefix("br").is_some_and(is_raw_string_body) {
         return Some("&[u8]".to_string());
     }
-    if text.starts_with('"') || text.starts_with("r\"") || text.starts_with("r#") {
+    if text.starts_with('"') || text.strip_prefix('r').is_some_and(is_raw_string_body) {
         return Some("&str".to_string());
     }
     None
 }
 
+/// Matches the `#*"` after the `r` of a raw string, so raw identifiers like
+/// `r#type` are not mistaken for one.
+fn is_raw_string_body(rest: &str) -> bool {
+    rest.trim_start_matches('#').starts_with('"')
+}
+
 fn is_char_literal(text: &str) -> bool {
     let bytes = text.as_bytes();
     bytes.len() >= 2 && bytes[0] == b'\'' && bytes[bytes.len() - 1] == b'\''
@@ -1056,31 +1595,24 @@ fn infer_struct_literal(expr: &str, index: &WorkspaceIndex) -> Option<String> {
     let mut i = 0usize;
     let mut name = None;
 
-    while i < tokens.len() {
-        if let Some(ident) = tokens[i].ident() {
-            name = Some(ident.to_string());
-            i += 1;
-            while i + 1 < tokens.len() && matches!(tokens[i].kind, TokenKind::DoubleColon) {
-                if let Some(next) = tokens[i + 1].ident() {
-                    name = Some(next.to_string());
-                    i += 2;
-                } else {
-                    break;
-                }
+    if let Some(ident) = tokens.first().and_then(|tok| tok.ident()) {
+        name = Some(ident.to_string());
+        i += 1;
+        while i + 1 < tokens.len() && matches!(tokens[i].kind, TokenKind::DoubleColon) {
+            if let Some(next) = tokens[i + 1].ident() {
+                name = Some(next.to_string());
+                i += 2;
+            } else {
+                break;
             }
-            break;
-        } else {
-            break;
         }
     }
 
     let name = name?;
     let next = tokens.get(i)?;
     match next.kind {
-        TokenKind::Punct('{') | TokenKind::Punct('(') => {
-            if index.is_unique_type(&name) {
-                return Some(name);
-            }
+        TokenKind::Punct('{') | TokenKind::Punct('(') if index.is_unique_type(&name) => {
+            return Some(name);
         }
         _ => {}
     }
@@ -1096,10 +1628,10 @@ fn infer_from_call(expr: &str, index: &WorkspaceIndex) -> Option<String> {
             .unique_method(&call.name)
             .and_then(|sig| sig.return_type.clone()),
         CallKind::Function => {
-            if let Some(sig) = index.unique_fn(&call.name) {
-                if let Some(ret) = sig.return_type.clone() {
-                    return Some(ret);
-                }
+            if let Some(sig) = index.unique_fn(&call.name)
+                && let Some(ret) = sig.return_type.clone()
+            {
+                return Some(ret);
             }
             if index.is_unique_type(&call.name) {
                 return Some(call.name.clone());
@@ -1109,24 +1641,36 @@ fn infer_from_call(expr: &str, index: &WorkspaceIndex) -> Option<String> {
     }
 }
 
-fn arg_name_hints(text: &str, index: &WorkspaceIndex) -> Vec<InlayHint> {
+fn arg_name_hints(text: &str, ctx: &HintContext) -> Vec<InlayHint> {
     let calls = collect_calls(text);
+    let locals = LocalBindings::new(text, &lex(text));
     let mut hints = Vec::new();
 
     for call in calls {
-        let sig = match call.kind {
-            CallKind::Function => index.unique_fn(&call.name),
-            CallKind::Method => index.unique_method(&call.name),
+        let Some((name, sig)) = resolve_call(&call, &locals, ctx.index) else {
+            continue;
         };
-        let Some(sig) = sig else { continue };
 
         let count = sig.params.len().min(call.arg_starts.len());
         for idx in 0..count {
-            if sig.params[idx].is_empty() || sig.params[idx] == "_" {
+            let param = &sig.params[idx];
+            if param.name.is_empty() || param.name == "_" {
                 continue;
             }
-            if let Some(position) = offset_to_position(text, call.arg_starts[idx]) {
-                hints.push(param_hint(position, &sig.params[idx]));
+            let label = match &param.pattern {
+                Some(pattern) if ctx.config.pattern_param_hints => pattern,
+                Some(_) => continue,
+                None => &param.name,
+            };
+            if let Some(position) = ctx.position(call.arg_starts[idx]) {
+                let mut hint = param_hint(position, label, param.location.as_ref(), ctx);
+                let name = name.to_string();
+                let data = match call.kind {
+                    CallKind::Function => TooltipData::Function { name },
+                    CallKind::Method => TooltipData::Method { name },
+                };
+                ctx.set_tooltip(&mut hint, &sig.signature, data);
+                hints.push(hint);
             }
         }
     }
@@ -1134,26 +1678,33 @@ fn arg_name_hints(text: &str, index: &WorkspaceIndex) -> Vec<InlayHint> {
     hints
 }
 
-fn const_generic_hints(text: &str, index: &WorkspaceIndex) -> Vec<InlayHint> {
+fn const_generic_hints(text: &str, ctx: &HintContext) -> Vec<InlayHint> {
     let tokens = lex(text);
     let mut hints = Vec::new();
 
     let mut i = 0usize;
     while i < tokens.len() {
-        if tokens[i].is_punct('<') {
-            if let Some((name, end_idx)) = detect_generic_arg_list(&tokens, i) {
-                let args = parse_generic_arg_starts(&tokens, i + 1, end_idx);
-                if let Some(generics) = index.unique_generics(&name) {
-                    let limit = generics.len().min(args.len());
-                    for idx in 0..limit {
-                        if generics[idx].kind == GenericParamKind::Const {
-                            if let Some(position) = offset_to_position(text, args[idx]) {
-                                hints.push(param_hint(position, &generics[idx].name));
-                            }
-                        }
+        if tokens[i].is_punct('<')
+            && let Some((name, end_idx)) = detect_generic_arg_list(&tokens, i)
+        {
+            let args = parse_generic_arg_starts(&tokens, i + 1, end_idx);
+            if let Some(generics) = ctx.index.unique_generics(&name) {
+                let limit = generics.len().min(args.len());
+                for idx in 0..limit {
+                    if generics[idx].kind == GenericParamKind::Const
+                        && !arg_is_name(text, args[idx], &generics[idx].name)
+                        && let Some(position) = ctx.position(args[idx])
+                    {
+                        let param = &generics[idx];
+                        let mut hint = param_hint(position, &param.name, None, ctx);
+                        let data = TooltipData::Generic {
+                            owner: name.clone(),
+                            index: idx,
+                        };
+                        ctx.set_tooltip(&mut hint, &param.decl, data);
+                        hints.push(hint);
                     }
                 }
-                i = end_idx;
             }
         }
         i += 1;
@@ -1162,6 +1713,15 @@ fn const_generic_hints(text: &str, index: &WorkspaceIndex) -> Vec<InlayHint> {
     hints
 }
 
+/// True when the argument at `offset` is just `name`, as in `Buffer<N>`
+/// inside `impl<const N: usize>`, where a hint would repeat itself.
+fn arg_is_name(text: &str, offset: usize, name: &str) -> bool {
+    text[offset..].strip_prefix(name).is_some_and(|rest| {
+        let rest = rest.trim_start();
+        rest.starts_with(',') || rest.starts_with('>')
+    })
+}
+
 fn detect_generic_arg_list(tokens: &[Token], idx: usize) -> Option<(String, usize)> {
     if idx == 0 {
         return None;
@@ -1175,16 +1735,15 @@ fn detect_generic_arg_list(tokens: &[Token], idx: usize) -> Option<(String, usiz
     }
 
     let name = tokens[name_idx].ident()?.to_string();
-    if is_keyword(&name) {
+    if tokens[name_idx].is_keyword() {
         return None;
     }
 
-    if name_idx > 0 {
-        if let Some(prev) = tokens[name_idx - 1].ident() {
-            if matches!(prev, "struct" | "enum" | "trait" | "type" | "fn") {
-                return None;
-            }
-        }
+    if name_idx > 0
+        && let Some(prev) = tokens[name_idx - 1].ident()
+        && matches!(prev, "struct" | "enum" | "trait" | "type" | "fn")
+    {
+        return None;
     }
 
     let end_idx = find_matching_angle(tokens, idx)?;
@@ -1198,10 +1757,15 @@ fn detect_generic_arg_list(tokens: &[Token], idx: usize) -> Option<(String, usiz
     Some((name, end_idx))
 }
 
+/// Checks the token after a generic argument list in expression position
+/// (`foo::<3>()`) or type position (`let a: Buf<3> =`, `impl Buf<3> where`).
 fn generic_follows(tokens: &[Token], end_idx: usize) -> bool {
     if end_idx + 1 >= tokens.len() {
         return true;
     }
+    if tokens[end_idx + 1].is_ident("where") {
+        return true;
+    }
     matches!(
         tokens[end_idx + 1].kind,
         TokenKind::Punct('(')
@@ -1214,6 +1778,8 @@ fn generic_follows(tokens: &[Token], end_idx: usize) -> bool {
             | TokenKind::Punct(']')
             | TokenKind::Punct('>')
             | TokenKind::Punct('=')
+            | TokenKind::Punct('+')
+            | TokenKind::Punct('|')
             | TokenKind::DoubleColon
     )
 }
@@ -1226,32 +1792,23 @@ fn parse_generic_arg_starts(tokens: &[Token], start: usize, end: usize) -> Vec<u
     let mut brace_depth = 0i32;
     let mut angle_depth = 0i32;
 
-    for idx in start..end {
-        let tok = &tokens[idx];
+    for tok in &tokens[start..end] {
         match tok.kind {
             TokenKind::Punct('(') => paren_depth += 1,
-            TokenKind::Punct(')') => {
-                if paren_depth > 0 {
-                    paren_depth -= 1;
-                }
+            TokenKind::Punct(')') if paren_depth > 0 => {
+                paren_depth -= 1;
             }
             TokenKind::Punct('[') => bracket_depth += 1,
-            TokenKind::Punct(']') => {
-                if bracket_depth > 0 {
-                    bracket_depth -= 1;
-                }
+            TokenKind::Punct(']') if bracket_depth > 0 => {
+                bracket_depth -= 1;
             }
             TokenKind::Punct('{') => brace_depth += 1,
-            TokenKind::Punct('}') => {
-                if brace_depth > 0 {
-                    brace_depth -= 1;
-                }
+            TokenKind::Punct('}') if brace_depth > 0 => {
+                brace_depth -= 1;
             }
             TokenKind::Punct('<') => angle_depth += 1,
-            TokenKind::Punct('>') => {
-                if angle_depth > 0 {
-                    angle_depth -= 1;
-                }
+            TokenKind::Punct('>') if angle_depth > 0 => {
+                angle_depth -= 1;
             }
             TokenKind::Punct(',')
                 if paren_depth == 0
@@ -1267,10 +1824,13 @@ fn parse_generic_arg_starts(tokens: &[Token], start: usize, end: usize) -> Vec<u
             _ => {}
         }
 
-        if paren_depth == 0 && bracket_depth == 0 && brace_depth == 0 && angle_depth == 0 {
-            if arg_start.is_none() {
-                arg_start = Some(tok.start);
-            }
+        if paren_depth == 0
+            && bracket_depth == 0
+            && brace_depth == 0
+            && angle_depth == 0
+            && arg_start.is_none()
+        {
+            arg_start = Some(tok.start);
         }
     }
 
@@ -1281,8 +1841,9 @@ fn parse_generic_arg_starts(tokens: &[Token], start: usize, end: usize) -> Vec<u
     args
 }
 
-fn chained_expr_type_hints(text: &str, index: &WorkspaceIndex) -> Vec<InlayHint> {
+fn chained_expr_type_hints(text: &str, ctx: &HintContext) -> Vec<InlayHint> {
     let calls = collect_calls(text);
+    let locals = LocalBindings::new(text, &lex(text));
     let mut hints = Vec::new();
 
     for call in calls {
@@ -1293,29 +1854,631 @@ fn chained_expr_type_hints(text: &str, index: &WorkspaceIndex) -> Vec<InlayHint>
         if !is_chain_segment {
             continue;
         }
-        let ty = match call.kind {
-            CallKind::Method => index
-                .unique_method(&call.name)
-                .and_then(|sig| sig.return_type.clone()),
-            CallKind::Function => index
-                .unique_fn(&call.name)
-                .and_then(|sig| sig.return_type.clone()),
+        let Some(ty) =
+            resolve_call(&call, &locals, ctx.index).and_then(|(_, sig)| sig.return_type.as_ref())
+        else {
+            continue;
         };
-        let Some(ty) = ty else { continue };
 
         let offset = (call.close_paren + 1).min(text.len());
-        if let Some(position) = offset_to_position(text, offset) {
-            hints.push(type_hint(position, &ty));
+        if let Some(position) = ctx.position(offset) {
+            hints.push(type_hint(position, ty, ctx));
+        }
+    }
+
+    hints
+}
+
+/// Resolves the signature a call refers to, returning the resolved function
+/// name with it. A plain `name(..)` call whose callee is a local binding in
+/// scope only resolves when the binding is initialized with a known function.
+fn resolve_call<'a>(
+    call: &'a Call,
+    locals: &'a LocalBindings,
+    index: &'a WorkspaceIndex,
+) -> Option<(&'a str, &'a FunctionSig)> {
+    match call.kind {
+        CallKind::Method => Some((&call.name, index.unique_method(&call.name)?)),
+        CallKind::Function if call.qualified => Some((&call.name, index.unique_fn(&call.name)?)),
+        CallKind::Function => match locals.visible(&call.name, call.close_paren) {
+            Some(binding) => {
+                let alias = fn_alias(binding.init?)?;
+                Some((alias, index.unique_fn(alias)?))
+            }
+            None => Some((&call.name, index.unique_fn(&call.name)?)),
+        },
+    }
+}
+
+/// A local name visible between `start` and `scope_end` (byte offsets).
+#[derive(Debug, Clone)]
+struct LocalBinding<'a> {
+    name: &'a str,
+    start: usize,
+    scope_end: usize,
+    /// The `let` initializer; `None` for function parameters.
+    init: Option<&'a str>,
+    /// The type annotation or declared parameter type.
+    ty: Option<String>,
+}
+
+/// The `let` bindings and plain `fn` parameters of a file in source order,
+/// with the extent of the block that declares them, found by name.
+struct LocalBindings<'a> {
+    bindings: Vec<LocalBinding<'a>>,
+    by_name: HashMap<&'a str, Vec<usize>>,
+}
+
+impl<'a> LocalBindings<'a> {
+    /// Collects the bindings in one pass over `tokens`, closing the scope of
+    /// each when the `}` of its block is reached.
+    fn new(text: &'a str, tokens: &[Token]) -> Self {
+        let mut bindings: Vec<LocalBinding> = Vec::new();
+        // The open blocks, each with the bindings declared in it.
+        let mut blocks: Vec<Vec<usize>> = Vec::new();
+        // Parameters waiting for the `{` of their function's body.
+        let mut params: HashMap<usize, Vec<usize>> = HashMap::new();
+
+        for (i, tok) in tokens.iter().enumerate() {
+            if tok.is_punct('{') {
+                blocks.push(params.remove(&i).unwrap_or_default());
+            } else if tok.is_punct('}') {
+                for idx in blocks.pop().unwrap_or_default() {
+                    bindings[idx].scope_end = tok.start;
+                }
+            } else if tok.is_ident("let") {
+                let Some(binding) = parse_let_binding(text, tokens, i) else {
+                    continue;
+                };
+                if let Some(block) = blocks.last_mut() {
+                    block.push(bindings.len());
+                }
+                bindings.push(LocalBinding {
+                    name: binding.name,
+                    start: binding.end,
+                    scope_end: text.len(),
+                    init: Some(binding.init),
+                    ty: binding.ty.map(str::to_string),
+                });
+            } else if tok.is_ident("fn")
+                && let Some((_, sig, _)) = parse_fn_def(text, tokens, i)
+                && let Some((open, _)) = item_block_header(text, tokens, i)
+            {
+                for param in sig.params.iter().filter(|param| param.pattern.is_none()) {
+                    params.entry(open).or_default().push(bindings.len());
+                    bindings.push(LocalBinding {
+                        name: &text[param.start..param.end],
+                        start: tokens[open].start,
+                        scope_end: text.len(),
+                        init: None,
+                        ty: param.ty.clone(),
+                    });
+                }
+            }
+        }
+
+        let mut by_name: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (idx, binding) in bindings.iter().enumerate() {
+            by_name.entry(binding.name).or_default().push(idx);
+        }
+        LocalBindings { bindings, by_name }
+    }
+
+    fn len(&self) -> usize {
+        self.bindings.len()
+    }
+
+    /// Returns the innermost binding named `name` that is in scope at
+    /// `offset`.
+    fn visible(&self, name: &str, offset: usize) -> Option<&LocalBinding<'a>> {
+        let idx = self.visible_index(name, offset, self.len())?;
+        Some(&self.bindings[idx])
+    }
+
+    /// `visible`, among the first `limit` bindings only.
+    fn visible_index(&self, name: &str, offset: usize, limit: usize) -> Option<usize> {
+        self.by_name
+            .get(name)?
+            .iter()
+            .copied()
+            .filter(|&idx| {
+                let binding = &self.bindings[idx];
+                idx < limit && binding.start <= offset && offset < binding.scope_end
+            })
+            .max_by_key(|&idx| self.bindings[idx].start)
+    }
+}
+
+/// Types of the local bindings in a file, inferred in source order so that
+/// each initializer sees the bindings declared before it in its function.
+struct LocalTypes<'a> {
+    bindings: LocalBindings<'a>,
+    types: Vec<Option<String>>,
+}
+
+impl<'a> LocalTypes<'a> {
+    fn new(text: &'a str, tokens: &[Token], index: &WorkspaceIndex) -> Self {
+        let mut locals = LocalTypes {
+            bindings: LocalBindings::new(text, tokens),
+            types: Vec::new(),
+        };
+        for idx in 0..locals.bindings.len() {
+            let binding = &locals.bindings.bindings[idx];
+            let ty = match (&binding.ty, binding.init) {
+                (Some(ty), _) => Some(ty.clone()),
+                (None, Some(init)) => infer_type(init, index, &locals, binding.start),
+                (None, None) => None,
+            };
+            locals.types.push(ty);
+        }
+        locals
+    }
+
+    /// Returns the type of the binding `name` in scope at `offset`. While
+    /// building, only bindings already inferred are considered, so
+    /// `let x = x.clone();` refers to the shadowed `x`.
+    fn type_of(&self, name: &str, offset: usize) -> Option<&str> {
+        let idx = self
+            .bindings
+            .visible_index(name, offset, self.types.len())?;
+        self.types[idx].as_deref()
+    }
+}
+
+/// A struct field reached through `receiver.field`.
+pub struct Field {
+    pub owner: String,
+    pub name: String,
+    pub ty: String,
+    pub docs: Option<String>,
+    pub location: Option<Location>,
+}
+
+/// Resolves the field named by the token at `idx`, which follows a `.`, from
+/// the type of the receiver: a local binding, `self` inside an `impl` block,
+/// or an earlier field access.
+pub fn resolve_field(
+    docs: &DocumentStore,
+    root: Option<&Path>,
+    text: &str,
+    tokens: &[Token],
+    idx: usize,
+    encoding: PositionEncoding,
+) -> Option<Field> {
+    let name = tokens.get(idx)?.ident()?;
+    let dot = idx.checked_sub(1)?;
+    let index = WorkspaceIndex::build(docs, root, encoding);
+    let locals = LocalTypes::new(text, tokens, &index);
+    let owner = receiver_type(tokens, dot, &index, &locals)?;
+    let field = index.unique_field(&owner, name)?;
+    Some(Field {
+        owner,
+        name: field.name.clone(),
+        ty: field.ty.clone(),
+        docs: field.docs.clone(),
+        location: field.location.clone(),
+    })
+}
+
+/// Returns the struct name of the expression before the `.` at `dot`.
+fn receiver_type(
+    tokens: &[Token],
+    dot: usize,
+    index: &WorkspaceIndex,
+    locals: &LocalTypes,
+) -> Option<String> {
+    if !tokens.get(dot)?.is_punct('.') {
+        return None;
+    }
+    let receiver = &tokens[dot.checked_sub(1)?];
+    let name = receiver.ident()?;
+    if dot >= 2 && tokens[dot - 2].is_punct('.') {
+        let owner = receiver_type(tokens, dot - 2, index, locals)?;
+        let field = index.unique_field(&owner, name)?;
+        return type_name(&field.ty);
+    }
+    if name == "self" {
+        return enclosing_impl_type(tokens, dot - 1);
+    }
+    type_name(locals.type_of(name, receiver.start)?)
+}
+
+/// Reduces a type such as `&'a mut doc::Document<T>` to `Document`.
+fn type_name(ty: &str) -> Option<String> {
+    let tokens = lex(ty);
+    let mut name = None;
+    for tok in &tokens {
+        if tok.is_punct('<') {
+            break;
+        }
+        if let Some(ident) = tok.ident()
+            && ident != "mut"
+            && ident != "dyn"
+        {
+            name = Some(ident);
+        }
+    }
+    name.map(str::to_string)
+}
+
+/// Returns the self type of the innermost `impl` block containing the token
+/// at `idx`.
+fn enclosing_impl_type(tokens: &[Token], idx: usize) -> Option<String> {
+    tokens[..idx]
+        .iter()
+        .enumerate()
+        .filter(|(_, tok)| tok.is_ident("impl"))
+        .filter_map(|(start, _)| {
+            let open = start + tokens[start..].iter().position(|tok| tok.is_punct('{'))?;
+            let close = find_matching_brace(tokens, open)?;
+            (open < idx && idx < close).then_some((start, open))
+        })
+        .next_back()
+        .and_then(|(start, open)| impl_self_type(&tokens[start + 1..open]))
+        .map(str::to_string)
+}
+
+/// Returns the function name when `init` is a bare path such as `foo` or
+/// `module::foo`.
+fn fn_alias(init: &str) -> Option<&str> {
+    let tokens = lex(init);
+    let is_path = tokens
+        .iter()
+        .all(|tok| tok.ident().is_some() || matches!(tok.kind, TokenKind::DoubleColon));
+    let last = tokens.last()?;
+    if !is_path || last.ident().is_none() {
+        return None;
+    }
+    Some(&init[last.start..last.end])
+}
+
+/// Shows the `&`/`ref` that match ergonomics inserts when a non-reference
+/// pattern matches a reference. The scrutinee counts as a reference when it
+/// starts with `&` or names a `let` binding initialized with one.
+fn binding_mode_hints(text: &str, ctx: &HintContext) -> Vec<InlayHint> {
+    let tokens = lex(text);
+    let mut hints = Vec::new();
+    let locals = LocalTypes::new(text, &tokens, ctx.index);
+    let mut ref_vars: HashMap<&str, bool> = HashMap::new();
+
+    for (i, tok) in tokens.iter().enumerate() {
+        if tok.is_ident("let") {
+            if let Some(binding) = parse_let_binding(text, &tokens, i) {
+                match reference_mutability(binding.init, ctx.index, &locals, tok.start) {
+                    Some(is_mut) => ref_vars.insert(binding.name, is_mut),
+                    None => ref_vars.remove(binding.name),
+                };
+            }
+            continue;
+        }
+        if !tok.is_ident("match") {
+            continue;
+        }
+
+        let Some(open_idx) = tokens[i + 1..]
+            .iter()
+            .position(|tok| tok.is_punct('{'))
+            .map(|pos| i + 1 + pos)
+        else {
+            continue;
+        };
+        let scrutinee = &tokens[i + 1..open_idx];
+        let is_mut = match scrutinee {
+            [first, second, ..] if first.is_punct('&') => Some(second.is_ident("mut")),
+            [first, ..] if first.is_punct('&') => Some(false),
+            [var] => var.ident().and_then(|name| ref_vars.get(name).copied()),
+            _ => None,
+        };
+        let Some(is_mut) = is_mut else { continue };
+        let Some(close_idx) = find_matching_brace(&tokens, open_idx) else {
+            continue;
+        };
+
+        for pattern in match_arm_patterns(&tokens, open_idx + 1, close_idx) {
+            for alt in pattern.split(|tok| tok.is_punct('|')) {
+                push_binding_mode_hints(alt, is_mut, ctx, &mut hints);
+            }
         }
     }
 
     hints
 }
 
+/// Returns `Some(is_mut)` when `expr` evaluates to a reference.
+fn reference_mutability(
+    expr: &str,
+    index: &WorkspaceIndex,
+    locals: &LocalTypes,
+    offset: usize,
+) -> Option<bool> {
+    let ty = match expr.strip_prefix('&') {
+        Some(_) => expr.to_string(),
+        None => infer_type(expr, index, locals, offset)?,
+    };
+    let rest = ty.strip_prefix('&')?;
+    Some(
+        rest.strip_prefix("mut")
+            .is_some_and(|rest| rest.starts_with(char::is_whitespace)),
+    )
+}
+
+fn match_arm_patterns(tokens: &[Token], start: usize, end: usize) -> Vec<&[Token]> {
+    let mut patterns = Vec::new();
+    let mut k = start;
+    while k < end {
+        let pat_start = k;
+        let mut pat_end = None;
+        let mut arrow = None;
+        let mut depth = 0i32;
+        while k < end {
+            let tok = &tokens[k];
+            match tok.kind {
+                TokenKind::Punct('(') | TokenKind::Punct('[') | TokenKind::Punct('{') => depth += 1,
+                TokenKind::Punct(')') | TokenKind::Punct(']') | TokenKind::Punct('}')
+                    if depth > 0 =>
+                {
+                    depth -= 1;
+                }
+                TokenKind::Ident(ref name) if name == "if" && depth == 0 => {
+                    pat_end.get_or_insert(k);
+                }
+                TokenKind::Punct('=')
+                    if depth == 0
+                        && tokens
+                            .get(k + 1)
+                            .is_some_and(|next| next.is_punct('>') && next.start == tok.end) =>
+                {
+                    arrow = Some(k);
+                    break;
+                }
+                _ => {}
+            }
+            k += 1;
+        }
+        let Some(arrow) = arrow else { break };
+        patterns.push(&tokens[pat_start..pat_end.unwrap_or(arrow)]);
+
+        k = arrow + 2;
+        if k < end && tokens[k].is_punct('{') {
+            k = find_matching_brace(tokens, k).map_or(end, |idx| idx + 1);
+            if k < end && tokens[k].is_punct(',') {
+                k += 1;
+            }
+            continue;
+        }
+        let mut depth = 0i32;
+        while k < end {
+            match tokens[k].kind {
+                TokenKind::Punct('(') | TokenKind::Punct('[') | TokenKind::Punct('{') => depth += 1,
+                TokenKind::Punct(')') | TokenKind::Punct(']') | TokenKind::Punct('}')
+                    if depth > 0 =>
+                {
+                    depth -= 1;
+                }
+                TokenKind::Punct(',') if depth == 0 => {
+                    k += 1;
+                    break;
+                }
+                _ => {}
+            }
+            k += 1;
+        }
+    }
+    patterns
+}
+
+fn push_binding_mode_hints(
+    alt: &[Token],
+    is_mut: bool,
+    ctx: &HintContext,
+    hints: &mut Vec<InlayHint>,
+) {
+    let Some(first) = alt.first() else { return };
+    if first.is_punct('&') || alt.iter().any(|tok| tok.is_ident("ref")) {
+        return;
+    }
+    let is_constructor = match first.ident() {
+        Some(name) => {
+            starts_uppercase(name)
+                || alt.get(1).is_some_and(|next| {
+                    next.is_punct('(')
+                        || next.is_punct('{')
+                        || matches!(next.kind, TokenKind::DoubleColon)
+                })
+        }
+        None => first.is_punct('(') || first.is_punct('['),
+    };
+    if !is_constructor {
+        return;
+    }
+
+    let deref = if is_mut { "&mut" } else { "&" };
+    if let Some(position) = ctx.position(first.start) {
+        hints.push(binding_mode_hint(position, deref, ctx));
+    }
+
+    let by_ref = if is_mut { "ref mut" } else { "ref" };
+    for (idx, tok) in alt.iter().enumerate() {
+        let Some(name) = tok.ident() else { continue };
+        if name == "_" || starts_uppercase(name) || is_keyword(name) || is_pattern_keyword(name) {
+            continue;
+        }
+        let prev = idx.checked_sub(1).map(|prev| &alt[prev]);
+        if prev.is_some_and(|prev| {
+            prev.is_punct('&')
+                || prev.is_ident("mut")
+                || matches!(prev.kind, TokenKind::DoubleColon)
+        }) {
+            continue;
+        }
+        if alt.get(idx + 1).is_some_and(|next| {
+            next.is_punct('(')
+                || next.is_punct('{')
+                || next.is_punct(':')
+                || next.is_punct('!')
+                || matches!(next.kind, TokenKind::DoubleColon)
+        }) {
+            continue;
+        }
+        if let Some(position) = ctx.position(tok.start) {
+            hints.push(binding_mode_hint(position, by_ref, ctx));
+        }
+    }
+}
+
+fn starts_uppercase(name: &str) -> bool {
+    name.chars()
+        .next()
+        .is_some_and(|ch| ch.is_ascii_uppercase())
+}
+
+fn is_pattern_keyword(name: &str) -> bool {
+    matches!(name, "ref" | "mut" | "box" | "true" | "false" | "if")
+}
+
+/// Labels the `}` closing a long `fn`, `impl` or `mod` block with the item
+/// header, e.g. `// fn handle_request`. Blocks inside macros are skipped.
+fn closing_brace_hints(text: &str, ctx: &HintContext) -> Vec<InlayHint> {
+    let tokens = lex(text);
+    let mut hints = Vec::new();
+    let mut labels: HashMap<usize, String> = HashMap::new();
+    let mut stack = Vec::new();
+
+    let mut i = 0usize;
+    while i < tokens.len() {
+        let tok = &tokens[i];
+        if tok.is_punct('!') {
+            if let Some(end) = macro_body_end(&tokens, i) {
+                i = end + 1;
+                continue;
+            }
+        } else if let Some((open_idx, label)) = item_block_header(text, &tokens, i) {
+            labels.insert(open_idx, label);
+        } else if tok.is_punct('{') {
+            stack.push(i);
+        } else if tok.is_punct('}')
+            && let Some(open_idx) = stack.pop()
+            && let Some(label) = labels.get(&open_idx)
+        {
+            let lines = text[tokens[open_idx].start..tok.start]
+                .bytes()
+                .filter(|b| *b == b'\n')
+                .count();
+            if lines > ctx.config.closing_brace_min_lines
+                && let Some(position) = ctx.position(tok.end)
+            {
+                hints.push(InlayHint {
+                    position,
+                    label: InlayHintLabel::String(format!("// {}", label)),
+                    kind: None,
+                    text_edits: None,
+                    tooltip: None,
+                    padding_left: ctx.config.padding.then_some(true),
+                    padding_right: None,
+                    data: None,
+                });
+            }
+        }
+        i += 1;
+    }
+
+    hints
+}
+
+/// For a `!` starting a macro invocation or `macro_rules!` definition,
+/// returns the index of the token closing the macro body.
+fn macro_body_end(tokens: &[Token], idx: usize) -> Option<usize> {
+    let prev_tok = &tokens[idx.checked_sub(1)?];
+    let prev = prev_tok.ident()?;
+    let mut open = idx + 1;
+    if prev == "macro_rules" {
+        tokens.get(open)?.ident()?;
+        open += 1;
+    } else if prev_tok.is_keyword() {
+        return None;
+    }
+    let (open_ch, close_ch) = match tokens.get(open)?.kind {
+        TokenKind::Punct('(') => ('(', ')'),
+        TokenKind::Punct('[') => ('[', ']'),
+        TokenKind::Punct('{') => ('{', '}'),
+        _ => return None,
+    };
+    let mut depth = 0i32;
+    for (i, tok) in tokens.iter().enumerate().skip(open) {
+        if tok.is_punct(open_ch) {
+            depth += 1;
+        } else if tok.is_punct(close_ch) {
+            depth -= 1;
+            if depth == 0 {
+                return Some(i);
+            }
+        }
+    }
+    None
+}
+
+/// Recognizes `fn name`, `impl ...` and `mod name` headers at `idx` and
+/// returns the index of the opening brace of their body with a label.
+fn item_block_header(text: &str, tokens: &[Token], idx: usize) -> Option<(usize, String)> {
+    let keyword = tokens[idx].ident()?;
+    if !matches!(keyword, "fn" | "impl" | "mod") {
+        return None;
+    }
+    if idx > 0 && tokens[idx - 1].is_punct('.') {
+        return None;
+    }
+    // `impl Trait` in a type, e.g. `-> impl Iterator<Item = u8>`, is not an
+    // item.
+    if keyword == "impl"
+        && idx > 0
+        && matches!(
+            tokens[idx - 1].kind,
+            TokenKind::Arrow | TokenKind::Punct(':' | '(' | ',' | '<' | '=' | '&')
+        )
+    {
+        return None;
+    }
+
+    let mut header_start = tokens.get(idx + 1)?.start;
+    if keyword == "impl" && tokens[idx + 1].is_punct('<') {
+        let end = find_matching_angle(tokens, idx + 1)?;
+        header_start = tokens.get(end + 1)?.start;
+    } else if keyword != "impl" {
+        tokens[idx + 1].ident()?;
+    }
+
+    let mut header_end = None;
+    let mut depth = 0i32;
+    for (i, tok) in tokens.iter().enumerate().skip(idx + 1) {
+        match tok.kind {
+            TokenKind::Punct('(') | TokenKind::Punct('[') | TokenKind::Punct('<') => depth += 1,
+            TokenKind::Punct(')') | TokenKind::Punct(']') | TokenKind::Punct('>') => depth -= 1,
+            TokenKind::Ident(ref name) if name == "where" && depth == 0 => {
+                header_end.get_or_insert(tok.start);
+            }
+            TokenKind::Punct('{') if depth == 0 => {
+                let label = match keyword {
+                    "impl" => {
+                        let end = header_end.unwrap_or(tok.start);
+                        format!("impl {}", collapse_whitespace(&text[header_start..end]))
+                    }
+                    _ => format!("{} {}", keyword, tokens[idx + 1].ident()?),
+                };
+                return Some((i, label));
+            }
+            TokenKind::Punct(';') | TokenKind::Punct('}') if depth == 0 => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
 #[derive(Debug, Clone)]
 struct Call {
     name: String,
     kind: CallKind,
+    /// The callee is written with a path, e.g. `module::name(..)`.
+    qualified: bool,
     arg_starts: Vec<usize>,
     close_paren: usize,
 }
@@ -1328,24 +2491,31 @@ enum CallKind {
 
 fn collect_calls(text: &str) -> Vec<Call> {
     let tokens = lex(text);
+    let patterns = pattern_spans(&tokens);
+    let in_pattern = |offset: usize| {
+        patterns
+            .iter()
+            .any(|&(start, end)| start <= offset && offset < end)
+    };
     let mut calls = Vec::new();
     let mut i = 0usize;
 
     while i < tokens.len() {
-        if tokens[i].is_punct('(') {
-            if let Some((name, kind)) = detect_call_name(&tokens, i) {
-                if let Some(close_idx) = find_matching_paren(&tokens, i) {
-                    let args = parse_arg_starts(&tokens, i + 1, close_idx);
-                    calls.push(Call {
-                        name,
-                        kind,
-                        arg_starts: args,
-                        close_paren: tokens[close_idx].start,
-                    });
-                    i = close_idx;
-                    continue;
-                }
-            }
+        if tokens[i].is_punct('(')
+            && !in_pattern(tokens[i].start)
+            && let Some((name, kind, qualified)) = detect_call_name(&tokens, i)
+            && let Some(close_idx) = find_matching_paren(&tokens, i)
+        {
+            let args = parse_arg_starts(&tokens, i + 1, close_idx);
+            calls.push(Call {
+                name,
+                kind,
+                qualified,
+                arg_starts: args,
+                close_paren: tokens[close_idx].start,
+            });
+            i = close_idx;
+            continue;
         }
         i += 1;
     }
@@ -1353,7 +2523,94 @@ fn collect_calls(text: &str) -> Vec<Call> {
     calls
 }
 
-fn detect_call_name(tokens: &[Token], idx: usize) -> Option<(String, CallKind)> {
+/// Byte spans of pattern positions, where `Name(..)` is a destructuring
+/// pattern rather than a call: match arm patterns, `let`/`if let`/`while let`
+/// and `for` patterns, and `fn` parameter lists.
+fn pattern_spans(tokens: &[Token]) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let span_of = |pattern: &[Token]| match (pattern.first(), pattern.last()) {
+        (Some(first), Some(last)) => Some((first.start, last.end)),
+        _ => None,
+    };
+
+    for (i, tok) in tokens.iter().enumerate() {
+        if tok.is_ident("match") {
+            let Some(open_idx) = tokens[i + 1..]
+                .iter()
+                .position(|tok| tok.is_punct('{'))
+                .map(|pos| i + 1 + pos)
+            else {
+                continue;
+            };
+            let Some(close_idx) = find_matching_brace(tokens, open_idx) else {
+                continue;
+            };
+            spans.extend(
+                match_arm_patterns(tokens, open_idx + 1, close_idx)
+                    .into_iter()
+                    .filter_map(span_of),
+            );
+        } else if tok.is_ident("let") || (tok.is_ident("for") && is_for_loop(tokens, i)) {
+            let mut depth = 0i32;
+            for (j, next) in tokens.iter().enumerate().skip(i + 1) {
+                match next.kind {
+                    TokenKind::Punct('(') | TokenKind::Punct('[') | TokenKind::Punct('{') => {
+                        depth += 1
+                    }
+                    TokenKind::Punct(')') | TokenKind::Punct(']') | TokenKind::Punct('}') => {
+                        depth -= 1;
+                        if depth < 0 {
+                            break;
+                        }
+                    }
+                    TokenKind::Punct('=') | TokenKind::Punct(':') | TokenKind::Punct(';')
+                        if depth == 0 =>
+                    {
+                        spans.extend(span_of(&tokens[i + 1..j]));
+                        break;
+                    }
+                    TokenKind::Ident(ref name) if name == "in" && depth == 0 => {
+                        spans.extend(span_of(&tokens[i + 1..j]));
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        } else if tok.is_ident("fn") && tokens.get(i + 1).is_some_and(|next| next.ident().is_some())
+        {
+            let mut j = i + 2;
+            if tokens.get(j).is_some_and(|tok| tok.is_punct('<')) {
+                match find_matching_angle(tokens, j) {
+                    Some(end) => j = end + 1,
+                    None => continue,
+                }
+            }
+            if tokens.get(j).is_some_and(|tok| tok.is_punct('('))
+                && let Some(close_idx) = find_matching_paren(tokens, j)
+            {
+                spans.push((tokens[j].end, tokens[close_idx].start));
+            }
+        }
+    }
+
+    spans
+}
+
+/// Tells a `for` loop apart from `impl Trait for Type` and `for<'a>` bounds.
+fn is_for_loop(tokens: &[Token], idx: usize) -> bool {
+    if tokens.get(idx + 1).is_some_and(|next| next.is_punct('<')) {
+        return false;
+    }
+    match idx.checked_sub(1).map(|prev| &tokens[prev]) {
+        Some(prev) if prev.ident().is_some() => prev.is_keyword(),
+        Some(prev) if prev.is_punct('>') => {
+            idx >= 2 && tokens[idx - 2].is_punct('=') && tokens[idx - 2].end == prev.start
+        }
+        _ => true,
+    }
+}
+
+fn detect_call_name(tokens: &[Token], idx: usize) -> Option<(String, CallKind, bool)> {
     if idx == 0 {
         return None;
     }
@@ -1375,15 +2632,15 @@ fn detect_call_name(tokens: &[Token], idx: usize) -> Option<(String, CallKind)>
     }
 
     let name = tokens[j].ident()?.to_string();
-    if is_keyword(&name) {
+    if tokens[j].is_keyword() {
         return None;
     }
 
     if j > 0 {
-        if let Some(prev) = tokens[j - 1].ident() {
-            if matches!(prev, "fn" | "struct" | "enum" | "trait" | "type" | "impl") {
-                return None;
-            }
+        if let Some(prev) = tokens[j - 1].ident()
+            && matches!(prev, "fn" | "struct" | "enum" | "trait" | "type" | "impl")
+        {
+            return None;
         }
         if tokens[j - 1].is_punct('!') {
             return None;
@@ -1395,8 +2652,9 @@ fn detect_call_name(tokens: &[Token], idx: usize) -> Option<(String, CallKind)>
     } else {
         CallKind::Function
     };
+    let qualified = j > 0 && matches!(tokens[j - 1].kind, TokenKind::DoubleColon);
 
-    Some((name, kind))
+    Some((name, kind, qualified))
 }
 
 fn parse_arg_starts(tokens: &[Token], start: usize, end: usize) -> Vec<usize> {
@@ -1407,32 +2665,23 @@ fn parse_arg_starts(tokens: &[Token], start: usize, end: usize) -> Vec<usize> {
     let mut brace_depth = 0i32;
     let mut angle_depth = 0i32;
 
-    for idx in start..end {
-        let tok = &tokens[idx];
+    for tok in &tokens[start..end] {
         match tok.kind {
             TokenKind::Punct('(') => paren_depth += 1,
-            TokenKind::Punct(')') => {
-                if paren_depth > 0 {
-                    paren_depth -= 1;
-                }
+            TokenKind::Punct(')') if paren_depth > 0 => {
+                paren_depth -= 1;
             }
             TokenKind::Punct('[') => bracket_depth += 1,
-            TokenKind::Punct(']') => {
-                if bracket_depth > 0 {
-                    bracket_depth -= 1;
-                }
+            TokenKind::Punct(']') if bracket_depth > 0 => {
+                bracket_depth -= 1;
             }
             TokenKind::Punct('{') => brace_depth += 1,
-            TokenKind::Punct('}') => {
-                if brace_depth > 0 {
-                    brace_depth -= 1;
-                }
+            TokenKind::Punct('}') if brace_depth > 0 => {
+                brace_depth -= 1;
             }
             TokenKind::Punct('<') => angle_depth += 1,
-            TokenKind::Punct('>') => {
-                if angle_depth > 0 {
-                    angle_depth -= 1;
-                }
+            TokenKind::Punct('>') if angle_depth > 0 => {
+                angle_depth -= 1;
             }
             TokenKind::Punct(',')
                 if paren_depth == 0
@@ -1448,10 +2697,13 @@ fn parse_arg_starts(tokens: &[Token], start: usize, end: usize) -> Vec<usize> {
             _ => {}
         }
 
-        if paren_depth == 0 && bracket_depth == 0 && brace_depth == 0 && angle_depth == 0 {
-            if arg_start.is_none() {
-                arg_start = Some(tok.start);
-            }
+        if paren_depth == 0
+            && bracket_depth == 0
+            && brace_depth == 0
+            && angle_depth == 0
+            && arg_start.is_none()
+        {
+            arg_start = Some(tok.start);
         }
     }
 
@@ -1483,28 +2735,60 @@ fn is_chained_call(text: &str, close_paren: usize) -> bool {
     false
 }
 
-fn type_hint(position: Position, ty: &str) -> InlayHint {
+fn type_hint(position: Position, ty: &str, ctx: &HintContext) -> InlayHint {
     InlayHint {
         position,
-        label: InlayHintLabel::String(format!(": {}", ty)),
+        label: ctx.type_label(ty),
         kind: Some(InlayHintKind::TYPE),
         text_edits: None,
         tooltip: None,
-        padding_left: None,
+        padding_left: ctx.config.padding.then_some(true),
         padding_right: None,
         data: None,
     }
 }
 
-fn param_hint(position: Position, name: &str) -> InlayHint {
+/// A `let` binding type hint that can be accepted into the source as an
+/// explicit `: Type` annotation.
+fn binding_type_hint(position: Position, ty: &str, ctx: &HintContext) -> InlayHint {
+    let mut hint = type_hint(position, ty, ctx);
+    hint.text_edits = Some(vec![TextEdit {
+        range: Range {
+            start: position,
+            end: position,
+        },
+        new_text: format!(": {}", ty),
+    }]);
+    hint
+}
+
+fn binding_mode_hint(position: Position, label: &str, ctx: &HintContext) -> InlayHint {
     InlayHint {
         position,
-        label: InlayHintLabel::String(format!("{}:", name)),
+        label: InlayHintLabel::String(label.to_string()),
+        kind: None,
+        text_edits: None,
+        tooltip: None,
+        padding_left: None,
+        padding_right: (ctx.config.padding && label.starts_with("ref")).then_some(true),
+        data: None,
+    }
+}
+
+fn param_hint(
+    position: Position,
+    name: &str,
+    location: Option<&Location>,
+    ctx: &HintContext,
+) -> InlayHint {
+    InlayHint {
+        position,
+        label: ctx.param_label(name, location),
         kind: Some(InlayHintKind::PARAMETER),
         text_edits: None,
         tooltip: None,
         padding_left: None,
-        padding_right: None,
+        padding_right: ctx.config.padding.then_some(true),
         data: None,
     }
 }
@@ -1528,62 +2812,46 @@ fn position_le(a: Position, b: Position) -> bool {
     a.line < b.line || (a.line == b.line && a.character <= b.character)
 }
 
-fn should_skip_dir(path: &Path) -> bool {
-    match path.file_name().and_then(|s| s.to_str()) {
-        Some("target") | Some(".git") => true,
-        _ => false,
-    }
-}
-
-fn is_keyword(name: &str) -> bool {
-    matches!(
-        name,
-        "if" | "while"
-            | "for"
-            | "match"
-            | "loop"
-            | "return"
-            | "fn"
-            | "struct"
-            | "enum"
-            | "trait"
-            | "type"
-            | "impl"
-            | "pub"
-            | "use"
-            | "const"
-            | "static"
-            | "async"
-            | "await"
-            | "move"
-            | "unsafe"
-            | "extern"
-            | "crate"
-            | "super"
-            | "self"
-    )
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::cell::RefCell;
+    use std::path::PathBuf;
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    use crate::config::{WorkspaceConfig, WorkspaceMode};
+    use crate::doc::position::offset_to_position;
 
     fn index_from_sources(sources: &[&str]) -> WorkspaceIndex {
         let mut index = WorkspaceIndex::default();
-        for source in sources {
-            index.add_source(source);
+        for (idx, source) in sources.iter().enumerate() {
+            let uri = Uri::from_str(&format!("file:///src{}.rs", idx)).unwrap();
+            index.add_source(Some(&uri), source);
         }
         index
     }
 
+    fn ctx<'a>(
+        index: &'a WorkspaceIndex,
+        config: &'a InlayHintsConfig,
+        lines: &'a LineIndex,
+    ) -> HintContext<'a> {
+        HintContext {
+            index,
+            config,
+            support: ClientHintSupport::default(),
+            encoding: PositionEncoding::Utf16,
+            lines,
+        }
+    }
+
     fn hint_labels(hints: &[InlayHint]) -> Vec<String> {
-        hints
-            .iter()
-            .map(|hint| match &hint.label {
-                InlayHintLabel::String(value) => value.clone(),
-                _ => "".to_string(),
-            })
-            .collect()
+        hints.iter().map(|hint| label_text(&hint.label)).collect()
+    }
+
+    fn param_names(sig: &FunctionSig) -> Vec<&str> {
+        sig.params.iter().map(|param| param.name.as_str()).collect()
     }
 
     #[test]
@@ -1591,26 +2859,45 @@ mod tests {
         let src = "fn foo<const N: usize, T>(a: i32, b: T) -> Option<T> { }";
         let index = index_from_sources(&[src]);
         let sig = index.unique_fn("foo").expect("fn signature");
-        assert_eq!(sig.params, vec!["a", "b"]);
+        assert_eq!(param_names(sig), vec!["a", "b"]);
         assert_eq!(sig.return_type.as_deref(), Some("Option<T>"));
         let generics = index.unique_generics("foo").expect("generics");
         assert_eq!(generics[0].kind, GenericParamKind::Const);
         assert_eq!(generics[0].name, "N");
     }
 
+    #[test]
+    fn fn_sig_parsing_raw_identifiers() {
+        let src = "fn r#try(r#type: u32) -> r#struct { } fn main() { r#try(1); let s = r#\"raw \"# str\"#; }";
+        let index = index_from_sources(&[src]);
+        let sig = index.unique_fn("try").expect("fn signature");
+        assert_eq!(param_names(sig), vec!["type"]);
+        assert_eq!(sig.return_type.as_deref(), Some("r#struct"));
+        assert_eq!(sig.params[0].end - sig.params[0].start, "r#type".len());
+        assert!(!index.is_unique_type("type"));
+
+        let config = InlayHintsConfig::default();
+        let hints = arg_name_hints(src, &ctx(&index, &config, &LineIndex::new(src)));
+        assert_eq!(hint_labels(&hints), vec!["type:"]);
+        let hints = local_var_type_hints(src, &ctx(&index, &config, &LineIndex::new(src)));
+        assert_eq!(hint_labels(&hints), vec![": &str"]);
+        assert_eq!(infer_string_literal("r#type"), None);
+    }
+
     #[test]
     fn method_sig_parsing_skips_self() {
         let src = "impl Foo { fn method(&self, x: i32) {} }";
         let index = index_from_sources(&[src]);
         let sig = index.unique_method("method").expect("method sig");
-        assert_eq!(sig.params, vec!["x"]);
+        assert_eq!(param_names(sig), vec!["x"]);
     }
 
     #[test]
     fn local_var_type_literal() {
         let src = "fn main() { let x = 1; }";
         let index = index_from_sources(&[src]);
-        let hints = local_var_type_hints(src, &index);
+        let config = InlayHintsConfig::default();
+        let hints = local_var_type_hints(src, &ctx(&index, &config, &LineIndex::new(src)));
         let labels = hint_labels(&hints);
         assert!(labels.iter().any(|label| label == ": i32"));
     }
@@ -1619,37 +2906,674 @@ mod tests {
     fn local_var_type_struct_lit() {
         let src = "struct Foo { a: i32 } fn main() { let x = Foo { a: 1 }; }";
         let index = index_from_sources(&[src]);
-        let hints = local_var_type_hints(src, &index);
+        let config = InlayHintsConfig {
+            hide_obvious_types: false,
+            ..InlayHintsConfig::default()
+        };
+        let hints = local_var_type_hints(src, &ctx(&index, &config, &LineIndex::new(src)));
         let labels = hint_labels(&hints);
         assert!(labels.iter().any(|label| label == ": Foo"));
     }
 
+    #[test]
+    fn local_var_type_hides_obvious_initializers() {
+        let src = "struct Foo { a: i32 } struct Config; impl Config { fn default() -> Config { Config } } fn make_config() -> Config { Config } fn main() { let a = Foo { a: 1 }; let b = Config::default(); let c = make_config(); }";
+        let index = index_from_sources(&[src]);
+        let config = InlayHintsConfig::default();
+        let hints = local_var_type_hints(src, &ctx(&index, &config, &LineIndex::new(src)));
+        let labels = hint_labels(&hints);
+        assert_eq!(labels, vec![": Config"]);
+        let offset = src.find("let c").unwrap() + "let c".len();
+        assert_eq!(
+            hints[0].position,
+            offset_to_position(src, offset, PositionEncoding::Utf16).unwrap()
+        );
+    }
+
+    #[test]
+    fn local_var_type_known_methods_use_earlier_bindings() {
+        let src = "struct Foo { a: i32 } struct Bar; impl Bar { fn clone(&self) -> Bar { Bar } }
+fn greet(name: &str, tags: &[u8]) {
+    let original = Foo { a: 1 };
+    let copy = original.clone();
+    let label = name.to_owned();
+    let text = copy.to_string();
+    let bytes = tags.to_owned();
+    let name = name.clone();
+    {
+        let original = 1;
+        let shadow = original.clone();
+    }
+    let unknown = missing.clone();
+}";
+        let index = index_from_sources(&[src]);
+        let config = InlayHintsConfig::default();
+        let hints = local_var_type_hints(src, &ctx(&index, &config, &LineIndex::new(src)));
+        assert_eq!(
+            hint_labels(&hints),
+            vec![
+                ": Foo",
+                ": String",
+                ": String",
+                ": Vec<u8>",
+                ": &str",
+                ": i32",
+                ": i32"
+            ]
+        );
+        let copy = src.find("let copy").unwrap() + "let copy".len();
+        assert_eq!(
+            hints[0].position,
+            offset_to_position(src, copy, PositionEncoding::Utf16).unwrap()
+        );
+    }
+
+    #[test]
+    fn local_var_type_text_edit_utf16() {
+        let src = "fn main() { let s = \"😀é\"; let mut x = 1; }";
+        let index = index_from_sources(&[src]);
+        let config = InlayHintsConfig::default();
+        let hints = local_var_type_hints(src, &ctx(&index, &config, &LineIndex::new(src)));
+        let hint = hints
+            .iter()
+            .find(|hint| hint_labels(std::slice::from_ref(hint)) == [": i32"])
+            .expect("i32 hint");
+        // `😀` is four bytes but two UTF-16 units and `é` is two bytes but one unit.
+        let byte_offset = src.find("let mut x").unwrap() + "let mut x".len();
+        let expected = Position::new(0, byte_offset as u32 - 3);
+        assert_eq!(hint.position, expected);
+        let edits = hint.text_edits.as_ref().expect("text edits");
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].new_text, ": i32");
+        assert_eq!(edits[0].range, Range::new(expected, expected));
+    }
+
+    #[test]
+    fn chained_hints_have_no_text_edits() {
+        let src = "struct Foo; struct Bar; impl Foo { fn bar(&self) -> Bar { Bar } } fn foo() -> Foo { Foo } fn main() { foo().bar(); }";
+        let index = index_from_sources(&[src]);
+        let config = InlayHintsConfig::default();
+        let hints = chained_expr_type_hints(src, &ctx(&index, &config, &LineIndex::new(src)));
+        assert!(!hints.is_empty());
+        assert!(hints.iter().all(|hint| hint.text_edits.is_none()));
+    }
+
     #[test]
     fn arg_name_hints_simple_call() {
         let src = "fn foo(a: i32, b: i32) {} fn main() { foo(1, 2); }";
         let index = index_from_sources(&[src]);
-        let hints = arg_name_hints(src, &index);
+        let config = InlayHintsConfig::default();
+        let hints = arg_name_hints(src, &ctx(&index, &config, &LineIndex::new(src)));
         let labels = hint_labels(&hints);
         assert!(labels.iter().any(|label| label == "a:"));
         assert!(labels.iter().any(|label| label == "b:"));
     }
 
+    #[test]
+    fn arg_name_hints_for_pattern_params() {
+        let src = "struct Point { x: i32, y: i32 } fn dist((x1, y1): (i32, i32), Point { x: px, y }: Point, scale: i32) {} fn main() { dist(a, b, 2); }";
+        let index = index_from_sources(&[src]);
+        let sig = index.unique_fn("dist").expect("fn signature");
+        assert_eq!(sig.params[0].pattern.as_deref(), Some("(x1, y1)"));
+        assert_eq!(sig.params[1].pattern.as_deref(), Some("Point { x: px, y }"));
+        assert_eq!(sig.params[2].pattern, None);
+
+        let config = InlayHintsConfig::default();
+        let hints = arg_name_hints(src, &ctx(&index, &config, &LineIndex::new(src)));
+        assert_eq!(hint_labels(&hints), vec!["scale:"]);
+
+        let config = InlayHintsConfig {
+            pattern_param_hints: true,
+            ..InlayHintsConfig::default()
+        };
+        let hints = arg_name_hints(src, &ctx(&index, &config, &LineIndex::new(src)));
+        assert_eq!(
+            hint_labels(&hints),
+            vec!["(x1, y1):", "Point { x: px, y }:", "scale:"]
+        );
+    }
+
+    #[test]
+    fn hint_padding_follows_config() {
+        let position = Position::new(0, 0);
+        let index = WorkspaceIndex::default();
+        let config = InlayHintsConfig::default();
+        let param = param_hint(
+            position,
+            "a",
+            None,
+            &ctx(&index, &config, &LineIndex::new("")),
+        );
+        assert_eq!(param.padding_left, None);
+        assert_eq!(param.padding_right, Some(true));
+        let ty = type_hint(position, "Foo", &ctx(&index, &config, &LineIndex::new("")));
+        assert_eq!(ty.padding_left, Some(true));
+        assert_eq!(ty.padding_right, None);
+
+        let config = InlayHintsConfig {
+            padding: false,
+            ..InlayHintsConfig::default()
+        };
+        let lines = LineIndex::new("");
+        let ctx = ctx(&index, &config, &lines);
+        assert_eq!(param_hint(position, "a", None, &ctx).padding_right, None);
+        assert_eq!(type_hint(position, "Foo", &ctx).padding_left, None);
+    }
+
     #[test]
     fn const_generic_hints_smoke() {
         let src = "fn foo<const N: usize, T>() {} fn main() { foo::<3, u8>(); }";
         let index = index_from_sources(&[src]);
-        let hints = const_generic_hints(src, &index);
+        let config = InlayHintsConfig::default();
+        let hints = const_generic_hints(src, &ctx(&index, &config, &LineIndex::new(src)));
         let labels = hint_labels(&hints);
         assert!(labels.iter().any(|label| label == "N:"));
     }
 
+    #[test]
+    fn const_generic_hints_in_type_positions() {
+        let src = "struct ArrayVec<T, const CAP: usize> {} struct Matrix<const ROWS: usize, const COLS: usize> {} struct Buffer<const N: usize> {}
+struct Grid { data: Matrix<3, 4>, nested: Vec<Matrix<1, 2>> }
+impl Buffer<64> {}
+impl Clone for Buffer<32> where u8: Copy {}
+impl<const N: usize> Buffer<N> {}
+fn main() { let buf: ArrayVec<u8, 16> = make(); }";
+        let index = index_from_sources(&[src]);
+        let config = InlayHintsConfig::default();
+        let hints = const_generic_hints(src, &ctx(&index, &config, &LineIndex::new(src)));
+        assert_eq!(
+            hint_labels(&hints),
+            vec!["ROWS:", "COLS:", "ROWS:", "COLS:", "N:", "N:", "CAP:"]
+        );
+        let cap = src.find("16>").unwrap();
+        assert_eq!(
+            hints[6].position,
+            offset_to_position(src, cap, PositionEncoding::Utf16).unwrap()
+        );
+    }
+
     #[test]
     fn chained_call_type_hints() {
         let src = "struct Foo; struct Bar; impl Foo { fn bar(&self) -> Bar { Bar } } fn foo() -> Foo { Foo } fn main() { foo().bar(); }";
         let index = index_from_sources(&[src]);
-        let hints = chained_expr_type_hints(src, &index);
+        let config = InlayHintsConfig::default();
+        let hints = chained_expr_type_hints(src, &ctx(&index, &config, &LineIndex::new(src)));
         let labels = hint_labels(&hints);
         assert!(labels.iter().any(|label| label == ": Foo"));
         assert!(labels.iter().any(|label| label == ": Bar"));
     }
+
+    #[test]
+    fn hint_labels_link_to_definitions() {
+        let src = "struct Foo; fn make(count: i32) -> Option<Foo> { None } fn main() { let x = make(1); }";
+        let index = index_from_sources(&[src]);
+        let config = InlayHintsConfig::default();
+        let lines = LineIndex::new(src);
+        let mut ctx = ctx(&index, &config, &lines);
+        ctx.support.label_location = true;
+
+        let hints = local_var_type_hints(src, &ctx);
+        let InlayHintLabel::LabelParts(parts) = &hints[0].label else {
+            panic!("expected label parts");
+        };
+        let values: Vec<_> = parts.iter().map(|part| part.value.as_str()).collect();
+        assert_eq!(values, vec![": Option<", "Foo", ">"]);
+        let location = parts[1].location.as_ref().expect("type location");
+        assert_eq!(location.uri.as_str(), "file:///src0.rs");
+        assert_eq!(location.range.start, Position::new(0, 7));
+        assert_eq!(location.range.end, Position::new(0, 10));
+
+        let hints = arg_name_hints(src, &ctx);
+        let InlayHintLabel::LabelParts(parts) = &hints[0].label else {
+            panic!("expected label parts");
+        };
+        assert_eq!(parts[0].value, "count");
+        let location = parts[0].location.as_ref().expect("param location");
+        assert_eq!(location.range.start, Position::new(0, 20));
+
+        ctx.support.label_location = false;
+        let hints = arg_name_hints(src, &ctx);
+        assert!(matches!(&hints[0].label, InlayHintLabel::String(label) if label == "count:"));
+    }
+
+    #[test]
+    fn param_hint_tooltips_show_signature() {
+        let src = "fn foo<T>(\n    a: i32,\n    b: T,\n) -> Option<T> { None }\nfn bar<const N: usize>() {}\nfn main() { foo(1, 2); bar::<3>(); }";
+        let index = index_from_sources(&[src]);
+        let config = InlayHintsConfig::default();
+        let tooltip_text = |hint: &InlayHint| match &hint.tooltip {
+            Some(InlayHintTooltip::MarkupContent(content)) => content.value.clone(),
+            _ => String::new(),
+        };
+
+        let hints = arg_name_hints(src, &ctx(&index, &config, &LineIndex::new(src)));
+        assert_eq!(
+            tooltip_text(&hints[0]),
+            "```rust\nfn foo<T>(a: i32, b: T) -> Option<T>\n```"
+        );
+        let hints = const_generic_hints(src, &ctx(&index, &config, &LineIndex::new(src)));
+        assert_eq!(tooltip_text(&hints[0]), "```rust\nconst N: usize\n```");
+    }
+
+    #[test]
+    fn param_hint_tooltips_defer_to_resolve() {
+        let src = "fn foo(a: i32) {} fn main() { foo(1); }";
+        let index = index_from_sources(&[src]);
+        let config = InlayHintsConfig::default();
+        let lines = LineIndex::new(src);
+        let mut ctx = ctx(&index, &config, &lines);
+        ctx.support.resolve_tooltip = true;
+
+        let hints = arg_name_hints(src, &ctx);
+        assert!(hints[0].tooltip.is_none());
+        let data = hints[0].data.clone().expect("resolve data");
+        assert_eq!(
+            data,
+            serde_json::json!({ "kind": "function", "name": "foo" })
+        );
+    }
+
+    #[test]
+    fn binding_mode_hints_for_reference_scrutinee() {
+        let src = "fn main() { let opt = Some(1); match &opt { Some(x) => {} None => {} } }";
+        let index = index_from_sources(&[src]);
+        let config = InlayHintsConfig::default();
+        let hints = binding_mode_hints(src, &ctx(&index, &config, &LineIndex::new(src)));
+        let labels = hint_labels(&hints);
+        assert_eq!(labels, vec!["&", "ref", "&"]);
+        let some = src.find("Some(x)").unwrap();
+        assert_eq!(
+            hints[0].position,
+            offset_to_position(src, some, PositionEncoding::Utf16).unwrap()
+        );
+        assert_eq!(
+            hints[1].position,
+            offset_to_position(src, some + 5, PositionEncoding::Utf16).unwrap()
+        );
+    }
+
+    #[test]
+    fn binding_mode_hints_through_reference_variable() {
+        let src =
+            "fn main() { let opt = Some(1); let r = &mut opt; match r { Some(y) => {}, _ => {} } }";
+        let index = index_from_sources(&[src]);
+        let config = InlayHintsConfig::default();
+        let hints = binding_mode_hints(src, &ctx(&index, &config, &LineIndex::new(src)));
+        assert_eq!(hint_labels(&hints), vec!["&mut", "ref mut"]);
+    }
+
+    #[test]
+    fn binding_mode_hints_skip_explicit_patterns() {
+        let src = "fn main() { let opt = Some(1); match &opt { Some(ref x) => {} &None => {} } match opt { Some(z) => {} _ => {} } }";
+        let index = index_from_sources(&[src]);
+        let config = InlayHintsConfig::default();
+        let hints = binding_mode_hints(src, &ctx(&index, &config, &LineIndex::new(src)));
+        assert!(hints.is_empty());
+    }
+
+    #[test]
+    fn overlapping_hints_are_deduped_and_ordered() {
+        let src = "struct Foo; struct Bar; impl Foo { fn bar(&self, n: i32) -> Bar { Bar } } fn foo() -> Foo { Foo } fn main() { foo().bar(1); }";
+        let index = index_from_sources(&[src]);
+        let config = InlayHintsConfig::default();
+        let lines = LineIndex::new(src);
+        let ctx = ctx(&index, &config, &lines);
+        let mut hints = Vec::new();
+        hints.extend(chained_expr_type_hints(src, &ctx));
+        hints.extend(chained_expr_type_hints(src, &ctx));
+        hints.extend(arg_name_hints(src, &ctx));
+        let position = hints[0].position;
+        hints.push(type_hint(position, "Foo", &ctx));
+        hints.push(param_hint(position, "x", None, &ctx));
+        hints.reverse();
+
+        sort_and_dedup(&mut hints);
+        assert_eq!(hint_labels(&hints), vec!["x:", ": Foo", "n:", ": Bar"]);
+    }
+
+    #[test]
+    fn closing_brace_hints_label_long_items() {
+        let body = "    let x = 1;\n".repeat(30);
+        let src = format!(
+            "impl<T> Foo<T> where T: Clone {{\n    fn long() {{\n{body}    }}\n    fn short() {{ }}\n}}\nmod tests {{\n    m! {{\n        fn inside() {{\n{body}        }}\n    }}\n{body}}}\n"
+        );
+        let index = WorkspaceIndex::default();
+        let config = InlayHintsConfig::default();
+        let hints = closing_brace_hints(&src, &ctx(&index, &config, &LineIndex::new(&src)));
+        assert_eq!(
+            hint_labels(&hints),
+            vec!["// fn long", "// impl Foo<T>", "// mod tests"]
+        );
+
+        let close = src.find("    }\n    fn short").unwrap() + "    }".len();
+        assert_eq!(
+            hints[0].position,
+            offset_to_position(&src, close, PositionEncoding::Utf16).unwrap()
+        );
+    }
+
+    #[test]
+    fn closing_brace_hints_skip_impl_trait_types() {
+        let body = "    let x = 1;\n".repeat(30);
+        let src = format!(
+            "fn numbers() -> impl Iterator<Item = u8> {{\n{body}}}\nfn apply(f: impl Fn(u8), g: &impl Fn(u8)) {{\n{body}}}\n"
+        );
+        let index = WorkspaceIndex::default();
+        let config = InlayHintsConfig::default();
+        let hints = closing_brace_hints(&src, &ctx(&index, &config, &LineIndex::new(&src)));
+        assert_eq!(hint_labels(&hints), vec!["// fn numbers", "// fn apply"]);
+    }
+
+    #[test]
+    fn arg_name_hints_skip_patterns() {
+        let src = "struct Point(i32, i32); fn Point(x: i32, y: i32) -> Point { Point(x, y) } fn Some(n: i32) {}
+fn check(Point(a, b): Point) {}
+fn main() {
+    match v { Point(x, y) | Point(y, x) => {} Some(n) if n > 0 => Point(1, 2), _ => {} }
+    if let Some(n) = w {}
+    while let Point(x, y) = z {}
+    let Point(p, q) = z;
+    for Point(x, y) in items {}
+}
+impl Trait for Point { fn run() { Point(3, 4); } }";
+        let index = index_from_sources(&[src]);
+        let config = InlayHintsConfig::default();
+        let hints = arg_name_hints(src, &ctx(&index, &config, &LineIndex::new(src)));
+        let labels = hint_labels(&hints);
+        // Only the constructor body, the arm body and the impl method contain calls.
+        assert_eq!(labels, vec!["x:", "y:", "x:", "y:", "x:", "y:"]);
+        let arm_call = src.find("Point(1, 2)").unwrap() + "Point(".len();
+        assert_eq!(
+            hints[2].position,
+            offset_to_position(src, arm_call, PositionEncoding::Utf16).unwrap()
+        );
+    }
+
+    #[test]
+    fn arg_name_hints_skip_local_callees() {
+        let src = "fn push(item: i32) {} fn apply(f: fn(i32), value: i32) { f(value); }
+struct S { callback: fn(i32) }
+fn main(list: Vec<i32>) {
+    push(1);
+    let push = |x| list.push(x);
+    push(2);
+    {
+        let push = push_alias;
+        push(3);
+    }
+    let alias = crate::push;
+    alias(4);
+    (s.callback)(5);
+}
+fn push_alias(value: i32) {}";
+        let index = index_from_sources(&[src]);
+        let config = InlayHintsConfig::default();
+        let hints = arg_name_hints(src, &ctx(&index, &config, &LineIndex::new(src)));
+        assert_eq!(hint_labels(&hints), vec!["item:", "value:", "item:"]);
+        let first = src.find("push(1)").unwrap() + "push(".len();
+        assert_eq!(
+            hints[0].position,
+            offset_to_position(src, first, PositionEncoding::Utf16).unwrap()
+        );
+        let aliased = src.find("push(3)").unwrap() + "push(".len();
+        assert_eq!(
+            hints[1].position,
+            offset_to_position(src, aliased, PositionEncoding::Utf16).unwrap()
+        );
+    }
+
+    #[test]
+    fn whole_document_ranges_cover_every_hint() {
+        let src = "fn foo(a: i32) {}\nfn main() {\n    foo(1);\n}\nfn last() { foo(2) }";
+        let uri = Uri::from_str("file:///whole/main.rs").unwrap();
+        let mut docs = DocumentStore::new();
+        docs.open(lsp_types::TextDocumentItem {
+            uri: uri.clone(),
+            language_id: "rust".to_string(),
+            version: 1,
+            text: src.to_string(),
+        });
+        let config = InlayHintsConfig::default();
+        let hints = |end: Position| {
+            inlay_hints(
+                &docs,
+                None,
+                &uri,
+                Range::new(Position::new(0, 0), end),
+                &config,
+                ClientHintSupport::default(),
+                PositionEncoding::Utf16,
+            )
+        };
+
+        let last = Position::new(4, 16);
+        for end in [
+            Position::new(u32::MAX, u32::MAX),
+            Position::new(999_999, 0),
+            Position::new(4, 200),
+        ] {
+            let positions: Vec<_> = hints(end).iter().map(|hint| hint.position).collect();
+            assert_eq!(positions, [Position::new(2, 8), last]);
+        }
+    }
+
+    #[test]
+    fn oversized_documents_get_no_hints_and_are_not_indexed() {
+        let big = Uri::from_str("file:///large/big.rs").unwrap();
+        let small = Uri::from_str("file:///large/main.rs").unwrap();
+        let mut docs = DocumentStore::new();
+        for (uri, text) in [
+            (
+                &big,
+                "fn foo(a: i32) {}
+fn main() { foo(1); }
+",
+            ),
+            (&small, "fn main() { foo(2); }"),
+        ] {
+            docs.open(lsp_types::TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "rust".to_string(),
+                version: 1,
+                text: text.to_string(),
+            });
+        }
+        let config = InlayHintsConfig::default();
+        let hints = |docs: &DocumentStore, uri: &Uri| {
+            inlay_hints(
+                docs,
+                None,
+                uri,
+                Range::new(Position::new(0, 0), Position::new(u32::MAX, 0)),
+                &config,
+                ClientHintSupport::default(),
+                PositionEncoding::Utf16,
+            )
+        };
+        assert_eq!(hint_labels(&hints(&docs, &small)), ["a:"]);
+
+        docs.set_max_analyzed_size(30);
+        let started = std::time::Instant::now();
+        assert!(hints(&docs, &big).is_empty());
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+        // `foo` is only defined in the oversized document.
+        assert!(hints(&docs, &small).is_empty());
+    }
+
+    #[test]
+    fn open_definitions_are_reparsed_after_changes() {
+        let defs = Uri::from_str("file:///cached/defs.rs").unwrap();
+        let main = Uri::from_str("file:///cached/main.rs").unwrap();
+        let mut docs = DocumentStore::new();
+        for (uri, text) in [
+            (&defs, "fn foo(a: i32) {}"),
+            (&main, "fn main() { foo(1); }"),
+        ] {
+            docs.open(lsp_types::TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "rust".to_string(),
+                version: 1,
+                text: text.to_string(),
+            });
+        }
+        let config = InlayHintsConfig::default();
+        let labels = |docs: &DocumentStore| {
+            let hints = inlay_hints(
+                docs,
+                None,
+                &main,
+                Range::new(Position::new(0, 0), Position::new(1, 0)),
+                &config,
+                ClientHintSupport::default(),
+                PositionEncoding::Utf16,
+            );
+            hint_labels(&hints)
+        };
+        assert_eq!(labels(&docs), ["a:"]);
+
+        let rename = lsp_types::TextDocumentContentChangeEvent {
+            range: Some(Range::new(Position::new(0, 7), Position::new(0, 8))),
+            range_length: None,
+            text: "count".to_string(),
+        };
+        docs.change(defs.clone(), 2, vec![rename], PositionEncoding::Utf16)
+            .unwrap();
+        assert_eq!(labels(&docs), ["count:"]);
+
+        docs.close(&defs);
+        assert!(labels(&docs).is_empty());
+    }
+
+    #[test]
+    fn untitled_buffers_get_hints_and_views_are_not_indexed() {
+        let untitled = Uri::from_str("untitled:Untitled-1").unwrap();
+        let view = Uri::from_str("git:/views/main.rs?%7B%22ref%22%3A%22HEAD%22%7D").unwrap();
+        let mut docs = DocumentStore::new();
+        for (uri, text) in [
+            (&untitled, "fn foo(a: i32) {}\nfn main() { foo(1); }"),
+            // An older version of a file that defines `foo` too.
+            (&view, "fn foo(old: i32) {}"),
+        ] {
+            docs.open(lsp_types::TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "rust".to_string(),
+                version: 1,
+                text: text.to_string(),
+            });
+        }
+        let hints = inlay_hints(
+            &docs,
+            None,
+            &untitled,
+            Range::new(Position::new(0, 0), Position::new(2, 0)),
+            &InlayHintsConfig::default(),
+            ClientHintSupport {
+                label_location: true,
+                ..ClientHintSupport::default()
+            },
+            PositionEncoding::Utf16,
+        );
+        assert_eq!(hint_labels(&hints), ["a:"]);
+        let InlayHintLabel::LabelParts(parts) = &hints[0].label else {
+            panic!("expected label parts");
+        };
+        assert_eq!(parts[0].location.as_ref().unwrap().uri, untitled);
+        assert!(docs.open_texts().is_empty());
+    }
+
+    /// A directory tree in memory that records what is read from it.
+    struct FakeFs {
+        files: Vec<(&'static str, &'static str)>,
+        reads: RefCell<Vec<PathBuf>>,
+    }
+
+    impl SourceFs for FakeFs {
+        fn read_dir(&self, dir: &Path) -> std::io::Result<Vec<(PathBuf, bool)>> {
+            self.reads.borrow_mut().push(dir.to_path_buf());
+            let mut entries = Vec::new();
+            for (file, _) in &self.files {
+                let Ok(rest) = Path::new(file).strip_prefix(dir) else {
+                    continue;
+                };
+                let first = rest.components().next().unwrap();
+                let entry = (dir.join(first), rest.components().count() > 1);
+                if !entries.contains(&entry) {
+                    entries.push(entry);
+                }
+            }
+            Ok(entries)
+        }
+
+        fn read(&self, path: &Path) -> Option<Arc<str>> {
+            self.reads.borrow_mut().push(path.to_path_buf());
+            let (_, text) = self
+                .files
+                .iter()
+                .find(|(file, _)| Path::new(file) == path)?;
+            Some(Arc::from(*text))
+        }
+    }
+
+    #[test]
+    fn the_workspace_settings_decide_which_files_are_read() {
+        let fs = FakeFs {
+            files: vec![
+                ("/fake/src/lib.rs", "fn in_lib(a: i32) {}"),
+                ("/fake/src/more.rs", "fn in_more(a: i32) {}"),
+                ("/fake/vendor/dep.rs", "fn vendored(a: i32) {}"),
+                ("/fake/crates/gen/out.rs", "fn generated(a: i32) {}"),
+            ],
+            reads: Default::default(),
+        };
+        let root = Path::new("/fake");
+        let index = |workspace: WorkspaceConfig| {
+            let mut docs = DocumentStore::new();
+            docs.set_workspace(workspace);
+            fs.reads.borrow_mut().clear();
+            let (index, _) = WorkspaceIndex::build_with_progress(
+                &docs,
+                Some(root),
+                &fs,
+                PositionEncoding::Utf16,
+                |_, _| {},
+            );
+            let mut names: Vec<String> = index.fn_defs.into_keys().collect();
+            names.sort();
+            names
+        };
+
+        let open_files_only = WorkspaceConfig {
+            mode: WorkspaceMode::OpenFilesOnly,
+            ..WorkspaceConfig::default()
+        };
+        assert!(index(open_files_only).is_empty());
+        assert!(fs.reads.borrow().is_empty());
+
+        let excluded = WorkspaceConfig {
+            exclude_dirs: vec!["vendor".to_string(), "crates/gen/".to_string()],
+            ..WorkspaceConfig::default()
+        };
+        assert_eq!(index(excluded), ["in_lib", "in_more"]);
+        let reads = fs.reads.borrow();
+        assert!(!reads.iter().any(|read| read.starts_with("/fake/vendor")));
+        assert!(
+            !reads
+                .iter()
+                .any(|read| read.starts_with("/fake/crates/gen"))
+        );
+        drop(reads);
+
+        let limited = WorkspaceConfig {
+            max_files: 1,
+            ..WorkspaceConfig::default()
+        };
+        assert_eq!(index(limited).len(), 1);
+        let files_read = fs
+            .reads
+            .borrow()
+            .iter()
+            .filter(|read| read.extension().is_some())
+            .count();
+        assert_eq!(files_read, 1);
+    }
 }
@@ -0,0 +1,21 @@
+//! A small Rust language server. The binary in `main.rs` parses the command
+//! line and calls `lsp::server::run`; everything else lives here so that
+//! tests can drive the server in process through `lsp::server::run_with`.
+
+#[macro_use]
+pub mod log;
+
+pub mod batch;
+pub mod cli;
+pub mod config;
+pub mod config_env;
+pub mod config_file;
+pub mod diagnostics;
+pub mod doc;
+pub mod hover;
+pub mod inlay;
+pub mod lsp;
+pub mod syntax;
+
+#[cfg(test)]
+mod test_support;
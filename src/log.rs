@@ -0,0 +1,257 @@
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::LogLevel;
+
+/// Once the log file would grow past this, it is moved to `<name>.old`,
+/// replacing the previous one, and a new file is started.
+const MAX_LOG_SIZE: u64 = 10 * 1024 * 1024;
+
+/// The most verbose level written, as `LogLevel as u8`.
+static LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Warn as u8);
+
+static LOGGER: OnceLock<Mutex<Logger>> = OnceLock::new();
+
+macro_rules! log_error {
+    ($($arg:tt)*) => {
+        $crate::log::write($crate::config::LogLevel::Error, format_args!($($arg)*))
+    };
+}
+
+macro_rules! log_warn {
+    ($($arg:tt)*) => {
+        $crate::log::write($crate::config::LogLevel::Warn, format_args!($($arg)*))
+    };
+}
+
+macro_rules! log_info {
+    ($($arg:tt)*) => {
+        $crate::log::write($crate::config::LogLevel::Info, format_args!($($arg)*))
+    };
+}
+
+macro_rules! log_debug {
+    ($($arg:tt)*) => {
+        $crate::log::write($crate::config::LogLevel::Debug, format_args!($($arg)*))
+    };
+}
+
+/// Sends the log to `path`, or to stderr when it is None or cannot be
+/// opened. Lines logged before this go to stderr.
+pub fn init(path: Option<PathBuf>) {
+    let logger = match path {
+        Some(path) => Logger::file(&path, MAX_LOG_SIZE).unwrap_or_else(|err| {
+            eprintln!("hitagi: cannot open log file {}: {err}", path.display());
+            Logger::stderr()
+        }),
+        None => Logger::stderr(),
+    };
+    let _ = LOGGER.set(Mutex::new(logger));
+}
+
+pub fn set_level(level: LogLevel) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn enabled(level: LogLevel) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// Writes a line at `level` if it is enabled; used through the `log_*`
+/// macros.
+pub fn write(level: LogLevel, args: fmt::Arguments<'_>) {
+    if !enabled(level) {
+        return;
+    }
+    let line = format_line(SystemTime::now(), level, args);
+    LOGGER
+        .get_or_init(|| Mutex::new(Logger::stderr()))
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .write(&line);
+}
+
+fn format_line(time: SystemTime, level: LogLevel, args: fmt::Arguments<'_>) -> String {
+    let label = match level {
+        LogLevel::Error => "ERROR",
+        LogLevel::Warn => "WARN",
+        LogLevel::Info => "INFO",
+        LogLevel::Debug => "DEBUG",
+    };
+    format!("{} {label:<5} {args}\n", timestamp(time))
+}
+
+/// Formats `time` as UTC, e.g. `2024-03-09T14:05:00.123Z`.
+pub fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let secs_of_day = secs % 86_400;
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// The date `days` after 1970-01-01, after Howard Hinnant's
+/// `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Where log lines go.
+#[derive(Debug)]
+pub struct Logger {
+    target: Target,
+}
+
+#[derive(Debug)]
+enum Target {
+    Stderr,
+    File {
+        path: PathBuf,
+        file: File,
+        size: u64,
+        max_size: u64,
+    },
+}
+
+impl Logger {
+    pub fn stderr() -> Self {
+        Logger {
+            target: Target::Stderr,
+        }
+    }
+
+    /// Appends to `path`, moving it to `<name>.old` once it would grow past
+    /// `max_size` bytes.
+    pub fn file(path: &Path, max_size: u64) -> io::Result<Self> {
+        let file = open_append(path)?;
+        let size = file.metadata()?.len();
+        Ok(Logger {
+            target: Target::File {
+                path: path.to_path_buf(),
+                file,
+                size,
+                max_size,
+            },
+        })
+    }
+
+    pub fn write(&mut self, line: &str) {
+        match &mut self.target {
+            Target::Stderr => {
+                let _ = io::stderr().write_all(line.as_bytes());
+            }
+            Target::File {
+                path,
+                file,
+                size,
+                max_size,
+            } => {
+                let len = line.len() as u64;
+                if *size > 0 && *size + len > *max_size {
+                    // When the file cannot be moved, it keeps growing rather
+                    // than losing lines.
+                    if let Ok(next) = rotate(path) {
+                        *file = next;
+                        *size = 0;
+                    }
+                }
+                if file.write_all(line.as_bytes()).is_ok() {
+                    *size += len;
+                }
+            }
+        }
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn rotate(path: &Path) -> io::Result<File> {
+    fs::rename(path, old_path(path))?;
+    open_append(path)
+}
+
+fn old_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".old");
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    #[test]
+    fn lines_carry_a_utc_timestamp_and_the_level() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_709_993_100_123);
+        assert_eq!(
+            format_line(time, LogLevel::Info, format_args!("checked {} files", 3)),
+            "2024-03-09T14:05:00.123Z INFO  checked 3 files\n"
+        );
+        assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        // A leap day.
+        let leap = UNIX_EPOCH + Duration::from_secs(951_782_400);
+        assert_eq!(timestamp(leap), "2000-02-29T00:00:00.000Z");
+    }
+
+    #[test]
+    fn the_file_is_rotated_once_it_grows_too_large() {
+        let dir = temp_dir("log-rotate");
+        let path = dir.join("hitagi.log");
+        let mut logger = Logger::file(&path, 30).unwrap();
+        logger.write("first line of twenty\n");
+        logger.write("second line\n");
+        logger.write("third line\n");
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "second line\nthird line\n"
+        );
+        assert_eq!(
+            fs::read_to_string(old_path(&path)).unwrap(),
+            "first line of twenty\n"
+        );
+
+        // Only one old file is kept, and reopening appends.
+        let mut logger = Logger::file(&path, 30).unwrap();
+        logger.write("fourth line\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth line\n");
+        assert_eq!(
+            fs::read_to_string(old_path(&path)).unwrap(),
+            "second line\nthird line\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn levels_up_to_the_configured_one_are_enabled() {
+        set_level(LogLevel::Info);
+        assert!(enabled(LogLevel::Error));
+        assert!(enabled(LogLevel::Info));
+        assert!(!enabled(LogLevel::Debug));
+        set_level(LogLevel::Warn);
+    }
+}
@@ -1 +1,3 @@
+pub mod outgoing;
+pub mod pool;
 pub mod server;
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+/// The error a client answered a request with, or one standing in for an
+/// answer that never came.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ResponseError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl fmt::Display for ResponseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
+
+/// Called with the context the response is handled in, e.g. the server
+/// state, and the result of the request.
+pub type Callback<C> = Box<dyn FnOnce(&mut C, Result<Value, ResponseError>)>;
+
+/// Requests sent to the client that are waiting for a response.
+pub struct OutgoingRequests<C> {
+    next_id: i64,
+    pending: HashMap<i64, Pending<C>>,
+    /// How long a request waits before it fails.
+    timeout: Duration,
+}
+
+struct Pending<C> {
+    method: String,
+    sent: Instant,
+    callback: Callback<C>,
+}
+
+impl<C> OutgoingRequests<C> {
+    pub fn new(timeout: Duration) -> Self {
+        OutgoingRequests {
+            next_id: 0,
+            pending: HashMap::new(),
+            timeout,
+        }
+    }
+
+    /// Allocates the id of a request for `method` that is about to be sent,
+    /// to be answered through `callback`.
+    pub fn register(&mut self, method: &str, callback: Callback<C>) -> i64 {
+        self.next_id += 1;
+        self.pending.insert(
+            self.next_id,
+            Pending {
+                method: method.to_string(),
+                sent: Instant::now(),
+                callback,
+            },
+        );
+        self.next_id
+    }
+
+    /// Takes the callback waiting for `response` along with its result, or
+    /// None if no request with its id is pending, e.g. once it timed out.
+    pub fn complete(
+        &mut self,
+        response: &Value,
+    ) -> Option<(Callback<C>, Result<Value, ResponseError>)> {
+        let id = response.get("id")?.as_i64()?;
+        let pending = self.pending.remove(&id)?;
+        let result = match response.get("error") {
+            Some(error) => {
+                Err(
+                    ResponseError::deserialize(error).unwrap_or_else(|err| ResponseError {
+                        code: -32603,
+                        message: format!("invalid error: {err}"),
+                    }),
+                )
+            }
+            None => Ok(response.get("result").cloned().unwrap_or(Value::Null)),
+        };
+        Some((pending.callback, result))
+    }
+
+    /// Takes the callbacks of the requests sent more than the timeout before
+    /// `now`, each with the error to call it with.
+    pub fn expire(&mut self, now: Instant) -> Vec<(Callback<C>, Result<Value, ResponseError>)> {
+        let expired: Vec<i64> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| now.duration_since(pending.sent) >= self.timeout)
+            .map(|(id, _)| *id)
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|id| self.pending.remove(&id))
+            .map(|pending| {
+                let error = ResponseError {
+                    code: -32803,
+                    message: format!(
+                        "the client did not answer {} within {} seconds",
+                        pending.method,
+                        self.timeout.as_secs()
+                    ),
+                };
+                (pending.callback, Err(error))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    type Results = Vec<(i64, Result<Value, ResponseError>)>;
+
+    fn record(tag: i64) -> Callback<Results> {
+        Box::new(move |results: &mut Results, result| results.push((tag, result)))
+    }
+
+    #[test]
+    fn responses_reach_the_callback_of_their_request() {
+        let mut requests = OutgoingRequests::new(Duration::from_secs(30));
+        let first = requests.register("workspace/configuration", record(1));
+        let second = requests.register("window/workDoneProgress/create", record(2));
+        assert_ne!(first, second);
+
+        let mut results = Results::new();
+        let error = json!({ "id": second, "error": { "code": -32601, "message": "nope" } });
+        let (callback, result) = requests.complete(&error).unwrap();
+        callback(&mut results, result);
+        let (callback, result) = requests
+            .complete(&json!({ "id": first, "result": [1] }))
+            .unwrap();
+        callback(&mut results, result);
+        assert_eq!(
+            results,
+            [
+                (
+                    2,
+                    Err(ResponseError {
+                        code: -32601,
+                        message: "nope".to_string()
+                    })
+                ),
+                (1, Ok(json!([1]))),
+            ]
+        );
+
+        // Each request is answered once, and unknown ids are ignored.
+        assert!(
+            requests
+                .complete(&json!({ "id": first, "result": [] }))
+                .is_none()
+        );
+        assert!(
+            requests
+                .complete(&json!({ "id": 99, "result": [] }))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn unanswered_requests_time_out() {
+        let mut requests = OutgoingRequests::new(Duration::from_secs(30));
+        let id = requests.register("client/registerCapability", record(1));
+        assert!(requests.expire(Instant::now()).is_empty());
+
+        let expired = requests.expire(Instant::now() + Duration::from_secs(31));
+        assert_eq!(expired.len(), 1);
+        let mut results = Results::new();
+        for (callback, result) in expired {
+            callback(&mut results, result);
+        }
+        let Err(error) = &results[0].1 else {
+            panic!("{results:?}");
+        };
+        assert_eq!(error.code, -32803);
+        assert!(error.message.contains("client/registerCapability"));
+        assert!(
+            requests
+                .complete(&json!({ "id": id, "result": null }))
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Runs jobs on a fixed number of threads, so that slow requests run while
+/// the main loop keeps applying notifications. Dropping the pool does not
+/// wait for the jobs; the threads stop once the queue is empty.
+pub struct WorkerPool {
+    sender: Sender<Job>,
+    /// Jobs queued or running, with a signal for when the count drops to 0.
+    pending: Arc<(Mutex<usize>, Condvar)>,
+}
+
+impl WorkerPool {
+    pub fn new(size: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let pending = Arc::new((Mutex::new(0), Condvar::new()));
+        for _ in 0..size.max(1) {
+            let receiver = Arc::clone(&receiver);
+            let pending = Arc::clone(&pending);
+            thread::spawn(move || work(&receiver, &pending));
+        }
+        WorkerPool { sender, pending }
+    }
+
+    /// A pool with a thread per core, up to four.
+    pub fn with_default_size() -> Self {
+        let cores = thread::available_parallelism().map_or(2, |cores| cores.get());
+        WorkerPool::new(cores.min(4))
+    }
+
+    pub fn execute(&self, job: impl FnOnce() + Send + 'static) {
+        *self.pending.0.lock().unwrap() += 1;
+        let _ = self.sender.send(Box::new(job));
+    }
+
+    /// Waits until every job queued so far has finished.
+    pub fn drain(&self) {
+        let (count, done) = &*self.pending;
+        let mut count = count.lock().unwrap();
+        while *count > 0 {
+            count = done.wait(count).unwrap();
+        }
+    }
+}
+
+fn work(receiver: &Mutex<Receiver<Job>>, pending: &(Mutex<usize>, Condvar)) {
+    loop {
+        let job = receiver.lock().unwrap().recv();
+        let Ok(job) = job else {
+            return;
+        };
+        let _finished = Finished(pending);
+        job();
+    }
+}
+
+/// Counts a job as finished when dropped, even if the job panicked.
+struct Finished<'a>(&'a (Mutex<usize>, Condvar));
+
+impl Drop for Finished<'_> {
+    fn drop(&mut self) {
+        let (count, done) = self.0;
+        let mut count = count.lock().unwrap_or_else(|err| err.into_inner());
+        *count -= 1;
+        if *count == 0 {
+            done.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn drain_waits_for_queued_jobs() {
+        let pool = WorkerPool::new(2);
+        let done = Arc::new(AtomicUsize::new(0));
+        for _ in 0..8 {
+            let done = Arc::clone(&done);
+            pool.execute(move || {
+                thread::sleep(Duration::from_millis(20));
+                done.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        pool.drain();
+        assert_eq!(done.load(Ordering::SeqCst), 8);
+
+        // The pool keeps working after a drain.
+        let (tx, rx) = mpsc::channel();
+        pool.execute(move || tx.send(thread::current().id()).unwrap());
+        assert_ne!(rx.recv().unwrap(), thread::current().id());
+    }
+
+    #[test]
+    fn slow_jobs_do_not_hold_up_others() {
+        let pool = WorkerPool::new(2);
+        let (tx, rx) = mpsc::channel();
+        let slow = tx.clone();
+        pool.execute(move || {
+            thread::sleep(Duration::from_millis(200));
+            slow.send("slow").unwrap();
+        });
+        pool.execute(move || tx.send("fast").unwrap());
+        assert_eq!(rx.recv().unwrap(), "fast");
+        assert_eq!(rx.recv().unwrap(), "slow");
+    }
+}
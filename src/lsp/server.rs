@@ -1,79 +1,264 @@
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::collections::{HashMap, HashSet};
 use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
-use std::path::PathBuf;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::net::{TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, PoisonError};
 use std::thread;
+use std::time::{Duration, Instant};
 
 use lsp_types::notification::Notification;
 use lsp_types::request::Request;
 use lsp_types::{
-    DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
-    DidSaveTextDocumentParams, Hover, HoverParams, InitializeParams, InitializeResult,
-    InitializedParams, InlayHint, InlayHintParams, SaveOptions, ServerCapabilities,
-    TextDocumentSyncCapability, TextDocumentSyncKind, TextDocumentSyncOptions, Uri,
-    WorkspaceFoldersServerCapabilities, WorkspaceServerCapabilities, notification, request,
+    ApplyWorkspaceEditParams, ClientCapabilities, ConfigurationItem, ConfigurationParams,
+    Diagnostic, DidChangeTextDocumentParams, DidChangeWatchedFilesParams,
+    DidChangeWatchedFilesRegistrationOptions, DidChangeWorkspaceFoldersParams,
+    DidCloseTextDocumentParams, DidOpenTextDocumentParams, DidSaveTextDocumentParams,
+    DocumentChanges, ExecuteCommandOptions, ExecuteCommandParams, FileSystemWatcher, GlobPattern,
+    HoverParams, InitializeParams, InitializeResult, InitializedParams, InlayHint, InlayHintParams,
+    MessageType, OneOf, OptionalVersionedTextDocumentIdentifier, ProgressParams,
+    ProgressParamsValue, ProgressToken, Registration, RegistrationParams, SaveOptions,
+    ServerCapabilities, ServerInfo, ShowMessageParams, TextDocumentEdit,
+    TextDocumentSyncCapability, TextDocumentSyncKind, TextDocumentSyncOptions, TextEdit, Uri,
+    WorkDoneProgress, WorkDoneProgressBegin, WorkDoneProgressCreateParams, WorkDoneProgressEnd,
+    WorkDoneProgressReport, WorkspaceEdit, WorkspaceFoldersServerCapabilities,
+    WorkspaceServerCapabilities, notification, request,
 };
 use serde_json::{Value, json};
 
-use crate::config::Config;
-use crate::diagnostics::run_check;
-use crate::doc::store::DocumentStore;
-use crate::doc::uri::uri_to_path;
-use crate::hover::hover as hover_at;
-use crate::inlay::inlay_hints;
+use crate::cli::Transport;
+use crate::config::{
+    Config, InlayHintsConfig, LimitsConfig, WorkspaceMode, merge_settings, settings_root,
+};
+use crate::config_env::EnvSettings;
+use crate::config_file;
+use crate::diagnostics::metadata::{self, is_cargo_manifest};
+use crate::diagnostics::runner::{CheckJob, CheckRunner};
+use crate::diagnostics::{
+    ClientDiagnosticSupport, check_command, diagnostic_fixes, standalone_command,
+};
+use crate::doc::file_cache;
+use crate::doc::position::PositionEncoding;
+use crate::doc::store::SharedDocuments;
+use crate::doc::uri::{NormalizedUri, path_to_uri, uri_to_path};
+use crate::hover::{content_format, hover as hover_at};
+use crate::inlay::{ClientHintSupport, build_index, index_stats, inlay_hints, resolve_inlay_hint};
+use crate::log;
+use crate::lsp::outgoing::{OutgoingRequests, ResponseError};
+use crate::lsp::pool::WorkerPool;
+
+/// Answers with what the server knows about the workspace, to include in bug
+/// reports.
+const STATUS_METHOD: &str = "hitagi/status";
+
+/// Applies the fixes rustc suggested for a document; takes its URI.
+const FIX_ALL_COMMAND: &str = "hitagi.fixAll";
+
+/// Drops the files read from disk and indexes the workspace again.
+const RELOAD_WORKSPACE_COMMAND: &str = "hitagi.reloadWorkspace";
+
+/// How long a request sent to the client waits for its response.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often requests sent to the client are checked for timeouts while no
+/// message arrives.
+const REQUEST_POLL: Duration = Duration::from_secs(1);
+
+/// How long a task runs before its progress is shown; quicker ones show
+/// nothing.
+const PROGRESS_DELAY: Duration = Duration::from_millis(500);
 
-pub fn run() {
+/// How long to wait for more manifest changes before re-checking.
+const MANIFEST_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Serves one client over `transport` and returns the exit code; see
+/// `run_with`.
+pub fn run(transport: &Transport) -> i32 {
+    // Handlers that panic are answered with an error; the hook logs where.
+    panic::set_hook(Box::new(|info| {
+        log_error!("{info}\n{}", Backtrace::force_capture());
+    }));
+
+    let stream = match transport {
+        Transport::Stdio => return run_with(io::stdin(), io::stdout()),
+        Transport::Listen(addr) => accept(addr),
+        Transport::Connect(addr) => TcpStream::connect(addr),
+    };
+    let streams = stream.and_then(|stream| {
+        stream.set_nodelay(true)?;
+        Ok((stream.try_clone()?, stream))
+    });
+    match streams {
+        Ok((input, output)) => run_with(input, output),
+        Err(err) => {
+            log_error!("cannot reach the client: {err}");
+            1
+        }
+    }
+}
+
+/// Waits for the first client to connect to `addr`.
+fn accept(addr: &str) -> io::Result<TcpStream> {
+    let listener = TcpListener::bind(addr)?;
+    log_info!("listening on {}", listener.local_addr()?);
+    let (stream, client) = listener.accept()?;
+    log_info!("serving {client}");
+    Ok(stream)
+}
+
+/// Serves the client until it sends `exit` or closes `input`, and returns the
+/// exit code: 0 when `shutdown` came first, else 1. Unlike `run`, this leaves
+/// the panic hook alone, so tests can serve a client over in-memory pipes.
+pub fn run_with(input: impl Read + Send + 'static, output: impl Write + Send + 'static) -> i32 {
     let (tx, rx) = mpsc::channel::<String>();
-    let writer = thread::spawn(move || writer_loop(rx));
+    let stop = Arc::new(AtomicBool::new(false));
+    let writer = {
+        let stop = Arc::clone(&stop);
+        thread::spawn(move || writer_loop(rx, output, &stop))
+    };
 
-    let stdin = io::stdin();
-    let mut reader = BufReader::new(stdin.lock());
+    let mut state = State::new(tx);
+    state.env_settings = EnvSettings::read();
+    if !state.env_settings.vars.is_empty() || !state.env_settings.issues.is_empty() {
+        state.reload_config();
+    }
 
-    let mut state = State::new(tx.clone());
+    // Messages are read ahead on their own thread, so that the changes
+    // queued up while one message is handled can be applied at once.
+    let (input_tx, input_rx) = mpsc::channel();
+    {
+        let max_message_size = Arc::clone(&state.max_message_size);
+        thread::spawn(move || reader_loop(BufReader::new(input), &max_message_size, input_tx));
+    }
 
-    loop {
-        match read_message(&mut reader) {
-            Ok(Some(value)) => {
-                let should_exit = state.handle_message(value);
-                if should_exit {
-                    break;
-                }
+    log_info!("hitagi {} started", env!("CARGO_PKG_VERSION"));
+    let code = 'serve: loop {
+        let first = match input_rx.recv_timeout(REQUEST_POLL) {
+            Ok(first) => first,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                state.expire_requests();
+                continue;
             }
-            Ok(None) => break,
-            Err(err) => {
-                eprintln!("lsp: failed to read message: {err}");
-                break;
+            Err(mpsc::RecvTimeoutError::Disconnected) => break state.exit_code(),
+        };
+        let queued = std::iter::once(first).chain(input_rx.try_iter()).collect();
+        state.expire_requests();
+        for input in coalesce_changes(queued) {
+            match input {
+                Ok(Some(Incoming::Message(value))) => {
+                    if let Some(code) = state.handle_message(value) {
+                        break 'serve code;
+                    }
+                }
+                Ok(Some(Incoming::Invalid { ids, code, error })) => {
+                    log_warn!("skipped a message: {error}");
+                    for id in ids {
+                        send_error(&state.sender, id, code, &error);
+                    }
+                }
+                Ok(None) => break 'serve state.exit_code(),
+                Err(err) => {
+                    log_error!("failed to read message: {err}");
+                    break 'serve state.exit_code();
+                }
             }
         }
-    }
+    };
 
-    drop(tx);
+    // The client stops reading once it sent `exit`, so messages still queued,
+    // e.g. diagnostics of a running check, are dropped.
+    stop.store(true, Ordering::SeqCst);
     let _ = writer.join();
+    log_info!("exiting with status {code}");
+    code
 }
 
 struct State {
     config: Config,
+    /// Settings from the project's `hitagi.toml`, which those of the editor
+    /// override.
+    project_settings: Option<(PathBuf, Value)>,
+    /// The settings the editor sent so far, merged.
+    editor_settings: Value,
+    /// Overrides from `HITAGI_*` variables, read at startup, which take
+    /// precedence over all other settings.
+    env_settings: EnvSettings,
+    /// The problems with the settings last shown to the user, so that they
+    /// are not repeated until the settings change.
+    config_issues: Vec<String>,
+    /// The workspace folders, each with the configuration of its files.
+    folders: Vec<Folder>,
     root: Option<PathBuf>,
-    docs: DocumentStore,
+    client_capabilities: ClientCapabilities,
+    /// The unit positions are exchanged in, agreed on at initialization.
+    encoding: PositionEncoding,
+    /// Shared so that workers can take snapshots while changes come in.
+    docs: SharedDocuments,
     sender: Sender<String>,
     shutdown: bool,
-    diag_running: Arc<AtomicBool>,
+    checks: Arc<CheckRunner>,
+    /// Runs requests, so that notifications are applied while they run.
+    workers: WorkerPool,
+    /// Documents the user was told are too large to analyze.
+    reported_too_large: HashSet<NormalizedUri>,
+    /// `limits.maxMessageSize`, shared with the thread reading messages.
+    max_message_size: Arc<AtomicUsize>,
+    /// Requests sent to the client, waiting for its response.
+    outgoing: OutgoingRequests<State>,
+    /// The fixes of the diagnostics last published, with the version of the
+    /// document they apply to.
+    fixes: Fixes,
+    /// `PROGRESS_DELAY`, shortened by tests.
+    progress_delay: Duration,
 }
 
+type Fixes = Arc<Mutex<HashMap<NormalizedUri, (Option<i32>, Vec<TextEdit>)>>>;
+
 impl State {
     fn new(sender: Sender<String>) -> Self {
+        let fixes = Fixes::default();
         Self {
             config: Config::default(),
+            project_settings: None,
+            editor_settings: Value::Null,
+            env_settings: EnvSettings::default(),
+            config_issues: Vec::new(),
+            folders: Vec::new(),
             root: None,
-            docs: DocumentStore::new(),
+            client_capabilities: ClientCapabilities::default(),
+            encoding: PositionEncoding::default(),
+            docs: SharedDocuments::new(),
+            checks: Arc::new(CheckRunner::new(
+                {
+                    let sender = sender.clone();
+                    let fixes = Arc::clone(&fixes);
+                    move |uri, diagnostics, version| {
+                        record_fixes(&fixes, &uri, &diagnostics, version);
+                        publish_diagnostics(&sender, uri, diagnostics, version)
+                    }
+                },
+                {
+                    let sender = sender.clone();
+                    move |typ, message| show_message(&sender, typ, message)
+                },
+            )),
             sender,
             shutdown: false,
-            diag_running: Arc::new(AtomicBool::new(false)),
+            workers: WorkerPool::with_default_size(),
+            reported_too_large: HashSet::new(),
+            max_message_size: Arc::new(AtomicUsize::new(LimitsConfig::default().max_message_size)),
+            outgoing: OutgoingRequests::new(REQUEST_TIMEOUT),
+            fixes,
+            progress_delay: PROGRESS_DELAY,
         }
     }
 
-    fn handle_message(&mut self, value: Value) -> bool {
+    /// Handles one message and returns the exit code once it is `exit`.
+    fn handle_message(&mut self, value: Value) -> Option<i32> {
         let method = value
             .get("method")
             .and_then(|v| v.as_str())
@@ -81,18 +266,151 @@ impl State {
         let id = value.get("id").cloned();
 
         match (method.as_deref(), id) {
-            (Some(method), Some(id)) => self.handle_request(method, id, value),
-            (Some(method), None) => self.handle_notification(method, value),
-            (None, _) => false,
+            (Some(notification::Exit::METHOD), None) => return Some(self.exit_code()),
+            (None, Some(_)) => {
+                let handled = panic::catch_unwind(AssertUnwindSafe(|| self.handle_response(value)));
+                if let Err(panic) = handled {
+                    log_error!("handling a response failed: {}", internal_error(&*panic));
+                }
+            }
+            // After `shutdown`, only `exit` is handled.
+            (Some(_), Some(id)) if self.shutdown => {
+                send_error(&self.sender, id, -32600, "the server is shutting down");
+            }
+            (Some(_), None) if self.shutdown => {}
+            (Some(method), Some(id)) => {
+                log_debug!("request {method} ({id})");
+                let started = Instant::now();
+                let handled = panic::catch_unwind(AssertUnwindSafe(|| {
+                    self.handle_request(method, id.clone(), value)
+                }));
+                match handled {
+                    Ok(()) => log_debug!("{method} dispatched in {:?}", started.elapsed()),
+                    Err(panic) => {
+                        send_error(&self.sender, id, -32603, &internal_error(&*panic));
+                    }
+                }
+            }
+            (Some(method), None) => {
+                log_debug!("notification {method}");
+                let started = Instant::now();
+                let handled = panic::catch_unwind(AssertUnwindSafe(|| {
+                    self.handle_notification(method, value)
+                }));
+                match handled {
+                    Ok(()) => log_debug!("{method} handled in {:?}", started.elapsed()),
+                    Err(panic) => log_error!("{method} failed: {}", internal_error(&*panic)),
+                }
+            }
+            (None, None) => {}
         }
+        None
     }
 
-    fn handle_request(&mut self, method: &str, id: Value, value: Value) -> bool {
+    /// Sends request `R` to the client and calls `callback` on the main loop
+    /// with its result, or with an error if the client does not answer in
+    /// time.
+    fn send_request<R: Request>(
+        &mut self,
+        params: R::Params,
+        callback: impl FnOnce(&mut State, Result<R::Result, ResponseError>) + 'static,
+    ) {
+        let id = self.outgoing.register(
+            R::METHOD,
+            Box::new(move |state, result| {
+                let result = result.and_then(|value| {
+                    serde_json::from_value(value).map_err(|err| ResponseError {
+                        code: -32603,
+                        message: format!("invalid result for {}: {err}", R::METHOD),
+                    })
+                });
+                callback(state, result)
+            }),
+        );
+        send_request(
+            &self.sender,
+            json!(id),
+            R::METHOD,
+            serde_json::to_value(params).unwrap_or(Value::Null),
+        );
+    }
+
+    /// Asks the client to apply `edit`, and tells the user when it does not.
+    fn apply_edit(&mut self, label: &str, edit: WorkspaceEdit) {
+        let supported = self
+            .client_capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.apply_edit)
+            == Some(true);
+        if !supported {
+            show_message(
+                &self.sender,
+                MessageType::ERROR,
+                format!("hitagi: could not apply {label}: the client cannot apply edits"),
+            );
+            return;
+        }
+        let params = ApplyWorkspaceEditParams {
+            label: Some(label.to_string()),
+            edit,
+        };
+        let label = label.to_string();
+        self.send_request::<request::ApplyWorkspaceEdit>(params, move |state, result| {
+            let reason = match result {
+                Ok(response) if response.applied => return,
+                Ok(response) => response
+                    .failure_reason
+                    .unwrap_or_else(|| "the client rejected the edit".to_string()),
+                Err(err) => err.to_string(),
+            };
+            log_warn!("could not apply {label}: {reason}");
+            show_message(
+                &state.sender,
+                MessageType::ERROR,
+                format!("hitagi: could not apply {label}: {reason}"),
+            );
+        });
+    }
+
+    fn handle_response(&mut self, response: Value) {
+        match self.outgoing.complete(&response) {
+            Some((callback, result)) => callback(self, result),
+            None => log_warn!("ignoring a response to unknown request {}", response["id"]),
+        }
+    }
+
+    /// Fails the requests the client did not answer in time.
+    fn expire_requests(&mut self) {
+        for (callback, result) in self.outgoing.expire(Instant::now()) {
+            callback(self, result);
+        }
+    }
+
+    fn exit_code(&self) -> i32 {
+        if self.shutdown { 0 } else { 1 }
+    }
+
+    fn handle_request(&mut self, method: &str, id: Value, value: Value) {
         match method {
             request::Initialize::METHOD => match parse_params::<InitializeParams>(&value) {
                 Ok(params) => {
                     self.root = extract_root(&params);
-                    let result = initialize_result();
+                    let mut roots = folder_roots(&params);
+                    if roots.is_empty() {
+                        roots.extend(self.root.clone());
+                    }
+                    self.folders = roots.into_iter().map(Folder::new).collect();
+                    self.encoding = PositionEncoding::negotiate(&params.capabilities);
+                    self.client_capabilities = params.capabilities;
+                    // Settings sent along with `initialize` apply from the
+                    // start; later configuration changes override them.
+                    self.load_project_settings();
+                    if let Some(options) = &params.initialization_options {
+                        merge_settings(&mut self.editor_settings, &settings_root(options));
+                    }
+                    self.reload_config();
+                    let result = initialize_result(self.encoding);
                     send_response(
                         &self.sender,
                         id,
@@ -101,65 +419,142 @@ impl State {
                 }
                 Err(err) => send_error(&self.sender, id, -32602, &err),
             },
+            STATUS_METHOD => send_response(&self.sender, id, self.status()),
+            request::ExecuteCommand::METHOD => {
+                match parse_params::<ExecuteCommandParams>(&value) {
+                    Ok(params) if params.command == FIX_ALL_COMMAND => {
+                        let uri = params
+                            .arguments
+                            .into_iter()
+                            .next()
+                            .map(serde_json::from_value);
+                        let Some(Ok(uri)) = uri else {
+                            let message = format!("{FIX_ALL_COMMAND} takes the URI of a document");
+                            send_error(&self.sender, id, -32602, &message);
+                            return;
+                        };
+                        // Invoked directly, so the edit goes through
+                        // `workspace/applyEdit` rather than the response.
+                        self.fix_all(uri);
+                        send_response(&self.sender, id, Value::Null);
+                    }
+                    Ok(params) if params.command == RELOAD_WORKSPACE_COMMAND => {
+                        self.reload_workspace();
+                        send_response(&self.sender, id, Value::Null);
+                    }
+                    Ok(params) => {
+                        let message = format!("unknown command {}", params.command);
+                        send_error(&self.sender, id, -32602, &message);
+                    }
+                    Err(err) => send_error(&self.sender, id, -32602, &err),
+                }
+            }
             request::Shutdown::METHOD => {
                 self.shutdown = true;
+                // Requests received before the shutdown are still answered.
+                self.workers.drain();
                 send_response(&self.sender, id, Value::Null);
             }
-            request::HoverRequest::METHOD => match parse_params::<HoverParams>(&value) {
-                Ok(params) => {
-                    let result = self.handle_hover(params);
-                    send_response(
-                        &self.sender,
-                        id,
-                        serde_json::to_value(result).unwrap_or(Value::Null),
-                    );
-                }
-                Err(err) => send_error(&self.sender, id, -32602, &err),
-            },
-            request::InlayHintRequest::METHOD => match parse_params::<InlayHintParams>(&value) {
-                Ok(params) => {
-                    let result = self.handle_inlay_hints(params);
-                    send_response(
-                        &self.sender,
-                        id,
-                        serde_json::to_value(result).unwrap_or(Value::Null),
-                    );
-                }
-                Err(err) => send_error(&self.sender, id, -32602, &err),
-            },
+            request::HoverRequest::METHOD => {
+                let docs = self.docs.snapshot();
+                let root = self.root.clone();
+                let config = self
+                    .config_for(request_path(&value).as_deref())
+                    .hover
+                    .clone();
+                let format = content_format(&self.client_capabilities, &config);
+                let encoding = self.encoding;
+                self.respond_later(method, id, move || {
+                    let params = parse_params::<HoverParams>(&value)?;
+                    let position = params.text_document_position_params;
+                    Ok(hover_at(
+                        &docs,
+                        root.as_deref(),
+                        &position.text_document.uri,
+                        position.position,
+                        &config,
+                        format,
+                        encoding,
+                    ))
+                });
+            }
+            request::InlayHintRequest::METHOD => {
+                let docs = self.docs.snapshot();
+                let root = self.root.clone();
+                let path = request_path(&value);
+                let config = self.config_for(path.as_deref()).inlay_hints.clone();
+                let support = ClientHintSupport::from_capabilities(&self.client_capabilities);
+                let encoding = self.encoding;
+                self.respond_later(method, id, move || {
+                    let params = parse_params::<InlayHintParams>(&value)?;
+                    Ok(inlay_hints(
+                        &docs,
+                        root.as_deref(),
+                        &params.text_document.uri,
+                        params.range,
+                        &config,
+                        support,
+                        encoding,
+                    ))
+                });
+            }
+            request::InlayHintResolveRequest::METHOD => {
+                let docs = self.docs.snapshot();
+                let root = self.root.clone();
+                let encoding = self.encoding;
+                self.respond_later(method, id, move || {
+                    let hint = parse_params::<InlayHint>(&value)?;
+                    Ok(resolve_inlay_hint(&docs, root.as_deref(), hint, encoding))
+                });
+            }
             _ => {
                 send_error(&self.sender, id, -32601, "method not found");
             }
         }
-
-        false
     }
 
-    fn handle_notification(&mut self, method: &str, value: Value) -> bool {
+    fn handle_notification(&mut self, method: &str, value: Value) {
         match method {
             notification::Initialized::METHOD => {
                 let _ = parse_params::<InitializedParams>(&value);
-            }
-            notification::Exit::METHOD => {
-                return true;
+                self.watch_manifests();
+                self.request_folder_settings();
+                self.index_workspace();
             }
             notification::DidOpenTextDocument::METHOD => {
                 if let Ok(params) = parse_params::<DidOpenTextDocumentParams>(&value) {
+                    let uri = params.text_document.uri.clone();
                     self.docs.open(params.text_document);
+                    self.report_too_large(&uri);
                 }
             }
             notification::DidChangeTextDocument::METHOD => {
                 if let Ok(params) = parse_params::<DidChangeTextDocumentParams>(&value) {
                     let uri = params.text_document.uri;
                     let version = params.text_document.version;
-                    if let Some(change) = params.content_changes.into_iter().last() {
-                        self.docs.change_full(uri, version, change.text);
+                    let changes = params.content_changes;
+                    match self
+                        .docs
+                        .change(uri.clone(), version, changes, self.encoding)
+                    {
+                        Ok(()) => self.report_too_large(&uri),
+                        Err(err) => log_warn!("{err}"),
                     }
                 }
             }
             notification::DidCloseTextDocument::METHOD => {
                 if let Ok(params) = parse_params::<DidCloseTextDocumentParams>(&value) {
                     self.docs.close(&params.text_document.uri);
+                    let key = NormalizedUri::new(&params.text_document.uri);
+                    self.reported_too_large.remove(&key);
+                    let closed = uri_to_path(&params.text_document.uri);
+                    if self
+                        .config_for(closed.as_deref())
+                        .diagnostics
+                        .open_files_only
+                    {
+                        self.checks.clear(&params.text_document.uri);
+                    }
                 }
             }
             notification::DidSaveTextDocument::METHOD => {
@@ -167,67 +562,631 @@ impl State {
                     self.handle_did_save(params);
                 }
             }
+            notification::DidChangeWatchedFiles::METHOD => {
+                if let Ok(params) = parse_params::<DidChangeWatchedFilesParams>(&value) {
+                    for change in params.changes {
+                        let Some(path) = uri_to_path(&change.uri) else {
+                            continue;
+                        };
+                        file_cache::evict(&path);
+                        if is_cargo_manifest(&path) {
+                            self.manifest_changed(&path);
+                        } else if self.is_config_file(&path) {
+                            self.load_project_settings();
+                            self.reload_config();
+                        }
+                    }
+                }
+            }
             notification::DidChangeConfiguration::METHOD => {
                 if let Some(settings) = value.get("params").and_then(|p| p.get("settings")) {
-                    self.config.update_from_settings(settings);
+                    self.apply_settings(settings);
+                }
+                self.request_folder_settings();
+            }
+            notification::DidChangeWorkspaceFolders::METHOD => {
+                if let Ok(params) = parse_params::<DidChangeWorkspaceFoldersParams>(&value) {
+                    let removed: Vec<PathBuf> = params
+                        .event
+                        .removed
+                        .iter()
+                        .filter_map(|folder| uri_to_path(&folder.uri))
+                        .collect();
+                    self.folders
+                        .retain(|folder| !removed.contains(&folder.root));
+                    for folder in &params.event.added {
+                        if let Some(root) = uri_to_path(&folder.uri)
+                            && !self.folders.iter().any(|folder| folder.root == root)
+                        {
+                            self.folders.push(Folder::new(root));
+                        }
+                    }
+                    self.load_project_settings();
+                    self.reload_config();
+                    self.request_folder_settings();
                 }
             }
             _ => {}
         }
+    }
 
-        false
+    fn handle_did_save(&mut self, params: DidSaveTextDocumentParams) {
+        let uri = &params.text_document.uri;
+        let saved = uri_to_path(uri);
+        if let Some(saved) = &saved {
+            file_cache::evict(saved);
+        }
+        // The stored text can fall behind, e.g. when a formatter or git
+        // rewrote the file without the client sending the change.
+        if self.docs.save(uri, params.text) {
+            log_info!(
+                "{} differed from its saved text, which replaced it",
+                uri.as_str()
+            );
+            self.report_too_large(uri);
+        }
+        if let Some(manifest) = saved.as_deref().filter(|path| is_cargo_manifest(path)) {
+            self.manifest_changed(manifest);
+            return;
+        }
+        if saved
+            .as_deref()
+            .is_some_and(|path| self.is_config_file(path))
+        {
+            self.load_project_settings();
+            self.reload_config();
+            return;
+        }
+        if !self.config_for(saved.as_deref()).check.on_save {
+            return;
+        }
+        if let Some(job) = self.check_job(saved.as_deref()) {
+            self.checks.request(job);
+        }
     }
 
-    fn handle_hover(&self, params: HoverParams) -> Option<Hover> {
-        let HoverParams {
-            text_document_position_params,
-            ..
-        } = params;
-        let uri = text_document_position_params.text_document.uri;
-        let position = text_document_position_params.position;
-        hover_at(&self.docs, &uri, position)
+    /// Re-checks once a `Cargo.toml` or `Cargo.lock` changed, as added or
+    /// removed dependencies change which diagnostics apply.
+    fn manifest_changed(&mut self, manifest: &Path) {
+        metadata::invalidate();
+        if !self.config_for(Some(manifest)).check.on_save {
+            return;
+        }
+        if let Some(job) = self.check_job(Some(manifest)) {
+            self.checks.request_after(job, MANIFEST_DEBOUNCE);
+        }
     }
 
-    fn handle_did_save(&mut self, _params: DidSaveTextDocumentParams) {
-        if !self.config.check_on_save {
+    /// Asks the client to report changes to `Cargo.toml` and `Cargo.lock`,
+    /// which are usually edited without being open, and to the project's
+    /// configuration file.
+    fn watch_manifests(&mut self) {
+        let dynamic = self
+            .client_capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.did_change_watched_files.as_ref())
+            .and_then(|watched| watched.dynamic_registration)
+            .unwrap_or(false);
+        if !dynamic {
             return;
         }
-        let root = match self.root.as_ref() {
-            Some(root) => root.clone(),
-            None => return,
+        let options = DidChangeWatchedFilesRegistrationOptions {
+            watchers: vec![
+                FileSystemWatcher {
+                    glob_pattern: GlobPattern::String("**/Cargo.{toml,lock}".to_string()),
+                    kind: None,
+                },
+                FileSystemWatcher {
+                    glob_pattern: GlobPattern::String(
+                        "**/{hitagi.toml,.hitagi/config.toml}".to_string(),
+                    ),
+                    kind: None,
+                },
+            ],
+        };
+        let params = RegistrationParams {
+            registrations: vec![Registration {
+                id: "hitagi-cargo-manifests".to_string(),
+                method: notification::DidChangeWatchedFiles::METHOD.to_string(),
+                register_options: serde_json::to_value(options).ok(),
+            }],
         };
+        self.send_request::<request::RegisterCapability>(params, |_, result| {
+            if let Err(err) = result {
+                log_warn!("could not watch Cargo manifests: {err}");
+            }
+        });
+    }
+
+    /// Merges the settings the client sent into those it sent before and
+    /// applies them.
+    fn apply_settings(&mut self, settings: &Value) {
+        merge_settings(&mut self.editor_settings, &settings_root(settings));
+        self.reload_config();
+    }
 
-        if self.diag_running.swap(true, Ordering::SeqCst) {
+    /// Reads the configuration files at the root and of each folder.
+    fn load_project_settings(&mut self) {
+        if let Some(root) = &self.root {
+            read_project_settings(&self.sender, root, &mut self.project_settings);
+        }
+        for folder in &mut self.folders {
+            read_project_settings(&self.sender, &folder.root, &mut folder.project_settings);
+        }
+    }
+
+    fn is_config_file(&self, path: &Path) -> bool {
+        self.root
+            .iter()
+            .chain(self.folders.iter().map(|folder| &folder.root))
+            .any(|root| config_file::is_config_file(root, path))
+    }
+
+    /// The configuration of the innermost folder containing `path`, or the
+    /// global one.
+    fn config_for(&self, path: Option<&Path>) -> &Config {
+        path.and_then(|path| {
+            self.folders
+                .iter()
+                .filter(|folder| path.starts_with(&folder.root))
+                .max_by_key(|folder| folder.root.components().count())
+        })
+        .map_or(&self.config, |folder| &folder.config)
+    }
+
+    /// Asks the client for the settings of each folder, which apply over
+    /// the global ones to the files in it.
+    fn request_folder_settings(&mut self) {
+        let supported = self
+            .client_capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.configuration)
+            == Some(true);
+        if !supported || self.folders.is_empty() {
             return;
         }
+        let roots: Vec<PathBuf> = self
+            .folders
+            .iter()
+            .map(|folder| folder.root.clone())
+            .collect();
+        let params = ConfigurationParams {
+            items: roots
+                .iter()
+                .map(|root| ConfigurationItem {
+                    scope_uri: path_to_uri(root),
+                    section: Some("hitagi".to_string()),
+                })
+                .collect(),
+        };
+        self.send_request::<request::WorkspaceConfiguration>(params, move |state, result| {
+            let settings = match result {
+                Ok(settings) => settings,
+                Err(err) => {
+                    log_warn!("could not get the settings of the workspace folders: {err}");
+                    return;
+                }
+            };
+            // The folders may have changed while the client answered.
+            for (root, settings) in roots.iter().zip(settings) {
+                if let Some(folder) = state.folders.iter_mut().find(|f| &f.root == root) {
+                    folder.scoped_settings = settings_root(&settings);
+                }
+            }
+            state.reload_config();
+        });
+    }
 
-        let open_urls = self.docs.open_urls();
-        let check_command = self.config.check_command.clone();
+    /// Rebuilds the configuration from the defaults, the project's settings,
+    /// the editor's and the environment's, in increasing precedence, and
+    /// applies what changed.
+    fn reload_config(&mut self) {
+        let mode = self.config.workspace.mode;
+        let hints = self.inlay_hint_configs();
+        let mut issues = Vec::new();
+        let editor = &self.editor_settings;
+        let env = &self.env_settings;
+        self.config = build_config(self.project_settings.as_ref(), &[editor], env, &mut issues);
+        for folder in &mut self.folders {
+            let layers = [editor, &folder.scoped_settings];
+            let project = folder.project_settings.as_ref();
+            folder.config = build_config(project, &layers, env, &mut issues);
+        }
+        self.report_config_issues(issues);
+        log::set_level(self.config.log_level);
+        let limits = &self.config.limits;
+        self.docs
+            .set_max_analyzed_size(limits.max_analyzed_file_size);
+        self.max_message_size
+            .store(limits.max_message_size, Ordering::Relaxed);
+        self.docs.set_workspace(self.config.workspace.clone());
+        if self.config.workspace.mode != mode {
+            self.workspace_mode_changed();
+        }
+        if self.inlay_hint_configs() != hints {
+            self.request_inlay_hint_refresh();
+        }
+    }
+
+    /// The inlay hint settings, globally and of each folder.
+    fn inlay_hint_configs(&self) -> Vec<InlayHintsConfig> {
+        std::iter::once(&self.config)
+            .chain(self.folders.iter().map(|folder| &folder.config))
+            .map(|config| config.inlay_hints.clone())
+            .collect()
+    }
+
+    /// Asks the client to request the inlay hints it shows again, since
+    /// they were computed with other settings.
+    fn request_inlay_hint_refresh(&mut self) {
+        let supported = self
+            .client_capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.inlay_hint.as_ref())
+            .and_then(|inlay_hint| inlay_hint.refresh_support)
+            == Some(true);
+        if !supported {
+            return;
+        }
+        self.send_request::<request::InlayHintRefreshRequest>((), |_, result| {
+            if let Err(err) = result {
+                log_warn!("could not refresh inlay hints: {err}");
+            }
+        });
+    }
+
+    /// Tells the user which settings were left out, unless they were told
+    /// already.
+    fn report_config_issues(&mut self, issues: Vec<String>) {
+        if issues == self.config_issues {
+            return;
+        }
+        if !issues.is_empty() {
+            for issue in &issues {
+                log_warn!("ignored a setting: {issue}");
+            }
+            show_message(
+                &self.sender,
+                MessageType::WARNING,
+                format!(
+                    "hitagi: some settings were ignored:\n- {}",
+                    issues.join("\n- ")
+                ),
+            );
+        }
+        self.config_issues = issues;
+    }
+
+    /// Starts looking at the files on disk again, or stops and drops the
+    /// ones read so far.
+    fn workspace_mode_changed(&mut self) {
+        let mode = self.config.workspace.mode;
+        log_info!("workspace mode is now {mode:?}");
+        match mode {
+            WorkspaceMode::OpenFilesOnly => file_cache::clear(),
+            WorkspaceMode::Workspace => self.index_workspace(),
+        }
+    }
+
+    /// Builds the workspace index on a worker ahead of the first request,
+    /// with a progress token when the client supports them.
+    fn index_workspace(&mut self) {
+        let root = self.root.as_deref();
+        if root.is_none() || self.config_for(root).workspace.mode == WorkspaceMode::OpenFilesOnly {
+            return;
+        }
+        let supported = self
+            .client_capabilities
+            .window
+            .as_ref()
+            .and_then(|window| window.work_done_progress)
+            == Some(true);
+        if !supported {
+            self.build_index(None);
+            return;
+        }
+        let token = ProgressToken::String("hitagi/indexing".to_string());
+        let params = WorkDoneProgressCreateParams {
+            token: token.clone(),
+        };
+        self.send_request::<request::WorkDoneProgressCreate>(params, move |state, result| {
+            if let Err(err) = &result {
+                log_warn!("indexing without progress: {err}");
+            }
+            state.build_index(result.ok().map(|()| token));
+        });
+    }
+
+    /// Rebuilds the workspace index from scratch, e.g. after switching
+    /// branches changed files the client did not report.
+    fn reload_workspace(&mut self) {
+        log_info!("reloading the workspace");
+        file_cache::clear();
+        metadata::invalidate();
+        self.index_workspace();
+    }
+
+    fn build_index(&self, token: Option<ProgressToken>) {
+        let docs = self.docs.snapshot();
+        let root = self.root.clone();
+        let encoding = self.encoding;
+        let mut progress = token.map(|token| {
+            Progress::new(self.sender.clone(), token, "Indexing", self.progress_delay)
+        });
+        self.workers.execute(move || {
+            let built = panic::catch_unwind(AssertUnwindSafe(|| {
+                build_index(&docs, root.as_deref(), encoding, |indexed, found| {
+                    if let Some(progress) = &mut progress {
+                        progress.report(indexed, found);
+                    }
+                })
+            }));
+            let stats = match built {
+                Ok(stats) => stats,
+                Err(panic) => {
+                    log_error!("indexing failed: {}", internal_error(&*panic));
+                    return;
+                }
+            };
+            if let Some(progress) = progress {
+                progress.end(format!(
+                    "indexed {} files, {} definitions",
+                    thousands(stats.open_files + stats.other_files),
+                    thousands(stats.definitions)
+                ));
+            }
+        });
+    }
+
+    /// Runs `handle` on a worker with what it captured from the state when
+    /// the request arrived, and answers request `id` with its result, or with
+    /// an invalid params error, or an internal error if it panics.
+    fn respond_later<T: serde::Serialize>(
+        &self,
+        method: &str,
+        id: Value,
+        handle: impl FnOnce() -> Result<T, String> + Send + 'static,
+    ) {
         let sender = self.sender.clone();
-        let diag_running = Arc::clone(&self.diag_running);
+        let method = method.to_string();
+        let queued = Instant::now();
+        self.workers.execute(move || {
+            let started = Instant::now();
+            match panic::catch_unwind(AssertUnwindSafe(handle)) {
+                Ok(Ok(result)) => send_response(
+                    &sender,
+                    id,
+                    serde_json::to_value(result).unwrap_or(Value::Null),
+                ),
+                Ok(Err(err)) => send_error(&sender, id, -32602, &err),
+                Err(panic) => {
+                    log_error!("{method} failed: {}", internal_error(&*panic));
+                    send_error(&sender, id, -32603, &internal_error(&*panic));
+                }
+            }
+            log_debug!(
+                "{method} answered in {:?} after waiting {:?}",
+                started.elapsed(),
+                started - queued
+            );
+        });
+    }
+
+    /// Applies the fixes of the diagnostics last published for `uri`.
+    fn fix_all(&mut self, uri: Uri) {
+        let fixes = self
+            .fixes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&NormalizedUri::new(&uri))
+            .cloned();
+        let Some((version, fixes)) = fixes else {
+            show_message(
+                &self.sender,
+                MessageType::INFO,
+                format!("hitagi: there is nothing to fix in {}", uri.as_str()),
+            );
+            return;
+        };
+        let document_changes = self
+            .client_capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.workspace_edit.as_ref())
+            .and_then(|edit| edit.document_changes)
+            == Some(true);
+        let edit = fix_all_edit(uri, version.filter(|_| document_changes), fixes);
+        self.apply_edit("fix all", edit);
+    }
 
-        thread::spawn(move || {
-            if let Ok(map) = run_check(&root, &check_command) {
-                publish_diagnostics(&sender, open_urls, map);
+    fn status(&self) -> Value {
+        let index = index_stats().map(|stats| {
+            json!({
+                "openFiles": stats.open_files,
+                "otherFiles": stats.other_files,
+                "definitions": stats.definitions,
+            })
+        });
+        let mut roots: Vec<&PathBuf> = self.root.iter().collect();
+        for folder in &self.folders {
+            if !roots.contains(&&folder.root) {
+                roots.push(&folder.root);
             }
-            diag_running.store(false, Ordering::SeqCst);
+        }
+        let last_check = self.checks.last_check().map(|check| {
+            json!({
+                "finished": log::timestamp(check.finished),
+                "durationMs": check.duration.as_millis() as u64,
+                "exitCode": check.exit_code,
+                "error": check.error,
+            })
         });
+        json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "roots": roots,
+            "openDocuments": self.docs.snapshot().iter().count(),
+            "index": index,
+            "lastCheck": last_check,
+            "settings": {
+                "precedence": ["defaults", "hitagi.toml", "editor", "environment"],
+                "environment": self
+                    .env_settings
+                    .vars
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), json!(value)))
+                    .collect::<serde_json::Map<_, _>>(),
+            },
+        })
     }
 
-    fn handle_inlay_hints(&self, params: InlayHintParams) -> Option<Vec<InlayHint>> {
-        let uri = params.text_document.uri;
-        let range = params.range;
-        Some(inlay_hints(&self.docs, self.root.as_deref(), &uri, range))
+    /// Tells the user, once per document, that `uri` is too large to be
+    /// analyzed.
+    fn report_too_large(&mut self, uri: &Uri) {
+        if !self.docs.is_too_large(uri) || !self.reported_too_large.insert(NormalizedUri::new(uri))
+        {
+            return;
+        }
+        show_message(
+            &self.sender,
+            MessageType::WARNING,
+            format!(
+                "hitagi: {} is larger than limits.maxAnalyzedFileSize ({} bytes), so hover and \
+                 inlay hints are turned off for it.",
+                uri.as_str(),
+                self.config.limits.max_analyzed_file_size
+            ),
+        );
+    }
+
+    /// Returns the check to run after `saved` changed.
+    fn check_job(&self, saved: Option<&Path>) -> Option<CheckJob> {
+        let config = self.config_for(saved);
+        let docs = self.docs.snapshot();
+        // Rust files outside any Cargo project are checked on their own.
+        let rust = |saved: &Path| {
+            saved.extension().is_some_and(|ext| ext == "rs")
+                || path_to_uri(saved)
+                    .and_then(|uri| docs.get(&uri))
+                    .is_some_and(|doc| doc.is_rust())
+        };
+        let standalone = saved.filter(|saved| rust(saved)).and_then(|saved| {
+            let command = standalone_command(saved, &config.check)?;
+            Some((saved.parent()?.to_path_buf(), command))
+        });
+        let (root, command) = match standalone {
+            Some(standalone) => standalone,
+            None => {
+                // The LSP root may be a subdirectory without a `Cargo.toml`.
+                let root = saved
+                    .and_then(metadata::cargo_root)
+                    .or_else(|| self.root.clone())?;
+                let command =
+                    check_command(config.check.command.as_deref(), &config.check, &root, saved);
+                (root, command)
+            }
+        };
+        Some(CheckJob {
+            root,
+            command,
+            support: ClientDiagnosticSupport::from_capabilities(&self.client_capabilities),
+            open_documents: docs.open_texts(),
+            client_uris: docs.client_uris(),
+            config: config.diagnostics.clone(),
+            encoding: self.encoding,
+        })
+    }
+}
+
+/// A workspace folder and the configuration of the files in it.
+struct Folder {
+    root: PathBuf,
+    /// Settings from the folder's `hitagi.toml`.
+    project_settings: Option<(PathBuf, Value)>,
+    /// The settings the client has for the folder, which override the
+    /// global ones.
+    scoped_settings: Value,
+    config: Config,
+}
+
+impl Folder {
+    fn new(root: PathBuf) -> Self {
+        Folder {
+            root,
+            project_settings: None,
+            scoped_settings: Value::Null,
+            config: Config::default(),
+        }
     }
 }
 
-fn initialize_result() -> InitializeResult {
+/// The defaults updated from the project's settings, then from each of the
+/// editor's and last from the environment's, adding the problems with them
+/// to `issues`.
+fn build_config(
+    project: Option<&(PathBuf, Value)>,
+    editor: &[&Value],
+    env: &EnvSettings,
+    issues: &mut Vec<String>,
+) -> Config {
+    let mut config = Config::default();
+    let mut found = Vec::new();
+    if let Some((path, settings)) = project {
+        for issue in config.update_from_settings(settings) {
+            found.push(format!("{issue} (in {})", path.display()));
+        }
+    }
+    for settings in editor {
+        found.extend(
+            config
+                .update_from_settings(settings)
+                .iter()
+                .map(ToString::to_string),
+        );
+    }
+    for issue in config.update_from_settings(&env.settings) {
+        found.push(format!("{issue} (in the environment)"));
+    }
+    found.extend(env.issues.iter().cloned());
+    // Folders share the global settings, and so their problems.
+    for issue in found {
+        if !issues.contains(&issue) {
+            issues.push(issue);
+        }
+    }
+    config
+}
+
+/// Reads the configuration file at `root` into `settings`. When it cannot
+/// be read, the user is told why, and the settings read from it before stay.
+fn read_project_settings(
+    sender: &Sender<String>,
+    root: &Path,
+    settings: &mut Option<(PathBuf, Value)>,
+) {
+    match config_file::load(root) {
+        None => *settings = None,
+        Some((path, Ok(read))) => {
+            log_info!("read settings from {}", path.display());
+            *settings = Some((path, read));
+        }
+        Some((path, Err(err))) => show_message(
+            sender,
+            MessageType::WARNING,
+            format!("hitagi: could not read {}: {err}", path.display()),
+        ),
+    }
+}
+
+fn initialize_result(encoding: PositionEncoding) -> InitializeResult {
     let text_document_sync = TextDocumentSyncCapability::Options(TextDocumentSyncOptions {
         open_close: Some(true),
-        change: Some(TextDocumentSyncKind::FULL),
+        change: Some(TextDocumentSyncKind::INCREMENTAL),
         save: Some(
             SaveOptions {
-                include_text: Some(false),
+                include_text: Some(true),
             }
             .into(),
         ),
@@ -235,9 +1194,22 @@ fn initialize_result() -> InitializeResult {
     });
 
     let capabilities = ServerCapabilities {
+        position_encoding: Some(encoding.kind()),
         text_document_sync: Some(text_document_sync),
         hover_provider: Some(lsp_types::HoverProviderCapability::Simple(true)),
-        inlay_hint_provider: Some(lsp_types::OneOf::Left(true)),
+        execute_command_provider: Some(ExecuteCommandOptions {
+            commands: vec![
+                FIX_ALL_COMMAND.to_string(),
+                RELOAD_WORKSPACE_COMMAND.to_string(),
+            ],
+            ..Default::default()
+        }),
+        inlay_hint_provider: Some(lsp_types::OneOf::Right(
+            lsp_types::InlayHintServerCapabilities::Options(lsp_types::InlayHintOptions {
+                resolve_provider: Some(true),
+                ..Default::default()
+            }),
+        )),
         workspace: Some(WorkspaceServerCapabilities {
             workspace_folders: Some(WorkspaceFoldersServerCapabilities {
                 supported: Some(true),
@@ -245,21 +1217,25 @@ fn initialize_result() -> InitializeResult {
             }),
             ..Default::default()
         }),
+        experimental: Some(json!({ "customRequests": [STATUS_METHOD] })),
         ..Default::default()
     };
 
     InitializeResult {
         capabilities,
-        server_info: None,
+        server_info: Some(ServerInfo {
+            name: "hitagi".to_string(),
+            version: Some(env!("CARGO_PKG_VERSION").to_string()),
+        }),
     }
 }
 
 #[allow(deprecated)]
 fn extract_root(params: &InitializeParams) -> Option<PathBuf> {
-    if let Some(root_uri) = &params.root_uri {
-        if let Some(path) = uri_to_path(root_uri) {
-            return Some(path);
-        }
+    if let Some(root_uri) = &params.root_uri
+        && let Some(path) = uri_to_path(root_uri)
+    {
+        return Some(path);
     }
 
     if let Some(root_path) = &params.root_path {
@@ -277,9 +1253,32 @@ fn extract_root(params: &InitializeParams) -> Option<PathBuf> {
     None
 }
 
+fn folder_roots(params: &InitializeParams) -> Vec<PathBuf> {
+    params
+        .workspace_folders
+        .iter()
+        .flatten()
+        .filter_map(|folder| uri_to_path(&folder.uri))
+        .collect()
+}
+
+/// The path of the document a request is about, read before its params are
+/// parsed so that it can be answered with the settings of the file's folder.
+fn request_path(value: &Value) -> Option<PathBuf> {
+    let uri = value.pointer("/params/textDocument/uri")?;
+    uri_to_path(&serde_json::from_value(uri.clone()).ok()?)
+}
+
 fn parse_params<T: serde::de::DeserializeOwned>(value: &Value) -> Result<T, String> {
     let params = value.get("params").cloned().unwrap_or(Value::Null);
-    serde_json::from_value(params).map_err(|err| err.to_string())
+    serde_json::from_value(params).map_err(|err| {
+        let method = value
+            .get("method")
+            .and_then(Value::as_str)
+            .unwrap_or("response");
+        log_warn!("invalid params for {method}: {err}");
+        err.to_string()
+    })
 }
 
 fn send_response(sender: &Sender<String>, id: Value, result: Value) {
@@ -291,6 +1290,19 @@ fn send_response(sender: &Sender<String>, id: Value, result: Value) {
     send_value(sender, response);
 }
 
+fn send_request(sender: &Sender<String>, id: Value, method: &str, params: Value) {
+    let mut request = json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": method,
+    });
+    // Requests without parameters, such as refreshes, leave them out.
+    if !params.is_null() {
+        request["params"] = params;
+    }
+    send_value(sender, request);
+}
+
 fn send_error(sender: &Sender<String>, id: Value, code: i32, message: &str) {
     let response = json!({
         "jsonrpc": "2.0",
@@ -300,37 +1312,270 @@ fn send_error(sender: &Sender<String>, id: Value, code: i32, message: &str) {
     send_value(sender, response);
 }
 
-fn publish_diagnostics(
-    sender: &Sender<String>,
-    open_urls: Vec<Uri>,
-    map: std::collections::HashMap<Uri, Vec<lsp_types::Diagnostic>>,
-) {
-    for uri in open_urls {
-        let diagnostics = map.get(&uri).cloned().unwrap_or_default();
-        let params = lsp_types::PublishDiagnosticsParams::new(uri, diagnostics, None);
+/// The message of a caught panic, for the error sent to the client.
+fn internal_error(panic: &(dyn Any + Send)) -> String {
+    let message = panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic");
+    format!("internal error: {message}")
+}
+
+/// Keeps the fixes of the diagnostics published for `uri` for
+/// `hitagi.fixAll`.
+fn record_fixes(fixes: &Fixes, uri: &Uri, diagnostics: &[Diagnostic], version: Option<i32>) {
+    let edits: Vec<TextEdit> = diagnostics.iter().flat_map(diagnostic_fixes).collect();
+    let mut fixes = fixes.lock().unwrap_or_else(PoisonError::into_inner);
+    if edits.is_empty() {
+        fixes.remove(&NormalizedUri::new(uri));
+    } else {
+        fixes.insert(NormalizedUri::new(uri), (version, edits));
+    }
+}
+
+/// The edit applying `fixes` to `uri`, leaving out those overlapping an
+/// earlier one. With a `version`, the client rejects the edit once the
+/// document changed since the fixes were computed.
+fn fix_all_edit(uri: Uri, version: Option<i32>, mut fixes: Vec<TextEdit>) -> WorkspaceEdit {
+    fixes.sort_by_key(|fix| (fix.range.start, fix.range.end));
+    fixes.dedup();
+    let mut end = None;
+    fixes.retain(|fix| {
+        let disjoint = end.is_none_or(|end| fix.range.start >= end);
+        if disjoint {
+            end = Some(fix.range.end);
+        }
+        disjoint
+    });
+    match version {
+        Some(version) => WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Edits(vec![TextDocumentEdit {
+                text_document: OptionalVersionedTextDocumentIdentifier {
+                    uri,
+                    version: Some(version),
+                },
+                edits: fixes.into_iter().map(OneOf::Left).collect(),
+            }])),
+            ..Default::default()
+        },
+        None => WorkspaceEdit {
+            changes: Some(HashMap::from([(uri, fixes)])),
+            ..Default::default()
+        },
+    }
+}
+
+/// Reports a task through `$/progress` from the thread running it, once it
+/// has run for `delay`, so that quick tasks show nothing.
+struct Progress {
+    sender: Sender<String>,
+    token: ProgressToken,
+    title: &'static str,
+    delay: Duration,
+    started: Instant,
+    /// The last percentage sent and when, once the progress began.
+    reported: Option<(u32, Instant)>,
+}
+
+impl Progress {
+    fn new(
+        sender: Sender<String>,
+        token: ProgressToken,
+        title: &'static str,
+        delay: Duration,
+    ) -> Self {
+        Progress {
+            sender,
+            token,
+            title,
+            delay,
+            started: Instant::now(),
+            reported: None,
+        }
+    }
+
+    /// Reports `done` out of `total` steps. The percentage only grows, and
+    /// is sent at most five times per `delay`.
+    fn report(&mut self, done: usize, total: usize) {
+        let now = Instant::now();
+        if now - self.started < self.delay {
+            return;
+        }
+        let percentage = (done * 100 / total.max(1)).min(100) as u32;
+        let value = match self.reported {
+            None => WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                title: self.title.to_string(),
+                cancellable: Some(false),
+                message: None,
+                percentage: Some(percentage),
+            }),
+            Some((last, at)) if percentage > last && now - at >= self.delay / 5 => {
+                WorkDoneProgress::Report(WorkDoneProgressReport {
+                    cancellable: Some(false),
+                    message: Some(format!("{done}/{total} files")),
+                    percentage: Some(percentage),
+                })
+            }
+            Some(_) => return,
+        };
+        self.send(value);
+        self.reported = Some((percentage, now));
+    }
+
+    /// Ends the progress with `message`, if it began.
+    fn end(self, message: String) {
+        if self.reported.is_some() {
+            self.send(WorkDoneProgress::End(WorkDoneProgressEnd {
+                message: Some(message),
+            }));
+        }
+    }
+
+    fn send(&self, value: WorkDoneProgress) {
+        let params = ProgressParams {
+            token: self.token.clone(),
+            value: ProgressParamsValue::WorkDone(value),
+        };
         let notification = json!({
             "jsonrpc": "2.0",
-            "method": notification::PublishDiagnostics::METHOD,
+            "method": notification::Progress::METHOD,
             "params": params,
         });
-        send_value(sender, notification);
+        send_value(&self.sender, notification);
+    }
+}
+
+/// Formats `n` with thousands separators, e.g. `4,210`.
+fn thousands(n: usize) -> String {
+    let digits = n.to_string();
+    let mut out = String::new();
+    for (idx, digit) in digits.chars().enumerate() {
+        if idx > 0 && (digits.len() - idx).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(digit);
     }
+    out
+}
+
+fn publish_diagnostics(
+    sender: &Sender<String>,
+    uri: Uri,
+    diagnostics: Vec<lsp_types::Diagnostic>,
+    version: Option<i32>,
+) {
+    let params = lsp_types::PublishDiagnosticsParams::new(uri, diagnostics, version);
+    let notification = json!({
+        "jsonrpc": "2.0",
+        "method": notification::PublishDiagnostics::METHOD,
+        "params": params,
+    });
+    send_value(sender, notification);
+}
+
+fn show_message(sender: &Sender<String>, typ: MessageType, message: String) {
+    let params = ShowMessageParams { typ, message };
+    let notification = json!({
+        "jsonrpc": "2.0",
+        "method": notification::ShowMessage::METHOD,
+        "params": params,
+    });
+    send_value(sender, notification);
 }
 
 fn send_value(sender: &Sender<String>, value: Value) {
     let text = match serde_json::to_string(&value) {
         Ok(text) => text,
         Err(err) => {
-            eprintln!("lsp: failed to serialize message: {err}");
+            log_error!("failed to serialize message: {err}");
             return;
         }
     };
-    let len = text.as_bytes().len();
+    let len = text.len();
     let message = format!("Content-Length: {}\r\n\r\n{}", len, text);
     let _ = sender.send(message);
 }
 
-fn read_message(reader: &mut BufReader<impl Read>) -> io::Result<Option<Value>> {
+/// A message read from the client, None once the input ended, or the error
+/// that stopped reading.
+type Input = io::Result<Option<Incoming>>;
+
+/// Reads messages into `queue` until the input ends or the main loop stops.
+fn reader_loop(mut reader: impl BufRead, max_message_size: &AtomicUsize, queue: Sender<Input>) {
+    loop {
+        let input = read_message(&mut reader, max_message_size.load(Ordering::Relaxed));
+        let end = !matches!(input, Ok(Some(_)));
+        if queue.send(input).is_err() || end {
+            return;
+        }
+    }
+}
+
+/// Merges `didChange` notifications for the same document that follow each
+/// other in `queued` into one, so that a burst of changes, like the full text
+/// sent on every keystroke, is applied once. Other messages, and changes to
+/// other documents in between, keep their order.
+fn coalesce_changes(queued: Vec<Input>) -> Vec<Input> {
+    fn changed_uri(input: &Input) -> Option<&Value> {
+        let Ok(Some(Incoming::Message(message))) = input else {
+            return None;
+        };
+        if message.get("method")?.as_str()? != notification::DidChangeTextDocument::METHOD
+            || message.get("id").is_some()
+        {
+            return None;
+        }
+        message["params"]["contentChanges"].as_array()?;
+        message["params"]["textDocument"].get("uri")
+    }
+
+    let mut merged: Vec<Input> = Vec::with_capacity(queued.len());
+    for input in queued {
+        let same_document = match (merged.last().and_then(changed_uri), changed_uri(&input)) {
+            (Some(last), Some(uri)) => last == uri,
+            _ => false,
+        };
+        if same_document
+            && let Some(Ok(Some(Incoming::Message(last)))) = merged.last_mut()
+            && let Ok(Some(Incoming::Message(mut next))) = input
+        {
+            let next = &mut next["params"];
+            last["params"]["textDocument"]["version"] = next["textDocument"]["version"].take();
+            if let (Some(changes), Some(more)) = (
+                last["params"]["contentChanges"].as_array_mut(),
+                next["contentChanges"].as_array_mut(),
+            ) {
+                changes.append(more);
+            }
+            continue;
+        }
+        merged.push(input);
+    }
+    merged
+}
+
+/// How much of a message that is too large is kept to look for its id.
+const SALVAGED_PREFIX: usize = 64 * 1024;
+
+/// A message read from the client.
+#[derive(Debug, PartialEq)]
+enum Incoming {
+    Message(Value),
+    /// A message that was skipped because it was too large, not JSON or not
+    /// a JSON object, with the ids of the requests found in it, so that they
+    /// can still be answered with the error `code`.
+    Invalid {
+        ids: Vec<Value>,
+        code: i32,
+        error: String,
+    },
+}
+
+/// Reads the next message, or None once the input ends, also in the middle
+/// of a message. Bodies larger than `max_len` bytes are skipped without being
+/// kept in memory.
+fn read_message(reader: &mut impl BufRead, max_len: usize) -> io::Result<Option<Incoming>> {
     let mut content_length: Option<usize> = None;
     let mut line = String::new();
 
@@ -343,10 +1588,10 @@ fn read_message(reader: &mut BufReader<impl Read>) -> io::Result<Option<Value>>
         if line.trim().is_empty() {
             break;
         }
-        if let Some((name, value)) = line.split_once(':') {
-            if name.trim().eq_ignore_ascii_case("Content-Length") {
-                content_length = value.trim().parse::<usize>().ok();
-            }
+        if let Some((name, value)) = line.split_once(':')
+            && name.trim().eq_ignore_ascii_case("Content-Length")
+        {
+            content_length = value.trim().parse::<usize>().ok();
         }
     }
 
@@ -355,17 +1600,143 @@ fn read_message(reader: &mut BufReader<impl Read>) -> io::Result<Option<Value>>
         None => return Ok(None),
     };
 
+    if length > max_len {
+        let mut prefix = Vec::new();
+        let mut body = reader.take(length as u64);
+        body.by_ref()
+            .take(SALVAGED_PREFIX as u64)
+            .read_to_end(&mut prefix)?;
+        let skipped = prefix.len() as u64 + io::copy(&mut body, &mut io::sink())?;
+        if skipped < length as u64 {
+            log_warn!("the input ended in the middle of a message");
+            return Ok(None);
+        }
+        return Ok(Some(Incoming::Invalid {
+            ids: salvage_id(&prefix).into_iter().collect(),
+            code: -32700,
+            error: format!(
+                "the message is {length} bytes, more than limits.maxMessageSize ({max_len} bytes)"
+            ),
+        }));
+    }
+
     let mut buf = vec![0u8; length];
-    reader.read_exact(&mut buf)?;
-    let value: Value = serde_json::from_slice(&buf)
-        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
-    Ok(Some(value))
+    if let Err(err) = reader.read_exact(&mut buf) {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            log_warn!("the input ended in the middle of a message");
+            return Ok(None);
+        }
+        return Err(err);
+    }
+    match serde_json::from_slice(&buf) {
+        Ok(value) => Ok(Some(check_object(value))),
+        Err(err) => Ok(Some(Incoming::Invalid {
+            ids: salvage_id(&buf).into_iter().collect(),
+            code: -32700,
+            error: format!("the message is not valid JSON: {err}"),
+        })),
+    }
+}
+
+/// Passes on a message that is a JSON object. Other values are skipped,
+/// answering the requests they hold: those of a batch, which LSP does not
+/// have, or the one in a string, e.g. a message encoded twice.
+fn check_object(value: Value) -> Incoming {
+    let request_id = |message: &Value| {
+        message.get("method")?;
+        message
+            .get("id")
+            .filter(|id| id.is_string() || id.is_number())
+            .cloned()
+    };
+    let (ids, error) = match value {
+        Value::Object(_) => return Incoming::Message(value),
+        Value::Array(messages) => (
+            messages.iter().filter_map(request_id).collect(),
+            "the message is an array, but batches are not supported".to_string(),
+        ),
+        Value::String(text) => (
+            serde_json::from_str(&text)
+                .ok()
+                .and_then(|message| request_id(&message))
+                .into_iter()
+                .collect(),
+            "the message is a string rather than an object; was it encoded twice?".to_string(),
+        ),
+        other => (Vec::new(), format!("the message is not an object: {other}")),
+    };
+    Incoming::Invalid {
+        ids,
+        code: -32600,
+        error,
+    }
 }
 
-fn writer_loop(receiver: mpsc::Receiver<String>) {
-    let stdout = io::stdout();
-    let mut writer = BufWriter::new(stdout.lock());
-    while let Ok(message) = receiver.recv() {
+/// Finds the top-level `"id"` of a message that could not be parsed, if it
+/// is a number or a string.
+fn salvage_id(body: &[u8]) -> Option<Value> {
+    let mut depth = 0usize;
+    let mut idx = 0;
+    while idx < body.len() {
+        match body[idx] {
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            b'"' => {
+                let end = string_end(body, idx)?;
+                if depth == 1 && &body[idx..=end] == b"\"id\"" {
+                    let rest = body[end + 1..].trim_ascii_start().strip_prefix(b":")?;
+                    return parse_id(rest.trim_ascii_start());
+                }
+                idx = end;
+            }
+            _ => {}
+        }
+        idx += 1;
+    }
+    None
+}
+
+/// The index of the quote closing the string that starts at `start`.
+fn string_end(text: &[u8], start: usize) -> Option<usize> {
+    let mut idx = start + 1;
+    while idx < text.len() {
+        match text[idx] {
+            b'\\' => idx += 2,
+            b'"' => return Some(idx),
+            _ => idx += 1,
+        }
+    }
+    None
+}
+
+fn parse_id(text: &[u8]) -> Option<Value> {
+    let len = if text.first() == Some(&b'"') {
+        string_end(text, 0)? + 1
+    } else {
+        text.iter()
+            .take_while(|b| b.is_ascii_digit() || matches!(b, b'-' | b'+' | b'.' | b'e' | b'E'))
+            .count()
+    };
+    let id: Value = serde_json::from_slice(&text[..len]).ok()?;
+    (id.is_string() || id.is_number()).then_some(id)
+}
+
+/// How often the writer checks for `stop` while no message arrives.
+const WRITER_POLL: Duration = Duration::from_millis(50);
+
+/// Writes messages until every sender is gone or `stop` is set, without
+/// writing the messages still queued at that point.
+fn writer_loop(receiver: mpsc::Receiver<String>, output: impl Write, stop: &AtomicBool) {
+    let mut writer = BufWriter::new(output);
+    while !stop.load(Ordering::SeqCst) {
+        let message = match receiver.recv_timeout(WRITER_POLL) {
+            Ok(message) => message,
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
         if writer.write_all(message.as_bytes()).is_err() {
             break;
         }
@@ -374,3 +1745,978 @@ fn writer_loop(receiver: mpsc::Receiver<String>) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    fn notify(state: &mut State, method: &str, params: Value) {
+        state.handle_message(json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+
+    #[test]
+    fn requests_are_answered_before_shutdown() {
+        let (tx, rx) = mpsc::channel();
+        let mut state = State::new(tx);
+        let uri = "file:///project/src/main.rs";
+        notify(
+            &mut state,
+            notification::DidOpenTextDocument::METHOD,
+            json!({ "textDocument": {
+                "uri": uri, "languageId": "rust", "version": 1,
+                "text": "fn foo(a: i32) {}\nfn main() { foo(1); }",
+            } }),
+        );
+        let range =
+            json!({ "start": { "line": 0, "character": 0 }, "end": { "line": 2, "character": 0 } });
+        for id in 1..=3 {
+            state.handle_message(json!({
+                "jsonrpc": "2.0", "id": id, "method": request::InlayHintRequest::METHOD,
+                "params": { "textDocument": { "uri": uri }, "range": range },
+            }));
+        }
+        state.handle_message(
+            json!({ "jsonrpc": "2.0", "id": 4, "method": request::Shutdown::METHOD }),
+        );
+
+        let responses = responses(&rx);
+        assert_eq!(responses.len(), 4);
+        // Responses to requests may arrive in any order, but all before the
+        // shutdown response.
+        let mut ids: Vec<i64> = responses[..3]
+            .iter()
+            .map(|r| r["id"].as_i64().unwrap())
+            .collect();
+        ids.sort();
+        assert_eq!(ids, [1, 2, 3]);
+        assert!(
+            responses[..3]
+                .iter()
+                .all(|r| r["result"][0]["label"] == "a:")
+        );
+        assert_eq!(responses[3]["id"], 4);
+    }
+
+    #[test]
+    fn panicking_requests_are_answered_with_an_internal_error() {
+        let (tx, rx) = mpsc::channel();
+        let mut state = State::new(tx);
+        let uri = "file:///project/src/main.rs";
+        notify(
+            &mut state,
+            notification::DidOpenTextDocument::METHOD,
+            json!({ "textDocument": {
+                "uri": uri, "languageId": "rust", "version": 1,
+                "text": "fn foo(a: i32) {}\nfn main() { foo(1); }",
+            } }),
+        );
+        state.respond_later("test/panic", json!(1), || -> Result<(), String> {
+            panic!("boom")
+        });
+        state.workers.drain();
+        let failed = responses(&rx);
+        assert_eq!(failed[0]["id"], 1);
+        assert_eq!(failed[0]["error"]["code"], -32603);
+        assert_eq!(failed[0]["error"]["message"], "internal error: boom");
+
+        // The server keeps answering requests afterwards.
+        let range =
+            json!({ "start": { "line": 0, "character": 0 }, "end": { "line": 2, "character": 0 } });
+        state.handle_message(json!({
+            "jsonrpc": "2.0", "id": 2, "method": request::InlayHintRequest::METHOD,
+            "params": { "textDocument": { "uri": uri }, "range": range },
+        }));
+        state.workers.drain();
+        let responses = responses(&rx);
+        assert_eq!(responses[0]["id"], 2);
+        assert_eq!(responses[0]["result"][0]["label"], "a:");
+    }
+
+    fn responses(rx: &mpsc::Receiver<String>) -> Vec<Value> {
+        rx.try_iter()
+            .map(|message| serde_json::from_str(message.split_once("\r\n\r\n").unwrap().1).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn requests_after_shutdown_are_rejected_and_exit_succeeds() {
+        let (tx, rx) = mpsc::channel();
+        let mut state = State::new(tx);
+        let shutdown = json!({ "jsonrpc": "2.0", "id": 1, "method": request::Shutdown::METHOD });
+        assert_eq!(state.handle_message(shutdown), None);
+        let uri = "file:///project/src/main.rs";
+        notify(
+            &mut state,
+            notification::DidOpenTextDocument::METHOD,
+            json!({ "textDocument": {
+                "uri": uri, "languageId": "rust", "version": 1, "text": "fn main() {}",
+            } }),
+        );
+        let hover = json!({
+            "jsonrpc": "2.0", "id": 2, "method": request::HoverRequest::METHOD,
+            "params": { "textDocument": { "uri": uri }, "position": { "line": 0, "character": 3 } },
+        });
+        assert_eq!(state.handle_message(hover), None);
+
+        let responses = responses(&rx);
+        assert_eq!(responses.len(), 2, "{responses:?}");
+        assert_eq!(responses[0]["id"], 1);
+        assert_eq!(responses[0]["result"], Value::Null);
+        assert_eq!(responses[1]["id"], 2);
+        assert_eq!(responses[1]["error"]["code"], -32600);
+        // Notifications after the shutdown are ignored.
+        assert!(
+            state
+                .docs
+                .snapshot()
+                .get(&Uri::from_str(uri).unwrap())
+                .is_none()
+        );
+
+        let exit = json!({ "jsonrpc": "2.0", "method": notification::Exit::METHOD });
+        assert_eq!(state.handle_message(exit), Some(0));
+    }
+
+    #[test]
+    fn exit_without_shutdown_fails() {
+        let (tx, rx) = mpsc::channel();
+        let mut state = State::new(tx);
+        let exit = json!({ "jsonrpc": "2.0", "method": notification::Exit::METHOD });
+        assert_eq!(state.handle_message(exit), Some(1));
+        assert!(responses(&rx).is_empty());
+    }
+
+    #[test]
+    fn the_server_reports_its_version_and_status() {
+        let (tx, rx) = mpsc::channel();
+        let mut state = State::new(tx);
+        state.handle_message(json!({
+            "jsonrpc": "2.0", "id": 1, "method": request::Initialize::METHOD,
+            "params": {
+                "capabilities": {},
+                "rootUri": "file:///project",
+                "workspaceFolders": [
+                    { "uri": "file:///project", "name": "project" },
+                    { "uri": "file:///vendor/tools", "name": "tools" },
+                ],
+            },
+        }));
+        let initialized = &responses(&rx)[0]["result"];
+        assert_eq!(initialized["serverInfo"]["name"], "hitagi");
+        assert_eq!(
+            initialized["serverInfo"]["version"],
+            env!("CARGO_PKG_VERSION")
+        );
+        assert_eq!(
+            initialized["capabilities"]["experimental"]["customRequests"],
+            json!([STATUS_METHOD])
+        );
+
+        notify(
+            &mut state,
+            notification::DidOpenTextDocument::METHOD,
+            json!({ "textDocument": {
+                "uri": "file:///project/src/main.rs", "languageId": "rust", "version": 1,
+                "text": "fn main() {}",
+            } }),
+        );
+        state.handle_message(json!({ "jsonrpc": "2.0", "id": 2, "method": STATUS_METHOD }));
+        let status = &responses(&rx)[0]["result"];
+        assert_eq!(status["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(status["roots"], json!(["/project", "/vendor/tools"]));
+        assert_eq!(status["openDocuments"], 1);
+        assert_eq!(status["lastCheck"], Value::Null);
+    }
+
+    #[test]
+    fn only_rust_files_outside_a_cargo_project_are_checked_on_their_own() {
+        let dir = temp_dir("standalone-save");
+        let notes = dir.join("notes.txt");
+        let script = dir.join("script");
+        for path in [&notes, &script] {
+            std::fs::write(path, "fn main() {}\n").unwrap();
+        }
+        let (tx, _rx) = mpsc::channel();
+        let mut state = State::new(tx);
+        state.handle_message(json!({
+            "jsonrpc": "2.0", "id": 1, "method": request::Initialize::METHOD,
+            "params": { "capabilities": {} },
+        }));
+        notify(&mut state, notification::Initialized::METHOD, json!({}));
+        for (path, language) in [(&notes, "plaintext"), (&script, "rust")] {
+            notify(
+                &mut state,
+                notification::DidOpenTextDocument::METHOD,
+                json!({ "textDocument": {
+                    "uri": path_to_uri(path).unwrap(), "languageId": language, "version": 1,
+                    "text": "fn main() {}\n",
+                } }),
+            );
+        }
+
+        notify(
+            &mut state,
+            notification::DidSaveTextDocument::METHOD,
+            json!({ "textDocument": { "uri": path_to_uri(&notes).unwrap() } }),
+        );
+        assert!(state.checks.is_idle());
+        assert!(state.check_job(Some(&dir.join("README.md"))).is_none());
+        assert!(state.check_job(Some(&script)).is_some());
+        assert!(state.check_job(Some(&dir.join("scratch.rs"))).is_some());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn bursts_of_changes_are_applied_once() {
+        let change = |uri: &str, version: i32, change: Value| -> Input {
+            Ok(Some(Incoming::Message(json!({
+                "jsonrpc": "2.0",
+                "method": notification::DidChangeTextDocument::METHOD,
+                "params": {
+                    "textDocument": { "uri": uri, "version": version },
+                    "contentChanges": [change],
+                },
+            }))))
+        };
+        let full = |text: String| json!({ "text": text });
+        let (a, b) = ("file:///project/src/a.rs", "file:///project/src/b.rs");
+        let mut queued = Vec::new();
+        for version in 2..=21 {
+            queued.push(change(a, version, full(format!("fn a{version}() {{}}"))));
+        }
+        let end = json!({ "start": { "line": 0, "character": 13 }, "end": { "line": 0, "character": 13 } });
+        queued.push(change(a, 22, json!({ "range": end, "text": "\n// typed" })));
+        queued.push(Ok(Some(Incoming::Message(json!({
+            "jsonrpc": "2.0", "id": 1, "method": request::HoverRequest::METHOD,
+            "params": { "textDocument": { "uri": a }, "position": { "line": 0, "character": 3 } },
+        })))));
+        for version in 23..=27 {
+            queued.push(change(a, version, full(format!("fn b{version}() {{}}"))));
+        }
+        queued.push(change(b, 2, full("fn b() {}".to_string())));
+        queued.push(change(a, 28, json!({ "range": end, "text": "!" })));
+
+        let is_change = |input: &Input| {
+            matches!(input, Ok(Some(Incoming::Message(message)))
+                if message["method"] == notification::DidChangeTextDocument::METHOD)
+        };
+        let apply = |queued: Vec<Input>| {
+            let (tx, _rx) = mpsc::channel();
+            let mut state = State::new(tx);
+            for uri in [a, b] {
+                notify(
+                    &mut state,
+                    notification::DidOpenTextDocument::METHOD,
+                    json!({ "textDocument": {
+                        "uri": uri, "languageId": "rust", "version": 1, "text": "",
+                    } }),
+                );
+            }
+            let changes = queued.iter().filter(|input| is_change(input)).count();
+            for input in queued {
+                if let Ok(Some(Incoming::Message(message))) = input {
+                    state.handle_message(message);
+                }
+            }
+            let docs = state.docs.snapshot();
+            let docs = [a, b].map(|uri| {
+                let doc = docs.get(&Uri::from_str(uri).unwrap()).unwrap();
+                (doc.text().to_string(), doc.version)
+            });
+            (changes, docs)
+        };
+
+        let (changes, expected) = apply(queued.iter().map(clone_input).collect());
+        assert_eq!(changes, 28);
+        let (coalesced, docs) = apply(coalesce_changes(queued));
+        // The changes to `a` before the hover, those after it, the change to
+        // `b`, and the last change to `a`, which follows it.
+        assert_eq!(coalesced, 4);
+        assert_eq!(docs, expected);
+        assert_eq!(docs[0], ("fn b27() {}!".to_string(), 28));
+        assert_eq!(docs[1], ("fn b() {}".to_string(), 2));
+    }
+
+    fn clone_input(input: &Input) -> Input {
+        match input {
+            Ok(Some(Incoming::Message(message))) => Ok(Some(Incoming::Message(message.clone()))),
+            _ => unreachable!("only messages are queued"),
+        }
+    }
+
+    #[test]
+    fn requests_to_the_client_are_matched_with_its_responses() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let (tx, rx) = mpsc::channel();
+        let mut state = State::new(tx);
+        let results = Rc::new(RefCell::new(Vec::new()));
+        for section in ["hitagi", "other"] {
+            let results = Rc::clone(&results);
+            state.send_request::<request::WorkspaceConfiguration>(
+                lsp_types::ConfigurationParams {
+                    items: vec![lsp_types::ConfigurationItem {
+                        scope_uri: None,
+                        section: Some(section.to_string()),
+                    }],
+                },
+                move |_, result| results.borrow_mut().push((section, result)),
+            );
+        }
+
+        // A fake client answers the second request, then the first.
+        let sent = responses(&rx);
+        assert_eq!(sent.len(), 2);
+        assert!(
+            sent.iter()
+                .all(|r| r["method"] == "workspace/configuration")
+        );
+        assert_ne!(sent[0]["id"], sent[1]["id"]);
+        let answers = [
+            json!({ "jsonrpc": "2.0", "id": sent[1]["id"], "error": { "code": -32601, "message": "unsupported" } }),
+            json!({ "jsonrpc": "2.0", "id": sent[0]["id"], "result": [{ "logLevel": "debug" }] }),
+            // Unknown ids are ignored.
+            json!({ "jsonrpc": "2.0", "id": 999, "result": null }),
+        ];
+        for answer in answers {
+            assert_eq!(state.handle_message(answer), None);
+        }
+        let results = results.borrow();
+        assert_eq!(results[0].0, "other");
+        assert_eq!(results[0].1.as_ref().unwrap_err().message, "unsupported");
+        assert_eq!(results[1].0, "hitagi");
+        assert_eq!(
+            results[1].1.as_ref().unwrap(),
+            &vec![json!({ "logLevel": "debug" })]
+        );
+        assert!(responses(&rx).is_empty());
+    }
+
+    #[test]
+    fn unanswered_requests_to_the_client_fail() {
+        let (tx, rx) = mpsc::channel();
+        let mut state = State::new(tx);
+        state.outgoing = OutgoingRequests::new(Duration::ZERO);
+        let failed = std::rc::Rc::new(std::cell::Cell::new(false));
+        let flag = std::rc::Rc::clone(&failed);
+        state.send_request::<request::WorkDoneProgressCreate>(
+            lsp_types::WorkDoneProgressCreateParams {
+                token: lsp_types::NumberOrString::Number(1),
+            },
+            move |_, result| flag.set(result.is_err()),
+        );
+        let id = responses(&rx)[0]["id"].clone();
+        state.expire_requests();
+        assert!(failed.get());
+
+        // A late answer finds nothing waiting.
+        state.handle_message(json!({ "jsonrpc": "2.0", "id": id, "result": null }));
+    }
+
+    fn initialize(state: &mut State, root: &Path, capabilities: Value) {
+        state.handle_message(json!({
+            "jsonrpc": "2.0", "id": 1, "method": request::Initialize::METHOD,
+            "params": { "rootUri": format!("file://{}", root.display()), "capabilities": capabilities },
+        }));
+        notify(state, notification::Initialized::METHOD, json!({}));
+    }
+
+    #[test]
+    fn indexing_reports_progress_to_clients_that_support_it() {
+        let root = temp_dir("progress");
+        for module in 0..4 {
+            let dir = root.join(format!("src/m{module}"));
+            std::fs::create_dir_all(&dir).unwrap();
+            for file in 0..5 {
+                std::fs::write(
+                    dir.join(format!("f{file}.rs")),
+                    format!("fn f{file}() {{}}\n"),
+                )
+                .unwrap();
+            }
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let mut state = State::new(tx);
+        state.progress_delay = Duration::ZERO;
+        initialize(
+            &mut state,
+            &root,
+            json!({ "window": { "workDoneProgress": true } }),
+        );
+        let sent = responses(&rx);
+        let create = sent
+            .iter()
+            .find(|message| message["method"] == request::WorkDoneProgressCreate::METHOD)
+            .unwrap();
+        let token = create["params"]["token"].clone();
+        // Nothing is indexed before the fake client accepts the token.
+        state.workers.drain();
+        assert!(responses(&rx).is_empty());
+        state.handle_message(json!({ "jsonrpc": "2.0", "id": create["id"], "result": null }));
+        state.workers.drain();
+
+        let progress: Vec<Value> = responses(&rx)
+            .into_iter()
+            .filter(|message| message["method"] == notification::Progress::METHOD)
+            .inspect(|message| assert_eq!(message["params"]["token"], token))
+            .map(|message| message["params"]["value"].clone())
+            .collect();
+        let kinds: Vec<&str> = progress
+            .iter()
+            .map(|value| value["kind"].as_str().unwrap())
+            .collect();
+        assert_eq!(kinds.first(), Some(&"begin"));
+        assert_eq!(kinds.last(), Some(&"end"));
+        assert_eq!(kinds.iter().filter(|kind| **kind == "begin").count(), 1);
+        assert_eq!(kinds.iter().filter(|kind| **kind == "end").count(), 1);
+        let percentages: Vec<u64> = progress
+            .iter()
+            .filter_map(|value| value["percentage"].as_u64())
+            .collect();
+        assert!(percentages.len() >= 2, "{progress:?}");
+        assert!(percentages.is_sorted(), "{percentages:?}");
+        assert_eq!(
+            progress.last().unwrap()["message"],
+            "indexed 20 files, 20 definitions"
+        );
+
+        // Reloading the workspace builds the index again, with progress.
+        std::fs::write(root.join("src/added.rs"), "fn added() {}\n").unwrap();
+        state.handle_message(json!({
+            "jsonrpc": "2.0", "id": 2, "method": request::ExecuteCommand::METHOD,
+            "params": { "command": RELOAD_WORKSPACE_COMMAND, "arguments": [] },
+        }));
+        let sent = responses(&rx);
+        assert!(sent.iter().any(|message| message["id"] == 2
+            && message["result"].is_null()
+            && message["error"].is_null()));
+        let create = sent
+            .iter()
+            .find(|message| message["method"] == request::WorkDoneProgressCreate::METHOD)
+            .unwrap();
+        state.handle_message(json!({ "jsonrpc": "2.0", "id": create["id"], "result": null }));
+        state.workers.drain();
+        let ends: Vec<Value> = responses(&rx)
+            .into_iter()
+            .filter(|message| message["method"] == notification::Progress::METHOD)
+            .map(|message| message["params"]["value"].clone())
+            .filter(|value| value["kind"] == "end")
+            .collect();
+        assert_eq!(ends.len(), 1);
+        assert_eq!(ends[0]["message"], "indexed 21 files, 21 definitions");
+
+        // Without progress support the index is built silently.
+        let (tx, rx) = mpsc::channel();
+        let mut state = State::new(tx);
+        state.progress_delay = Duration::ZERO;
+        initialize(&mut state, &root, json!({}));
+        state.workers.drain();
+        assert!(responses(&rx).iter().all(|message| message["method"]
+            != notification::Progress::METHOD
+            && message["method"] != request::WorkDoneProgressCreate::METHOD));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn large_counts_are_grouped_in_thousands() {
+        assert_eq!(thousands(0), "0");
+        assert_eq!(thousands(312), "312");
+        assert_eq!(thousands(4210), "4,210");
+        assert_eq!(thousands(1234567), "1,234,567");
+    }
+
+    #[test]
+    fn open_files_only_mode_reads_nothing_from_disk() {
+        let root = temp_dir("mode");
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        let helper = root.join("src/helper.rs");
+        std::fs::write(&helper, "pub fn assist(n: u8) {}\n").unwrap();
+        let uri = format!("file://{}/src/main.rs", root.display());
+
+        let (tx, rx) = mpsc::channel();
+        let mut state = State::new(tx);
+        let configure = |state: &mut State, mode: &str| {
+            notify(
+                state,
+                notification::DidChangeConfiguration::METHOD,
+                json!({ "settings": { "hitagi": { "workspaceMode": mode } } }),
+            );
+            state.workers.drain();
+        };
+        configure(&mut state, "openFilesOnly");
+        initialize(&mut state, &root, json!({}));
+        notify(
+            &mut state,
+            notification::DidOpenTextDocument::METHOD,
+            json!({ "textDocument": {
+                "uri": uri, "languageId": "rust", "version": 1,
+                "text": "mod helper;\nfn main() { helper::assist(1); }\n",
+            } }),
+        );
+        let hover = |state: &mut State, id: i64| {
+            state.handle_message(json!({
+                "jsonrpc": "2.0", "id": id, "method": request::HoverRequest::METHOD,
+                "params": { "textDocument": { "uri": uri }, "position": { "line": 1, "character": 22 } },
+            }));
+            state.workers.drain();
+            responses(&rx)
+                .into_iter()
+                .find(|message| message["id"] == id)
+                .unwrap()["result"]
+                .clone()
+        };
+        assert_eq!(hover(&mut state, 2), Value::Null);
+        assert!(!file_cache::contains(&helper));
+
+        configure(&mut state, "workspace");
+        let found = hover(&mut state, 3);
+        assert!(
+            found["contents"]["value"]
+                .as_str()
+                .unwrap()
+                .contains("pub fn assist(n: u8)"),
+            "{found}"
+        );
+        assert!(file_cache::contains(&helper));
+
+        // Going back drops what was read.
+        configure(&mut state, "openFilesOnly");
+        assert!(!file_cache::contains(&helper));
+        assert_eq!(hover(&mut state, 4), Value::Null);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn initialization_options_apply_until_the_configuration_changes() {
+        let (tx, _rx) = mpsc::channel();
+        let mut state = State::new(tx);
+        state.handle_message(json!({
+            "jsonrpc": "2.0", "id": 1, "method": request::Initialize::METHOD,
+            "params": {
+                "capabilities": {},
+                "initializationOptions": { "hitagi": {
+                    "checkCommand": ["cargo", "clippy", "--message-format=json"],
+                    "checkOnSave": false,
+                } },
+            },
+        }));
+        assert_eq!(
+            state.config.check.command.as_deref(),
+            Some(&["cargo", "clippy", "--message-format=json"].map(String::from)[..])
+        );
+        assert!(!state.config.check.on_save);
+
+        notify(
+            &mut state,
+            notification::DidChangeConfiguration::METHOD,
+            json!({ "settings": { "hitagi": { "checkOnSave": true } } }),
+        );
+        assert!(state.config.check.on_save);
+        // Settings the change leaves out keep their initial value.
+        assert!(state.config.check.command.is_some());
+    }
+
+    #[test]
+    fn project_settings_are_overridden_by_the_editor() {
+        let root = temp_dir("project");
+        let file = root.join("hitagi.toml");
+        std::fs::write(
+            &file,
+            "checkOnSave = false\ncheckCommand = [\"from-file\"]\n[limits]\nmaxMessageSize = 1000\n",
+        )
+        .unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let mut state = State::new(tx);
+        state.handle_message(json!({
+            "jsonrpc": "2.0", "id": 1, "method": request::Initialize::METHOD,
+            "params": {
+                "rootUri": format!("file://{}", root.display()),
+                "capabilities": {},
+                "initializationOptions": { "checkCommand": ["from-options"] },
+            },
+        }));
+        let command = |state: &State| state.config.check.command.clone().unwrap()[0].clone();
+        assert_eq!(command(&state), "from-options");
+        assert!(!state.config.check.on_save);
+        assert_eq!(state.config.limits.max_message_size, 1000);
+
+        notify(
+            &mut state,
+            notification::DidChangeConfiguration::METHOD,
+            json!({ "settings": { "hitagi": { "checkOnSave": true } } }),
+        );
+        assert!(state.config.check.on_save);
+        assert_eq!(command(&state), "from-options");
+
+        // A changed file is read again, and the editor still wins.
+        std::fs::write(
+            &file,
+            "checkOnSave = false\n[limits]\nmaxMessageSize = 2000\n",
+        )
+        .unwrap();
+        let changed = json!({ "changes": [{
+            "uri": format!("file://{}", file.display()), "type": 2,
+        }] });
+        notify(
+            &mut state,
+            notification::DidChangeWatchedFiles::METHOD,
+            changed.clone(),
+        );
+        assert_eq!(state.config.limits.max_message_size, 2000);
+        assert!(state.config.check.on_save);
+
+        // A broken file is reported and leaves the settings as they were.
+        responses(&rx);
+        std::fs::write(&file, "[limits]\nmaxMessageSize = lots\n").unwrap();
+        notify(
+            &mut state,
+            notification::DidChangeWatchedFiles::METHOD,
+            changed,
+        );
+        assert_eq!(state.config.limits.max_message_size, 2000);
+        let sent = responses(&rx);
+        let message = sent[0]["params"]["message"].as_str().unwrap();
+        assert!(
+            message.contains("line 2: invalid value `lots` in the value of `maxMessageSize`"),
+            "{message}"
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn environment_variables_override_every_other_setting() {
+        let (tx, rx) = mpsc::channel();
+        let mut state = State::new(tx);
+        state.env_settings = EnvSettings::from_vars(|name| {
+            let value = match name {
+                "HITAGI_CHECK_COMMAND" => "cargo clippy --features 'a b'",
+                "HITAGI_CHECK_ON_SAVE" => "false",
+                "HITAGI_WORKSPACE_MODE" => "everything",
+                _ => return None,
+            };
+            Some(value.to_string())
+        });
+        state.handle_message(json!({
+            "jsonrpc": "2.0", "id": 1, "method": request::Initialize::METHOD,
+            "params": {
+                "capabilities": {},
+                "initializationOptions": {
+                    "checkOnSave": true,
+                    "checkCommand": ["from-options"],
+                    "logLevel": "info",
+                },
+            },
+        }));
+        notify(
+            &mut state,
+            notification::DidChangeConfiguration::METHOD,
+            json!({ "settings": { "hitagi": { "check": { "onSave": true } } } }),
+        );
+        assert!(!state.config.check.on_save);
+        assert_eq!(
+            state.config.check.command.as_deref().unwrap(),
+            ["cargo", "clippy", "--features", "a b"]
+        );
+        // Settings the environment leaves alone still come from the editor.
+        assert!(matches!(
+            state.config.log_level,
+            crate::config::LogLevel::Info
+        ));
+        let sent = responses(&rx);
+        let shown: Vec<&Value> = sent
+            .iter()
+            .filter(|message| message["method"] == notification::ShowMessage::METHOD)
+            .collect();
+        assert_eq!(shown.len(), 1);
+        let message = shown[0]["params"]["message"].as_str().unwrap();
+        assert!(
+            message.contains(
+                "`workspace.mode` should be one of `openFilesOnly`, `workspace`, not \"everything\" \
+                 (in the environment)"
+            ),
+            "{message}"
+        );
+
+        state.handle_message(json!({ "jsonrpc": "2.0", "id": 2, "method": STATUS_METHOD }));
+        let settings = &responses(&rx)[0]["result"]["settings"];
+        assert_eq!(
+            settings["precedence"],
+            json!(["defaults", "hitagi.toml", "editor", "environment"])
+        );
+        assert_eq!(settings["environment"]["HITAGI_CHECK_ON_SAVE"], "false");
+        assert!(settings["environment"].get("HITAGI_LOG_LEVEL").is_none());
+    }
+
+    #[test]
+    fn ignored_settings_are_reported_once() {
+        let (tx, rx) = mpsc::channel();
+        let mut state = State::new(tx);
+        let mut configure = |settings: Value| {
+            notify(
+                &mut state,
+                notification::DidChangeConfiguration::METHOD,
+                json!({ "settings": { "hitagi": settings } }),
+            );
+            responses(&rx)
+        };
+        let sent = configure(json!({ "checkComand": ["cargo", "clippy"], "checkOnSave": "no" }));
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0]["method"], notification::ShowMessage::METHOD);
+        assert_eq!(
+            sent[0]["params"]["message"],
+            "hitagi: some settings were ignored:\n\
+             - `checkOnSave` should be `true` or `false`, not \"no\"\n\
+             - `checkComand` is not a setting"
+        );
+        // Other changes that leave the problems as they were are quiet.
+        assert!(configure(json!({ "logLevel": "warn" })).is_empty());
+        assert_eq!(configure(json!({ "checkOnSave": true })).len(), 1);
+    }
+
+    #[test]
+    fn hint_settings_changes_refresh_the_inlay_hints() {
+        for refresh_support in [true, false] {
+            let (tx, rx) = mpsc::channel();
+            let mut state = State::new(tx);
+            state.handle_message(json!({
+                "jsonrpc": "2.0", "id": 1, "method": request::Initialize::METHOD,
+                "params": { "capabilities": {
+                    "workspace": { "inlayHint": { "refreshSupport": refresh_support } },
+                } },
+            }));
+            responses(&rx);
+            let mut configure = |settings: Value| {
+                notify(
+                    &mut state,
+                    notification::DidChangeConfiguration::METHOD,
+                    json!({ "settings": { "hitagi": settings } }),
+                );
+                responses(&rx)
+            };
+            let sent = configure(json!({ "inlayHints": { "hideObviousTypes": false } }));
+            if refresh_support {
+                assert_eq!(sent.len(), 1);
+                assert_eq!(sent[0]["method"], request::InlayHintRefreshRequest::METHOD);
+                assert!(sent[0].get("params").is_none());
+            } else {
+                assert!(sent.is_empty());
+            }
+            // Neither settings the hints do not depend on nor the same
+            // values again ask for a refresh.
+            assert!(configure(json!({ "logLevel": "warn" })).is_empty());
+            assert!(configure(json!({ "inlayHints": { "hideObviousTypes": false } })).is_empty());
+        }
+    }
+
+    #[test]
+    fn rejected_fixes_are_reported_to_the_user() {
+        let (tx, rx) = mpsc::channel();
+        let mut state = State::new(tx);
+        state.handle_message(json!({
+            "jsonrpc": "2.0", "id": 1, "method": request::Initialize::METHOD,
+            "params": { "capabilities": {
+                "workspace": { "applyEdit": true, "workspaceEdit": { "documentChanges": true } },
+            } },
+        }));
+        let uri: Uri = "file:///project/src/main.rs".parse().unwrap();
+        let fix = |line, start, end, text: &str| {
+            json!({
+                "range": {
+                    "start": { "line": line, "character": start },
+                    "end": { "line": line, "character": end },
+                },
+                "newText": text,
+            })
+        };
+        let diagnostic = |fixes| Diagnostic {
+            data: Some(json!({ "fixes": fixes })),
+            ..Diagnostic::default()
+        };
+        record_fixes(
+            &state.fixes,
+            &uri,
+            &[
+                diagnostic(json!([fix(3, 8, 12, "")])),
+                // Overlaps the first fix, so it is left out.
+                diagnostic(json!([fix(3, 10, 14, "x"), fix(1, 4, 4, "_")])),
+            ],
+            Some(7),
+        );
+        responses(&rx);
+
+        let execute = |id, arguments| {
+            json!({
+                "jsonrpc": "2.0", "id": id, "method": request::ExecuteCommand::METHOD,
+                "params": { "command": FIX_ALL_COMMAND, "arguments": arguments },
+            })
+        };
+        state.handle_message(execute(2, json!([uri.as_str()])));
+        let sent = responses(&rx);
+        assert_eq!(sent[0]["method"], request::ApplyWorkspaceEdit::METHOD);
+        let change = &sent[0]["params"]["edit"]["documentChanges"][0];
+        assert_eq!(change["textDocument"]["version"], 7);
+        assert_eq!(
+            change["edits"],
+            json!([fix(1, 4, 4, "_"), fix(3, 8, 12, "")])
+        );
+        assert_eq!(
+            sent[1],
+            json!({ "jsonrpc": "2.0", "id": 2, "result": null })
+        );
+
+        // The fake client rejects the edit.
+        state.handle_message(json!({
+            "jsonrpc": "2.0", "id": sent[0]["id"],
+            "result": { "applied": false, "failureReason": "the document changed" },
+        }));
+        let sent = responses(&rx);
+        assert_eq!(sent[0]["method"], notification::ShowMessage::METHOD);
+        assert_eq!(sent[0]["params"]["type"], 1);
+        assert_eq!(
+            sent[0]["params"]["message"],
+            "hitagi: could not apply fix all: the document changed"
+        );
+
+        state.handle_message(execute(3, json!([])));
+        assert_eq!(responses(&rx)[0]["error"]["code"], -32602);
+    }
+
+    fn frame(body: &str) -> String {
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    #[test]
+    fn oversized_and_invalid_messages_are_skipped() {
+        let big = format!(
+            r#"{{"jsonrpc":"2.0","id":7,"method":"textDocument/hover","params":"{}"}}"#,
+            "x".repeat(200)
+        );
+        let input = [
+            frame(&big),
+            frame(r#"{"jsonrpc":"2.0","params":{"id":1},"id":"a\"b","method":"#),
+            frame(r#"{"jsonrpc":"2.0","params":{"id":1},"method":"#),
+            frame(r#"{"jsonrpc":"2.0","method":"initialized"}"#),
+        ]
+        .concat();
+        let mut reader = io::Cursor::new(input);
+        let mut next = || read_message(&mut reader, 100).unwrap();
+
+        assert!(matches!(
+            next(),
+            Some(Incoming::Invalid { ids, code: -32700, error }) if ids == [7] && error.contains("maxMessageSize")
+        ));
+        assert!(matches!(
+            next(),
+            Some(Incoming::Invalid { ids, .. }) if ids == ["a\"b"]
+        ));
+        // An id nested in the params is not the message's.
+        assert!(matches!(next(), Some(Incoming::Invalid { ids, .. }) if ids.is_empty()));
+        // Reading continues with the next message.
+        assert_eq!(
+            next(),
+            Some(Incoming::Message(
+                json!({ "jsonrpc": "2.0", "method": "initialized" })
+            ))
+        );
+        assert_eq!(next(), None);
+    }
+
+    #[test]
+    fn messages_that_are_not_objects_are_skipped() {
+        let request = r#"{"jsonrpc":"2.0","id":3,"method":"shutdown"}"#;
+        let input = [
+            frame(&format!(
+                r#"[{request},{{"jsonrpc":"2.0","method":"initialized"}}]"#
+            )),
+            frame(&serde_json::to_string(request).unwrap()),
+            frame("42"),
+            frame(r#""hello""#),
+        ]
+        .concat();
+        let mut reader = io::Cursor::new(input);
+        let mut next = || read_message(&mut reader, 1000).unwrap();
+
+        // Only the requests in a batch are answered.
+        assert!(matches!(
+            next(),
+            Some(Incoming::Invalid { ids, code: -32600, error }) if ids == [3] && error.contains("batches")
+        ));
+        assert!(matches!(
+            next(),
+            Some(Incoming::Invalid { ids, error, .. }) if ids == [3] && error.contains("encoded twice")
+        ));
+        assert!(matches!(next(), Some(Incoming::Invalid { ids, .. }) if ids.is_empty()));
+        assert!(matches!(next(), Some(Incoming::Invalid { ids, .. }) if ids.is_empty()));
+        assert_eq!(next(), None);
+    }
+
+    #[test]
+    fn truncated_messages_end_the_input() {
+        let body = r#"{"jsonrpc":"2.0","method":"initialized"}"#;
+        let truncated = format!("Content-Length: {}\r\n\r\n{body}", body.len() + 10);
+        let mut reader = io::Cursor::new(truncated.clone());
+        assert_eq!(read_message(&mut reader, 100).unwrap(), None);
+
+        // Also when the message is skipped for being too large.
+        let mut reader = io::Cursor::new(truncated);
+        assert_eq!(read_message(&mut reader, 10).unwrap(), None);
+    }
+
+    #[test]
+    fn the_writer_stops_without_writing_queued_messages() {
+        let (tx, rx) = mpsc::channel();
+        tx.send("first".to_string()).unwrap();
+        tx.send("second".to_string()).unwrap();
+        let mut output = Vec::new();
+        // Returns although the sender is still alive.
+        writer_loop(rx, &mut output, &AtomicBool::new(true));
+        assert!(output.is_empty());
+
+        let (tx, rx) = mpsc::channel();
+        tx.send("first".to_string()).unwrap();
+        tx.send("second".to_string()).unwrap();
+        drop(tx);
+        writer_loop(rx, &mut output, &AtomicBool::new(false));
+        assert_eq!(String::from_utf8(output).unwrap(), "firstsecond");
+    }
+
+    #[test]
+    fn oversized_documents_are_reported_once() {
+        let (tx, rx) = mpsc::channel();
+        let mut state = State::new(tx);
+        notify(
+            &mut state,
+            notification::DidChangeConfiguration::METHOD,
+            json!({ "settings": { "limits": { "maxAnalyzedFileSize": 16 } } }),
+        );
+        let uri = "file:///project/src/big.rs";
+        notify(
+            &mut state,
+            notification::DidOpenTextDocument::METHOD,
+            json!({ "textDocument": {
+                "uri": uri, "languageId": "rust", "version": 1, "text": "fn main() { loop {} }",
+            } }),
+        );
+        for version in 2..4 {
+            notify(
+                &mut state,
+                notification::DidChangeTextDocument::METHOD,
+                json!({
+                    "textDocument": { "uri": uri, "version": version },
+                    "contentChanges": [{ "text": format!("fn main() {{ {version} }}") }],
+                }),
+            );
+        }
+
+        let messages: Vec<String> = rx.try_iter().collect();
+        assert_eq!(messages.len(), 1, "{messages:?}");
+        assert!(messages[0].contains(notification::ShowMessage::METHOD));
+        assert!(messages[0].contains("limits.maxAnalyzedFileSize (16 bytes)"));
+    }
+}
@@ -1,10 +1,28 @@
-mod config;
-mod diagnostics;
-mod doc;
-mod hover;
-mod inlay;
-mod lsp;
+use hitagi::cli::{self, Mode};
+use hitagi::{batch, log, lsp};
 
 fn main() {
-    lsp::server::run();
+    let code = match cli::parse(std::env::args_os().skip(1)) {
+        Ok(Mode::Serve {
+            transport,
+            log_file,
+        }) => {
+            log::init(log_file);
+            lsp::server::run(&transport)
+        }
+        Ok(Mode::Check { root, format }) => batch::run(&root, format),
+        Ok(Mode::Version) => {
+            println!("hitagi {}", env!("CARGO_PKG_VERSION"));
+            0
+        }
+        Ok(Mode::Help) => {
+            print!("{}", cli::USAGE);
+            0
+        }
+        Err(err) => {
+            eprintln!("hitagi: {err}\n\nRun 'hitagi --help' for usage.");
+            2
+        }
+    };
+    std::process::exit(code);
 }
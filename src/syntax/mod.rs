@@ -0,0 +1,442 @@
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone)]
+pub enum TokenKind {
+    Ident(String),
+    /// `r#name`; holds `name` while the token span covers the `r#` prefix.
+    RawIdent(String),
+    Lifetime(String),
+    Number,
+    Punct(char),
+    DoubleColon,
+    Arrow,
+}
+
+impl Token {
+    pub fn is_ident(&self, value: &str) -> bool {
+        matches!(&self.kind, TokenKind::Ident(name) if name == value)
+    }
+
+    pub fn ident(&self) -> Option<&str> {
+        match &self.kind {
+            TokenKind::Ident(name) | TokenKind::RawIdent(name) => Some(name.as_str()),
+            _ => None,
+        }
+    }
+
+    pub fn is_keyword(&self) -> bool {
+        matches!(&self.kind, TokenKind::Ident(name) if is_keyword(name))
+    }
+
+    pub fn is_punct(&self, ch: char) -> bool {
+        matches!(self.kind, TokenKind::Punct(value) if value == ch)
+    }
+}
+
+pub fn lex(text: &str) -> Vec<Token> {
+    let bytes = text.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0usize;
+
+    while i < bytes.len() {
+        let b = bytes[i];
+        if b.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        if b == b'/' && i + 1 < bytes.len() {
+            if bytes[i + 1] == b'/' {
+                i += 2;
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            if bytes[i + 1] == b'*' {
+                // Block comments nest, so `/* a /* b */ c */` is one comment.
+                i += 2;
+                let mut depth = 1;
+                while i + 1 < bytes.len() {
+                    if bytes[i] == b'/' && bytes[i + 1] == b'*' {
+                        depth += 1;
+                        i += 2;
+                    } else if bytes[i] == b'*' && bytes[i + 1] == b'/' {
+                        depth -= 1;
+                        i += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    } else {
+                        i += 1;
+                    }
+                }
+                continue;
+            }
+        }
+
+        if b == b'r' && i + 2 < bytes.len() && bytes[i + 1] == b'#' && is_ident_start(bytes[i + 2])
+        {
+            let start = i;
+            i += 3;
+            while i < bytes.len() && is_ident_continue(bytes[i]) {
+                i += 1;
+            }
+            tokens.push(Token {
+                kind: TokenKind::RawIdent(text[start + 2..i].to_string()),
+                start,
+                end: i,
+            });
+            continue;
+        }
+
+        if let Some(next) = skip_string_literal(bytes, i) {
+            i = next;
+            continue;
+        }
+
+        if b == b'\'' {
+            let (token, next) = lex_lifetime_or_char(text, bytes, i);
+            if let Some(token) = token {
+                tokens.push(token);
+            }
+            i = next;
+            continue;
+        }
+
+        if is_ident_start(b) {
+            let start = i;
+            i += 1;
+            while i < bytes.len() && is_ident_continue(bytes[i]) {
+                i += 1;
+            }
+            let ident = &text[start..i];
+            tokens.push(Token {
+                kind: TokenKind::Ident(ident.to_string()),
+                start,
+                end: i,
+            });
+            continue;
+        }
+
+        if b.is_ascii_digit() {
+            let start = i;
+            i += 1;
+            while i < bytes.len() {
+                let ch = bytes[i];
+                if ch.is_ascii_alphanumeric() || ch == b'_' || ch == b'.' {
+                    i += 1;
+                } else {
+                    break;
+                }
+            }
+            tokens.push(Token {
+                kind: TokenKind::Number,
+                start,
+                end: i,
+            });
+            continue;
+        }
+
+        if b == b':' && i + 1 < bytes.len() && bytes[i + 1] == b':' {
+            tokens.push(Token {
+                kind: TokenKind::DoubleColon,
+                start: i,
+                end: i + 2,
+            });
+            i += 2;
+            continue;
+        }
+
+        if b == b'-' && i + 1 < bytes.len() && bytes[i + 1] == b'>' {
+            tokens.push(Token {
+                kind: TokenKind::Arrow,
+                start: i,
+                end: i + 2,
+            });
+            i += 2;
+            continue;
+        }
+
+        tokens.push(Token {
+            kind: TokenKind::Punct(b as char),
+            start: i,
+            end: i + 1,
+        });
+        i += 1;
+    }
+
+    tokens
+}
+
+fn skip_string_literal(bytes: &[u8], idx: usize) -> Option<usize> {
+    let len = bytes.len();
+    if idx >= len {
+        return None;
+    }
+
+    if bytes[idx] == b'"' {
+        return Some(skip_normal_string(bytes, idx + 1));
+    }
+
+    if bytes[idx] == b'b' {
+        if idx + 1 < len && bytes[idx + 1] == b'"' {
+            return Some(skip_normal_string(bytes, idx + 2));
+        }
+        if idx + 1 < len
+            && bytes[idx + 1] == b'r'
+            && let Some(next) = skip_raw_string(bytes, idx + 2)
+        {
+            return Some(next);
+        }
+    }
+
+    if bytes[idx] == b'r'
+        && let Some(next) = skip_raw_string(bytes, idx + 1)
+    {
+        return Some(next);
+    }
+
+    None
+}
+
+fn skip_normal_string(bytes: &[u8], mut idx: usize) -> usize {
+    while idx < bytes.len() {
+        if bytes[idx] == b'\\' {
+            idx = idx.saturating_add(2);
+            continue;
+        }
+        if bytes[idx] == b'"' {
+            return idx + 1;
+        }
+        idx += 1;
+    }
+    bytes.len()
+}
+
+fn skip_raw_string(bytes: &[u8], mut idx: usize) -> Option<usize> {
+    let len = bytes.len();
+    let mut hashes = 0usize;
+    while idx < len && bytes[idx] == b'#' {
+        hashes += 1;
+        idx += 1;
+    }
+    if idx >= len || bytes[idx] != b'"' {
+        return None;
+    }
+    idx += 1;
+
+    while idx < len {
+        if bytes[idx] == b'"' {
+            let mut j = idx + 1;
+            let mut matched = 0usize;
+            while matched < hashes && j < len && bytes[j] == b'#' {
+                matched += 1;
+                j += 1;
+            }
+            if matched == hashes {
+                return Some(j);
+            }
+        }
+        idx += 1;
+    }
+
+    Some(len)
+}
+
+fn lex_lifetime_or_char(text: &str, bytes: &[u8], idx: usize) -> (Option<Token>, usize) {
+    let len = bytes.len();
+    if idx + 1 >= len {
+        return (None, idx + 1);
+    }
+    let next = bytes[idx + 1];
+    if is_ident_start(next) {
+        let mut j = idx + 1;
+        while j < len && is_ident_continue(bytes[j]) {
+            j += 1;
+        }
+        if j < len && bytes[j] == b'\'' {
+            return (None, j + 1);
+        }
+        let name = &text[idx + 1..j];
+        let token = Token {
+            kind: TokenKind::Lifetime(name.to_string()),
+            start: idx,
+            end: j,
+        };
+        return (Some(token), j);
+    }
+
+    let mut j = idx + 1;
+    while j < len {
+        if bytes[j] == b'\\' {
+            j = j.saturating_add(2);
+            continue;
+        }
+        if bytes[j] == b'\'' {
+            return (None, j + 1);
+        }
+        j += 1;
+    }
+
+    (None, len)
+}
+
+fn is_ident_start(b: u8) -> bool {
+    b == b'_' || (b as char).is_ascii_alphabetic()
+}
+
+pub fn is_ident_continue(b: u8) -> bool {
+    b == b'_' || (b as char).is_ascii_alphanumeric()
+}
+
+pub fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+pub fn find_matching_paren(tokens: &[Token], idx: usize) -> Option<usize> {
+    let mut depth = 0i32;
+    for (i, tok) in tokens.iter().enumerate().skip(idx) {
+        match tok.kind {
+            TokenKind::Punct('(') => depth += 1,
+            TokenKind::Punct(')') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+pub fn find_matching_brace(tokens: &[Token], idx: usize) -> Option<usize> {
+    let mut depth = 0i32;
+    for (i, tok) in tokens.iter().enumerate().skip(idx) {
+        match tok.kind {
+            TokenKind::Punct('{') => depth += 1,
+            TokenKind::Punct('}') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+pub fn find_matching_angle(tokens: &[Token], idx: usize) -> Option<usize> {
+    let mut depth = 0i32;
+    for (i, tok) in tokens.iter().enumerate().skip(idx) {
+        match tok.kind {
+            TokenKind::Punct('<') => depth += 1,
+            TokenKind::Punct('>') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+pub fn find_matching_angle_backward(tokens: &[Token], idx: usize) -> Option<usize> {
+    let mut depth = 0i32;
+    let mut i = idx;
+    loop {
+        match tokens[i].kind {
+            TokenKind::Punct('>') => depth += 1,
+            TokenKind::Punct('<') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+        if i == 0 {
+            break;
+        }
+        i -= 1;
+    }
+    None
+}
+
+pub fn is_keyword(name: &str) -> bool {
+    matches!(
+        name,
+        "if" | "while"
+            | "for"
+            | "match"
+            | "loop"
+            | "return"
+            | "fn"
+            | "struct"
+            | "enum"
+            | "trait"
+            | "type"
+            | "impl"
+            | "pub"
+            | "use"
+            | "const"
+            | "static"
+            | "async"
+            | "await"
+            | "move"
+            | "unsafe"
+            | "extern"
+            | "crate"
+            | "super"
+            | "self"
+    )
+}
+
+/// Returns the name of the self type in the header of an `impl` block, e.g.
+/// `Bar` for `impl<T> fmt::Display for foo::Bar<T> where T: Copy`.
+pub fn impl_self_type(header: &[Token]) -> Option<&str> {
+    let mut depth = 0i32;
+    let mut name = None;
+    for tok in header {
+        if tok.is_punct('<') {
+            depth += 1;
+        } else if tok.is_punct('>') {
+            depth -= 1;
+        } else if depth == 0 && tok.is_ident("for") {
+            name = None;
+        } else if depth == 0 && tok.is_ident("where") {
+            break;
+        } else if depth == 0
+            && let Some(ident) = tok.ident()
+            && !tok.is_keyword()
+        {
+            name = Some(ident);
+        }
+    }
+    name
+}
+
+/// Returns the `///` comment above the line containing `offset`, skipping
+/// attributes in between, with the comment markers removed.
+pub fn doc_comment_above(text: &str, offset: usize) -> Option<String> {
+    let line_start = text[..offset].rfind('\n').map_or(0, |nl| nl + 1);
+    let mut lines: Vec<&str> = text[..line_start]
+        .lines()
+        .rev()
+        .map(str::trim)
+        .skip_while(|line| line.starts_with("#["))
+        .map_while(|line| line.strip_prefix("///"))
+        .map(|line| line.strip_prefix(' ').unwrap_or(line))
+        .collect();
+    lines.reverse();
+    (!lines.is_empty()).then(|| lines.join("\n"))
+}
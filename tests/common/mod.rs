@@ -0,0 +1,173 @@
+//! Drives the server in process over in-memory pipes, the way an editor
+//! would over stdio.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, BufRead, BufReader, PipeWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use serde_json::{Value, json};
+
+/// How long to wait for a message before failing the test.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A client connected to a server running on its own thread.
+pub struct Connection {
+    input: PipeWriter,
+    messages: Receiver<Value>,
+    /// Messages received while waiting for another one.
+    skipped: VecDeque<Value>,
+    server: JoinHandle<i32>,
+    next_id: i64,
+}
+
+impl Connection {
+    pub fn start() -> Self {
+        let (server_input, input) = io::pipe().unwrap();
+        let (output, server_output) = io::pipe().unwrap();
+        let server =
+            thread::spawn(move || hitagi::lsp::server::run_with(server_input, server_output));
+        let (tx, messages) = mpsc::channel();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(output);
+            while let Some(message) = read_message(&mut reader) {
+                if tx.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+        Connection {
+            input,
+            messages,
+            skipped: VecDeque::new(),
+            server,
+            next_id: 0,
+        }
+    }
+
+    /// Initializes the server for the workspace at `root` and returns the
+    /// `initialize` result.
+    pub fn initialize(&mut self, root: &Path, capabilities: Value) -> Value {
+        self.initialize_with(root, capabilities, Value::Null)
+    }
+
+    /// `initialize`, sending `options` as the `initializationOptions`.
+    pub fn initialize_with(&mut self, root: &Path, capabilities: Value, options: Value) -> Value {
+        let result = self.request(
+            "initialize",
+            json!({
+                "rootUri": file_uri(root),
+                "capabilities": capabilities,
+                "initializationOptions": options,
+            }),
+        );
+        self.notify("initialized", json!({}));
+        result
+    }
+
+    /// Sends a request and returns its result, failing on an error.
+    pub fn request(&mut self, method: &str, params: Value) -> Value {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }));
+        let response = self.receive(|message| message["id"] == id && message["method"].is_null());
+        assert!(response["error"].is_null(), "{method} failed: {response}");
+        response["result"].clone()
+    }
+
+    pub fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+
+    /// Waits for the next `method` notification and returns its params;
+    /// other messages are kept for later.
+    pub fn notification(&mut self, method: &str) -> Value {
+        self.receive(|message| message["method"] == method && message["id"].is_null())["params"]
+            .clone()
+    }
+
+    /// Waits for the next `method` request from the server.
+    pub fn server_request(&mut self, method: &str) -> Value {
+        self.receive(|message| message["method"] == method && !message["id"].is_null())
+    }
+
+    /// Answers the server's request `id`.
+    pub fn respond(&mut self, id: Value, result: Value) {
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "result": result }));
+    }
+
+    /// Sends `exit` and returns the exit code of the server.
+    pub fn exit(mut self) -> i32 {
+        self.send(json!({ "jsonrpc": "2.0", "method": "exit" }));
+        self.server.join().unwrap()
+    }
+
+    /// Sends `body` as a message as it is, e.g. one that is not valid JSON.
+    pub fn send_raw(&mut self, body: &str) {
+        write!(self.input, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+    }
+
+    /// Waits for the response to the request with `id`, also an error.
+    pub fn response(&mut self, id: Value) -> Value {
+        self.receive(|message| message["id"] == id && message["method"].is_null())
+    }
+
+    fn send(&mut self, message: Value) {
+        self.send_raw(&message.to_string());
+    }
+
+    fn receive(&mut self, matches: impl Fn(&Value) -> bool) -> Value {
+        if let Some(idx) = self.skipped.iter().position(&matches) {
+            return self.skipped.remove(idx).unwrap();
+        }
+        loop {
+            let message = self
+                .messages
+                .recv_timeout(TIMEOUT)
+                .expect("the server sent nothing in time");
+            if matches(&message) {
+                return message;
+            }
+            self.skipped.push_back(message);
+        }
+    }
+}
+
+fn read_message(reader: &mut impl BufRead) -> Option<Value> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length: ") {
+            len = value.parse().ok();
+        }
+    }
+    let mut body = vec![0; len?];
+    reader.read_exact(&mut body).ok()?;
+    serde_json::from_slice(&body).ok()
+}
+
+/// Creates a fresh directory holding `files`, given by relative path.
+pub fn project(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("hitagi-test-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    for (path, text) in files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+    }
+    dir
+}
+
+pub fn file_uri(path: &Path) -> String {
+    format!("file://{}", path.display())
+}
@@ -0,0 +1,258 @@
+mod common;
+
+use std::fs;
+
+use common::{Connection, file_uri, project};
+use serde_json::{Value, json};
+
+const MAIN: &str = "fn foo(a: i32) {}\nfn main() {\n    let mut total = 1;\n    foo(total);\n}\n";
+
+fn open(client: &mut Connection, uri: &str, text: &str) {
+    client.notify(
+        "textDocument/didOpen",
+        json!({ "textDocument": {
+            "uri": uri, "languageId": "rust", "version": 1, "text": text,
+        } }),
+    );
+}
+
+#[test]
+fn hover_shows_the_definition_of_an_open_file() {
+    let root = project("hover", &[("src/main.rs", MAIN)]);
+    let uri = file_uri(&root.join("src/main.rs"));
+    let mut client = Connection::start();
+    let result = client.initialize(&root, json!({}));
+    assert_eq!(result["serverInfo"]["name"], "hitagi");
+    assert_eq!(result["capabilities"]["hoverProvider"], true);
+
+    open(&mut client, &uri, MAIN);
+    let hover = client.request(
+        "textDocument/hover",
+        json!({ "textDocument": { "uri": uri }, "position": { "line": 3, "character": 5 } }),
+    );
+    let contents = hover["contents"]["value"].as_str().unwrap();
+    assert!(contents.contains("fn foo(a: i32)"), "{contents}");
+
+    client.request("shutdown", Value::Null);
+    assert_eq!(client.exit(), 0);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+/// A fake check command that prints one compiler message, about the
+/// `let mut` in `MAIN`.
+fn unused_mut_check() -> Value {
+    fake_check("variable does not need to be mutable")
+}
+
+/// A fake check command that prints a warning with `text` about the
+/// `let mut` in `MAIN`.
+fn fake_check(text: &str) -> Value {
+    let message = json!({
+        "reason": "compiler-message",
+        "message": {
+            "$message_type": "diagnostic",
+            "message": text,
+            "level": "warning",
+            "code": { "code": "unused_mut", "explanation": null },
+            "children": [],
+            "spans": [{
+                "file_name": "src/main.rs", "is_primary": true,
+                "line_start": 3, "line_end": 3, "column_start": 9, "column_end": 18,
+            }],
+        },
+    });
+    json!(["sh", "-c", format!("printf '%s\\n' '{message}'")])
+}
+
+const MANIFEST: &str = "[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2024\"\n";
+
+#[test]
+fn saving_publishes_the_diagnostics_of_the_check_command() {
+    let root = project("check", &[("Cargo.toml", MANIFEST), ("src/main.rs", MAIN)]);
+    let uri = file_uri(&root.join("src/main.rs"));
+    let mut client = Connection::start();
+    client.initialize(&root, json!({}));
+    client.notify(
+        "workspace/didChangeConfiguration",
+        json!({ "settings": { "checkCommand": unused_mut_check() } }),
+    );
+    open(&mut client, &uri, MAIN);
+    client.notify(
+        "textDocument/didSave",
+        json!({ "textDocument": { "uri": uri } }),
+    );
+
+    let published = client.notification("textDocument/publishDiagnostics");
+    assert_eq!(published["uri"], uri);
+    assert_eq!(published["version"], 1);
+    let diagnostic = &published["diagnostics"][0];
+    assert_eq!(
+        diagnostic["message"],
+        "variable does not need to be mutable"
+    );
+    assert_eq!(diagnostic["code"], "unused_mut");
+    assert_eq!(
+        diagnostic["range"],
+        json!({ "start": { "line": 2, "character": 8 }, "end": { "line": 2, "character": 17 } })
+    );
+
+    client.request("shutdown", Value::Null);
+    assert_eq!(client.exit(), 0);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn initialization_options_configure_the_first_check() {
+    let root = project(
+        "options",
+        &[("Cargo.toml", MANIFEST), ("src/main.rs", MAIN)],
+    );
+    let uri = file_uri(&root.join("src/main.rs"));
+    let mut client = Connection::start();
+    // The client never sends `workspace/didChangeConfiguration`.
+    let options = json!({ "hitagi": { "checkCommand": unused_mut_check() } });
+    client.initialize_with(&root, json!({}), options);
+    open(&mut client, &uri, MAIN);
+    client.notify(
+        "textDocument/didSave",
+        json!({ "textDocument": { "uri": uri } }),
+    );
+
+    let published = client.notification("textDocument/publishDiagnostics");
+    assert_eq!(published["diagnostics"][0]["code"], "unused_mut");
+
+    client.request("shutdown", Value::Null);
+    assert_eq!(client.exit(), 0);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn each_workspace_folder_uses_its_own_settings() {
+    let files = [("Cargo.toml", MANIFEST), ("src/main.rs", MAIN)];
+    let firmware = project("folder-firmware", &files);
+    let app = project("folder-app", &files);
+    let mut client = Connection::start();
+    let folders: Vec<Value> = [&firmware, &app]
+        .iter()
+        .map(|root| json!({ "uri": file_uri(root), "name": "folder" }))
+        .collect();
+    client.request(
+        "initialize",
+        json!({
+            "rootUri": file_uri(&firmware),
+            "workspaceFolders": folders,
+            "capabilities": { "workspace": { "configuration": true } },
+        }),
+    );
+    client.notify("initialized", json!({}));
+
+    let request = client.server_request("workspace/configuration");
+    let items = &request["params"]["items"];
+    assert_eq!(items[0]["scopeUri"], file_uri(&firmware));
+    assert_eq!(items[1]["scopeUri"], file_uri(&app));
+    assert_eq!(items[0]["section"], "hitagi");
+    client.respond(
+        request["id"].clone(),
+        json!([
+            {
+                "checkCommand": fake_check("checked for thumbv7em-none-eabihf"),
+                "inlayHints": { "padding": false },
+            },
+            { "checkCommand": fake_check("checked for the host") },
+        ]),
+    );
+
+    for (root, expected) in [
+        (&firmware, "checked for thumbv7em-none-eabihf"),
+        (&app, "checked for the host"),
+    ] {
+        let uri = file_uri(&root.join("src/main.rs"));
+        open(&mut client, &uri, MAIN);
+        client.notify(
+            "textDocument/didSave",
+            json!({ "textDocument": { "uri": uri } }),
+        );
+        // Diagnostics stream in as the check runs and again once it ends,
+        // so the previous folder's may still be queued.
+        let published = loop {
+            let published = client.notification("textDocument/publishDiagnostics");
+            if published["uri"] == uri {
+                break published;
+            }
+        };
+        assert_eq!(published["diagnostics"][0]["message"], expected);
+    }
+
+    for (root, padded) in [(&firmware, false), (&app, true)] {
+        let hints = client.request(
+            "textDocument/inlayHint",
+            json!({
+                "textDocument": { "uri": file_uri(&root.join("src/main.rs")) },
+                "range": { "start": { "line": 0, "character": 0 }, "end": { "line": 5, "character": 0 } },
+            }),
+        );
+        let total = &hints[0];
+        assert_eq!(total["label"], ": i32");
+        assert_eq!(total["paddingLeft"] == true, padded, "{total}");
+    }
+
+    client.request("shutdown", Value::Null);
+    assert_eq!(client.exit(), 0);
+    fs::remove_dir_all(&firmware).unwrap();
+    fs::remove_dir_all(&app).unwrap();
+}
+
+#[test]
+fn saved_text_replaces_a_stale_document() {
+    let root = project("save", &[("src/main.rs", MAIN)]);
+    let uri = file_uri(&root.join("src/main.rs"));
+    let mut client = Connection::start();
+    let result = client.initialize(&root, json!({}));
+    assert_eq!(
+        result["capabilities"]["textDocumentSync"]["save"]["includeText"],
+        true
+    );
+    open(&mut client, &uri, MAIN);
+
+    // A formatter rewrote the file without the client sending a change.
+    let formatted = MAIN.replace("fn foo(a: i32)", "fn foo(value: i64)");
+    client.notify(
+        "textDocument/didSave",
+        json!({ "textDocument": { "uri": uri }, "text": formatted }),
+    );
+    let hover = client.request(
+        "textDocument/hover",
+        json!({ "textDocument": { "uri": uri }, "position": { "line": 3, "character": 5 } }),
+    );
+    let contents = hover["contents"]["value"].as_str().unwrap();
+    assert!(contents.contains("fn foo(value: i64)"), "{contents}");
+
+    client.request("shutdown", Value::Null);
+    assert_eq!(client.exit(), 0);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn exit_without_shutdown_fails() {
+    let root = project("exit", &[]);
+    let mut client = Connection::start();
+    client.initialize(&root, json!({}));
+    assert_eq!(client.exit(), 1);
+}
+
+#[test]
+fn malformed_messages_are_answered_and_skipped() {
+    let root = project("malformed", &[]);
+    let mut client = Connection::start();
+    client.send_raw(r#"{"jsonrpc":"2.0","id":90,"method":"initialize","params":{"#);
+    client.send_raw(r#"[{"jsonrpc":"2.0","id":91,"method":"initialize","params":{}}]"#);
+    client.send_raw("\u{0}\u{1}garbage");
+    assert_eq!(client.response(json!(90))["error"]["code"], -32700);
+    assert_eq!(client.response(json!(91))["error"]["code"], -32600);
+
+    // The server still answers the messages that follow.
+    let result = client.initialize(&root, json!({}));
+    assert_eq!(result["serverInfo"]["name"], "hitagi");
+    client.request("shutdown", Value::Null);
+    assert_eq!(client.exit(), 0);
+}
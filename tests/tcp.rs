@@ -0,0 +1,97 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{Value, json};
+
+fn spawn_server(args: &[&str]) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_hitagi"))
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap()
+}
+
+fn send(stream: &mut TcpStream, message: Value) {
+    let text = message.to_string();
+    write!(stream, "Content-Length: {}\r\n\r\n{}", text.len(), text).unwrap();
+}
+
+fn receive(reader: &mut BufReader<TcpStream>) -> Value {
+    let mut len = 0;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length: ") {
+            len = value.parse().unwrap();
+        }
+    }
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+/// Initializes, shuts down and exits over `stream`, and checks that the
+/// server exits successfully.
+fn round_trip(mut stream: TcpStream, mut server: Child) {
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    send(
+        &mut stream,
+        json!({
+            "jsonrpc": "2.0", "id": 1, "method": "initialize",
+            "params": { "capabilities": {} },
+        }),
+    );
+    let response = receive(&mut reader);
+    assert_eq!(response["id"], 1);
+    assert_eq!(response["result"]["capabilities"]["hoverProvider"], true);
+
+    send(
+        &mut stream,
+        json!({ "jsonrpc": "2.0", "id": 2, "method": "shutdown" }),
+    );
+    assert_eq!(receive(&mut reader)["id"], 2);
+    send(&mut stream, json!({ "jsonrpc": "2.0", "method": "exit" }));
+    assert!(server.wait().unwrap().success());
+}
+
+#[test]
+fn initialize_round_trips_in_listen_mode() {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let addr = format!("127.0.0.1:{port}");
+    let server = spawn_server(&["--listen", &addr]);
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let stream = loop {
+        match TcpStream::connect(&addr) {
+            Ok(stream) => break stream,
+            Err(_) if Instant::now() < deadline => thread::sleep(Duration::from_millis(20)),
+            Err(err) => panic!("the server did not listen on {addr}: {err}"),
+        }
+    };
+    round_trip(stream, server);
+}
+
+#[test]
+fn initialize_round_trips_in_connect_mode() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let server = spawn_server(&["--connect", &addr]);
+    let (stream, _) = listener.accept().unwrap();
+    round_trip(stream, server);
+}